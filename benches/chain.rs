@@ -0,0 +1,77 @@
+//! A 30-route component: nested `or` chains against a flat `chain!`.
+//!
+//! The stanza only matches the last route, so every route is tried.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use futures_util::FutureExt;
+use tower_service::Service;
+use wax::reply::Response;
+use wax::xmpp_parsers::iq::Iq;
+use wax::xmpp_parsers::jid::Jid;
+use wax::xmpp_parsers::minidom::Element;
+use wax::{Filter, Stanza};
+
+macro_rules! routes {
+    ($combine:ident; $($id:literal),+) => {
+        $combine!($(wax::iq().and(wax::id($id)).map(wax::sink)),+)
+    };
+}
+
+macro_rules! nested {
+    ($first:expr $(, $rest:expr)*) => {
+        $first $(.or($rest))*
+    };
+}
+
+macro_rules! flat {
+    ($($route:expr),+) => {
+        wax::chain![$($route),+]
+    };
+}
+
+macro_rules! thirty {
+    ($combine:ident) => {
+        routes!($combine;
+            "r00", "r01", "r02", "r03", "r04", "r05", "r06", "r07", "r08", "r09",
+            "r10", "r11", "r12", "r13", "r14", "r15", "r16", "r17", "r18", "r19",
+            "r20", "r21", "r22", "r23", "r24", "r25", "r26", "r27", "r28", "r29")
+    };
+}
+
+fn ping() -> Stanza {
+    Stanza::Iq(Iq::Get {
+        from: Some(Jid::new("juliet@capulet.lit/balcony").unwrap()),
+        to: Some(Jid::new("component.example.org").unwrap()),
+        id: "r29".to_owned(),
+        payload: Element::builder("ping", "urn:xmpp:ping").build(),
+    })
+}
+
+fn bench<S>(c: &mut Criterion, name: &str, mut service: S)
+where
+    S: Service<Stanza, Response = Response>,
+    S::Error: std::fmt::Debug,
+{
+    c.bench_function(name, |b| {
+        b.iter_batched(
+            ping,
+            |stanza| {
+                let reply = service
+                    .call(stanza)
+                    .now_or_never()
+                    .expect("routes are synchronous")
+                    .unwrap();
+                black_box(reply)
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+fn thirty_routes(c: &mut Criterion) {
+    bench(c, "thirty_routes/or", wax::service(thirty!(nested)));
+    bench(c, "thirty_routes/chain", wax::service(thirty!(flat)));
+}
+
+criterion_group!(benches, thirty_routes);
+criterion_main!(benches);
@@ -0,0 +1,74 @@
+//! Extraction cost on multi-route chains.
+//!
+//! Each route extracts something before rejecting, so a stanza matching
+//! only the last route runs every earlier extractor. Extractors borrow the
+//! filtered stanza and only clone what they hand to the handler, so the
+//! cost of the chain should not grow with the size of the payloads.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use futures_util::FutureExt;
+use tower_service::Service;
+use wax::xmpp_parsers::jid::Jid;
+use wax::xmpp_parsers::message::{Id, Lang, Message};
+use wax::xmpp_parsers::minidom::Element;
+use wax::xmpp_parsers::presence::Presence;
+use wax::{Filter, Stanza};
+
+fn message(payloads: usize) -> Stanza {
+    let mut msg = Message::new(Some(Jid::new("juliet@capulet.lit/balcony").unwrap()))
+        .with_body(Lang::default(), "Wherefore art thou, Romeo?".to_owned());
+    msg.from = Some(Jid::new("romeo@montague.lit/orchard").unwrap());
+    msg.id = Some(Id("bench".to_owned()));
+    for i in 0..payloads {
+        msg.payloads.push(
+            Element::builder("x", "urn:example:bench")
+                .attr("n", i.to_string())
+                .append("a reasonably long text node standing in for real payload data")
+                .build(),
+        );
+    }
+    Stanza::Message(msg)
+}
+
+fn multi_route(c: &mut Criterion) {
+    // Routes that extract and then reject a message, followed by the one
+    // that accepts it.
+    let routes = wax::iq()
+        .and(wax::require_from())
+        .map(|_| wax::sink())
+        .or(wax::presence::param()
+            .and(wax::require_to())
+            .map(|_: Presence, _| wax::sink()))
+        .or(wax::require_from()
+            .and(wax::require_to())
+            .and_then(|_, _| async { Err::<(), _>(wax::reject::item_not_found()) })
+            .map(|_| wax::sink()))
+        .or(wax::message::param().map(|_: Message| wax::sink()));
+    let mut service = wax::service(routes);
+
+    let mut group = c.benchmark_group("multi_route");
+    for payloads in [0, 16, 256] {
+        let stanza = message(payloads);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(payloads),
+            &stanza,
+            |b, stanza| {
+                b.iter_batched(
+                    || stanza.clone(),
+                    |stanza| {
+                        let reply = service
+                            .call(stanza)
+                            .now_or_never()
+                            .expect("routes are synchronous");
+                        black_box(reply)
+                    },
+                    BatchSize::SmallInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, multi_route);
+criterion_main!(benches);
@@ -0,0 +1,77 @@
+//! Relaying a message to another JID: rebuilding it from `message::param()`
+//! against forwarding it in place with `relay::param()`.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use futures_util::FutureExt;
+use tower_service::Service;
+use wax::relay::Relay;
+use wax::reply::Response;
+use wax::xmpp_parsers::jid::Jid;
+use wax::xmpp_parsers::message::{Id, Lang, Message};
+use wax::xmpp_parsers::minidom::Element;
+use wax::{Filter, Stanza};
+
+fn message(payloads: usize) -> Stanza {
+    let mut msg = Message::new(Some(Jid::new("gateway.example.org").unwrap()))
+        .with_body(Lang::default(), "Wherefore art thou, Romeo?".to_owned());
+    msg.from = Some(Jid::new("juliet@capulet.lit/balcony").unwrap());
+    msg.id = Some(Id("bench".to_owned()));
+    for i in 0..payloads {
+        msg.payloads.push(
+            Element::builder("x", "urn:example:bench")
+                .attr("n", i.to_string())
+                .append("a reasonably long text node standing in for real payload data")
+                .build(),
+        );
+    }
+    Stanza::Message(msg)
+}
+
+fn run<S>(c: &mut Criterion, name: &str, mut service: S)
+where
+    S: Service<Stanza, Response = Response>,
+    S::Error: std::fmt::Debug,
+{
+    let mut group = c.benchmark_group(name);
+    for payloads in [0, 16, 256] {
+        let stanza = message(payloads);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(payloads),
+            &stanza,
+            |b, stanza| {
+                b.iter_batched(
+                    || stanza.clone(),
+                    |stanza| {
+                        let reply = service
+                            .call(stanza)
+                            .now_or_never()
+                            .expect("routes are synchronous")
+                            .unwrap();
+                        black_box(reply)
+                    },
+                    BatchSize::SmallInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+fn relay(c: &mut Criterion) {
+    let upstream = Jid::new("upstream.example.org").unwrap();
+
+    let target = upstream.clone();
+    let rebuild = wax::message::param().map(move |mut msg: Message| {
+        msg.to = Some(target.clone());
+        msg
+    });
+    run(c, "relay/rebuild", wax::service(rebuild));
+
+    let passthrough = wax::message()
+        .and(wax::relay::param())
+        .map(move |relay: Relay| relay.to(upstream.clone()));
+    run(c, "relay/passthrough", wax::service(passthrough));
+}
+
+criterion_group!(benches, relay);
+criterion_main!(benches);
@@ -1,8 +1,8 @@
 use redis::{FromRedisValue, ParsingError, ToRedisArgs};
 use tokio_xmpp::jid::Jid;
 
-use crate::redis::{ByJid, RedisKey};
 use crate::tel::Tel;
+use wax::ext::redis::{ByJid, RedisKey};
 
 #[derive(Debug)]
 pub struct CatapultCred {
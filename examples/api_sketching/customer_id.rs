@@ -1,7 +1,7 @@
 use redis::{FromRedisValue, ParsingError, ToRedisArgs};
 use tokio_xmpp::jid::Jid;
 
-use crate::redis::{ByJid, RedisKey};
+use wax::ext::redis::{ByJid, FindInRedis, RedisKey};
 
 #[derive(Debug)]
 pub struct CustomerId(pub(crate) String);
@@ -21,6 +21,8 @@ impl FromRedisValue for CustomerId {
     }
 }
 
+impl FindInRedis for CustomerId {}
+
 impl ByJid for CustomerId {
     fn by_jid(jid: &Jid) -> impl ToRedisArgs {
         format!("jmp_customer_id-{0}", jid.as_str())
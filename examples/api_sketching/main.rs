@@ -5,19 +5,15 @@
 
 mod catapult_cred;
 mod customer_id;
-mod redis;
-mod tel;
 
 use bb8_redis::RedisConnectionManager;
 use tokio_xmpp::Component;
 use xmpp_parsers::jid::Jid;
 
+use wax::ext::redis::{self, with_redis, FindInRedis, RedisPool};
 use wax::{Filter, ServeComponent};
 
-use crate::{
-    catapult_cred::CatapultCred,
-    redis::{with_redis, FindInRedis, RedisPool},
-};
+use crate::catapult_cred::CatapultCred;
 
 #[tokio::main]
 async fn main() {
@@ -31,21 +27,17 @@ async fn main() {
         .get()
         .require_from()
         .and(with_redis(redis_pool))
-        .and_then(
-            async |from: Jid, pool: RedisPool| {
-                let mut con = pool.get().await.map_err(|_| wax::reject::reject())?;
-                let catapult_cred = from
-                    .find::<CatapultCred, _>(&mut *con)
-                    .await
-                    .map_err(|_| wax::reject::reject())?;
-                Ok::<_, wax::Rejection>(wax::sink())
-            },
-        );
+        .and_then(async |from: Jid, pool: RedisPool| {
+            let mut con = redis::connection(&pool).await?;
+            let catapult_cred = from.find::<CatapultCred, _>(&mut *con).await?;
+            Ok::<_, wax::Rejection>(wax::sink())
+        });
 
     Component::new("sgxbwmsgsv2.localhost", "secret")
         .await
         .expect("Failed to connect")
         .serve(ibr)
         .run()
-        .await;
+        .await
+        .expect("Server failed");
 }
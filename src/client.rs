@@ -0,0 +1,338 @@
+//! Serving filters from a client (c2s) connection.
+//!
+//! The same filter chains that power a component can power a bot logged in
+//! as a regular account:
+//!
+//! ```ignore
+//! use wax::ServeClient;
+//!
+//! let client = tokio_xmpp::Client::new("bot@example.org", password);
+//!
+//! client
+//!     .serve(routes)
+//!     .roster()
+//!     .graceful(async {
+//!         tokio::signal::ctrl_c().await.ok();
+//!     })
+//!     .run()
+//!     .await?;
+//! ```
+//!
+//! Resource binding is done by `tokio_xmpp`. Once the session is bound, the
+//! client fetches the roster if asked to, then sends its initial presence,
+//! and only then handles stanzas. Resumed sessions keep their state, so are
+//! not bootstrapped again.
+
+use std::cell::RefCell;
+use std::future::Future;
+
+use futures_util::future::{self, BoxFuture};
+use futures_util::{StreamExt, TryFuture};
+use tokio_xmpp::connect::ServerConnector;
+use tokio_xmpp::{Client, Event, Stanza};
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::jid::Jid;
+use xmpp_parsers::ns;
+use xmpp_parsers::presence::Presence;
+use xmpp_parsers::roster::Roster;
+
+use crate::correlation::{self, CorrelationContext};
+use crate::filter::Filter;
+use crate::reject::IsReject;
+use crate::reply::{Reply, Response};
+
+/// A trait for clients that can serve XMPP stanzas using a filter chain.
+pub trait ServeClient: Sized {
+    /// The connection of this client.
+    type Connector: ServerConnector;
+
+    /// Start serving stanzas using the provided filter.
+    fn serve<F>(self, filter: F) -> ClientServer<F, Self::Connector>
+    where
+        F: Filter + Clone + Send + Sync + 'static,
+        F::Extract: Reply,
+        F::Error: IsReject;
+}
+
+impl<C: ServerConnector> ServeClient for Client<C> {
+    type Connector = C;
+
+    fn serve<F>(self, filter: F) -> ClientServer<F, C>
+    where
+        F: Filter + Clone + Send + Sync + 'static,
+        F::Extract: Reply,
+        F::Error: IsReject,
+    {
+        ClientServer {
+            client: self,
+            filter,
+            roster: false,
+            presence: Some(Presence::available()),
+            outbound_capacity: None,
+            shutdown_signal: None,
+        }
+    }
+}
+
+/// A wax server running over a client connection.
+///
+/// Construct this type using [`ServeClient::serve`].
+pub struct ClientServer<F, C: ServerConnector> {
+    client: Client<C>,
+    filter: F,
+    roster: bool,
+    presence: Option<Presence>,
+    outbound_capacity: Option<usize>,
+    shutdown_signal: Option<BoxFuture<'static, ()>>,
+}
+
+impl<F, C: ServerConnector> std::fmt::Debug for ClientServer<F, C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientServer")
+            .field("roster", &self.roster)
+            .field("presence", &self.presence)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<F, C> ClientServer<F, C>
+where
+    F: Filter + Clone + Send + Sync + 'static,
+    <F::Future as TryFuture>::Ok: Reply,
+    <F::Future as TryFuture>::Error: IsReject,
+    C: ServerConnector,
+{
+    /// Fetch the roster once the session is bound.
+    ///
+    /// The roster result and later roster pushes run through the filters
+    /// like any stanza, so routes can keep track of contacts. Roster pushes
+    /// are acknowledged by the client, so replies to them are dropped.
+    pub fn roster(mut self) -> Self {
+        self.roster = true;
+        self
+    }
+
+    /// Send `presence` as the initial presence, instead of a plain
+    /// available presence.
+    pub fn presence(mut self, presence: Presence) -> Self {
+        self.presence = Some(presence);
+        self
+    }
+
+    /// Send no initial presence, so the account stays unavailable and
+    /// receives no messages addressed to its bare JID.
+    pub fn without_presence(mut self) -> Self {
+        self.presence = None;
+        self
+    }
+
+    /// Bound the queue of stanzas sent outside of replies.
+    ///
+    /// See `Server::outbound_capacity`.
+    pub fn outbound_capacity(mut self, capacity: usize) -> Self {
+        self.outbound_capacity = Some(capacity);
+        self
+    }
+
+    /// Add graceful shutdown support to this server.
+    ///
+    /// Once `shutdown_signal` resolves, the client stops reading stanzas,
+    /// sends the stanzas handlers already queued, ends the stream, and
+    /// `run()` returns.
+    pub fn graceful<Fut>(mut self, shutdown_signal: Fut) -> Self
+    where
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.shutdown_signal = Some(Box::pin(shutdown_signal));
+        self
+    }
+
+    /// Run this server.
+    ///
+    /// Only returns `Ok` once a graceful shutdown completes.
+    pub async fn run(mut self) -> Result<(), crate::Error> {
+        let (outbound_tx, mut outbound_rx) = correlation::outbound_channel(self.outbound_capacity);
+        let ctx = RefCell::new(CorrelationContext::new(outbound_tx));
+        let svc = crate::service(self.filter.clone());
+        let mut shutdown_signal = self
+            .shutdown_signal
+            .take()
+            .unwrap_or_else(|| Box::pin(future::pending()));
+        let mut account: Option<Jid> = None;
+
+        loop {
+            tokio::select! {
+                event = self.client.next() => match event {
+                    Some(Event::Online { bound_jid, resumed }) => {
+                        tracing::debug!("online as {}", bound_jid);
+                        if !resumed {
+                            self.bootstrap().await?;
+                        }
+                        account = Some(bound_jid);
+                    }
+                    Some(Event::Disconnected(err)) => {
+                        tracing::warn!("XMPP stream disconnected: {}", err);
+                    }
+                    Some(Event::Stanza(stanza)) => {
+                        let ack = roster_push_ack(&stanza, account.as_ref());
+                        let answerable = ack.is_none() && !is_iq_response(&stanza);
+                        if let Some(ack) = ack {
+                            send(&mut self.client, ack.into()).await?;
+                        }
+                        let response = correlation::set(&ctx, || svc.call_stanza(stanza))
+                            .await
+                            .unwrap_or_else(|infallible| match infallible {});
+                        if answerable {
+                            send(&mut self.client, response).await?;
+                        }
+                    }
+                    None => return Err(crate::Error::stream_closed()),
+                },
+
+                Some(outbound) = outbound_rx.recv() => {
+                    send(&mut self.client, outbound.into()).await?;
+                }
+
+                () = &mut shutdown_signal => {
+                    tracing::debug!("shutdown signal received, starting graceful shutdown");
+                    break;
+                }
+            }
+        }
+
+        while let Some(outbound) = outbound_rx.try_recv() {
+            send(&mut self.client, outbound.into()).await?;
+        }
+        if let Err(err) = self.client.send_end().await {
+            tracing::error!("failed to close the XMPP stream: {:?}", err);
+        }
+        Ok(())
+    }
+
+    /// Request the roster and send the initial presence of a new session.
+    async fn bootstrap(&mut self) -> Result<(), crate::Error> {
+        if self.roster {
+            let request = Iq::from_get(
+                correlation::unique_id(),
+                Roster {
+                    ver: None,
+                    items: vec![],
+                },
+            );
+            send(&mut self.client, Stanza::Iq(request).into()).await?;
+        }
+        if let Some(presence) = self.presence.clone() {
+            send(&mut self.client, Stanza::Presence(presence).into()).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Send the stanzas of `response` in order.
+async fn send<C: ServerConnector>(
+    client: &mut Client<C>,
+    response: Response,
+) -> Result<(), crate::Error> {
+    let mut stanzas = response.into_stream();
+    while let Some(stanza) = stanzas.next().await {
+        client
+            .send_stanza(stanza)
+            .await
+            .map_err(crate::Error::send)?;
+    }
+    Ok(())
+}
+
+/// Whether `stanza` answers a request, so must not be answered itself.
+fn is_iq_response(stanza: &Stanza) -> bool {
+    matches!(stanza, Stanza::Iq(Iq::Result { .. } | Iq::Error { .. }))
+}
+
+/// The result acknowledging `stanza`, if it is a roster push from the
+/// account's own server.
+fn roster_push_ack(stanza: &Stanza, account: Option<&Jid>) -> Option<Stanza> {
+    let Stanza::Iq(Iq::Set {
+        from, id, payload, ..
+    }) = stanza
+    else {
+        return None;
+    };
+    if !payload.is("query", ns::ROSTER) {
+        return None;
+    }
+    let from_account = match (from, account) {
+        (None, _) => true,
+        (Some(from), Some(account)) => from.to_bare() == account.to_bare(),
+        (Some(_), None) => false,
+    };
+    from_account.then(|| {
+        Stanza::Iq(Iq::Result {
+            from: None,
+            to: from.clone(),
+            id: id.clone(),
+            payload: None,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::oneshot;
+    use tokio_xmpp::connect::DnsConfig;
+    use tokio_xmpp::xmlstream::Timeouts;
+    use xmpp_parsers::jid::BareJid;
+
+    use super::*;
+    use crate::fake_server::{attr, FakeServer};
+
+    #[tokio::test]
+    async fn replies_over_the_client_connection() {
+        let fake = FakeServer::bind().await;
+        let client = Client::new_plaintext(
+            BareJid::new("bot@example.org").unwrap(),
+            "secret".to_owned(),
+            DnsConfig::no_srv("127.0.0.1", fake.port()),
+            Timeouts::default(),
+        );
+        let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+        let server = client
+            .serve(crate::any().map(crate::reply::iq_empty_result))
+            .roster()
+            .graceful(async {
+                let _ = shutdown_rx.await;
+            });
+
+        let test = async {
+            let mut peer = fake.accept_client("bot@example.org/wax").await;
+            let roster = peer.stanza().await;
+            assert_eq!(attr(&roster, "type"), Some("get"), "in {}", roster);
+            assert!(roster.contains("jabber:iq:roster"), "in {}", roster);
+            let presence = peer.stanza().await;
+            assert!(
+                presence.trim_start().starts_with("<presence"),
+                "in {}",
+                presence
+            );
+
+            peer.send(
+                "<iq type='get' id='ping' from='juliet@capulet.lit/balcony' \
+                 to='bot@example.org/wax'><ping xmlns='urn:xmpp:ping'/></iq>",
+            )
+            .await;
+            let result = peer.stanza().await;
+            assert_eq!(attr(&result, "id"), Some("ping"), "in {}", result);
+            assert_eq!(attr(&result, "type"), Some("result"), "in {}", result);
+            assert_eq!(
+                attr(&result, "to"),
+                Some("juliet@capulet.lit/balcony"),
+                "in {}",
+                result
+            );
+
+            shutdown.send(()).unwrap();
+            peer.expect_end().await;
+        };
+        let (result, ()) = tokio::join!(server.run(), test);
+        result.unwrap();
+    }
+}
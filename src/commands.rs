@@ -0,0 +1,834 @@
+//! Ad-Hoc Commands (XEP-0050).
+//!
+//! A [`Commands`] registry maps command nodes to [`Command`]s and serves
+//! them with [`Commands::filter`]. Multi-step commands keep their state
+//! between steps in a [`Namespace`] of the shared
+//! [`KvStore`](crate::store::KvStore), keyed by the `sessionid` handed to the
+//! requester, so sessions survive restarts with a persistent store.
+//!
+//! Each step gets the requested [`Action`] and the submitted form, and
+//! answers with a [`Step`]: another form to fill in, or completion.
+//! Cancellations end the session without calling the command. Sessions left
+//! idle for longer than [`Commands::session_ttl`] expire.
+//!
+//! [`Commands::items`] lists the commands for `disco#items` on the
+//! commands node.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use futures_util::future::BoxFuture;
+//! use wax::commands::{Action, Command, Commands, Input, Step};
+//! use wax::form::{DataForm, Field, FormType};
+//! use wax::store::{KvStore, MemoryStore};
+//! use wax::Rejection;
+//!
+//! struct Announce;
+//!
+//! impl Command for Announce {
+//!     type State = ();
+//!
+//!     fn execute<'a>(&'a self, _: &'a mut (), input: Input) -> BoxFuture<'a, Result<Step, Rejection>> {
+//!         Box::pin(async move {
+//!             match input.form {
+//!                 None => Ok(Step::form(
+//!                     DataForm::new(FormType::Form).field(Field::new("text").required()),
+//!                 )),
+//!                 Some(form) => {
+//!                     // broadcast form.values("text")...
+//!                     Ok(Step::complete().note("Announcement sent."))
+//!                 }
+//!             }
+//!         })
+//!     }
+//! }
+//!
+//! let commands = Commands::new(MemoryStore::new().namespace("commands"))
+//!     .command("announce", "Send an announcement", Announce);
+//!
+//! let routes = commands.filter().or(wax::disco::items(commands.items()));
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures_util::future::{self, BoxFuture};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::jid::Jid;
+use xmpp_parsers::minidom::Element;
+use xmpp_parsers::stanza_error::{DefinedCondition, ErrorType, StanzaError};
+
+use crate::correlation;
+use crate::disco::{Item, ItemProvider, ItemsQuery};
+use crate::filter::{filter_fn_one, Filter};
+use crate::form::{self, DataForm};
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+use crate::store::Namespace;
+
+/// The `http://jabber.org/protocol/commands` namespace, also the disco node
+/// listing the commands.
+pub const NS: &str = "http://jabber.org/protocol/commands";
+
+/// The action requested by a command step.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Start the command, or proceed in the default direction.
+    Execute,
+    /// Proceed to the next stage.
+    Next,
+    /// Go back to the previous stage.
+    Prev,
+    /// Finish the command.
+    Complete,
+    /// Abandon the command.
+    Cancel,
+}
+
+impl Action {
+    fn parse(action: Option<&str>) -> Option<Self> {
+        match action {
+            None | Some("execute") => Some(Action::Execute),
+            Some("next") => Some(Action::Next),
+            Some("prev") => Some(Action::Prev),
+            Some("complete") => Some(Action::Complete),
+            Some("cancel") => Some(Action::Cancel),
+            Some(_) => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Action::Execute => "execute",
+            Action::Next => "next",
+            Action::Prev => "prev",
+            Action::Complete => "complete",
+            Action::Cancel => "cancel",
+        }
+    }
+}
+
+/// The input of a command step.
+#[derive(Clone, Debug)]
+pub struct Input {
+    /// The entity executing the command.
+    pub from: Jid,
+    /// The requested action.
+    pub action: Action,
+    /// The submitted form, if any.
+    pub form: Option<DataForm>,
+    /// Whether this is the first step of the session.
+    pub is_new: bool,
+}
+
+/// The outcome of a command step.
+#[derive(Clone, Debug)]
+pub struct Step {
+    completed: bool,
+    form: Option<DataForm>,
+    actions: Vec<Action>,
+    default: Option<Action>,
+    notes: Vec<(&'static str, String)>,
+}
+
+impl Step {
+    /// Ask for `form`, offering to complete the command.
+    pub fn form(form: DataForm) -> Self {
+        Step {
+            completed: false,
+            form: Some(form),
+            actions: vec![Action::Complete],
+            default: Some(Action::Complete),
+            notes: Vec::new(),
+        }
+    }
+
+    /// Finish the command.
+    pub fn complete() -> Self {
+        Step {
+            completed: true,
+            form: None,
+            actions: Vec::new(),
+            default: None,
+            notes: Vec::new(),
+        }
+    }
+
+    /// Offer `actions` instead, with `default` as the default one.
+    pub fn actions(mut self, actions: impl IntoIterator<Item = Action>, default: Action) -> Self {
+        self.actions = actions.into_iter().collect();
+        self.default = Some(default);
+        self
+    }
+
+    /// Attach a result form to a completed step.
+    pub fn with_form(mut self, form: DataForm) -> Self {
+        self.form = Some(form);
+        self
+    }
+
+    /// Add an informational note.
+    pub fn note(mut self, text: impl Into<String>) -> Self {
+        self.notes.push(("info", text.into()));
+        self
+    }
+
+    /// Add a warning note.
+    pub fn warning(mut self, text: impl Into<String>) -> Self {
+        self.notes.push(("warn", text.into()));
+        self
+    }
+
+    /// Add an error note.
+    pub fn error(mut self, text: impl Into<String>) -> Self {
+        self.notes.push(("error", text.into()));
+        self
+    }
+}
+
+/// An ad-hoc command.
+pub trait Command: Send + Sync + 'static {
+    /// The state kept between the steps of a session.
+    type State: Serialize + DeserializeOwned + Default + Send;
+
+    /// Run one step, updating `state`.
+    ///
+    /// Rejecting ends the session.
+    fn execute<'a>(
+        &'a self,
+        state: &'a mut Self::State,
+        input: Input,
+    ) -> BoxFuture<'a, Result<Step, Rejection>>;
+}
+
+// `Command` with the state type erased, so commands with different states
+// share a registry.
+trait ErasedCommand: Send + Sync {
+    fn execute(
+        &self,
+        state: Option<serde_json::Value>,
+        input: Input,
+    ) -> BoxFuture<'_, Result<(Step, serde_json::Value), Rejection>>;
+}
+
+impl<C: Command> ErasedCommand for C {
+    fn execute(
+        &self,
+        state: Option<serde_json::Value>,
+        input: Input,
+    ) -> BoxFuture<'_, Result<(Step, serde_json::Value), Rejection>> {
+        Box::pin(async move {
+            let mut state: C::State = match state {
+                Some(state) => serde_json::from_value(state).map_err(|err| {
+                    tracing::error!("invalid command session state: {}", err);
+                    reject::internal_server_error()
+                })?,
+                None => C::State::default(),
+            };
+            let step = Command::execute(self, &mut state, input).await?;
+            let state = serde_json::to_value(&state).map_err(|err| {
+                tracing::error!("unserializable command session state: {}", err);
+                reject::internal_server_error()
+            })?;
+            Ok((step, state))
+        })
+    }
+}
+
+struct Registered {
+    name: String,
+    command: Box<dyn ErasedCommand>,
+}
+
+/// A registry of ad-hoc commands.
+///
+/// Clones share the same commands and session store.
+#[derive(Clone)]
+pub struct Commands {
+    commands: Arc<HashMap<String, Registered>>,
+    sessions: Namespace,
+    ttl: Duration,
+    // When to next sweep the store for expired sessions, in milliseconds
+    // since the epoch.
+    next_sweep: Arc<AtomicU64>,
+}
+
+// A session as stored: the command node, the bare JID of its owner, the
+// actions offered by the last step, when the session expires in
+// milliseconds since the epoch, and the command state.
+type StoredSession = (String, String, Vec<String>, u64, serde_json::Value);
+
+impl Commands {
+    /// Create an empty registry keeping sessions in `sessions`.
+    pub fn new(sessions: Namespace) -> Self {
+        Commands {
+            commands: Arc::new(HashMap::new()),
+            sessions,
+            ttl: Duration::from_secs(600),
+            next_sweep: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Expire sessions idle for longer than `ttl`. Defaults to ten minutes.
+    ///
+    /// Expired sessions are removed when next used, and swept from the store
+    /// at most once per `ttl` when new sessions start.
+    pub fn session_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Register `command` under `node`, listed as `name`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the registry has already been cloned.
+    pub fn command(
+        mut self,
+        node: impl Into<String>,
+        name: impl Into<String>,
+        command: impl Command,
+    ) -> Self {
+        Arc::get_mut(&mut self.commands)
+            .expect("commands must be registered before the registry is cloned")
+            .insert(
+                node.into(),
+                Registered {
+                    name: name.into(),
+                    command: Box::new(command),
+                },
+            );
+        self
+    }
+
+    /// An [`ItemProvider`] listing the commands on the [`NS`] node.
+    pub fn items(&self) -> CommandItems {
+        CommandItems {
+            commands: self.commands.clone(),
+        }
+    }
+
+    /// Serve the registered commands.
+    ///
+    /// Rejects with `item-not-found` for unknown nodes and with `bad-request`
+    /// for malformed requests. Unknown, expired or foreign sessions are
+    /// answered with `bad-request` and `<bad-sessionid/>`, and actions the
+    /// last step did not offer with `bad-request` and `<bad-action/>`.
+    pub fn filter(&self) -> impl Filter<Extract = One<Iq>, Error = Rejection> + Clone {
+        let commands = self.clone();
+        filter_fn_one(|stanza: &Stanza| future::ready(Request::parse(stanza)))
+            .and_then(move |request: Request| {
+                let commands = commands.clone();
+                async move { commands.run(request).await }
+            })
+            .advertises(NS)
+    }
+
+    async fn run(&self, request: Request) -> Result<Iq, Rejection> {
+        let registered = self
+            .commands
+            .get(&request.node)
+            .ok_or_else(reject::item_not_found)?;
+        let owner = request.from.to_bare().to_string();
+        let now = unix_millis(SystemTime::now());
+
+        let (session_id, state) = match request.session_id {
+            Some(ref session_id) => {
+                let Some(stored) = self.sessions.get::<StoredSession>(session_id).await? else {
+                    return Ok(request.error(BAD_SESSION_ID));
+                };
+                let (node, stored_owner, actions, expires, state) = stored;
+                if expires <= now {
+                    self.sessions.delete(session_id).await?;
+                    return Ok(request.error(BAD_SESSION_ID));
+                }
+                if node != request.node || stored_owner != owner {
+                    return Ok(request.error(BAD_SESSION_ID));
+                }
+                // Execute proceeds in the default direction and cancel is
+                // always possible; anything else must have been offered.
+                let offered = matches!(request.action, Action::Execute | Action::Cancel)
+                    || actions.iter().any(|a| a == request.action.as_str());
+                if !offered {
+                    return Ok(request.error(BAD_ACTION));
+                }
+                (session_id.clone(), Some(state))
+            }
+            None => {
+                if !matches!(request.action, Action::Execute | Action::Cancel) {
+                    return Ok(request.error(BAD_ACTION));
+                }
+                self.sweep(now).await?;
+                (correlation::unique_id(), None)
+            }
+        };
+
+        if request.action == Action::Cancel {
+            self.sessions.delete(&session_id).await?;
+            return Ok(request.reply(&session_id, "canceled", None));
+        }
+
+        let input = Input {
+            from: request.from.clone(),
+            action: request.action,
+            form: request.form.clone(),
+            is_new: state.is_none(),
+        };
+        let (step, state) = match registered.command.execute(state, input).await {
+            Ok(done) => done,
+            Err(rejection) => {
+                self.sessions.delete(&session_id).await?;
+                return Err(rejection);
+            }
+        };
+
+        if step.completed {
+            self.sessions.delete(&session_id).await?;
+            Ok(request.reply(&session_id, "completed", Some(step)))
+        } else {
+            let actions = step
+                .actions
+                .iter()
+                .map(|action| action.as_str().to_owned())
+                .collect();
+            let expires = now.saturating_add(self.ttl.as_millis() as u64);
+            let stored: StoredSession = (request.node.clone(), owner, actions, expires, state);
+            self.sessions.put(&session_id, &stored).await?;
+            Ok(request.reply(&session_id, "executing", Some(step)))
+        }
+    }
+
+    // Remove expired sessions, unless a sweep already ran within the TTL.
+    async fn sweep(&self, now: u64) -> Result<(), Rejection> {
+        let next = self.next_sweep.load(Ordering::Relaxed);
+        let after = now.saturating_add(self.ttl.as_millis() as u64);
+        if now < next
+            || self
+                .next_sweep
+                .compare_exchange(next, after, Ordering::Relaxed, Ordering::Relaxed)
+                .is_err()
+        {
+            return Ok(());
+        }
+        for session_id in self.sessions.list().await? {
+            if let Some((_, _, _, expires, _)) =
+                self.sessions.get::<StoredSession>(&session_id).await?
+            {
+                if expires <= now {
+                    self.sessions.delete(&session_id).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Commands {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Commands")
+            .field("nodes", &self.commands.keys().collect::<Vec<_>>())
+            .field("sessions", &self.sessions)
+            .field("ttl", &self.ttl)
+            .finish()
+    }
+}
+
+/// Lists the commands of a [`Commands`] registry.
+pub struct CommandItems {
+    commands: Arc<HashMap<String, Registered>>,
+}
+
+impl fmt::Debug for CommandItems {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CommandItems").finish()
+    }
+}
+
+impl ItemProvider for CommandItems {
+    fn items<'a>(&'a self, query: &'a ItemsQuery) -> BoxFuture<'a, Result<Vec<Item>, Rejection>> {
+        let items = match (query.node.as_deref(), &query.to) {
+            (Some(NS), Some(to)) => {
+                let mut items: Vec<_> = self
+                    .commands
+                    .iter()
+                    .map(|(node, registered)| {
+                        Item::new(to.clone()).node(node).name(&registered.name)
+                    })
+                    .collect();
+                items.sort_by(|a, b| a.node.cmp(&b.node));
+                Ok(items)
+            }
+            _ => Err(reject::item_not_found()),
+        };
+        Box::pin(future::ready(items))
+    }
+}
+
+struct Request {
+    from: Jid,
+    to: Option<Jid>,
+    id: String,
+    node: String,
+    session_id: Option<String>,
+    action: Action,
+    form: Option<DataForm>,
+}
+
+impl Request {
+    fn parse(stanza: &Stanza) -> Result<Request, Rejection> {
+        let Stanza::Iq(Iq::Set {
+            from,
+            to,
+            id,
+            payload,
+        }) = stanza
+        else {
+            return Err(reject::item_not_found());
+        };
+        if !payload.is("command", NS) {
+            return Err(reject::item_not_found());
+        }
+        let from = from.clone().ok_or_else(reject::bad_request)?;
+        let node = payload.attr("node").ok_or_else(reject::bad_request)?;
+        let action = Action::parse(payload.attr("action")).ok_or_else(reject::bad_request)?;
+        let form = match payload.get_child("x", form::NS) {
+            Some(elem) => Some(DataForm::try_from(elem).map_err(|err| {
+                tracing::debug!("invalid command form: {}", err);
+                reject::bad_request()
+            })?),
+            None => None,
+        };
+        Ok(Request {
+            from,
+            to: to.clone(),
+            id: id.clone(),
+            node: node.to_owned(),
+            session_id: payload.attr("sessionid").map(str::to_owned),
+            action,
+            form,
+        })
+    }
+
+    fn reply(&self, session_id: &str, status: &str, step: Option<Step>) -> Iq {
+        let mut command = Element::builder("command", NS)
+            .attr("node", self.node.as_str())
+            .attr("sessionid", session_id)
+            .attr("status", status);
+        if let Some(step) = step {
+            if !step.completed {
+                let mut actions = Element::builder("actions", NS)
+                    .attr("execute", step.default.map(Action::as_str));
+                for action in step.actions {
+                    actions = actions.append(Element::builder(action.as_str(), NS).build());
+                }
+                command = command.append(actions.build());
+            }
+            for (type_, text) in step.notes {
+                command = command.append(
+                    Element::builder("note", NS)
+                        .attr("type", type_)
+                        .append(text)
+                        .build(),
+                );
+            }
+            if let Some(form) = step.form {
+                command = command.append(Element::from(form));
+            }
+        }
+        Iq::Result {
+            from: self.to.clone(),
+            to: Some(self.from.clone()),
+            id: self.id.clone(),
+            payload: Some(command.build()),
+        }
+    }
+
+    // A `bad-request` error carrying the commands-specific `condition`.
+    fn error(&self, condition: &str) -> Iq {
+        let mut error = StanzaError::new(ErrorType::Modify, DefinedCondition::BadRequest, "en", "");
+        error.texts.clear();
+        error.other = Some(Element::builder(condition, NS).build());
+        Iq::Error {
+            from: self.to.clone(),
+            to: Some(self.from.clone()),
+            id: self.id.clone(),
+            error,
+            payload: None,
+        }
+    }
+}
+
+// The application conditions of XEP-0050 errors.
+const BAD_ACTION: &str = "bad-action";
+const BAD_SESSION_ID: &str = "bad-sessionid";
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::form::{Field, FormType};
+    use crate::store::{KvStore, MemoryStore};
+
+    struct Counter;
+
+    impl Command for Counter {
+        type State = u32;
+
+        fn execute<'a>(
+            &'a self,
+            count: &'a mut u32,
+            input: Input,
+        ) -> BoxFuture<'a, Result<Step, Rejection>> {
+            Box::pin(async move {
+                *count += 1;
+                Ok(match input.action {
+                    Action::Complete => Step::complete().note(count.to_string()),
+                    _ => Step::form(DataForm::new(FormType::Form).field(Field::new("n")))
+                        .actions([Action::Next, Action::Complete], Action::Next),
+                })
+            })
+        }
+    }
+
+    fn request(from: &str, session_id: Option<&str>, action: Action) -> Request {
+        Request {
+            from: from.parse().unwrap(),
+            to: Some("commands.example.com".parse().unwrap()),
+            id: "cmd".to_owned(),
+            node: "count".to_owned(),
+            session_id: session_id.map(str::to_owned),
+            action,
+            form: None,
+        }
+    }
+
+    fn commands() -> Commands {
+        Commands::new(MemoryStore::new().namespace("commands")).command("count", "Count", Counter)
+    }
+
+    fn status(iq: &Iq) -> Option<&str> {
+        match iq {
+            Iq::Result {
+                payload: Some(command),
+                ..
+            } => command.attr("status"),
+            _ => None,
+        }
+    }
+
+    fn session_id(iq: &Iq) -> String {
+        let Iq::Result {
+            payload: Some(command),
+            ..
+        } = iq
+        else {
+            panic!("expected a result");
+        };
+        command.attr("sessionid").unwrap().to_owned()
+    }
+
+    // The commands-specific condition of a `bad-request` error.
+    fn condition(iq: &Iq) -> Option<&str> {
+        match iq {
+            Iq::Error { error, .. } => {
+                assert_eq!(error.defined_condition, DefinedCondition::BadRequest);
+                error.other.as_ref().map(|other| {
+                    assert_eq!(other.ns(), NS);
+                    other.name()
+                })
+            }
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn sessions_span_steps() {
+        let commands = commands();
+
+        let first = commands
+            .run(request("juliet@example.com/a", None, Action::Execute))
+            .await
+            .unwrap();
+        assert_eq!(status(&first), Some("executing"));
+        let session_id = session_id(&first);
+
+        let done = commands
+            .run(request(
+                "juliet@example.com/b",
+                Some(&session_id),
+                Action::Complete,
+            ))
+            .await
+            .unwrap();
+        let Iq::Result {
+            payload: Some(command),
+            ..
+        } = done
+        else {
+            panic!("expected a result");
+        };
+        assert_eq!(command.attr("status"), Some("completed"));
+        assert_eq!(command.get_child("note", NS).unwrap().text(), "2");
+
+        // Completed sessions are gone.
+        let gone = commands
+            .run(request(
+                "juliet@example.com/a",
+                Some(&session_id),
+                Action::Next,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(condition(&gone), Some(BAD_SESSION_ID));
+    }
+
+    #[tokio::test]
+    async fn cancels_sessions() {
+        let commands = commands();
+        let first = commands
+            .run(request("juliet@example.com/a", None, Action::Execute))
+            .await
+            .unwrap();
+        let session_id = session_id(&first);
+
+        let canceled = commands
+            .run(request(
+                "juliet@example.com/a",
+                Some(&session_id),
+                Action::Cancel,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(status(&canceled), Some("canceled"));
+        assert!(commands.sessions.list().await.unwrap().is_empty());
+
+        let gone = commands
+            .run(request(
+                "juliet@example.com/a",
+                Some(&session_id),
+                Action::Next,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(condition(&gone), Some(BAD_SESSION_ID));
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_and_foreign_sessions() {
+        let commands = commands();
+        let first = commands
+            .run(request("juliet@example.com/a", None, Action::Execute))
+            .await
+            .unwrap();
+        let session_id = session_id(&first);
+
+        let unknown = commands
+            .run(request("juliet@example.com/a", Some("nope"), Action::Next))
+            .await
+            .unwrap();
+        assert_eq!(condition(&unknown), Some(BAD_SESSION_ID));
+
+        // Another user cannot take over the session.
+        let foreign = commands
+            .run(request(
+                "romeo@example.com/a",
+                Some(&session_id),
+                Action::Next,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(condition(&foreign), Some(BAD_SESSION_ID));
+
+        // Which is still there for its owner.
+        let next = commands
+            .run(request(
+                "juliet@example.com/a",
+                Some(&session_id),
+                Action::Next,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(status(&next), Some("executing"));
+    }
+
+    #[tokio::test]
+    async fn rejects_actions_not_offered() {
+        let commands = commands();
+
+        // Only execute starts a session.
+        let unstarted = commands
+            .run(request("juliet@example.com/a", None, Action::Next))
+            .await
+            .unwrap();
+        assert_eq!(condition(&unstarted), Some(BAD_ACTION));
+
+        let first = commands
+            .run(request("juliet@example.com/a", None, Action::Execute))
+            .await
+            .unwrap();
+        let session_id = session_id(&first);
+
+        // The step offered next and complete, not prev.
+        let prev = commands
+            .run(request(
+                "juliet@example.com/a",
+                Some(&session_id),
+                Action::Prev,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(condition(&prev), Some(BAD_ACTION));
+
+        let next = commands
+            .run(request(
+                "juliet@example.com/a",
+                Some(&session_id),
+                Action::Next,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(status(&next), Some("executing"));
+    }
+
+    #[tokio::test]
+    async fn expires_idle_sessions() {
+        let commands = commands().session_ttl(Duration::ZERO);
+
+        let first = commands
+            .run(request("juliet@example.com/a", None, Action::Execute))
+            .await
+            .unwrap();
+        let first = session_id(&first);
+        let expired = commands
+            .run(request("juliet@example.com/a", Some(&first), Action::Next))
+            .await
+            .unwrap();
+        assert_eq!(condition(&expired), Some(BAD_SESSION_ID));
+        assert!(commands.sessions.list().await.unwrap().is_empty());
+
+        // Abandoned sessions are swept when new ones start.
+        let abandoned = commands
+            .run(request("romeo@example.com/a", None, Action::Execute))
+            .await
+            .unwrap();
+        let abandoned = session_id(&abandoned);
+        let latest = commands
+            .run(request("juliet@example.com/a", None, Action::Execute))
+            .await
+            .unwrap();
+        let latest = session_id(&latest);
+        let stored = commands.sessions.list().await.unwrap();
+        assert!(!stored.contains(&abandoned));
+        assert!(stored.contains(&latest));
+    }
+}
@@ -0,0 +1,431 @@
+//! Component configuration.
+//!
+//! Available with the `config` feature.
+//!
+//! A [`Config`] holds everything needed to connect a component to its XMPP
+//! server, so deployments can keep secrets out of `main.rs`. It can be read
+//! from a TOML file, from `WAX_*` environment variables, or from a file with
+//! environment overrides on top.
+//!
+//! ```toml
+//! jid = "sms.example.org"
+//! secret_file = "/run/secrets/component"
+//! host = "xmpp.internal"
+//! port = 5347
+//! fallback_hosts = ["xmpp-standby.internal:5347"]
+//! connect_timeout_secs = 10
+//!
+//! [reconnect]
+//! max_attempts = 10
+//! initial_delay_ms = 500
+//! max_delay_ms = 30000
+//!
+//! [keepalive]
+//! read_timeout_secs = 300
+//! response_timeout_secs = 30
+//!
+//! [queues]
+//! outbound = 1024
+//! outbound_batch = 64
+//! ```
+//!
+//! # Example
+//!
+//! ```ignore
+//! let config = wax::config::Config::from_file("component.toml")?.with_env();
+//! config.build_server(routes).await?.run().await?;
+//! ```
+
+use std::env;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Deserialize;
+use tokio_xmpp::xmlstream::Timeouts;
+
+use crate::connect::Connector;
+use crate::filter::Filter;
+use crate::reject::IsReject;
+use crate::reply::Reply;
+use crate::server::{self, ReconnectPolicy, ServeComponent, Server};
+use crate::Error;
+
+/// Component connection settings.
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The component JID, e.g. `sms.example.org`.
+    pub jid: String,
+    /// The shared secret. Takes precedence over `secret_file`.
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// A file containing the shared secret.
+    #[serde(default)]
+    pub secret_file: Option<PathBuf>,
+    /// The XMPP server host.
+    #[serde(default = "default_host")]
+    pub host: String,
+    /// The XMPP server's component port.
+    #[serde(default = "default_port")]
+    pub port: u16,
+    /// Resolve `host` through its `_xmpp-component._tcp` SRV record, using
+    /// `port` only if there is none. Requires the `dns` feature.
+    #[serde(default)]
+    pub srv: bool,
+    /// `host:port` pairs tried in order when `host` can't be reached.
+    #[serde(default)]
+    pub fallback_hosts: Vec<String>,
+    /// How long each connection attempt may take, in seconds.
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout_secs: u64,
+    /// How to retry when the connection is lost.
+    #[serde(default)]
+    pub reconnect: Reconnect,
+    /// Stream timeouts.
+    #[serde(default)]
+    pub keepalive: Keepalive,
+    /// Queue sizes.
+    #[serde(default)]
+    pub queues: Queues,
+}
+
+/// Reconnection settings; see [`ReconnectPolicy`].
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Reconnect {
+    /// Whether to reconnect at all.
+    pub enabled: bool,
+    /// Give up after this many consecutive failed attempts.
+    pub max_attempts: Option<u32>,
+    /// The delay before the first attempt, in milliseconds.
+    pub initial_delay_ms: u64,
+    /// The upper bound on the delay between attempts, in milliseconds.
+    pub max_delay_ms: u64,
+}
+
+/// Stream timeout settings.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Keepalive {
+    /// How long the stream may stay silent before the server is probed.
+    pub read_timeout_secs: u64,
+    /// How long to wait for the server to answer a probe.
+    pub response_timeout_secs: u64,
+}
+
+/// Queue size settings.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Queues {
+    /// Capacity of the outbound queue; unbounded if unset.
+    pub outbound: Option<usize>,
+    /// Most outbound stanzas written per flush; the server default if unset.
+    pub outbound_batch: Option<usize>,
+}
+
+fn default_host() -> String {
+    "127.0.0.1".to_owned()
+}
+
+fn default_port() -> u16 {
+    5347
+}
+
+fn default_connect_timeout() -> u64 {
+    10
+}
+
+impl Default for Reconnect {
+    fn default() -> Self {
+        let policy = ReconnectPolicy::default();
+        Reconnect {
+            enabled: true,
+            max_attempts: policy.max_attempts,
+            initial_delay_ms: policy.initial_delay.as_millis() as u64,
+            max_delay_ms: policy.max_delay.as_millis() as u64,
+        }
+    }
+}
+
+impl From<&Reconnect> for ReconnectPolicy {
+    fn from(reconnect: &Reconnect) -> Self {
+        ReconnectPolicy {
+            max_attempts: reconnect.max_attempts,
+            initial_delay: Duration::from_millis(reconnect.initial_delay_ms),
+            max_delay: Duration::from_millis(reconnect.max_delay_ms),
+        }
+    }
+}
+
+impl Default for Keepalive {
+    fn default() -> Self {
+        let timeouts = Timeouts::default();
+        Keepalive {
+            read_timeout_secs: timeouts.read_timeout.as_secs(),
+            response_timeout_secs: timeouts.response_timeout.as_secs(),
+        }
+    }
+}
+
+impl From<&Keepalive> for Timeouts {
+    fn from(keepalive: &Keepalive) -> Self {
+        Timeouts {
+            read_timeout: Duration::from_secs(keepalive.read_timeout_secs),
+            response_timeout: Duration::from_secs(keepalive.response_timeout_secs),
+        }
+    }
+}
+
+impl Config {
+    /// A configuration for the component `jid`, with every other setting
+    /// left at its default.
+    pub fn new(jid: impl Into<String>) -> Config {
+        Config {
+            jid: jid.into(),
+            secret: None,
+            secret_file: None,
+            host: default_host(),
+            port: default_port(),
+            srv: false,
+            fallback_hosts: Vec::new(),
+            connect_timeout_secs: default_connect_timeout(),
+            reconnect: Reconnect::default(),
+            keepalive: Keepalive::default(),
+            queues: Queues::default(),
+        }
+    }
+
+    /// Parse a configuration from TOML.
+    pub fn from_toml(toml: &str) -> Result<Config, Error> {
+        toml::from_str(toml).map_err(Error::new)
+    }
+
+    /// Read and parse a TOML configuration file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Config, Error> {
+        let toml = std::fs::read_to_string(path).map_err(Error::new)?;
+        Config::from_toml(&toml)
+    }
+
+    /// Build a configuration from the environment alone.
+    ///
+    /// `WAX_JID` is required; see [`with_env`](Config::with_env) for the
+    /// other variables.
+    pub fn from_env() -> Result<Config, Error> {
+        Config::from_vars(env_var)
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Config, Error> {
+        let jid = var("WAX_JID").ok_or_else(|| Error::new("WAX_JID is not set"))?;
+        Config::new(jid).try_with_vars(var)
+    }
+
+    /// Override settings with `WAX_*` environment variables.
+    ///
+    /// Recognized variables are `WAX_JID`, `WAX_SECRET`, `WAX_SECRET_FILE`,
+    /// `WAX_HOST`, `WAX_PORT` and `WAX_OUTBOUND_QUEUE`. Variables that fail to
+    /// parse are ignored with a warning; use
+    /// [`try_with_env`](Config::try_with_env) to fail instead.
+    pub fn with_env(self) -> Config {
+        let fallback = self.clone();
+        self.try_with_env().unwrap_or_else(|err| {
+            tracing::warn!("ignoring environment overrides: {}", err);
+            fallback
+        })
+    }
+
+    /// Like [`with_env`](Config::with_env), but fails on unparseable variables.
+    pub fn try_with_env(self) -> Result<Config, Error> {
+        self.try_with_vars(env_var)
+    }
+
+    /// Override settings with the `WAX_*` variables `var` looks up.
+    fn try_with_vars(mut self, var: impl Fn(&str) -> Option<String>) -> Result<Config, Error> {
+        if let Some(jid) = var("WAX_JID") {
+            self.jid = jid;
+        }
+        if let Some(secret) = var("WAX_SECRET") {
+            self.secret = Some(secret);
+        }
+        if let Some(path) = var("WAX_SECRET_FILE") {
+            self.secret_file = Some(path.into());
+        }
+        if let Some(host) = var("WAX_HOST") {
+            self.host = host;
+        }
+        if let Some(port) = var("WAX_PORT") {
+            self.port = port
+                .parse()
+                .map_err(|_| Error::new(format!("invalid WAX_PORT: {:?}", port)))?;
+        }
+        if let Some(size) = var("WAX_OUTBOUND_QUEUE") {
+            self.queues.outbound = Some(
+                size.parse()
+                    .map_err(|_| Error::new(format!("invalid WAX_OUTBOUND_QUEUE: {:?}", size)))?,
+            );
+        }
+        Ok(self)
+    }
+
+    /// Resolve the shared secret, reading `secret_file` if needed.
+    ///
+    /// Trailing whitespace is trimmed from secret files.
+    pub fn secret(&self) -> Result<String, Error> {
+        match (&self.secret, &self.secret_file) {
+            (Some(secret), _) => Ok(secret.clone()),
+            (None, Some(path)) => std::fs::read_to_string(path)
+                .map(|secret| secret.trim_end().to_owned())
+                .map_err(Error::new),
+            (None, None) => Err(Error::new("no secret or secret_file configured")),
+        }
+    }
+
+    /// The [`Connector`] described by this configuration.
+    ///
+    /// Fails if the secret can't be resolved, if a fallback host isn't a
+    /// valid `host:port` pair, or if `srv` is set without the `dns` feature.
+    pub fn connector(&self) -> Result<Connector, Error> {
+        let mut connector = Connector::new(self.jid.clone(), self.secret()?)
+            .attempt_timeout(Duration::from_secs(self.connect_timeout_secs))
+            .timeouts(Timeouts::from(&self.keepalive));
+
+        if self.srv {
+            #[cfg(feature = "dns")]
+            {
+                connector = connector.srv(self.host.clone(), self.port);
+            }
+            #[cfg(not(feature = "dns"))]
+            return Err(Error::new("srv = true requires the `dns` feature"));
+        } else {
+            connector = connector.host(self.host.clone(), self.port);
+        }
+
+        for fallback in &self.fallback_hosts {
+            let (host, port) = fallback
+                .rsplit_once(':')
+                .and_then(|(host, port)| Some((host, port.parse().ok()?)))
+                .ok_or_else(|| Error::new(format!("invalid fallback host: {:?}", fallback)))?;
+            connector = connector.host(host, port);
+        }
+        Ok(connector)
+    }
+
+    /// Connect to the XMPP server and build a [`Server`] for `filter`.
+    ///
+    /// With reconnection enabled, the initial connection is retried
+    /// according to the reconnect settings, which also govern reconnection
+    /// once the server runs. Otherwise it is tried once.
+    pub async fn build_server<F>(
+        &self,
+        filter: F,
+    ) -> Result<Server<F, server::run::Standard>, Error>
+    where
+        F: Filter + Clone + Send + Sync + 'static,
+        F::Extract: Reply,
+        F::Error: IsReject,
+    {
+        let connector = self.connector()?;
+        let connect = move || {
+            let connector = connector.clone();
+            async move { connector.connect().await }
+        };
+
+        let mut server = if self.reconnect.enabled {
+            let reconnect = server::reconnect(ReconnectPolicy::from(&self.reconnect), connect);
+            let component = reconnect.connect().await.map_err(Error::new)?;
+            component.serve(filter).with_reconnect(reconnect)
+        } else {
+            connect().await.map_err(Error::new)?.serve(filter)
+        };
+        if let Some(capacity) = self.queues.outbound {
+            server = server.outbound_capacity(capacity);
+        }
+        if let Some(max) = self.queues.outbound_batch {
+            server = server.outbound_batch(max);
+        }
+        Ok(server)
+    }
+}
+
+fn env_var(name: &str) -> Option<String> {
+    env::var(name).ok()
+}
+
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never print the secret itself.
+        f.debug_struct("Config")
+            .field("jid", &self.jid)
+            .field("secret", &self.secret.as_ref().map(|_| "<redacted>"))
+            .field("secret_file", &self.secret_file)
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("srv", &self.srv)
+            .field("fallback_hosts", &self.fallback_hosts)
+            .field("connect_timeout_secs", &self.connect_timeout_secs)
+            .field("reconnect", &self.reconnect)
+            .field("keepalive", &self.keepalive)
+            .field("queues", &self.queues)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn minimal_toml() {
+        let config = Config::from_toml(r#"jid = "sms.example.org""#).unwrap();
+        assert_eq!(config.host, "127.0.0.1");
+        assert_eq!(config.port, 5347);
+        assert!(config.reconnect.enabled);
+        assert!(config.secret().is_err());
+    }
+
+    #[test]
+    fn defaults_match_toml() {
+        let config = Config::new("sms.example.org");
+        let parsed = Config::from_toml(r#"jid = "sms.example.org""#).unwrap();
+        assert_eq!(format!("{:?}", config), format!("{:?}", parsed));
+    }
+
+    #[test]
+    fn jid_from_env_is_taken_verbatim() {
+        // Characters that Debug and TOML escape differently.
+        let jid = "sms\u{e9}\"\u{7f}.example.org";
+        let config = Config::from_vars(|name| (name == "WAX_JID").then(|| jid.to_owned()));
+        assert_eq!(config.unwrap().jid, jid);
+    }
+
+    #[test]
+    fn env_overrides_fail_to_parse() {
+        let vars = |name: &str| match name {
+            "WAX_HOST" => Some("xmpp.internal".to_owned()),
+            "WAX_PORT" => Some("component".to_owned()),
+            _ => None,
+        };
+        let config = Config::new("sms.example.org");
+        assert!(config.clone().try_with_vars(vars).is_err());
+        assert_eq!(
+            config
+                .try_with_vars(|name| vars(name).filter(|_| name != "WAX_PORT"))
+                .unwrap()
+                .host,
+            "xmpp.internal"
+        );
+    }
+
+    #[test]
+    fn debug_redacts_secret() {
+        let config = Config::from_toml(
+            r#"
+            jid = "sms.example.org"
+            secret = "hunter2"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.secret().unwrap(), "hunter2");
+        assert!(!format!("{:?}", config).contains("hunter2"));
+    }
+}
@@ -0,0 +1,496 @@
+//! Component connection targets.
+//!
+//! A [`Connector`] knows where a component's XMPP server lives and how to
+//! authenticate to it. It tries an ordered list of [`Target`]s, each with a
+//! per-attempt timeout, so a component survives the failover of its server
+//! without manual intervention.
+//!
+//! Combined with `Server::reconnect`, the whole list is retried with backoff
+//! whenever the connection is lost:
+//!
+//! ```ignore
+//! use wax::connect::Connector;
+//! use wax::{ReconnectPolicy, ServeComponent};
+//!
+//! let connector = Connector::new("sms.example.org", secret)
+//!     .host("xmpp1.internal", 5347)
+//!     .host("xmpp2.internal", 5347);
+//!
+//! connector
+//!     .connect()
+//!     .await?
+//!     .serve(routes)
+//!     .reconnect(ReconnectPolicy::default(), move || {
+//!         let connector = connector.clone();
+//!         async move { connector.connect().await }
+//!     })
+//!     .run()
+//!     .await?;
+//! ```
+//!
+//! With the `tls` feature, [`Connector::connect_tls`] connects over direct
+//! TLS instead, for component links crossing untrusted networks. XEP-0114
+//! has no StartTLS, so the router must accept TLS on the component port:
+//!
+//! ```ignore
+//! use wax::connect::{Connector, TlsConfig};
+//!
+//! let tls = TlsConfig::new()
+//!     .ca_file("/etc/wax/router-ca.pem")?
+//!     .identity_files("/etc/wax/sms.pem", "/etc/wax/sms.key")?;
+//!
+//! let component = Connector::new("sms.example.org", secret)
+//!     .host("xmpp.internal", 5348)
+//!     .connect_tls(&tls)
+//!     .await?;
+//! ```
+
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::time::Duration;
+
+use tokio_xmpp::connect::{DnsConfig, ServerConnector, TcpServerConnector};
+use tokio_xmpp::xmlstream::Timeouts;
+use tokio_xmpp::Component;
+
+#[cfg(feature = "tls")]
+pub use self::tls::{TlsConfig, TlsServerConnector};
+
+/// The SRV service conventionally used for component connections.
+#[cfg(feature = "dns")]
+pub const DEFAULT_SRV_SERVICE: &str = "_xmpp-component._tcp";
+
+/// A place to connect to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Target {
+    /// A fixed host and port.
+    Host {
+        /// The host name or address.
+        host: String,
+        /// The port.
+        port: u16,
+    },
+    /// Hosts found by resolving an SRV record, tried in priority order.
+    ///
+    /// Available with the `dns` feature.
+    #[cfg(feature = "dns")]
+    Srv {
+        /// The domain to resolve.
+        domain: String,
+        /// The SRV service, e.g. `_xmpp-component._tcp`.
+        service: String,
+        /// The port used on `domain` if no SRV record exists.
+        fallback_port: u16,
+    },
+}
+
+impl Target {
+    fn dns_config(&self) -> DnsConfig {
+        match self {
+            Target::Host { host, port } => DnsConfig::no_srv(host, *port),
+            #[cfg(feature = "dns")]
+            Target::Srv {
+                domain,
+                service,
+                fallback_port,
+            } => DnsConfig::srv(domain, service, *fallback_port),
+        }
+    }
+
+    /// The name the certificate of the server must be valid for.
+    #[cfg(feature = "tls")]
+    fn server_name(&self) -> &str {
+        match self {
+            Target::Host { host, .. } => host,
+            #[cfg(feature = "dns")]
+            Target::Srv { domain, .. } => domain,
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Host { host, port } => write!(f, "{}:{}", host, port),
+            #[cfg(feature = "dns")]
+            Target::Srv {
+                domain, service, ..
+            } => write!(f, "{}.{}", service, domain),
+        }
+    }
+}
+
+/// Connects a component to the first reachable of several targets.
+#[derive(Clone)]
+pub struct Connector {
+    jid: String,
+    secret: String,
+    targets: Vec<Target>,
+    attempt_timeout: Duration,
+    timeouts: Timeouts,
+}
+
+impl Connector {
+    /// Create a connector for the component `jid`, authenticating with
+    /// `secret`.
+    ///
+    /// Without any target added, it connects to `127.0.0.1:5347`. Each
+    /// attempt times out after 10 seconds.
+    pub fn new(jid: impl Into<String>, secret: impl Into<String>) -> Self {
+        Connector {
+            jid: jid.into(),
+            secret: secret.into(),
+            targets: Vec::new(),
+            attempt_timeout: Duration::from_secs(10),
+            timeouts: Timeouts::default(),
+        }
+    }
+
+    /// Add a target, tried after those already added.
+    pub fn target(mut self, target: Target) -> Self {
+        self.targets.push(target);
+        self
+    }
+
+    /// Add a fixed host and port, tried after the targets already added.
+    pub fn host(self, host: impl Into<String>, port: u16) -> Self {
+        self.target(Target::Host {
+            host: host.into(),
+            port,
+        })
+    }
+
+    /// Add the hosts of `domain`'s `_xmpp-component._tcp` SRV record,
+    /// tried after the targets already added. If there is no such record,
+    /// `domain` itself is tried on `fallback_port`.
+    ///
+    /// Available with the `dns` feature.
+    #[cfg(feature = "dns")]
+    pub fn srv(self, domain: impl Into<String>, fallback_port: u16) -> Self {
+        self.target(Target::Srv {
+            domain: domain.into(),
+            service: DEFAULT_SRV_SERVICE.to_owned(),
+            fallback_port,
+        })
+    }
+
+    /// Set how long each attempt may take before the next target is tried.
+    pub fn attempt_timeout(mut self, timeout: Duration) -> Self {
+        self.attempt_timeout = timeout;
+        self
+    }
+
+    /// Set the stream timeouts of established connections.
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// The targets, in the order they are tried.
+    pub fn targets(&self) -> &[Target] {
+        &self.targets
+    }
+
+    /// Try each target in turn, returning the first established connection,
+    /// or the error of the last attempt if none succeeds.
+    pub async fn connect(&self) -> Result<Component<TcpServerConnector>, tokio_xmpp::Error> {
+        self.connect_with(|target| {
+            Component::new_plaintext(&self.jid, &self.secret, target.dns_config(), self.timeouts)
+        })
+        .await
+    }
+
+    /// Like [`Connector::connect`], over direct TLS set up by `tls`.
+    ///
+    /// Each target's certificate must be valid for its host, or for the
+    /// domain of an SRV target, unless [`TlsConfig::server_name`] says
+    /// otherwise.
+    ///
+    /// Available with the `tls` feature.
+    #[cfg(feature = "tls")]
+    pub async fn connect_tls(
+        &self,
+        tls: &TlsConfig,
+    ) -> Result<Component<TlsServerConnector>, tokio_xmpp::Error> {
+        let config = tls.client_config()?;
+        self.connect_with(|target| {
+            let connector = TlsServerConnector::new(target, tls, config.clone());
+            async move {
+                Component::new_with_connector(&self.jid, &self.secret, connector?, self.timeouts)
+                    .await
+            }
+        })
+        .await
+    }
+
+    async fn connect_with<C, Fut>(
+        &self,
+        attempt: impl Fn(&Target) -> Fut,
+    ) -> Result<Component<C>, tokio_xmpp::Error>
+    where
+        C: ServerConnector,
+        Fut: Future<Output = Result<Component<C>, tokio_xmpp::Error>>,
+    {
+        let default = [Target::Host {
+            host: "127.0.0.1".to_owned(),
+            port: 5347,
+        }];
+        let targets = if self.targets.is_empty() {
+            &default[..]
+        } else {
+            &self.targets[..]
+        };
+
+        let mut last_err = None;
+        for target in targets {
+            match tokio::time::timeout(self.attempt_timeout, attempt(target)).await {
+                Ok(Ok(component)) => {
+                    tracing::info!("connected to {}", target);
+                    return Ok(component);
+                }
+                Ok(Err(err)) => {
+                    tracing::warn!("failed to connect to {}: {}", target, err);
+                    last_err = Some(err);
+                }
+                Err(_) => {
+                    tracing::warn!("timed out connecting to {}", target);
+                    last_err = Some(io::Error::from(io::ErrorKind::TimedOut).into());
+                }
+            }
+        }
+        Err(last_err.expect("at least one target is always tried"))
+    }
+}
+
+impl fmt::Debug for Connector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Connector")
+            .field("jid", &self.jid)
+            .field("targets", &self.targets)
+            .field("attempt_timeout", &self.attempt_timeout)
+            .finish()
+    }
+}
+
+#[cfg(feature = "tls")]
+mod tls {
+    use std::borrow::Cow;
+    use std::fmt;
+    use std::fs::File;
+    use std::io::{self, BufRead, BufReader};
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use sasl::common::ChannelBinding;
+    use tokio::io::BufStream;
+    use tokio::net::TcpStream;
+    use tokio_rustls::client::TlsStream;
+    use tokio_rustls::rustls::crypto::ring;
+    use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+    use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+    use tokio_rustls::TlsConnector;
+    use tokio_xmpp::connect::{DnsConfig, ServerConnector};
+    use tokio_xmpp::xmlstream::{initiate_stream, PendingFeaturesRecv, StreamHeader, Timeouts};
+    use xmpp_parsers::jid::Jid;
+
+    use super::Target;
+
+    /// How to secure component connections with TLS.
+    ///
+    /// Available with the `tls` feature.
+    #[derive(Clone)]
+    pub struct TlsConfig {
+        roots: RootCertStore,
+        identity: Option<(Vec<CertificateDer<'static>>, Arc<PrivateKeyDer<'static>>)>,
+        server_name: Option<String>,
+    }
+
+    impl TlsConfig {
+        /// Trust the certificate authorities of the web PKI, and present no
+        /// client certificate.
+        pub fn new() -> Self {
+            let mut roots = RootCertStore::empty();
+            roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+            TlsConfig {
+                roots,
+                identity: None,
+                server_name: None,
+            }
+        }
+
+        /// Also trust the certificate authorities in the PEM file at `path`,
+        /// e.g. the private CA of the router.
+        pub fn ca_file(self, path: impl AsRef<Path>) -> io::Result<Self> {
+            self.ca_pem(&mut BufReader::new(File::open(path)?))
+        }
+
+        /// Also trust the certificate authorities in `pem`.
+        pub fn ca_pem(mut self, mut pem: impl BufRead) -> io::Result<Self> {
+            for cert in rustls_pemfile::certs(&mut pem) {
+                self.roots.add(cert?).map_err(invalid)?;
+            }
+            Ok(self)
+        }
+
+        /// Present the certificate chain in the PEM file at `cert`, with the
+        /// private key in the PEM file at `key`, to routers that
+        /// authenticate components by certificate.
+        pub fn identity_files(
+            self,
+            cert: impl AsRef<Path>,
+            key: impl AsRef<Path>,
+        ) -> io::Result<Self> {
+            self.identity_pem(
+                &mut BufReader::new(File::open(cert)?),
+                &mut BufReader::new(File::open(key)?),
+            )
+        }
+
+        /// Present the certificate chain in `cert`, with the private key in
+        /// `key`, both PEM encoded.
+        pub fn identity_pem(
+            mut self,
+            mut cert: impl BufRead,
+            mut key: impl BufRead,
+        ) -> io::Result<Self> {
+            let chain = rustls_pemfile::certs(&mut cert).collect::<io::Result<Vec<_>>>()?;
+            if chain.is_empty() {
+                return Err(invalid("no certificate found"));
+            }
+            let key = rustls_pemfile::private_key(&mut key)?
+                .ok_or_else(|| invalid("no private key found"))?;
+            self.identity = Some((chain, Arc::new(key)));
+            Ok(self)
+        }
+
+        /// Check certificates against `name`, instead of the host of each
+        /// target.
+        pub fn server_name(mut self, name: impl Into<String>) -> Self {
+            self.server_name = Some(name.into());
+            self
+        }
+
+        pub(super) fn client_config(&self) -> io::Result<Arc<ClientConfig>> {
+            let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .map_err(invalid)?
+                .with_root_certificates(self.roots.clone());
+            let config = match &self.identity {
+                Some((chain, key)) => builder
+                    .with_client_auth_cert(chain.clone(), key.clone_key())
+                    .map_err(invalid)?,
+                None => builder.with_no_client_auth(),
+            };
+            Ok(Arc::new(config))
+        }
+    }
+
+    impl Default for TlsConfig {
+        fn default() -> Self {
+            TlsConfig::new()
+        }
+    }
+
+    impl fmt::Debug for TlsConfig {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("TlsConfig")
+                .field("roots", &self.roots.len())
+                .field("identity", &self.identity.is_some())
+                .field("server_name", &self.server_name)
+                .finish()
+        }
+    }
+
+    /// Connects to one target over direct TLS.
+    ///
+    /// Made by [`Connector::connect_tls`](super::Connector::connect_tls).
+    #[derive(Clone)]
+    pub struct TlsServerConnector {
+        dns: DnsConfig,
+        server_name: ServerName<'static>,
+        config: Arc<ClientConfig>,
+    }
+
+    impl TlsServerConnector {
+        pub(super) fn new(
+            target: &Target,
+            tls: &TlsConfig,
+            config: Arc<ClientConfig>,
+        ) -> io::Result<Self> {
+            let name = tls
+                .server_name
+                .clone()
+                .unwrap_or_else(|| target.server_name().to_owned());
+            Ok(TlsServerConnector {
+                dns: target.dns_config(),
+                server_name: ServerName::try_from(name).map_err(invalid)?,
+                config,
+            })
+        }
+    }
+
+    impl fmt::Debug for TlsServerConnector {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("TlsServerConnector")
+                .field("dns", &self.dns)
+                .field("server_name", &self.server_name)
+                .finish()
+        }
+    }
+
+    impl ServerConnector for TlsServerConnector {
+        type Stream = BufStream<TlsStream<TcpStream>>;
+
+        async fn connect(
+            &self,
+            jid: &Jid,
+            ns: &'static str,
+            timeouts: Timeouts,
+        ) -> Result<(PendingFeaturesRecv<Self::Stream>, ChannelBinding), tokio_xmpp::Error>
+        {
+            let tcp = self.dns.resolve().await?;
+            let tls = TlsConnector::from(self.config.clone())
+                .connect(self.server_name.clone(), tcp)
+                .await?;
+            let header = StreamHeader {
+                to: Some(Cow::Borrowed(jid.domain().as_str())),
+                from: None,
+                id: None,
+            };
+            let stream = initiate_stream(BufStream::new(tls), ns, header, timeouts).await?;
+            Ok((stream, ChannelBinding::None))
+        }
+    }
+
+    fn invalid(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        // The certificate and key of the TLS example.
+        const CERT: &str = include_str!("../examples/tls/cert.pem");
+        const KEY: &str = include_str!("../examples/tls/key.rsa");
+
+        #[test]
+        fn loads_pem_identities_and_authorities() {
+            let tls = TlsConfig::new()
+                .ca_pem(CERT.as_bytes())
+                .unwrap()
+                .identity_pem(CERT.as_bytes(), KEY.as_bytes())
+                .unwrap();
+            assert!(tls.client_config().is_ok());
+        }
+
+        #[test]
+        fn rejects_missing_keys() {
+            let err = TlsConfig::new()
+                .identity_pem(CERT.as_bytes(), &b""[..])
+                .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
@@ -5,9 +5,12 @@
 //! requests and deliver responses via oneshot channels.
 
 use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::Arc;
 
 use dashmap::DashMap;
 use scoped_tls::scoped_thread_local;
+use smol_str::SmolStr;
 use tokio::sync::{mpsc, oneshot};
 use tokio_xmpp::Stanza;
 
@@ -19,6 +22,7 @@ pub(crate) mod stanza_id {
     use std::borrow::Borrow;
     use std::hash::{Hash, Hasher};
 
+    use smol_str::SmolStr;
     use xmpp_parsers::iq::Iq;
 
     /// Private token that prevents external construction of `StanzaId`.
@@ -37,8 +41,14 @@ pub(crate) mod stanza_id {
             self.0.as_ref()
         }
 
-        pub fn to_owned(&self) -> StanzaId<String> {
-            StanzaId(self.0.as_ref().to_owned(), Seal)
+        /// An owned copy of this ID, suitable as a [`PendingTable`] key.
+        ///
+        /// IDs of up to 23 bytes, which covers generated stanza IDs, are
+        /// stored inline without allocating.
+        ///
+        /// [`PendingTable`]: super::PendingTable
+        pub fn to_owned(&self) -> StanzaId<SmolStr> {
+            StanzaId(SmolStr::new(self.0.as_ref()), Seal)
         }
     }
 
@@ -56,7 +66,7 @@ pub(crate) mod stanza_id {
         }
     }
 
-    impl Borrow<str> for StanzaId<String> {
+    impl Borrow<str> for StanzaId<SmolStr> {
         fn borrow(&self) -> &str {
             self.as_str()
         }
@@ -90,18 +100,92 @@ pub(crate) mod stanza_id {
     }
 }
 
+/// Create the server's outbound channel, bounded to `capacity` if given.
+pub(crate) fn outbound_channel(capacity: Option<usize>) -> (Outbound, OutboundReceiver) {
+    match capacity {
+        Some(capacity) => {
+            let (tx, rx) = mpsc::channel(capacity);
+            (Outbound::Bounded(tx), OutboundReceiver::Bounded(rx))
+        }
+        None => {
+            let (tx, rx) = mpsc::unbounded_channel();
+            (Outbound::Unbounded(tx), OutboundReceiver::Unbounded(rx))
+        }
+    }
+}
+
+/// The sending half of the server's outbound channel.
+#[derive(Clone, Debug)]
+pub(crate) enum Outbound {
+    Unbounded(mpsc::UnboundedSender<Stanza>),
+    Bounded(mpsc::Sender<Stanza>),
+    /// The channel of a server run alongside others, which also reaches
+    /// theirs, keyed by the domain of their JID.
+    Routed {
+        own: Box<Outbound>,
+        routes: Arc<HashMap<String, Outbound>>,
+    },
+}
+
+impl Outbound {
+    /// Queue a stanza, failing if the channel is closed or full.
+    pub(crate) fn send(&self, stanza: Stanza) -> Result<(), mpsc::error::SendError<Stanza>> {
+        match self {
+            Outbound::Unbounded(tx) => tx.send(stanza),
+            Outbound::Bounded(tx) => tx.try_send(stanza).map_err(|err| match err {
+                mpsc::error::TrySendError::Full(stanza) => {
+                    tracing::warn!("outbound queue full, dropping stanza");
+                    mpsc::error::SendError(stanza)
+                }
+                mpsc::error::TrySendError::Closed(stanza) => mpsc::error::SendError(stanza),
+            }),
+            // Sent by whichever server the stanza is from.
+            Outbound::Routed { own, routes } => crate::filters::stanza::from_of(&stanza)
+                .and_then(|from| routes.get(from.domain().as_str()))
+                .unwrap_or(&**own)
+                .send(stanza),
+        }
+    }
+}
+
+/// The receiving half of the server's outbound channel.
+#[derive(Debug)]
+pub(crate) enum OutboundReceiver {
+    Unbounded(mpsc::UnboundedReceiver<Stanza>),
+    Bounded(mpsc::Receiver<Stanza>),
+}
+
+impl OutboundReceiver {
+    pub(crate) async fn recv(&mut self) -> Option<Stanza> {
+        match self {
+            OutboundReceiver::Unbounded(rx) => rx.recv().await,
+            OutboundReceiver::Bounded(rx) => rx.recv().await,
+        }
+    }
+
+    /// Take a stanza that is already queued, without waiting.
+    pub(crate) fn try_recv(&mut self) -> Option<Stanza> {
+        match self {
+            OutboundReceiver::Unbounded(rx) => rx.try_recv().ok(),
+            OutboundReceiver::Bounded(rx) => rx.try_recv().ok(),
+        }
+    }
+}
+
 /// The pending table maps stanza IDs to oneshot senders for response delivery.
-pub type PendingTable = DashMap<StanzaId<String>, oneshot::Sender<Stanza>>;
+///
+/// Keys borrow as `str`, so lookups never allocate.
+pub type PendingTable = DashMap<StanzaId<SmolStr>, oneshot::Sender<Stanza>>;
 
 /// Context for correlating outbound stanzas with their responses.
 pub struct CorrelationContext {
     pending: PendingTable,
-    outbound_tx: mpsc::UnboundedSender<Stanza>,
+    outbound_tx: Outbound,
 }
 
 impl CorrelationContext {
     /// Create a new correlation context with the given outbound channel.
-    pub fn new(outbound_tx: mpsc::UnboundedSender<Stanza>) -> Self {
+    pub(crate) fn new(outbound_tx: Outbound) -> Self {
         Self {
             pending: DashMap::new(),
             outbound_tx,
@@ -109,12 +193,19 @@ impl CorrelationContext {
     }
 
     /// Register a pending request and return a receiver for the response.
-    pub fn register(&mut self, id: StanzaId<String>) -> oneshot::Receiver<Stanza> {
+    pub fn register(&mut self, id: StanzaId<SmolStr>) -> oneshot::Receiver<Stanza> {
         let (tx, rx) = oneshot::channel();
         self.pending.insert(id, tx);
         rx
     }
 
+    /// Register a pending request for the ID of `stanza`, if it has one.
+    pub fn register_stanza(&mut self, stanza: &Stanza) -> Option<oneshot::Receiver<Stanza>> {
+        stanza
+            .get_stanza_id()
+            .map(|id| self.register(id.to_owned()))
+    }
+
     /// Remove a pending entry and return the sender.
     pub fn take_pending(&mut self, id: &str) -> Option<oneshot::Sender<Stanza>> {
         self.pending.remove(id).map(|(_, tx)| tx)
@@ -126,6 +217,7 @@ impl CorrelationContext {
             .and_then(|id| self.pending.remove(id.as_str()))
             .map(|(_, tx)| tx)
     }
+
     /// Send a stanza to the outbound channel.
     pub fn send(&self, stanza: Stanza) -> Result<(), mpsc::error::SendError<Stanza>> {
         self.outbound_tx.send(stanza)
@@ -147,3 +239,30 @@ where
 {
     CORRELATION_CTX.with(|ctx| func(&mut ctx.borrow_mut()))
 }
+
+/// A fresh identifier, unique within the process and unlikely to repeat
+/// across restarts, for stanza ids, sessions and the like.
+pub(crate) fn unique_id() -> String {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default();
+    format!("{:x}-{:x}", nanos, COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+/// The outbound channel of the server handling the current stanza.
+///
+/// Only available while a filter is being built for a stanza the server
+/// received, so handlers that send stanzas of their own must grab it
+/// synchronously. `None` elsewhere, e.g. under `wax::test`.
+pub(crate) fn outbound() -> Option<Outbound> {
+    if CORRELATION_CTX.is_set() {
+        Some(CORRELATION_CTX.with(|ctx| ctx.borrow().outbound_tx.clone()))
+    } else {
+        None
+    }
+}
@@ -1,7 +1,16 @@
+//! Errors, and error stanza filters.
+//!
+//! - `wax::error::param()` - Extract the [`Bounce`] of an error stanza
+//!
+//! Running a server fails with an [`Error`], whose [`Kind`] tells what went
+//! wrong.
+
 use std::convert::Infallible;
 use std::error::Error as StdError;
 use std::fmt;
 
+pub use crate::filters::error::{param, Bounce};
+
 type BoxError = Box<dyn std::error::Error + Send + Sync>;
 
 /// Errors that can happen inside wax.
@@ -13,6 +22,77 @@ impl Error {
     pub(crate) fn new<E: Into<BoxError>>(err: E) -> Error {
         Error { inner: err.into() }
     }
+
+    fn with_kind(kind: Kind, source: Option<BoxError>) -> Error {
+        Error::new(Failure { kind, source })
+    }
+
+    pub(crate) fn handshake<E: Into<BoxError>>(err: E) -> Error {
+        Error::with_kind(Kind::Handshake, Some(err.into()))
+    }
+
+    pub(crate) fn stream_closed() -> Error {
+        Error::with_kind(Kind::StreamClosed, None)
+    }
+
+    pub(crate) fn send<E: Into<BoxError>>(err: E) -> Error {
+        Error::with_kind(Kind::Send, Some(err.into()))
+    }
+
+    /// What went wrong.
+    pub fn kind(&self) -> Kind {
+        self.inner
+            .downcast_ref::<Failure>()
+            .map_or(Kind::Other, |failure| failure.kind)
+    }
+}
+
+/// The kinds of [`Error`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Kind {
+    /// Connecting or authenticating to the XMPP server failed, e.g. when
+    /// reconnecting.
+    Handshake,
+    /// The XMPP stream closed, and the server was not set to reconnect.
+    StreamClosed,
+    /// Writing a stanza to the XMPP stream failed.
+    Send,
+    /// Any other error.
+    Other,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Kind::Handshake => "failed to connect to the XMPP server",
+            Kind::StreamClosed => "the XMPP stream closed unexpectedly",
+            Kind::Send => "failed to send a stanza",
+            Kind::Other => "error",
+        })
+    }
+}
+
+// The error of a known kind, kept inside the box so `Error` stays small.
+#[derive(Debug)]
+struct Failure {
+    kind: Kind,
+    source: Option<BoxError>,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.source {
+            Some(ref source) => write!(f, "{}: {}", self.kind, source),
+            None => fmt::Display::fmt(&self.kind, f),
+        }
+    }
+}
+
+impl StdError for Failure {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.source.as_deref().map(|source| source as _)
+    }
 }
 
 impl fmt::Debug for Error {
@@ -30,7 +110,10 @@ impl fmt::Display for Error {
 
 impl StdError for Error {
     fn source(&self) -> Option<&(dyn StdError + 'static)> {
-        Some(self.inner.as_ref())
+        match self.inner.downcast_ref::<Failure>() {
+            Some(failure) => failure.source(),
+            None => Some(self.inner.as_ref()),
+        }
     }
 }
 
@@ -54,6 +137,16 @@ fn error_source() {
     assert!(e.source().unwrap().is::<std::fmt::Error>());
 }
 
+#[test]
+fn error_kind() {
+    assert_eq!(Error::new(std::fmt::Error {}).kind(), Kind::Other);
+    let e = Error::send(std::fmt::Error {});
+    assert_eq!(e.kind(), Kind::Send);
+    assert!(e.source().unwrap().is::<std::fmt::Error>());
+    assert_eq!(Error::stream_closed().kind(), Kind::StreamClosed);
+    assert!(Error::stream_closed().source().is_none());
+}
+
 /// Macro to define a simple unit error type with Display and Debug implementations.
 #[macro_export]
 macro_rules! unit_error {
@@ -0,0 +1,86 @@
+//! AMQP integration.
+//!
+//! Available with the `ext-amqp` feature.
+//!
+//! - `wax::ext::amqp::publish(channel, exchange, routing_key)` - Publish the current stanza on an exchange
+//!
+//! Enable publisher confirms on the channel (`confirm_select`) to have
+//! broker-side refusals reported as delivery failures; otherwise only
+//! channel errors are.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::Filter;
+//!
+//! let conn = lapin::Connection::connect(addr, Default::default()).await?;
+//! let channel = conn.create_channel().await?;
+//!
+//! let route = wax::message::param()
+//!     .and(wax::ext::amqp::publish(channel, "xmpp", "inbound"))
+//!     .map(|_, reply| reply);
+//! ```
+
+use futures_util::future::BoxFuture;
+use lapin::options::BasicPublishOptions;
+use lapin::publisher_confirm::Confirmation;
+use lapin::BasicProperties;
+
+use super::publish::{self, Publish, Publisher};
+
+/// Create a filter publishing the current stanza on `exchange` with
+/// `routing_key`.
+///
+/// Stanzas are published as persistent messages of type `application/xml`.
+pub fn publish(
+    channel: lapin::Channel,
+    exchange: impl Into<String>,
+    routing_key: impl Into<String>,
+) -> Publish<Amqp> {
+    publish::publish(
+        Amqp {
+            channel,
+            exchange: exchange.into(),
+        },
+        routing_key,
+    )
+}
+
+/// A [`Publisher`] publishing on an AMQP exchange.
+///
+/// The topic is used as the routing key.
+#[derive(Clone, Debug)]
+pub struct Amqp {
+    channel: lapin::Channel,
+    exchange: String,
+}
+
+impl Publisher for Amqp {
+    fn publish<'a>(
+        &'a self,
+        topic: &'a str,
+        _key: Option<&'a str>,
+        payload: Vec<u8>,
+    ) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error + Send + Sync>>> {
+        Box::pin(async move {
+            let properties = BasicProperties::default()
+                .with_content_type("application/xml".into())
+                .with_delivery_mode(2);
+            let confirm = self
+                .channel
+                .basic_publish(
+                    &self.exchange,
+                    topic,
+                    BasicPublishOptions::default(),
+                    &payload,
+                    properties,
+                )
+                .await?
+                .await?;
+            match confirm {
+                Confirmation::Nack(_) => Err("message was nacked by the broker".into()),
+                Confirmation::Ack(_) | Confirmation::NotRequested => Ok(()),
+            }
+        })
+    }
+}
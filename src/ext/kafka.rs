@@ -0,0 +1,80 @@
+//! Kafka integration.
+//!
+//! Available with the `ext-kafka` feature.
+//!
+//! - `wax::ext::kafka::publish(producer, topic)` - Produce the current stanza to a topic
+//!
+//! Records are keyed by the sender's bare JID, so each sender's stanzas
+//! land on the same partition and keep their order.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use rdkafka::producer::FutureProducer;
+//! use rdkafka::ClientConfig;
+//! use wax::Filter;
+//!
+//! let producer: FutureProducer = ClientConfig::new()
+//!     .set("bootstrap.servers", "localhost:9092")
+//!     .create()?;
+//!
+//! let route = wax::message::param()
+//!     .and(wax::ext::kafka::publish(producer, "xmpp-inbound"))
+//!     .map(|_, reply| reply);
+//! ```
+
+use std::time::Duration;
+
+use futures_util::future::BoxFuture;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+
+use super::publish::{self, Publish, Publisher};
+
+/// Create a filter producing the current stanza to `topic`.
+///
+/// Each delivery waits at most 5 seconds for room in the producer queue.
+pub fn publish(producer: FutureProducer, topic: impl Into<String>) -> Publish<Kafka> {
+    publish::publish(
+        Kafka {
+            producer,
+            queue_timeout: Duration::from_secs(5),
+        },
+        topic,
+    )
+}
+
+/// A [`Publisher`] backed by a Kafka producer.
+#[derive(Clone)]
+pub struct Kafka {
+    producer: FutureProducer,
+    queue_timeout: Duration,
+}
+
+impl std::fmt::Debug for Kafka {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Kafka")
+            .field("queue_timeout", &self.queue_timeout)
+            .finish()
+    }
+}
+
+impl Publisher for Kafka {
+    fn publish<'a>(
+        &'a self,
+        topic: &'a str,
+        key: Option<&'a str>,
+        payload: Vec<u8>,
+    ) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error + Send + Sync>>> {
+        Box::pin(async move {
+            let mut record = FutureRecord::<str, [u8]>::to(topic).payload(&payload);
+            if let Some(key) = key {
+                record = record.key(key);
+            }
+            self.producer
+                .send(record, self.queue_timeout)
+                .await
+                .map(|_| ())
+                .map_err(|(err, _)| err.into())
+        })
+    }
+}
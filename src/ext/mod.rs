@@ -0,0 +1,22 @@
+//! Integrations with external services.
+//!
+//! Each integration lives behind its own feature flag, so components only
+//! pull in the client libraries they actually use.
+
+#[cfg(feature = "ext-amqp")]
+pub mod amqp;
+
+#[cfg(feature = "ext-kafka")]
+pub mod kafka;
+
+#[cfg(feature = "ext-nats")]
+pub mod nats;
+
+#[cfg(feature = "ext-publish")]
+pub mod publish;
+
+#[cfg(feature = "wax-redis")]
+pub mod redis;
+
+#[cfg(feature = "ext-sqlx")]
+pub mod sqlx;
@@ -0,0 +1,50 @@
+//! NATS integration.
+//!
+//! Available with the `ext-nats` feature.
+//!
+//! - `wax::ext::nats::publish(client, subject)` - Publish the current stanza on a subject
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::ext::publish::DeliveryPolicy;
+//! use wax::Filter;
+//!
+//! let client = async_nats::connect("nats://localhost:4222").await?;
+//!
+//! let route = wax::message::param()
+//!     .and(wax::ext::nats::publish(client, "xmpp.inbound").on_failure(DeliveryPolicy::Ignore))
+//!     .map(|_, reply| reply);
+//! ```
+
+use futures_util::future::BoxFuture;
+
+use super::publish::{self, Publish, Publisher};
+
+/// Create a filter publishing the current stanza on `subject`.
+///
+/// Stanzas are published as XML text. With the default
+/// [`DeliveryPolicy`](super::publish::DeliveryPolicy), the stanza is
+/// rejected if the message can't be handed to the server.
+pub fn publish(client: async_nats::Client, subject: impl Into<String>) -> Publish<Nats> {
+    publish::publish(Nats(client), subject)
+}
+
+/// A [`Publisher`] backed by a NATS client.
+#[derive(Clone, Debug)]
+pub struct Nats(pub async_nats::Client);
+
+impl Publisher for Nats {
+    fn publish<'a>(
+        &'a self,
+        topic: &'a str,
+        _key: Option<&'a str>,
+        payload: Vec<u8>,
+    ) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error + Send + Sync>>> {
+        Box::pin(async move {
+            self.0.publish(topic.to_owned(), payload.into()).await?;
+            self.0.flush().await?;
+            Ok(())
+        })
+    }
+}
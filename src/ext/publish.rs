@@ -0,0 +1,278 @@
+//! Publishing stanzas onto message brokers.
+//!
+//! The broker integrations ([`nats`](super::nats), [`amqp`](super::amqp) and
+//! [`kafka`](super::kafka), each behind its own feature) share the terminal
+//! filter defined here. It serializes the stanza being filtered as XML text,
+//! publishes it on a topic, and extracts no reply, like [`sink`](crate::sink).
+//!
+//! What happens when the broker refuses or cannot be reached is decided by
+//! a [`DeliveryPolicy`]. By default the stanza is rejected with a
+//! `wait`-type `service-unavailable`, so the sender knows to retry.
+
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::future::BoxFuture;
+use tokio_xmpp::Stanza;
+
+use crate::filter::{FilterBase, Internal};
+use crate::filtered_stanza;
+use crate::filters::stanza::from_of;
+use crate::reject::{self, Rejection};
+use crate::serialize;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A broker client stanzas can be published with.
+///
+/// Implemented by the client wrappers of each broker integration.
+pub trait Publisher: Send + Sync + 'static {
+    /// Publish `payload` on `topic`.
+    ///
+    /// `key` is the sender's bare JID, for brokers that partition by key.
+    fn publish<'a>(
+        &'a self,
+        topic: &'a str,
+        key: Option<&'a str>,
+        payload: Vec<u8>,
+    ) -> BoxFuture<'a, Result<(), BoxError>>;
+}
+
+/// What to do when a stanza cannot be published.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DeliveryPolicy {
+    /// Reject the stanza with a `wait`-type `service-unavailable`.
+    #[default]
+    Reject,
+    /// Log the failure and carry on as if the stanza had been published.
+    Ignore,
+    /// Retry up to `attempts` times in total, doubling `backoff` between
+    /// attempts, then reject.
+    Retry {
+        /// The total number of attempts.
+        attempts: u32,
+        /// The delay before the first retry.
+        backoff: Duration,
+    },
+}
+
+/// A terminal filter publishing the current stanza on a topic.
+///
+/// Created by the `publish` function of each broker integration.
+pub struct Publish<P> {
+    publisher: Arc<P>,
+    topic: Arc<str>,
+    policy: DeliveryPolicy,
+}
+
+pub(crate) fn publish<P: Publisher>(publisher: P, topic: impl Into<String>) -> Publish<P> {
+    Publish {
+        publisher: Arc::new(publisher),
+        topic: topic.into().into(),
+        policy: DeliveryPolicy::default(),
+    }
+}
+
+impl<P> Publish<P> {
+    /// Set what happens when publishing fails.
+    pub fn on_failure(mut self, policy: DeliveryPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+}
+
+impl<P> Clone for Publish<P> {
+    fn clone(&self) -> Self {
+        Publish {
+            publisher: self.publisher.clone(),
+            topic: self.topic.clone(),
+            policy: self.policy,
+        }
+    }
+}
+
+impl<P> fmt::Debug for Publish<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Publish")
+            .field("topic", &self.topic)
+            .field("policy", &self.policy)
+            .finish()
+    }
+}
+
+impl<P: Publisher> FilterBase for Publish<P> {
+    type Extract = (Option<Stanza>,);
+    type Error = Rejection;
+    type Future = BoxFuture<'static, Result<Self::Extract, Self::Error>>;
+
+    fn filter(&self, _: Internal) -> Self::Future {
+        let (payload, key) = filtered_stanza::with(|stanza| {
+            (
+                serialize::to_xml(stanza),
+                from_of(stanza).map(|jid| jid.to_bare().to_string()),
+            )
+        });
+        let this = self.clone();
+        Box::pin(async move {
+            let payload = payload.map_err(|err| {
+                tracing::error!("failed to serialize stanza for {}: {}", this.topic, err);
+                reject::internal_server_error()
+            })?;
+            this.send(key.as_deref(), payload.into_bytes()).await?;
+            Ok((None,))
+        })
+    }
+}
+
+impl<P: Publisher> Publish<P> {
+    async fn send(&self, key: Option<&str>, payload: Vec<u8>) -> Result<(), Rejection> {
+        let (attempts, mut backoff) = match self.policy {
+            DeliveryPolicy::Retry { attempts, backoff } => (attempts.max(1), backoff),
+            DeliveryPolicy::Reject | DeliveryPolicy::Ignore => (1, Duration::ZERO),
+        };
+
+        for attempt in 1..=attempts {
+            match self
+                .publisher
+                .publish(&self.topic, key, payload.clone())
+                .await
+            {
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    tracing::warn!(
+                        "failed to publish to {} (attempt {}/{}): {}",
+                        self.topic,
+                        attempt,
+                        attempts,
+                        err
+                    );
+                }
+            }
+            if attempt < attempts {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+        }
+
+        match self.policy {
+            DeliveryPolicy::Ignore => Ok(()),
+            DeliveryPolicy::Reject | DeliveryPolicy::Retry { .. } => {
+                Err(reject::known(BrokerUnavailable { _p: () }))
+            }
+        }
+    }
+}
+
+crate::unit_error! {
+    /// The message broker could not be reached, or refused the stanza.
+    pub BrokerUnavailable: "broker unavailable"
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Mutex;
+
+    use xmpp_parsers::message::{Lang, Message};
+
+    use super::*;
+
+    type Published = (String, Option<String>, Vec<u8>);
+
+    /// Records what it publishes, failing the first `failures` attempts.
+    #[derive(Default)]
+    struct Mock {
+        published: Mutex<Vec<Published>>,
+        attempts: AtomicU32,
+        failures: u32,
+    }
+
+    impl Publisher for Arc<Mock> {
+        fn publish<'a>(
+            &'a self,
+            topic: &'a str,
+            key: Option<&'a str>,
+            payload: Vec<u8>,
+        ) -> BoxFuture<'a, Result<(), BoxError>> {
+            Box::pin(async move {
+                if self.attempts.fetch_add(1, Ordering::SeqCst) < self.failures {
+                    return Err("broker down".into());
+                }
+                self.published.lock().unwrap().push((
+                    topic.to_owned(),
+                    key.map(str::to_owned),
+                    payload,
+                ));
+                Ok(())
+            })
+        }
+    }
+
+    fn failing(failures: u32) -> Arc<Mock> {
+        Arc::new(Mock {
+            failures,
+            ..Mock::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn publishes_the_stanza() {
+        let mock = failing(0);
+        let mut msg = Message::new(Some("sms.example.org".parse().unwrap()))
+            .with_body(Lang::default(), "hi".to_owned());
+        msg.from = Some("juliet@capulet.lit/balcony".parse().unwrap());
+        let stanza = Stanza::Message(msg);
+
+        let response = crate::service(publish(mock.clone(), "stanzas"))
+            .call_stanza(stanza.clone())
+            .await
+            .unwrap();
+
+        assert!(response.is_empty());
+        let published = mock.published.lock().unwrap();
+        let [(topic, key, payload)] = &published[..] else {
+            panic!("expected one publish, got {}", published.len());
+        };
+        assert_eq!(topic, "stanzas");
+        assert_eq!(key.as_deref(), Some("juliet@capulet.lit"));
+        let payload = std::str::from_utf8(payload).unwrap();
+        assert_eq!(payload, serialize::to_xml(&stanza).unwrap());
+    }
+
+    #[tokio::test]
+    async fn rejects_by_default() {
+        let mock = failing(1);
+        let publish = publish(mock.clone(), "stanzas");
+
+        assert!(publish.send(None, b"<message/>".to_vec()).await.is_err());
+        assert_eq!(mock.attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn ignores_failures() {
+        let mock = failing(1);
+        let publish = publish(mock.clone(), "stanzas").on_failure(DeliveryPolicy::Ignore);
+
+        assert!(publish.send(None, b"<message/>".to_vec()).await.is_ok());
+        assert!(mock.published.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn retries_up_to_the_limit() {
+        let retry = DeliveryPolicy::Retry {
+            attempts: 3,
+            backoff: Duration::ZERO,
+        };
+
+        let mock = failing(2);
+        let publish = publish(mock.clone(), "stanzas").on_failure(retry);
+        assert!(publish.send(None, b"<message/>".to_vec()).await.is_ok());
+        assert_eq!(mock.published.lock().unwrap().len(), 1);
+
+        let mock = failing(3);
+        let publish = super::publish(mock.clone(), "stanzas").on_failure(retry);
+        assert!(publish.send(None, b"<message/>".to_vec()).await.is_err());
+        assert_eq!(mock.attempts.load(Ordering::SeqCst), 3);
+    }
+}
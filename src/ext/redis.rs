@@ -0,0 +1,184 @@
+//! Redis integration.
+//!
+//! Available with the `wax-redis` feature.
+//!
+//! - `wax::ext::redis::with_redis(pool)` - Thread a connection pool into a filter chain
+//! - [`RedisKey`] / [`FindInRedis`] - Describe how a type is looked up, and look it up
+//!
+//! Errors are mapped into rejections: a pool or connection failure rejects
+//! with a `wait`-type `internal-server-error`, so the sender knows to retry,
+//! while any other Redis error rejects with a `cancel`-type one.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::ext::redis::{self, FindInRedis, RedisPool};
+//! use wax::Filter;
+//! use xmpp_parsers::jid::Jid;
+//!
+//! let route = wax::iq()
+//!     .get()
+//!     .require_from()
+//!     .and(redis::with_redis(pool))
+//!     .and_then(|from: Jid, pool: RedisPool| async move {
+//!         let mut con = redis::connection(&pool).await?;
+//!         let cred = from.find::<Credentials, _>(&mut *con).await?;
+//!         Ok::<_, wax::Rejection>(wax::sink())
+//!     });
+//! ```
+
+use std::convert::Infallible;
+
+use bb8_redis::bb8::{Pool, PooledConnection, RunError};
+use bb8_redis::RedisConnectionManager;
+use redis::aio::ConnectionLike;
+use redis::{FromRedisValue, RedisError, ToRedisArgs};
+use xmpp_parsers::jid::Jid;
+
+use crate::filter::Filter;
+use crate::reject::{self, Rejection};
+
+/// A pool of Redis connections.
+pub type RedisPool = Pool<RedisConnectionManager>;
+
+/// A connection checked out of a [`RedisPool`].
+pub type RedisConnection<'a> = PooledConnection<'a, RedisConnectionManager>;
+
+/// Extract a clone of the given pool, for use in later `and_then`s.
+pub fn with_redis(
+    pool: RedisPool,
+) -> impl Filter<Extract = (RedisPool,), Error = Infallible> + Clone {
+    crate::any().map(move || pool.clone())
+}
+
+/// Check out a connection from the pool.
+///
+/// Rejects with a `wait`-type `internal-server-error` if no connection could
+/// be established.
+pub async fn connection(pool: &RedisPool) -> Result<RedisConnection<'_>, Rejection> {
+    pool.get().await.map_err(|err| match err {
+        RunError::User(err) => rejection(err),
+        RunError::TimedOut => {
+            tracing::warn!("timed out waiting for a redis connection");
+            reject::known(RedisUnavailable { _p: () })
+        }
+    })
+}
+
+/// Convert a [`RedisError`] into a [`Rejection`].
+///
+/// Connection-level failures (refused, dropped, timed out, I/O) reject with
+/// a `wait`-type `internal-server-error`; everything else rejects with a
+/// `cancel`-type `internal-server-error`.
+pub fn rejection(err: RedisError) -> Rejection {
+    if err.is_io_error()
+        || err.is_connection_refusal()
+        || err.is_connection_dropped()
+        || err.is_timeout()
+    {
+        tracing::warn!("redis unavailable: {}", err);
+        reject::known(RedisUnavailable { _p: () })
+    } else {
+        tracing::error!("redis error: {}", err);
+        reject::internal_server_error()
+    }
+}
+
+/// A type that can be loaded from Redis given some key.
+pub trait RedisKey: FromRedisValue {
+    /// The value the Redis key is derived from.
+    type Key;
+
+    /// The command that loads `Self` for the given key.
+    fn find_cmd(key: &Self::Key) -> redis::Cmd;
+}
+
+/// Look up [`RedisKey`] types by their key.
+///
+/// Implemented for [`Jid`]; implement it for your own key types with an
+/// empty `impl`.
+pub trait FindInRedis: Sized {
+    /// Load a `T` keyed by `self`, mapping failures into rejections.
+    #[allow(async_fn_in_trait)]
+    async fn find<T, C>(&self, con: &mut C) -> Result<T, Rejection>
+    where
+        T: RedisKey<Key = Self>,
+        C: ConnectionLike,
+    {
+        T::find_cmd(self).query_async(con).await.map_err(rejection)
+    }
+}
+
+impl FindInRedis for Jid {}
+
+/// A [`RedisKey`] keyed by JID.
+pub trait ByJid: RedisKey {
+    /// The Redis key for the given JID.
+    fn by_jid(jid: &Jid) -> impl ToRedisArgs;
+}
+
+crate::unit_error! {
+    /// Redis could not be reached.
+    pub RedisUnavailable: "redis unavailable"
+}
+
+#[cfg(test)]
+mod tests {
+    use redis::{ParsingError, Value};
+    use redis_test::{MockCmd, MockRedisConnection};
+    use xmpp_parsers::stanza_error::{DefinedCondition, ErrorType};
+
+    use super::*;
+    use crate::reject::IsReject;
+
+    #[derive(Debug, PartialEq)]
+    struct Nick(String);
+
+    impl FromRedisValue for Nick {
+        fn from_redis_value(v: Value) -> Result<Self, ParsingError> {
+            String::from_redis_value(v).map(Nick)
+        }
+    }
+
+    impl RedisKey for Nick {
+        type Key = Jid;
+
+        fn find_cmd(key: &Self::Key) -> redis::Cmd {
+            redis::cmd("GET").arg(Self::by_jid(key)).take()
+        }
+    }
+
+    impl ByJid for Nick {
+        fn by_jid(jid: &Jid) -> impl ToRedisArgs {
+            format!("nick-{}", jid.to_bare())
+        }
+    }
+
+    #[tokio::test]
+    async fn find_by_jid() {
+        let mut con = MockRedisConnection::new(vec![MockCmd::new(
+            redis::cmd("GET").arg("nick-juliet@capulet.lit"),
+            Ok("Jules"),
+        )]);
+
+        let jid = Jid::new("juliet@capulet.lit/balcony").unwrap();
+        let nick = jid.find::<Nick, _>(&mut con).await.unwrap();
+        assert_eq!(nick, Nick("Jules".into()));
+    }
+
+    #[tokio::test]
+    async fn connection_failure_is_wait() {
+        let mut con = MockRedisConnection::new(vec![MockCmd::new::<_, Value>(
+            redis::cmd("GET").arg("nick-juliet@capulet.lit"),
+            Err(RedisError::from(std::io::Error::from(
+                std::io::ErrorKind::ConnectionRefused,
+            ))),
+        )]);
+
+        let jid = Jid::new("juliet@capulet.lit").unwrap();
+        let rejection = jid.find::<Nick, _>(&mut con).await.unwrap_err();
+        let err = rejection.into_stanza_error();
+        assert_eq!(err.defined_condition, DefinedCondition::InternalServerError);
+        assert_eq!(err.type_, ErrorType::Wait);
+    }
+}
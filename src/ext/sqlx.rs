@@ -0,0 +1,145 @@
+//! sqlx integration.
+//!
+//! Available with the `ext-sqlx` feature. Enable `ext-sqlx-postgres` or
+//! `ext-sqlx-sqlite` (or the matching feature on your own `sqlx` dependency)
+//! to pick a database driver.
+//!
+//! - `wax::ext::sqlx::with_pool(pool)` - Thread a connection pool into a filter chain
+//! - [`OrReject::or_reject`] - Map a query result into a [`Rejection`]
+//!
+//! Database errors map onto stanza errors as follows:
+//!
+//! | sqlx error | stanza error |
+//! |---|---|
+//! | `RowNotFound` | `item-not-found` |
+//! | unique violation | `conflict` |
+//! | foreign key violation | `item-not-found` |
+//! | not-null violation | `bad-request` |
+//! | check violation | `not-acceptable` |
+//! | pool timeout, pool closed, I/O, TLS | `internal-server-error` (`wait`) |
+//! | anything else | `internal-server-error` (`cancel`) |
+//!
+//! # Example
+//!
+//! ```ignore
+//! use sqlx::PgPool;
+//! use wax::ext::sqlx::{with_pool, OrReject};
+//! use wax::Filter;
+//! use xmpp_parsers::jid::Jid;
+//!
+//! let route = wax::iq()
+//!     .get()
+//!     .require_from()
+//!     .and(with_pool(pool))
+//!     .and_then(|from: Jid, pool: PgPool| async move {
+//!         let (nick,): (String,) = sqlx::query_as("SELECT nick FROM users WHERE jid = $1")
+//!             .bind(from.to_bare().to_string())
+//!             .fetch_one(&pool)
+//!             .await
+//!             .or_reject()?;
+//!         Ok::<_, wax::Rejection>(wax::sink())
+//!     });
+//! ```
+
+use std::convert::Infallible;
+
+use sqlx::error::ErrorKind;
+use sqlx::{Database, Pool};
+
+use crate::filter::Filter;
+use crate::reject::{self, Rejection};
+
+/// Extract a clone of the given pool, for use in later `and_then`s.
+pub fn with_pool<DB: Database>(
+    pool: Pool<DB>,
+) -> impl Filter<Extract = (Pool<DB>,), Error = Infallible> + Clone {
+    crate::any().map(move || pool.clone())
+}
+
+/// Convert a [`sqlx::Error`] into a [`Rejection`].
+///
+/// See the [module documentation](self) for how errors are mapped.
+pub fn rejection(err: sqlx::Error) -> Rejection {
+    match err {
+        sqlx::Error::RowNotFound => reject::item_not_found(),
+        sqlx::Error::Database(ref db) => match db.kind() {
+            ErrorKind::UniqueViolation => reject::conflict(),
+            ErrorKind::ForeignKeyViolation => reject::item_not_found(),
+            ErrorKind::NotNullViolation => reject::bad_request(),
+            ErrorKind::CheckViolation => reject::not_acceptable(),
+            _ => {
+                tracing::error!("database error: {}", err);
+                reject::internal_server_error()
+            }
+        },
+        sqlx::Error::PoolTimedOut
+        | sqlx::Error::PoolClosed
+        | sqlx::Error::Io(_)
+        | sqlx::Error::Tls(_) => {
+            tracing::warn!("database unavailable: {}", err);
+            reject::known(DatabaseUnavailable { _p: () })
+        }
+        _ => {
+            tracing::error!("database error: {}", err);
+            reject::internal_server_error()
+        }
+    }
+}
+
+/// Extension trait mapping `sqlx` results into rejections.
+pub trait OrReject<T> {
+    /// Map the error side of this result with [`rejection`].
+    fn or_reject(self) -> Result<T, Rejection>;
+}
+
+impl<T> OrReject<T> for Result<T, sqlx::Error> {
+    fn or_reject(self) -> Result<T, Rejection> {
+        self.map_err(rejection)
+    }
+}
+
+crate::unit_error! {
+    /// The database could not be reached.
+    pub DatabaseUnavailable: "database unavailable"
+}
+
+#[cfg(test)]
+mod tests {
+    use xmpp_parsers::stanza_error::{DefinedCondition, ErrorType};
+
+    use super::*;
+    use crate::reject::IsReject;
+
+    fn error(err: sqlx::Error) -> (ErrorType, DefinedCondition) {
+        let error = rejection(err).into_stanza_error();
+        (error.type_, error.defined_condition)
+    }
+
+    #[test]
+    fn missing_rows_are_not_found() {
+        assert_eq!(
+            error(sqlx::Error::RowNotFound),
+            (ErrorType::Cancel, DefinedCondition::ItemNotFound)
+        );
+        let missing: Result<(), _> = Err(sqlx::Error::RowNotFound);
+        assert!(missing.or_reject().unwrap_err().is_item_not_found());
+    }
+
+    #[test]
+    fn unavailable_databases_ask_to_wait() {
+        for err in [sqlx::Error::PoolTimedOut, sqlx::Error::PoolClosed] {
+            assert_eq!(
+                error(err),
+                (ErrorType::Wait, DefinedCondition::InternalServerError)
+            );
+        }
+    }
+
+    #[test]
+    fn other_errors_are_internal() {
+        assert_eq!(
+            error(sqlx::Error::Protocol("unexpected message".to_owned())),
+            (ErrorType::Cancel, DefinedCondition::InternalServerError)
+        );
+    }
+}
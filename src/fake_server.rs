@@ -0,0 +1,191 @@
+//! A fake XMPP server for testing the run loops over real connections.
+//!
+//! It speaks just enough of the protocol to let a component complete its
+//! handshake (XEP-0114), or a client log in, then exchanges raw XML with
+//! it. Received text has its `"` replaced with `'`, so tests can look for
+//! attributes without caring how they were quoted.
+
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A listener accepting connections on a free local port.
+pub(crate) struct FakeServer {
+    listener: TcpListener,
+}
+
+impl FakeServer {
+    pub(crate) async fn bind() -> FakeServer {
+        FakeServer {
+            listener: TcpListener::bind("127.0.0.1:0").await.unwrap(),
+        }
+    }
+
+    pub(crate) fn port(&self) -> u16 {
+        self.listener.local_addr().unwrap().port()
+    }
+
+    /// Accept a component, and complete its handshake whatever its secret.
+    pub(crate) async fn accept_component(&self) -> Peer {
+        let mut peer = self.accept().await;
+        peer.expect_header().await;
+        peer.send(
+            "<?xml version='1.0'?><stream:stream xmlns='jabber:component:accept' \
+             xmlns:stream='http://etherx.jabber.org/streams' id='fake'>",
+        )
+        .await;
+        peer.expect("</handshake>").await;
+        peer.send("<handshake/>").await;
+        peer
+    }
+
+    /// Accept a client, and log it in as `jid` whatever its password.
+    pub(crate) async fn accept_client(&self, jid: &str) -> Peer {
+        let mut peer = self.accept().await;
+        peer.expect_header().await;
+        peer.send_client_header(
+            "<mechanisms xmlns='urn:ietf:params:xml:ns:xmpp-sasl'>\
+             <mechanism>PLAIN</mechanism></mechanisms>",
+        )
+        .await;
+        peer.expect("</auth>").await;
+        peer.send("<success xmlns='urn:ietf:params:xml:ns:xmpp-sasl'/>")
+            .await;
+        peer.expect_header().await;
+        peer.send_client_header("<bind xmlns='urn:ietf:params:xml:ns:xmpp-bind'/>")
+            .await;
+        let bind = peer.expect("</iq>").await;
+        let id = attr(&bind, "id").expect("bind request without an id");
+        peer.send(&format!(
+            "<iq type='result' id='{}'><bind xmlns='urn:ietf:params:xml:ns:xmpp-bind'>\
+             <jid>{}</jid></bind></iq>",
+            id, jid
+        ))
+        .await;
+        peer
+    }
+
+    async fn accept(&self) -> Peer {
+        let (stream, _) = tokio::time::timeout(TIMEOUT, self.listener.accept())
+            .await
+            .expect("timed out waiting for a connection")
+            .unwrap();
+        Peer {
+            stream,
+            received: String::new(),
+        }
+    }
+}
+
+/// One connection to the fake server.
+pub(crate) struct Peer {
+    stream: TcpStream,
+    received: String,
+}
+
+impl Peer {
+    pub(crate) async fn send(&mut self, xml: &str) {
+        self.stream.write_all(xml.as_bytes()).await.unwrap();
+    }
+
+    /// Wait until `needle` is received, and return everything received up
+    /// to its end, leaving the rest for the next call.
+    async fn expect(&mut self, needle: &str) -> String {
+        loop {
+            if let Some(at) = self.received.find(needle) {
+                let rest = self.received.split_off(at + needle.len());
+                return std::mem::replace(&mut self.received, rest);
+            }
+            self.read().await;
+        }
+    }
+
+    /// Wait for the next top-level element, or for the end of the stream,
+    /// and return its text.
+    pub(crate) async fn stanza(&mut self) -> String {
+        loop {
+            if let Some(end) = element_end(&self.received) {
+                let rest = self.received.split_off(end);
+                return std::mem::replace(&mut self.received, rest);
+            }
+            self.read().await;
+        }
+    }
+
+    /// Wait until the other end closes the connection.
+    pub(crate) async fn expect_closed(&mut self) {
+        let mut buf = [0; 4096];
+        loop {
+            let read = tokio::time::timeout(TIMEOUT, self.stream.read(&mut buf))
+                .await
+                .expect("timed out waiting for the connection to close");
+            if matches!(read, Ok(0) | Err(_)) {
+                return;
+            }
+        }
+    }
+
+    /// End the stream once the other end ended it.
+    pub(crate) async fn expect_end(&mut self) {
+        assert_eq!(self.stanza().await.trim(), "</stream:stream>");
+        self.send("</stream:stream>").await;
+    }
+
+    async fn read(&mut self) {
+        let mut buf = [0; 4096];
+        let read = tokio::time::timeout(TIMEOUT, self.stream.read(&mut buf))
+            .await
+            .unwrap_or_else(|_| panic!("timed out with {:?} received", self.received))
+            .unwrap();
+        assert!(read > 0, "closed with {:?} received", self.received);
+        self.received
+            .push_str(&String::from_utf8_lossy(&buf[..read]).replace('"', "'"));
+    }
+
+    async fn expect_header(&mut self) {
+        self.expect("<stream:stream").await;
+        self.expect(">").await;
+    }
+
+    async fn send_client_header(&mut self, features: &str) {
+        self.send(&format!(
+            "<?xml version='1.0'?><stream:stream xmlns='jabber:client' \
+             xmlns:stream='http://etherx.jabber.org/streams' from='example.org' \
+             id='fake' version='1.0'><stream:features>{}</stream:features>",
+            features
+        ))
+        .await;
+    }
+}
+
+/// The value of the first attribute `name` in `xml`.
+pub(crate) fn attr<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let start = xml.find(&format!(" {}='", name))? + name.len() + 3;
+    let len = xml[start..].find('\'')?;
+    Some(&xml[start..start + len])
+}
+
+// The end of the first complete element of `xml`, which starts between
+// elements of the stream, or of the stream footer.
+fn element_end(xml: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut at = 0;
+    while let Some(start) = xml[at..].find('<') {
+        let start = at + start;
+        let end = start + xml[start..].find('>')? + 1;
+        let tag = &xml[start..end];
+        if tag.starts_with("</") {
+            depth -= 1;
+        } else if !tag.ends_with("/>") && !tag.starts_with("<?") {
+            depth += 1;
+        }
+        if depth <= 0 && !tag.starts_with("<?") {
+            return Some(end);
+        }
+        at = end;
+    }
+    None
+}
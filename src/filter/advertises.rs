@@ -0,0 +1,26 @@
+use super::{Filter, FilterBase, Internal};
+use crate::disco::Features;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Advertises<T> {
+    pub(super) filter: T,
+    pub(super) feature: &'static str,
+}
+
+impl<T> FilterBase for Advertises<T>
+where
+    T: Filter,
+{
+    type Extract = T::Extract;
+    type Error = T::Error;
+    type Future = T::Future;
+    #[inline]
+    fn filter(&self, _: Internal) -> Self::Future {
+        self.filter.filter(Internal)
+    }
+
+    fn advertise(&self, features: &mut Features) {
+        features.insert(self.feature);
+        self.filter.advertise(features);
+    }
+}
@@ -6,6 +6,7 @@ use futures_util::ready;
 use pin_project::pin_project;
 
 use super::{Combine, Filter, FilterBase, Internal, Tuple};
+use crate::disco::Features;
 use crate::generic::CombinedTuples;
 use crate::reject::CombineRejection;
 
@@ -33,6 +34,11 @@ where
             state: State::First(self.first.filter(Internal), self.second.clone()),
         }
     }
+
+    fn advertise(&self, features: &mut Features) {
+        self.first.advertise(features);
+        self.second.advertise(features);
+    }
 }
 
 #[allow(missing_debug_implementations)]
@@ -6,6 +6,7 @@ use std::sync::Arc;
 use futures_util::TryFutureExt;
 
 use super::{Filter, FilterBase, Internal, Tuple};
+use crate::disco::Features;
 use crate::reject::Rejection;
 
 /// A type representing a boxed [`Filter`](crate::Filter) trait object.
@@ -79,6 +80,10 @@ impl<T: Tuple + Send> FilterBase for BoxedFilter<T> {
     fn filter(&self, _: Internal) -> Self::Future {
         self.filter.filter(Internal)
     }
+
+    fn advertise(&self, features: &mut Features) {
+        self.filter.advertise(features);
+    }
 }
 
 struct BoxingFilter<F> {
@@ -97,4 +102,8 @@ where
     fn filter(&self, _: Internal) -> Self::Future {
         Box::pin(self.filter.filter(Internal).into_future())
     }
+
+    fn advertise(&self, features: &mut Features) {
+        self.filter.advertise(features);
+    }
 }
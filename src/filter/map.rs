@@ -6,6 +6,7 @@ use futures_util::{ready, TryFuture};
 use pin_project::pin_project;
 
 use super::{Filter, FilterBase, Func, Internal};
+use crate::disco::Features;
 
 #[derive(Clone, Copy, Debug)]
 pub struct Map<T, F> {
@@ -28,6 +29,10 @@ where
             callback: self.callback.clone(),
         }
     }
+
+    fn advertise(&self, features: &mut Features) {
+        self.filter.advertise(features);
+    }
 }
 
 #[allow(missing_debug_implementations)]
@@ -1,3 +1,4 @@
+mod advertises;
 mod and;
 mod and_then;
 mod boxed;
@@ -17,10 +18,12 @@ use std::future::Future;
 use futures_util::{future, TryFuture, TryFutureExt};
 use tokio_xmpp::Stanza;
 
+use crate::disco::Features;
 use crate::filtered_stanza;
 pub(crate) use crate::generic::{Combine, Either, Func, Tuple};
 use crate::reject::{CombineRejection, IsReject, Rejection};
 
+pub(crate) use self::advertises::Advertises;
 pub(crate) use self::and::And;
 use self::and_then::AndThen;
 pub use self::boxed::BoxedFilter;
@@ -44,6 +47,11 @@ pub trait FilterBase {
 
     fn filter(&self, internal: Internal) -> Self::Future;
 
+    // Add the service discovery features this filter handles. Combinators
+    // forward to the filters they wrap, so the features of a whole route
+    // tree can be collected from its root.
+    fn advertise(&self, _features: &mut Features) {}
+
     fn map_err<F, E>(self, _internal: Internal, fun: F) -> MapErr<Self, F>
     where
         Self: Sized,
@@ -400,6 +408,33 @@ pub trait Filter: FilterBase {
         wrapper.wrap(self)
     }
 
+    /// Advertise `feature` in service discovery whenever this filter is
+    /// part of the served routes.
+    ///
+    /// Built-in filters for XEPs advertise their namespaces already; use
+    /// this for features implemented by your own routes. See
+    /// [`disco::features`](crate::disco::features).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use wax::Filter;
+    ///
+    /// let route = wax::iq()
+    ///     .and(wax::any())
+    ///     .map(wax::sink)
+    ///     .advertises("urn:example:frobnicate");
+    /// ```
+    fn advertises(self, feature: &'static str) -> Advertises<Self>
+    where
+        Self: Sized,
+    {
+        Advertises {
+            filter: self,
+            feature,
+        }
+    }
+
     /// Boxes this filter into a trait object, making it easier to name the type.
     ///
     /// # Example
@@ -448,7 +483,7 @@ fn _assert_object_safe() {
 
 pub(crate) fn filter_fn<F, U>(func: F) -> FilterFn<F>
 where
-    F: Fn(&mut Stanza) -> U,
+    F: Fn(&Stanza) -> U,
     U: TryFuture,
     U::Ok: Tuple,
     U::Error: IsReject,
@@ -460,7 +495,7 @@ pub(crate) fn filter_fn_one<F, U>(
     func: F,
 ) -> impl Filter<Extract = (U::Ok,), Error = U::Error> + Copy
 where
-    F: Fn(&mut Stanza) -> U + Copy,
+    F: Fn(&Stanza) -> U + Copy,
     U: TryFuture + Send + 'static,
     U::Ok: Send,
     U::Error: IsReject,
@@ -477,7 +512,7 @@ pub(crate) struct FilterFn<F> {
 
 impl<F, U> FilterBase for FilterFn<F>
 where
-    F: Fn(&mut Stanza) -> U,
+    F: Fn(&Stanza) -> U,
     U: TryFuture + Send + 'static,
     U::Ok: Tuple + Send,
     U::Error: IsReject,
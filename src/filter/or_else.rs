@@ -6,6 +6,7 @@ use futures_util::{ready, TryFuture};
 use pin_project::pin_project;
 
 use super::{Filter, FilterBase, Func, Internal};
+use crate::disco::Features;
 use crate::reject::IsReject;
 
 #[derive(Clone, Copy, Debug)]
@@ -30,6 +31,10 @@ where
             state: State::First(self.filter.filter(Internal), self.callback.clone()),
         }
     }
+
+    fn advertise(&self, features: &mut Features) {
+        self.filter.advertise(features);
+    }
 }
 
 #[allow(missing_debug_implementations)]
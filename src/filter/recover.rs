@@ -6,6 +6,7 @@ use futures_util::{ready, TryFuture};
 use pin_project::pin_project;
 
 use super::{Filter, FilterBase, Func, Internal};
+use crate::disco::Features;
 use crate::generic::Either;
 use crate::reject::IsReject;
 
@@ -31,6 +32,10 @@ where
             state: State::First(self.filter.filter(Internal), self.callback.clone()),
         }
     }
+
+    fn advertise(&self, features: &mut Features) {
+        self.filter.advertise(features);
+    }
 }
 
 #[allow(missing_debug_implementations)]
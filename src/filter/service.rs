@@ -2,6 +2,7 @@ use std::cell::RefCell;
 use std::convert::Infallible;
 use std::future::Future;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 
 use futures_util::future::TryFuture;
@@ -10,12 +11,13 @@ use tokio_xmpp::Stanza;
 use tower_service::Service;
 use xmpp_parsers::iq::Iq;
 use xmpp_parsers::message::{Message, MessageType};
+use xmpp_parsers::minidom::Element;
 use xmpp_parsers::presence::{Presence, Type as PresenceType};
 use xmpp_parsers::stanza_error::StanzaError;
 
 use crate::filtered_stanza;
 use crate::reject::IsReject;
-use crate::reply::Reply;
+use crate::reply::{Reply, Response};
 use crate::Filter;
 
 /// Convert a `Filter` into a `Service`.
@@ -70,11 +72,11 @@ where
     pub(crate) fn call_stanza(&self, stanza: Stanza) -> FilteredFuture<F::Future> {
         debug_assert!(!filtered_stanza::is_set(), "nested route::set calls");
 
-        let stanza = RefCell::new(stanza);
+        let stanza = RefCell::new(Arc::new(stanza));
         let fut = filtered_stanza::set(&stanza, || self.filter.filter(super::Internal));
         FilteredFuture {
             future: fut,
-            stanza,
+            stanza: Some(stanza),
         }
     }
 }
@@ -85,7 +87,7 @@ where
     <F::Future as TryFuture>::Ok: Reply,
     <F::Future as TryFuture>::Error: IsReject,
 {
-    type Response = Option<Stanza>;
+    type Response = Response;
     type Error = Infallible;
     type Future = FilteredFuture<F::Future>;
 
@@ -104,7 +106,10 @@ where
 pub struct FilteredFuture<F> {
     #[pin]
     future: F,
-    stanza: ::std::cell::RefCell<Stanza>,
+    // Released as soon as the filter completes, so a reply still holding the
+    // stanza (like `wax::relay::Relay`) can take it back without a copy.
+    // Replies are then addressed from its envelope instead.
+    stanza: Option<RefCell<Arc<Stanza>>>,
 }
 
 impl<F> Future for FilteredFuture<F>
@@ -113,7 +118,7 @@ where
     F::Ok: Reply,
     F::Error: IsReject,
 {
-    type Output = Result<Option<Stanza>, Infallible>;
+    type Output = Result<Response, Infallible>;
 
     #[inline]
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
@@ -121,21 +126,102 @@ where
 
         let pin = self.project();
         let fut = pin.future;
-        match filtered_stanza::set(pin.stanza, || fut.try_poll(cx)) {
-            Poll::Ready(Ok(ok)) => Poll::Ready(Ok(ok.into_response())),
-            Poll::Pending => Poll::Pending,
-            Poll::Ready(Err(err)) => {
+        let stanza = pin
+            .stanza
+            .as_ref()
+            .expect("FilteredFuture polled after completion");
+        let Poll::Ready(result) = filtered_stanza::set(stanza, || fut.try_poll(cx)) else {
+            return Poll::Pending;
+        };
+        let stanza = pin.stanza.take().expect("checked above").into_inner();
+        match result {
+            Ok(ok) => {
+                // A reply still holding the stanza, like `wax::relay::Relay`,
+                // takes it back without a copy once it is the last holder, so
+                // only its envelope is kept set to address errors from.
+                let stanza = if Arc::strong_count(&stanza) > 1 {
+                    let shared = Arc::new(envelope(&stanza));
+                    drop(stanza);
+                    shared
+                } else {
+                    stanza
+                };
+                let stanza = RefCell::new(stanza);
+                Poll::Ready(Ok(filtered_stanza::set(&stanza, || ok.into_response())))
+            }
+            Err(err) => {
                 tracing::debug!("rejected: {:?}", err);
                 let stanza_error = err.into_stanza_error();
-                let error_stanza = make_error_stanza(&pin.stanza.borrow(), stanza_error);
-                Poll::Ready(Ok(error_stanza))
+                let error_stanza = make_error_stanza(&stanza, stanza_error);
+                Poll::Ready(Ok(error_stanza.into()))
             }
         }
     }
 }
 
+/// `stanza` without its content, keeping what replies are addressed from.
+fn envelope(stanza: &Stanza) -> Stanza {
+    match stanza {
+        Stanza::Iq(Iq::Get {
+            from,
+            to,
+            id,
+            payload,
+        }) => Stanza::Iq(Iq::Get {
+            from: from.clone(),
+            to: to.clone(),
+            id: id.clone(),
+            payload: Element::builder(payload.name(), payload.ns()).build(),
+        }),
+        Stanza::Iq(Iq::Set {
+            from,
+            to,
+            id,
+            payload,
+        }) => Stanza::Iq(Iq::Set {
+            from: from.clone(),
+            to: to.clone(),
+            id: id.clone(),
+            payload: Element::builder(payload.name(), payload.ns()).build(),
+        }),
+        Stanza::Iq(Iq::Result { from, to, id, .. }) => Stanza::Iq(Iq::Result {
+            from: from.clone(),
+            to: to.clone(),
+            id: id.clone(),
+            payload: None,
+        }),
+        Stanza::Iq(Iq::Error {
+            from,
+            to,
+            id,
+            error,
+            ..
+        }) => Stanza::Iq(Iq::Error {
+            from: from.clone(),
+            to: to.clone(),
+            id: id.clone(),
+            error: error.clone(),
+            payload: None,
+        }),
+        Stanza::Message(msg) => {
+            let mut envelope = Message::new(msg.to.clone());
+            envelope.from = msg.from.clone();
+            envelope.id = msg.id.clone();
+            envelope.type_ = msg.type_.clone();
+            Stanza::Message(envelope)
+        }
+        Stanza::Presence(pres) => {
+            let mut envelope = Presence::new(pres.type_.clone());
+            envelope.from = pres.from.clone();
+            envelope.to = pres.to.clone();
+            envelope.id = pres.id.clone();
+            Stanza::Presence(envelope)
+        }
+    }
+}
+
 /// Construct an error stanza from the original stanza and a StanzaError.
-fn make_error_stanza(original: &Stanza, error: StanzaError) -> Option<Stanza> {
+pub(crate) fn make_error_stanza(original: &Stanza, error: StanzaError) -> Option<Stanza> {
     match original {
         Stanza::Iq(iq) => {
             let (from, to, id) = match iq {
@@ -6,6 +6,7 @@ use futures_util::{ready, TryFuture};
 use pin_project::pin_project;
 
 use super::{Either, Filter, FilterBase, Internal, Tuple};
+use crate::disco::Features;
 
 #[derive(Clone, Copy, Debug)]
 pub struct Unify<F> {
@@ -26,6 +27,10 @@ where
             inner: self.filter.filter(Internal),
         }
     }
+
+    fn advertise(&self, features: &mut Features) {
+        self.filter.advertise(features);
+    }
 }
 
 #[allow(missing_debug_implementations)]
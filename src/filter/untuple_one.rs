@@ -6,6 +6,7 @@ use futures_util::{ready, TryFuture};
 use pin_project::pin_project;
 
 use super::{Filter, FilterBase, Internal, Tuple};
+use crate::disco::Features;
 
 #[derive(Clone, Copy, Debug)]
 pub struct UntupleOne<F> {
@@ -26,6 +27,10 @@ where
             extract: self.filter.filter(Internal),
         }
     }
+
+    fn advertise(&self, features: &mut Features) {
+        self.filter.advertise(features);
+    }
 }
 
 #[allow(missing_debug_implementations)]
@@ -1,10 +1,14 @@
 use scoped_tls::scoped_thread_local;
 use std::cell::RefCell;
+use std::sync::Arc;
 use tokio_xmpp::Stanza;
 
-scoped_thread_local!(static FILTERED_STANZA: RefCell<Stanza>);
+// Filters only ever borrow the stanza; those that need to keep the whole of
+// it past `filter()` take a reference count through `shared()` instead of
+// deep-cloning it.
+scoped_thread_local!(static FILTERED_STANZA: RefCell<Arc<Stanza>>);
 
-pub(crate) fn set<F, U>(r: &RefCell<Stanza>, func: F) -> U
+pub(crate) fn set<F, U>(r: &RefCell<Arc<Stanza>>, func: F) -> U
 where
     F: FnOnce() -> U,
 {
@@ -17,7 +21,12 @@ pub(crate) fn is_set() -> bool {
 
 pub(crate) fn with<F, R>(func: F) -> R
 where
-    F: FnOnce(&mut Stanza) -> R,
+    F: FnOnce(&Stanza) -> R,
 {
-    FILTERED_STANZA.with(move |maybe_stanza| func(&mut maybe_stanza.borrow_mut()))
+    FILTERED_STANZA.with(move |maybe_stanza| func(&maybe_stanza.borrow()))
+}
+
+/// A shared handle to the stanza.
+pub(crate) fn shared() -> Arc<Stanza> {
+    FILTERED_STANZA.with(|maybe_stanza| maybe_stanza.borrow().clone())
 }
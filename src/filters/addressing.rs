@@ -0,0 +1,308 @@
+//! Extended Stanza Addressing (XEP-0033).
+//!
+//! - `wax::addressing::param()` - Extract the [`Address`]es of a stanza
+//! - `wax::addressing::fan_out()` - Act as a multicast service, delivering
+//!   each addressed stanza to its recipients through the outbound queue
+//! - [`reply_to`] - Who a reply to an addressed stanza should go to
+//!
+//! Copies sent by [`fan_out`] list every address except the `bcc` ones,
+//! marked as delivered, so that recipients neither see blind copies nor
+//! deliver the stanza again.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::Filter;
+//!
+//! let routes = wax::addressing::fan_out().or(other_routes);
+//! ```
+
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::jid::Jid;
+use xmpp_parsers::minidom::Element;
+
+use crate::correlation;
+use crate::filter::{filter_fn_one, Filter};
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+
+/// The `http://jabber.org/protocol/address` namespace.
+pub const NS: &str = "http://jabber.org/protocol/address";
+
+/// The role of an address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Kind {
+    /// A primary recipient.
+    To,
+    /// A carbon copy recipient.
+    Cc,
+    /// A blind carbon copy recipient, hidden from the others.
+    Bcc,
+    /// Where replies should go.
+    ReplyTo,
+    /// The room where replies should go.
+    ReplyRoom,
+    /// Replies are not allowed.
+    NoReply,
+    /// The original sender of a forwarded stanza.
+    OFrom,
+}
+
+impl Kind {
+    const ALL: [Kind; 7] = [
+        Kind::To,
+        Kind::Cc,
+        Kind::Bcc,
+        Kind::ReplyTo,
+        Kind::ReplyRoom,
+        Kind::NoReply,
+        Kind::OFrom,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Kind::To => "to",
+            Kind::Cc => "cc",
+            Kind::Bcc => "bcc",
+            Kind::ReplyTo => "replyto",
+            Kind::ReplyRoom => "replyroom",
+            Kind::NoReply => "noreply",
+            Kind::OFrom => "ofrom",
+        }
+    }
+
+    fn is_recipient(self) -> bool {
+        matches!(self, Kind::To | Kind::Cc | Kind::Bcc)
+    }
+}
+
+/// An address of a stanza.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Address {
+    /// The role of the address.
+    pub kind: Kind,
+    /// The JID addressed, if any.
+    pub jid: Option<Jid>,
+    /// The URI addressed, for non-XMPP recipients.
+    pub uri: Option<String>,
+    /// The node addressed at `jid`, if any.
+    pub node: Option<String>,
+    /// A description of the address.
+    pub desc: Option<String>,
+    /// Whether a multicast service already delivered to the address.
+    pub delivered: bool,
+}
+
+impl Address {
+    /// An address of `kind` for `jid`, or none for `noreply`.
+    pub fn new(kind: Kind, jid: Option<Jid>) -> Self {
+        Address {
+            kind,
+            jid,
+            uri: None,
+            node: None,
+            desc: None,
+            delivered: false,
+        }
+    }
+
+    /// Set the description.
+    pub fn desc(mut self, desc: impl Into<String>) -> Self {
+        self.desc = Some(desc.into());
+        self
+    }
+}
+
+impl From<Address> for Element {
+    fn from(address: Address) -> Element {
+        Element::builder("address", NS)
+            .attr("type", address.kind.as_str())
+            .attr("jid", address.jid.map(|jid| jid.to_string()))
+            .attr("uri", address.uri)
+            .attr("node", address.node)
+            .attr("desc", address.desc)
+            .attr("delivered", address.delivered.then_some("true"))
+            .build()
+    }
+}
+
+/// The `<addresses/>` payload listing `addresses`.
+pub fn addresses(addresses: impl IntoIterator<Item = Address>) -> Element {
+    Element::builder("addresses", NS)
+        .append_all(addresses.into_iter().map(Element::from))
+        .build()
+}
+
+/// Extract the addresses of a stanza.
+///
+/// Rejects with `item-not-found` for stanzas without addresses, and with
+/// `bad-request` if one is malformed.
+pub fn param() -> impl Filter<Extract = One<Vec<Address>>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(match addresses_of(stanza) {
+            Some(elem) => parse(elem),
+            None => Err(reject::item_not_found()),
+        })
+    })
+    .advertises(NS)
+}
+
+/// Deliver addressed stanzas to each of their undelivered recipients,
+/// through the outbound queue.
+///
+/// Rejects with `item-not-found` for stanzas without addresses, with
+/// `bad-request` if one is malformed or none can be delivered, and with
+/// `service-unavailable` outside of a server.
+pub fn fan_out() -> impl Filter<Extract = One<Option<Stanza>>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        let copies = match expand(stanza) {
+            Some(Ok(copies)) if copies.is_empty() => Err(reject::bad_request()),
+            Some(Ok(copies)) => correlation::outbound()
+                .map(|outbound| (outbound, copies))
+                .ok_or_else(reject::service_unavailable),
+            Some(Err(rejection)) => Err(rejection),
+            None => Err(reject::item_not_found()),
+        };
+        future::ready(copies.map(|(outbound, copies)| {
+            for copy in copies {
+                if outbound.send(copy).is_err() {
+                    tracing::warn!("dropped multicast copy");
+                }
+            }
+            None::<Stanza>
+        }))
+    })
+    .advertises(NS)
+}
+
+/// Who a reply to a stanza from `sender` with `addresses` should go to:
+/// nobody with a `noreply` address, the `replyto` addresses if there are
+/// any, or else the sender.
+pub fn reply_to(addresses: &[Address], sender: &Jid) -> Vec<Jid> {
+    if addresses
+        .iter()
+        .any(|address| address.kind == Kind::NoReply)
+    {
+        return Vec::new();
+    }
+    let reply_to: Vec<Jid> = addresses
+        .iter()
+        .filter(|address| address.kind == Kind::ReplyTo)
+        .filter_map(|address| address.jid.clone())
+        .collect();
+    if reply_to.is_empty() {
+        vec![sender.clone()]
+    } else {
+        reply_to
+    }
+}
+
+// Copies of `stanza`, one for each undelivered recipient.
+fn expand(stanza: &Stanza) -> Option<Result<Vec<Stanza>, Rejection>> {
+    let addresses = match parse(addresses_of(stanza)?) {
+        Ok(addresses) => addresses,
+        Err(rejection) => return Some(Err(rejection)),
+    };
+    let recipients: Vec<Jid> = addresses
+        .iter()
+        .filter(|address| address.kind.is_recipient() && !address.delivered)
+        .filter_map(|address| address.jid.clone())
+        .collect();
+    let listed = self::addresses(
+        addresses
+            .into_iter()
+            .filter(|address| address.kind != Kind::Bcc)
+            .map(|mut address| {
+                address.delivered |= address.kind.is_recipient() && address.jid.is_some();
+                address
+            }),
+    );
+    let copies = recipients
+        .into_iter()
+        .map(|recipient| {
+            let mut copy = stanza.clone();
+            match copy {
+                Stanza::Message(ref mut msg) => {
+                    msg.to = Some(recipient);
+                    msg.payloads.retain(|payload| !payload.is("addresses", NS));
+                    msg.payloads.push(listed.clone());
+                }
+                Stanza::Presence(ref mut presence) => {
+                    presence.to = Some(recipient);
+                    presence
+                        .payloads
+                        .retain(|payload| !payload.is("addresses", NS));
+                    presence.payloads.push(listed.clone());
+                }
+                Stanza::Iq(_) => unreachable!("IQs carry no addresses"),
+            }
+            copy
+        })
+        .collect();
+    Some(Ok(copies))
+}
+
+// Only messages and presences may be addressed.
+fn addresses_of(stanza: &Stanza) -> Option<&Element> {
+    let payloads = match stanza {
+        Stanza::Message(msg) => &msg.payloads,
+        Stanza::Presence(presence) => &presence.payloads,
+        Stanza::Iq(_) => return None,
+    };
+    payloads.iter().find(|payload| payload.is("addresses", NS))
+}
+
+fn parse(elem: &Element) -> Result<Vec<Address>, Rejection> {
+    elem.children()
+        .filter(|child| child.is("address", NS))
+        .map(|address| {
+            let kind = address
+                .attr("type")
+                .and_then(|type_| Kind::ALL.into_iter().find(|kind| kind.as_str() == type_))
+                .ok_or_else(reject::bad_request)?;
+            let jid = match address.attr("jid") {
+                Some(jid) => Some(jid.parse().map_err(|_| reject::bad_request())?),
+                None => None,
+            };
+            Ok(Address {
+                kind,
+                jid,
+                uri: address.attr("uri").map(str::to_owned),
+                node: address.attr("node").map(str::to_owned),
+                desc: address.attr("desc").map(str::to_owned),
+                delivered: matches!(address.attr("delivered"), Some("true") | Some("1")),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xmpp_parsers::message::Message;
+
+    #[test]
+    fn expands_to_each_recipient_hiding_bcc() {
+        let jid = |jid: &str| Some(Jid::new(jid).unwrap());
+        let mut msg = Message::new(jid("multicast.capulet.lit"));
+        msg.payloads.push(addresses([
+            Address::new(Kind::To, jid("juliet@capulet.lit")),
+            Address::new(Kind::Cc, jid("nurse@capulet.lit")),
+            Address::new(Kind::Bcc, jid("romeo@montague.lit")),
+            Address::new(Kind::NoReply, None),
+        ]));
+
+        let copies = expand(&Stanza::Message(msg)).unwrap().unwrap();
+        assert_eq!(copies.len(), 3);
+        let Stanza::Message(ref copy) = copies[2] else {
+            panic!("not a message");
+        };
+        assert_eq!(copy.to, jid("romeo@montague.lit"));
+        let listed = parse(copy.payloads.last().unwrap()).unwrap();
+        assert_eq!(listed.len(), 3);
+        assert!(listed.iter().all(|address| address.kind != Kind::Bcc));
+        assert!(listed[0].delivered && !listed[2].delivered);
+        assert!(reply_to(&listed, &Jid::new("tybalt@capulet.lit").unwrap()).is_empty());
+    }
+}
@@ -0,0 +1,477 @@
+//! Advanced Message Processing (XEP-0079).
+//!
+//! - `wax::amp::param()` - Extract the `<amp/>` rules attached to a message
+//! - `wax::amp::optional()` - Same, but yields `None` when no rules are attached
+//!
+//! A component that cannot honor the rules a sender attached must say so
+//! instead of silently dropping the message. The builders in this module
+//! ([`unsupported_actions`], [`unsupported_conditions`], [`failed_rules`] and
+//! [`notification`]) produce the stanzas the XEP requires for each case.
+
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::jid::Jid;
+use xmpp_parsers::message::{Message, MessageType};
+use xmpp_parsers::minidom::Element;
+use xmpp_parsers::stanza_error::{DefinedCondition, ErrorType, StanzaError};
+
+use crate::filter::{filter_fn_one, Filter};
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+
+/// The `http://jabber.org/protocol/amp` namespace.
+pub const NS: &str = "http://jabber.org/protocol/amp";
+
+/// The `http://jabber.org/protocol/amp#errors` namespace.
+pub const NS_ERRORS: &str = "http://jabber.org/protocol/amp#errors";
+
+/// The set of processing rules attached to a message.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Amp {
+    /// The status, only present on notifications sent back by a processor.
+    pub status: Option<Action>,
+    /// The original sender, only present on notifications.
+    pub from: Option<Jid>,
+    /// The original recipient, only present on notifications.
+    pub to: Option<Jid>,
+    /// Whether the rules should be applied at every hop.
+    pub per_hop: bool,
+    /// The rules, in document order.
+    pub rules: Vec<Rule>,
+}
+
+/// A single `<rule/>`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+    /// The condition to evaluate.
+    pub condition: Condition,
+    /// The action to take when the condition is met.
+    pub action: Action,
+    /// The condition-specific value.
+    pub value: String,
+}
+
+/// A rule condition.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Condition {
+    /// `deliver`
+    Deliver,
+    /// `expire-at`
+    ExpireAt,
+    /// `match-resource`
+    MatchResource,
+    /// Any condition not defined by XEP-0079.
+    Other(String),
+}
+
+/// A rule action.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// `alert`
+    Alert,
+    /// `drop`
+    Drop,
+    /// `error`
+    Error,
+    /// `notify`
+    Notify,
+    /// Any action not defined by XEP-0079.
+    Other(String),
+}
+
+impl Condition {
+    /// The attribute value of this condition.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Condition::Deliver => "deliver",
+            Condition::ExpireAt => "expire-at",
+            Condition::MatchResource => "match-resource",
+            Condition::Other(other) => other,
+        }
+    }
+}
+
+impl From<&str> for Condition {
+    fn from(s: &str) -> Self {
+        match s {
+            "deliver" => Condition::Deliver,
+            "expire-at" => Condition::ExpireAt,
+            "match-resource" => Condition::MatchResource,
+            other => Condition::Other(other.to_owned()),
+        }
+    }
+}
+
+impl Action {
+    /// The attribute value of this action.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Action::Alert => "alert",
+            Action::Drop => "drop",
+            Action::Error => "error",
+            Action::Notify => "notify",
+            Action::Other(other) => other,
+        }
+    }
+}
+
+impl From<&str> for Action {
+    fn from(s: &str) -> Self {
+        match s {
+            "alert" => Action::Alert,
+            "drop" => Action::Drop,
+            "error" => Action::Error,
+            "notify" => Action::Notify,
+            other => Action::Other(other.to_owned()),
+        }
+    }
+}
+
+impl Amp {
+    /// Rules whose condition or action is not in the supported sets.
+    ///
+    /// Conditions and actions are checked separately, since XEP-0079 reports
+    /// them with different error conditions.
+    pub fn unsupported<'a>(
+        &'a self,
+        conditions: &'a [Condition],
+        actions: &'a [Action],
+    ) -> (Vec<&'a Rule>, Vec<&'a Rule>) {
+        let bad_conditions = self
+            .rules
+            .iter()
+            .filter(|rule| !conditions.contains(&rule.condition))
+            .collect();
+        let bad_actions = self
+            .rules
+            .iter()
+            .filter(|rule| !actions.contains(&rule.action))
+            .collect();
+        (bad_conditions, bad_actions)
+    }
+}
+
+impl TryFrom<&Element> for Amp {
+    type Error = Rejection;
+
+    fn try_from(elem: &Element) -> Result<Self, Self::Error> {
+        if !elem.is("amp", NS) {
+            return Err(reject::item_not_found());
+        }
+
+        let jid_attr = |name: &str| match elem.attr(name) {
+            Some(value) => Jid::new(value).map(Some).map_err(|_| reject::bad_request()),
+            None => Ok(None),
+        };
+
+        let rules = elem
+            .children()
+            .filter(|child| child.is("rule", NS))
+            .map(|child| {
+                match (
+                    child.attr("condition"),
+                    child.attr("action"),
+                    child.attr("value"),
+                ) {
+                    (Some(condition), Some(action), Some(value)) => Ok(Rule {
+                        condition: condition.into(),
+                        action: action.into(),
+                        value: value.to_owned(),
+                    }),
+                    _ => Err(reject::bad_request()),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Amp {
+            status: elem.attr("status").map(Action::from),
+            from: jid_attr("from")?,
+            to: jid_attr("to")?,
+            per_hop: matches!(elem.attr("per-hop"), Some("true") | Some("1")),
+            rules,
+        })
+    }
+}
+
+impl From<&Rule> for Element {
+    fn from(rule: &Rule) -> Element {
+        Element::builder("rule", NS)
+            .attr("condition", rule.condition.as_str())
+            .attr("action", rule.action.as_str())
+            .attr("value", rule.value.as_str())
+            .build()
+    }
+}
+
+impl From<Amp> for Element {
+    fn from(amp: Amp) -> Element {
+        let mut builder = Element::builder("amp", NS);
+        if let Some(ref status) = amp.status {
+            builder = builder.attr("status", status.as_str());
+        }
+        if let Some(ref from) = amp.from {
+            builder = builder.attr("from", from.as_str());
+        }
+        if let Some(ref to) = amp.to {
+            builder = builder.attr("to", to.as_str());
+        }
+        if amp.per_hop {
+            builder = builder.attr("per-hop", "true");
+        }
+        builder
+            .append_all(amp.rules.iter().map(Element::from))
+            .build()
+    }
+}
+
+/// Extract the AMP rules attached to an incoming message.
+///
+/// Rejects with `item-not-found` if the stanza is not a message or carries no
+/// `<amp/>` payload, and with `bad-request` if the payload is malformed.
+///
+/// # Example
+///
+/// ```ignore
+/// use wax::Filter;
+/// use wax::amp::{Action, Amp, Condition};
+///
+/// let route = wax::message::param()
+///     .and(wax::amp::param())
+///     .map(|msg, amp: Amp| {
+///         let (conditions, _) = amp.unsupported(&[Condition::Deliver], &[Action::Drop]);
+///         if conditions.is_empty() {
+///             None
+///         } else {
+///             Some(wax::amp::unsupported_conditions(&msg, conditions))
+///         }
+///     });
+/// ```
+pub fn param() -> impl Filter<Extract = One<Amp>, Error = Rejection> + Copy {
+    optional().and_then(|amp: Option<Amp>| future::ready(amp.ok_or_else(reject::item_not_found)))
+}
+
+/// Extract the AMP rules attached to an incoming message, if any.
+///
+/// Rejects with `item-not-found` if the stanza is not a message, and with
+/// `bad-request` if the `<amp/>` payload is malformed.
+pub fn optional() -> impl Filter<Extract = One<Option<Amp>>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        let result = match stanza {
+            Stanza::Message(msg) => msg
+                .payloads
+                .iter()
+                .find(|p| p.is("amp", NS))
+                .map(Amp::try_from)
+                .transpose(),
+            _ => Err(reject::item_not_found()),
+        };
+        future::ready(result)
+    })
+}
+
+/// Build the error reply for rules whose actions are not supported.
+pub fn unsupported_actions<'a>(
+    original: &Message,
+    rules: impl IntoIterator<Item = &'a Rule>,
+) -> Message {
+    error_reply(
+        original,
+        ErrorType::Modify,
+        DefinedCondition::BadRequest,
+        Element::builder("unsupported-actions", NS)
+            .append_all(rules.into_iter().map(Element::from))
+            .build(),
+    )
+}
+
+/// Build the error reply for rules whose conditions are not supported.
+pub fn unsupported_conditions<'a>(
+    original: &Message,
+    rules: impl IntoIterator<Item = &'a Rule>,
+) -> Message {
+    error_reply(
+        original,
+        ErrorType::Modify,
+        DefinedCondition::BadRequest,
+        Element::builder("unsupported-conditions", NS)
+            .append_all(rules.into_iter().map(Element::from))
+            .build(),
+    )
+}
+
+/// Build the error reply for rules that were triggered with the `error` action.
+pub fn failed_rules<'a>(original: &Message, rules: impl IntoIterator<Item = &'a Rule>) -> Message {
+    error_reply(
+        original,
+        ErrorType::Cancel,
+        DefinedCondition::UndefinedCondition,
+        Element::builder("failed-rules", NS_ERRORS)
+            .append_all(rules.into_iter().map(Element::from))
+            .build(),
+    )
+}
+
+/// Build the `alert` or `notify` message sent back to the original sender
+/// when a rule is triggered.
+pub fn notification(original: &Message, status: Action, rule: &Rule) -> Message {
+    let amp = Amp {
+        status: Some(status),
+        from: original.from.clone(),
+        to: original.to.clone(),
+        per_hop: false,
+        rules: vec![rule.clone()],
+    };
+    let mut msg = Message::new(original.from.clone());
+    msg.from = original.to.clone();
+    msg.id = original.id.clone();
+    msg.payloads.push(amp.into());
+    msg
+}
+
+fn error_reply(
+    original: &Message,
+    type_: ErrorType,
+    condition: DefinedCondition,
+    specific: Element,
+) -> Message {
+    let mut error = StanzaError::new(type_, condition, "en", "");
+    error.texts.clear();
+    error.other = Some(specific);
+
+    let mut msg = Message::new(original.from.clone());
+    msg.from = original.to.clone();
+    msg.id = original.id.clone();
+    msg.type_ = MessageType::Error;
+    msg.payloads.extend(
+        original
+            .payloads
+            .iter()
+            .filter(|p| p.is("amp", NS))
+            .cloned(),
+    );
+    msg.payloads.push(error.into());
+    msg
+}
+
+#[cfg(test)]
+mod tests {
+    use xmpp_parsers::message::Id;
+
+    use super::*;
+
+    // XEP-0079, example 1, extended with a rule outside of the XEP.
+    const RULES: &str = "<amp xmlns='http://jabber.org/protocol/amp' per-hop='true'>\
+        <rule condition='deliver' action='drop' value='stored'/>\
+        <rule condition='expire-at' action='notify' value='2004-09-10T08:33:14Z'/>\
+        <rule condition='x-custom' action='alert' value='1'/>\
+        </amp>";
+
+    fn message() -> Message {
+        let mut msg = Message::new(Some("northumberland@shakespeare.lit".parse().unwrap()));
+        msg.from = Some("bernardo@hamlet.lit/elsinore".parse().unwrap());
+        msg.id = Some(Id("chatty2".to_owned()));
+        msg.payloads.push(RULES.parse().unwrap());
+        msg
+    }
+
+    #[test]
+    fn parses_rules() {
+        let amp = Amp::try_from(&RULES.parse::<Element>().unwrap()).unwrap();
+        assert!(amp.per_hop);
+        assert_eq!(amp.status, None);
+        assert_eq!(
+            amp.rules,
+            vec![
+                Rule {
+                    condition: Condition::Deliver,
+                    action: Action::Drop,
+                    value: "stored".to_owned(),
+                },
+                Rule {
+                    condition: Condition::ExpireAt,
+                    action: Action::Notify,
+                    value: "2004-09-10T08:33:14Z".to_owned(),
+                },
+                Rule {
+                    condition: Condition::Other("x-custom".to_owned()),
+                    action: Action::Alert,
+                    value: "1".to_owned(),
+                },
+            ]
+        );
+
+        let missing_value: Element = "<amp xmlns='http://jabber.org/protocol/amp'>\
+            <rule condition='deliver' action='drop'/></amp>"
+            .parse()
+            .unwrap();
+        assert!(Amp::try_from(&missing_value).is_err());
+    }
+
+    #[test]
+    fn picks_unsupported_rules() {
+        let amp = Amp::try_from(&RULES.parse::<Element>().unwrap()).unwrap();
+        let (conditions, actions) = amp.unsupported(
+            &[Condition::Deliver, Condition::ExpireAt],
+            &[Action::Drop, Action::Alert],
+        );
+        assert_eq!(conditions, vec![&amp.rules[2]]);
+        assert_eq!(actions, vec![&amp.rules[1]]);
+    }
+
+    #[test]
+    fn reports_unsupported_conditions() {
+        let original = message();
+        let amp = Amp::try_from(&original.payloads[0]).unwrap();
+        let reply = unsupported_conditions(&original, &amp.rules[2..]);
+
+        assert_eq!(reply.type_, MessageType::Error);
+        assert_eq!(reply.to, original.from);
+        assert_eq!(reply.from, original.to);
+        assert_eq!(reply.id, original.id);
+        let error = reply
+            .payloads
+            .iter()
+            .find_map(|p| StanzaError::try_from(p.clone()).ok())
+            .unwrap();
+        assert_eq!(error.defined_condition, DefinedCondition::BadRequest);
+        let specific = error.other.unwrap();
+        assert!(specific.is("unsupported-conditions", NS));
+        let rules: Vec<_> = specific.children().collect();
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].attr("condition"), Some("x-custom"));
+    }
+
+    #[test]
+    fn reports_failed_rules() {
+        let original = message();
+        let amp = Amp::try_from(&original.payloads[0]).unwrap();
+        let reply = failed_rules(&original, &amp.rules[..1]);
+
+        let error = reply
+            .payloads
+            .iter()
+            .find_map(|p| StanzaError::try_from(p.clone()).ok())
+            .unwrap();
+        assert_eq!(error.type_, ErrorType::Cancel);
+        assert_eq!(
+            error.defined_condition,
+            DefinedCondition::UndefinedCondition
+        );
+        assert!(error.other.unwrap().is("failed-rules", NS_ERRORS));
+    }
+
+    #[test]
+    fn notifies_the_sender() {
+        let original = message();
+        let amp = Amp::try_from(&original.payloads[0]).unwrap();
+        let notification = notification(&original, Action::Notify, &amp.rules[1]);
+
+        assert_eq!(notification.to, original.from);
+        assert_eq!(notification.from, original.to);
+        let sent = Amp::try_from(&notification.payloads[0]).unwrap();
+        assert_eq!(sent.status, Some(Action::Notify));
+        assert_eq!(sent.from, original.from);
+        assert_eq!(sent.to, original.to);
+        assert_eq!(sent.rules, vec![amp.rules[1].clone()]);
+    }
+}
@@ -0,0 +1,79 @@
+//! Attention (XEP-0224).
+//!
+//! - `wax::attention::requested()` - Match messages asking for attention
+//! - `wax::attention::reply()` - Ask the sender for attention in return
+//! - [`request`] - Build a message asking someone for attention
+//!
+//! Clients show attention requests prominently, e.g. by shaking the
+//! window, so bots should only send them in answer to one.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::Filter;
+//!
+//! let buzz_back = wax::attention::requested().and(wax::attention::reply());
+//! ```
+
+use std::convert::Infallible;
+
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::jid::Jid;
+use xmpp_parsers::message::{Message, MessageType};
+use xmpp_parsers::minidom::Element;
+
+use crate::delay;
+use crate::filter::{filter_fn, Filter};
+use crate::filters::stanza::{from, to};
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+
+/// The `urn:xmpp:attention:0` namespace.
+pub const NS: &str = "urn:xmpp:attention:0";
+
+/// Match messages asking for attention.
+///
+/// Rejects with `item-not-found` for other stanzas. Delayed messages, e.g.
+/// from offline storage, are not matched, as the request is stale.
+pub fn requested() -> impl Filter<Extract = (), Error = Rejection> + Copy {
+    filter_fn(|stanza: &Stanza| match stanza {
+        Stanza::Message(msg)
+            if msg
+                .payloads
+                .iter()
+                .any(|payload| payload.is("attention", NS))
+                && !msg
+                    .payloads
+                    .iter()
+                    .any(|payload| payload.is("delay", delay::NS)) =>
+        {
+            future::ok(())
+        }
+        _ => future::err(reject::item_not_found()),
+    })
+    .advertises(NS)
+}
+
+/// A headline message asking `to` for attention.
+pub fn request(to: Jid) -> Message {
+    message(Some(to))
+}
+
+/// Reply with a message asking the sender for attention.
+pub fn reply() -> impl Filter<Extract = One<Message>, Error = Infallible> + Copy {
+    from()
+        .and(to())
+        .map(|sender: Option<Jid>, recipient: Option<Jid>| {
+            let mut msg = message(sender);
+            msg.from = recipient;
+            msg
+        })
+}
+
+fn message(to: Option<Jid>) -> Message {
+    let mut msg = Message::new(to);
+    msg.type_ = MessageType::Headline;
+    msg.payloads.push(Element::builder("attention", NS).build());
+    msg
+}
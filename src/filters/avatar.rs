@@ -0,0 +1,282 @@
+//! Avatars (XEP-0153 and XEP-0084).
+//!
+//! An [`AvatarCache`] keeps the avatars of the component and of the
+//! entities it hosts, keyed by their SHA-1 hash:
+//!
+//! - `.with(cache.clone())` - Advertise the avatar hash in presence replies (XEP-0153)
+//! - `cache.data()` / `cache.metadata()` - Answer PEP requests for avatars (XEP-0084)
+//! - `cache.photo(jid)` - The picture to put in a vCard
+//!
+//! To publish the avatar of a user instead, send [`Avatar::data_item`] and
+//! [`Avatar::metadata_item`] with `wax::pep::publish`.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::avatar::{Avatar, AvatarCache};
+//! use wax::Filter;
+//!
+//! let avatars = AvatarCache::new();
+//! avatars.set(&puppet, Avatar::new("image/png", remote_picture));
+//!
+//! let routes = avatars
+//!     .data()
+//!     .or(avatars.metadata())
+//!     .or(presence_routes)
+//!     .with(avatars.clone());
+//! ```
+
+use std::fmt;
+use std::sync::Arc;
+
+use base64::Engine;
+use dashmap::DashMap;
+use futures_util::future;
+use sha1::{Digest, Sha1};
+use tokio_xmpp::Stanza;
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::jid::{BareJid, Jid};
+use xmpp_parsers::minidom::Element;
+
+use crate::filter::{Filter, WrapSealed};
+use crate::generic::One;
+use crate::pubsub::{self, Item, Retrieve};
+use crate::reject::{self, IsReject, Rejection};
+use crate::reply::internal::{Transform, WithTransform};
+use crate::reply::Reply;
+use crate::vcard::Photo;
+
+/// The `vcard-temp:x:update` namespace.
+pub const NS_VCARD_UPDATE: &str = "vcard-temp:x:update";
+
+/// The `urn:xmpp:avatar:data` namespace, also the PEP node of avatar data.
+pub const NS_DATA: &str = "urn:xmpp:avatar:data";
+
+/// The `urn:xmpp:avatar:metadata` namespace, also the PEP node of avatar
+/// metadata.
+pub const NS_METADATA: &str = "urn:xmpp:avatar:metadata";
+
+/// An avatar image.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Avatar {
+    /// The MIME type of the image.
+    pub content_type: String,
+    /// The image.
+    pub bytes: Arc<[u8]>,
+    /// The width of the image in pixels, if known.
+    pub width: Option<u16>,
+    /// The height of the image in pixels, if known.
+    pub height: Option<u16>,
+}
+
+impl Avatar {
+    /// An avatar of type `content_type`.
+    pub fn new(content_type: impl Into<String>, bytes: impl Into<Arc<[u8]>>) -> Self {
+        Avatar {
+            content_type: content_type.into(),
+            bytes: bytes.into(),
+            width: None,
+            height: None,
+        }
+    }
+
+    /// Set the size of the image.
+    pub fn dimensions(mut self, width: u16, height: u16) -> Self {
+        self.width = Some(width);
+        self.height = Some(height);
+        self
+    }
+
+    /// The hex-encoded SHA-1 hash of the image, which identifies it.
+    pub fn hash(&self) -> String {
+        Sha1::digest(&self.bytes)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// The image as a vCard picture.
+    pub fn photo(&self) -> Photo {
+        Photo::Data {
+            content_type: self.content_type.clone(),
+            bytes: self.bytes.to_vec(),
+        }
+    }
+
+    /// The item to publish to the avatar data node.
+    pub fn data_item(&self) -> Item {
+        let data = base64::engine::general_purpose::STANDARD.encode(&self.bytes);
+        Item {
+            id: self.hash(),
+            payload: Some(Element::builder("data", NS_DATA).append(data).build()),
+        }
+    }
+
+    /// The item to publish to the avatar metadata node, after the data.
+    pub fn metadata_item(&self) -> Item {
+        let id = self.hash();
+        let info = Element::builder("info", NS_METADATA)
+            .attr("bytes", self.bytes.len().to_string())
+            .attr("id", id.clone())
+            .attr("type", self.content_type.clone())
+            .attr("width", self.width.map(|width| width.to_string()))
+            .attr("height", self.height.map(|height| height.to_string()))
+            .build();
+        Item {
+            id,
+            payload: Some(
+                Element::builder("metadata", NS_METADATA)
+                    .append(info)
+                    .build(),
+            ),
+        }
+    }
+}
+
+/// The `<x/>` advertising the avatar hash in presence, with `None` for
+/// entities without avatar.
+pub fn update(hash: Option<&str>) -> Element {
+    let photo = match hash {
+        Some(hash) => Element::builder("photo", NS_VCARD_UPDATE).append(hash),
+        None => Element::builder("photo", NS_VCARD_UPDATE),
+    };
+    Element::builder("x", NS_VCARD_UPDATE)
+        .append(photo.build())
+        .build()
+}
+
+/// The avatars of the component and the entities it hosts.
+///
+/// Clones share the same avatars.
+#[derive(Clone, Default)]
+pub struct AvatarCache {
+    avatars: Arc<DashMap<String, Avatar>>,
+    owners: Arc<DashMap<BareJid, String>>,
+}
+
+impl AvatarCache {
+    /// An empty cache.
+    pub fn new() -> Self {
+        AvatarCache::default()
+    }
+
+    /// Set the avatar of `jid`, and return its hash.
+    pub fn set(&self, jid: &Jid, avatar: Avatar) -> String {
+        let hash = avatar.hash();
+        self.avatars.insert(hash.clone(), avatar);
+        if let Some(old) = self.owners.insert(jid.to_bare(), hash.clone()) {
+            if old != hash {
+                self.forget(&old);
+            }
+        }
+        hash
+    }
+
+    /// Remove the avatar of `jid`.
+    pub fn clear(&self, jid: &Jid) {
+        if let Some((_, old)) = self.owners.remove(&jid.to_bare()) {
+            self.forget(&old);
+        }
+    }
+
+    /// The avatar with `hash`.
+    pub fn get(&self, hash: &str) -> Option<Avatar> {
+        self.avatars.get(hash).map(|avatar| avatar.clone())
+    }
+
+    /// The hash of the avatar of `jid`.
+    pub fn hash_of(&self, jid: &Jid) -> Option<String> {
+        self.owners.get(&jid.to_bare()).map(|hash| hash.clone())
+    }
+
+    /// The avatar of `jid`, as a vCard picture.
+    pub fn photo(&self, jid: &Jid) -> Option<Photo> {
+        let hash = self.hash_of(jid)?;
+        self.get(&hash).map(|avatar| avatar.photo())
+    }
+
+    /// Answer PEP requests for the avatar data of cached entities.
+    ///
+    /// Rejects with `item-not-found` for other requests, and for entities
+    /// without avatar.
+    pub fn data(&self) -> impl Filter<Extract = One<Iq>, Error = Rejection> + Clone {
+        self.serve(NS_DATA, Avatar::data_item)
+    }
+
+    /// Answer PEP requests for the avatar metadata of cached entities.
+    ///
+    /// Rejects with `item-not-found` for other requests, and for entities
+    /// without avatar.
+    pub fn metadata(&self) -> impl Filter<Extract = One<Iq>, Error = Rejection> + Clone {
+        self.serve(NS_METADATA, Avatar::metadata_item)
+    }
+
+    fn serve(
+        &self,
+        node: &'static str,
+        item: fn(&Avatar) -> Item,
+    ) -> impl Filter<Extract = One<Iq>, Error = Rejection> + Clone {
+        let cache = self.clone();
+        pubsub::items().and_then(move |request: Retrieve| {
+            let answer = match request.to {
+                Some(ref to) if request.node == node => {
+                    let avatar = cache.hash_of(to).and_then(|hash| cache.get(&hash));
+                    match avatar {
+                        Some(avatar)
+                            if request.item_ids.is_empty()
+                                || request.item_ids.contains(&avatar.hash()) =>
+                        {
+                            Ok(request.result([item(&avatar)]))
+                        }
+                        _ => Err(reject::item_not_found()),
+                    }
+                }
+                _ => Err(reject::item_not_found()),
+            };
+            future::ready(answer)
+        })
+    }
+
+    // Drop the avatar with `hash` once no entity uses it anymore.
+    fn forget(&self, hash: &str) {
+        if !self.owners.iter().any(|owner| owner.value() == hash) {
+            self.avatars.remove(hash);
+        }
+    }
+}
+
+impl fmt::Debug for AvatarCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AvatarCache")
+            .field("avatars", &self.avatars.len())
+            .field("owners", &self.owners.len())
+            .finish()
+    }
+}
+
+impl<F> WrapSealed<F> for AvatarCache
+where
+    F: Filter + Clone + Send,
+    F::Extract: Reply,
+    F::Error: IsReject,
+{
+    type Wrapped = WithTransform<AvatarCache, F>;
+
+    fn wrap(&self, filter: F) -> Self::Wrapped {
+        WithTransform::new(self.clone(), filter)
+    }
+}
+
+impl Transform for AvatarCache {
+    fn apply(&self, mut stanza: Stanza) -> Stanza {
+        if let Stanza::Presence(ref mut pres) = stanza {
+            let hash = pres.from.as_ref().and_then(|from| self.hash_of(from));
+            if let Some(hash) = hash {
+                pres.payloads
+                    .retain(|payload| !payload.is("x", NS_VCARD_UPDATE));
+                pres.payloads.push(update(Some(&hash)));
+            }
+        }
+        stanza
+    }
+}
@@ -0,0 +1,484 @@
+//! Blocking Command (XEP-0191).
+//!
+//! - `wax::blocking::blocklist()` - Extract a request for the blocklist
+//! - `wax::blocking::block()` / `unblock()` - Extract requests changing it
+//!
+//! To serve the whole protocol, give a [`BlocklistStore`] to [`Blocking`]:
+//! it answers every request from the store, and pushes changes to the
+//! resources of the user that asked for the blocklist before. A
+//! [`Namespace`] of the shared [store](crate::store) is one.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::blocking::Blocking;
+//! use wax::Filter;
+//!
+//! let blocking = Blocking::new(store);
+//!
+//! let routes = blocking.filter().or(other_routes);
+//!
+//! // when a resource goes offline:
+//! blocking.forget(&resource);
+//! ```
+
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use futures_util::future::{self, BoxFuture};
+use tokio_xmpp::Stanza;
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::jid::{BareJid, Jid};
+use xmpp_parsers::minidom::Element;
+
+use crate::correlation::{self, Outbound};
+use crate::filter::{filter_fn_one, Filter};
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+use crate::store::Namespace;
+
+/// The `urn:xmpp:blocking` namespace.
+pub const NS: &str = "urn:xmpp:blocking";
+
+/// A request for the blocklist of the sender.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlocklistRequest {
+    /// The user asking.
+    pub from: Option<Jid>,
+    /// The service asked.
+    pub to: Option<Jid>,
+    id: String,
+}
+
+impl BlocklistRequest {
+    /// Answer with `jids`.
+    pub fn result(self, jids: impl IntoIterator<Item = Jid>) -> Iq {
+        Iq::Result {
+            from: self.to,
+            to: self.from,
+            id: self.id,
+            payload: Some(items("blocklist", jids)),
+        }
+    }
+}
+
+/// A request to block JIDs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    /// The user blocking.
+    pub from: Option<Jid>,
+    /// The service asked.
+    pub to: Option<Jid>,
+    /// The JIDs to block, never empty.
+    pub jids: Vec<Jid>,
+    id: String,
+}
+
+impl Block {
+    /// Acknowledge the request.
+    pub fn success(self) -> Iq {
+        success(self.from, self.to, self.id)
+    }
+}
+
+/// A request to unblock JIDs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Unblock {
+    /// The user unblocking.
+    pub from: Option<Jid>,
+    /// The service asked.
+    pub to: Option<Jid>,
+    /// The JIDs to unblock, or empty to unblock every JID.
+    pub jids: Vec<Jid>,
+    id: String,
+}
+
+impl Unblock {
+    /// Acknowledge the request.
+    pub fn success(self) -> Iq {
+        success(self.from, self.to, self.id)
+    }
+}
+
+/// Storage for the blocklists of users.
+pub trait BlocklistStore: Send + Sync + 'static {
+    /// The JIDs blocked by `user`.
+    fn blocklist<'a>(&'a self, user: &'a BareJid) -> BoxFuture<'a, Result<Vec<Jid>, Rejection>>;
+
+    /// Add `jids` to the blocklist of `user`.
+    fn block<'a>(
+        &'a self,
+        user: &'a BareJid,
+        jids: &'a [Jid],
+    ) -> BoxFuture<'a, Result<(), Rejection>>;
+
+    /// Remove `jids` from the blocklist of `user`, or every JID if `jids`
+    /// is empty.
+    fn unblock<'a>(
+        &'a self,
+        user: &'a BareJid,
+        jids: &'a [Jid],
+    ) -> BoxFuture<'a, Result<(), Rejection>>;
+}
+
+impl<S: BlocklistStore + ?Sized> BlocklistStore for Arc<S> {
+    fn blocklist<'a>(&'a self, user: &'a BareJid) -> BoxFuture<'a, Result<Vec<Jid>, Rejection>> {
+        (**self).blocklist(user)
+    }
+
+    fn block<'a>(
+        &'a self,
+        user: &'a BareJid,
+        jids: &'a [Jid],
+    ) -> BoxFuture<'a, Result<(), Rejection>> {
+        (**self).block(user, jids)
+    }
+
+    fn unblock<'a>(
+        &'a self,
+        user: &'a BareJid,
+        jids: &'a [Jid],
+    ) -> BoxFuture<'a, Result<(), Rejection>> {
+        (**self).unblock(user, jids)
+    }
+}
+
+/// Blocklists kept in a [`Namespace`] of the shared store, as the list of
+/// blocked JIDs under each bare JID.
+///
+/// Changes are read-modify-write, so concurrent changes to the same
+/// blocklist may be lost.
+impl BlocklistStore for Namespace {
+    fn blocklist<'a>(&'a self, user: &'a BareJid) -> BoxFuture<'a, Result<Vec<Jid>, Rejection>> {
+        Box::pin(async move {
+            let jids = self.get::<Vec<String>>(user.as_str()).await?;
+            Ok(jids
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|jid| Jid::new(jid).ok())
+                .collect())
+        })
+    }
+
+    fn block<'a>(
+        &'a self,
+        user: &'a BareJid,
+        jids: &'a [Jid],
+    ) -> BoxFuture<'a, Result<(), Rejection>> {
+        Box::pin(async move {
+            let mut blocked = self
+                .get::<Vec<String>>(user.as_str())
+                .await?
+                .unwrap_or_default();
+            for jid in jids {
+                let jid = jid.to_string();
+                if !blocked.contains(&jid) {
+                    blocked.push(jid);
+                }
+            }
+            self.put(user.as_str(), &blocked).await
+        })
+    }
+
+    fn unblock<'a>(
+        &'a self,
+        user: &'a BareJid,
+        jids: &'a [Jid],
+    ) -> BoxFuture<'a, Result<(), Rejection>> {
+        Box::pin(async move {
+            let mut blocked = match self.get::<Vec<String>>(user.as_str()).await? {
+                Some(blocked) if !jids.is_empty() => blocked,
+                _ => return self.delete(user.as_str()).await,
+            };
+            blocked.retain(|blocked| !jids.iter().any(|jid| jid.to_string() == *blocked));
+            if blocked.is_empty() {
+                self.delete(user.as_str()).await
+            } else {
+                self.put(user.as_str(), &blocked).await
+            }
+        })
+    }
+}
+
+enum Request {
+    Blocklist(BlocklistRequest),
+    Block(Block),
+    Unblock(Unblock),
+}
+
+/// Extract a request for the blocklist.
+///
+/// Rejects with `item-not-found` for other stanzas.
+pub fn blocklist() -> impl Filter<Extract = One<BlocklistRequest>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(match parse(stanza) {
+            Some(Ok(Request::Blocklist(request))) => Ok(request),
+            Some(Err(rejection)) => Err(rejection),
+            _ => Err(reject::item_not_found()),
+        })
+    })
+    .advertises(NS)
+}
+
+/// Extract a request to block JIDs.
+///
+/// Rejects with `item-not-found` for other stanzas, and with `bad-request`
+/// if it names no JID or an invalid one.
+pub fn block() -> impl Filter<Extract = One<Block>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(match parse(stanza) {
+            Some(Ok(Request::Block(request))) => Ok(request),
+            Some(Err(rejection)) => Err(rejection),
+            _ => Err(reject::item_not_found()),
+        })
+    })
+    .advertises(NS)
+}
+
+/// Extract a request to unblock JIDs.
+///
+/// Rejects with `item-not-found` for other stanzas, and with `bad-request`
+/// if it names an invalid JID.
+pub fn unblock() -> impl Filter<Extract = One<Unblock>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(match parse(stanza) {
+            Some(Ok(Request::Unblock(request))) => Ok(request),
+            Some(Err(rejection)) => Err(rejection),
+            _ => Err(reject::item_not_found()),
+        })
+    })
+    .advertises(NS)
+}
+
+/// A blocking service answering requests from a [`BlocklistStore`].
+///
+/// Changes are pushed, through the outbound queue of the server, to every
+/// resource of the user that asked for the blocklist since it last went
+/// offline.
+pub struct Blocking<S> {
+    store: Arc<S>,
+    interested: Arc<DashMap<BareJid, HashSet<Jid>>>,
+}
+
+impl<S> Clone for Blocking<S> {
+    fn clone(&self) -> Self {
+        Blocking {
+            store: self.store.clone(),
+            interested: self.interested.clone(),
+        }
+    }
+}
+
+impl<S: BlocklistStore> Blocking<S> {
+    /// A service backed by `store`.
+    pub fn new(store: S) -> Self {
+        Blocking {
+            store: Arc::new(store),
+            interested: Arc::new(DashMap::new()),
+        }
+    }
+
+    /// The underlying store.
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Stop pushing changes to `resource`, e.g. when it goes offline.
+    pub fn forget(&self, resource: &Jid) {
+        let user = resource.to_bare();
+        if let Some(mut resources) = self.interested.get_mut(&user) {
+            resources.remove(resource);
+        }
+        self.interested
+            .remove_if(&user, |_, resources| resources.is_empty());
+    }
+
+    /// Serve blocking requests.
+    pub fn filter(&self) -> impl Filter<Extract = One<Iq>, Error = Rejection> + Clone {
+        let blocking = self.clone();
+        filter_fn_one(|stanza: &Stanza| {
+            future::ready(match parse(stanza) {
+                Some(Ok(request)) => Ok((request, correlation::outbound())),
+                Some(Err(rejection)) => Err(rejection),
+                None => Err(reject::item_not_found()),
+            })
+        })
+        .and_then(move |(request, outbound): (Request, Option<Outbound>)| {
+            let blocking = blocking.clone();
+            async move { blocking.handle(request, outbound).await }
+        })
+        .advertises(NS)
+    }
+
+    async fn handle(&self, request: Request, outbound: Option<Outbound>) -> Result<Iq, Rejection> {
+        match request {
+            Request::Blocklist(request) => {
+                let from = request.from.clone().ok_or_else(reject::bad_request)?;
+                let jids = self.store.blocklist(&from.to_bare()).await?;
+                self.interested
+                    .entry(from.to_bare())
+                    .or_default()
+                    .insert(from);
+                Ok(request.result(jids))
+            }
+            Request::Block(request) => {
+                let from = request.from.clone().ok_or_else(reject::bad_request)?;
+                self.store.block(&from.to_bare(), &request.jids).await?;
+                self.push(&from, &request.to, "block", &request.jids, outbound);
+                Ok(request.success())
+            }
+            Request::Unblock(request) => {
+                let from = request.from.clone().ok_or_else(reject::bad_request)?;
+                self.store.unblock(&from.to_bare(), &request.jids).await?;
+                self.push(&from, &request.to, "unblock", &request.jids, outbound);
+                Ok(request.success())
+            }
+        }
+    }
+
+    fn push(
+        &self,
+        user: &Jid,
+        service: &Option<Jid>,
+        name: &str,
+        jids: &[Jid],
+        outbound: Option<Outbound>,
+    ) {
+        let Some(resources) = self.interested.get(&user.to_bare()) else {
+            return;
+        };
+        let Some(outbound) = outbound else {
+            tracing::debug!("no outbound queue, not pushing blocklist changes");
+            return;
+        };
+        for resource in resources.iter() {
+            let push = Iq::Set {
+                from: service.clone(),
+                to: Some(resource.clone()),
+                id: correlation::unique_id(),
+                payload: items(name, jids.iter().cloned()),
+            };
+            if outbound.send(Stanza::Iq(push)).is_err() {
+                tracing::warn!("dropped blocklist push to {}", resource);
+            }
+        }
+    }
+}
+
+impl<S> fmt::Debug for Blocking<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Blocking")
+            .field("interested", &self.interested.len())
+            .finish()
+    }
+}
+
+fn parse(stanza: &Stanza) -> Option<Result<Request, Rejection>> {
+    let (from, to, id, payload, set) = match stanza {
+        Stanza::Iq(Iq::Get {
+            from,
+            to,
+            id,
+            payload,
+        }) => (from, to, id, payload, false),
+        Stanza::Iq(Iq::Set {
+            from,
+            to,
+            id,
+            payload,
+        }) => (from, to, id, payload, true),
+        _ => return None,
+    };
+    if payload.ns() != NS {
+        return None;
+    }
+    let (from, to, id) = (from.clone(), to.clone(), id.clone());
+    let jids = || {
+        payload
+            .children()
+            .filter(|child| child.is("item", NS))
+            .map(|item| {
+                item.attr("jid")
+                    .and_then(|jid| jid.parse().ok())
+                    .ok_or_else(reject::bad_request)
+            })
+            .collect::<Result<Vec<Jid>, Rejection>>()
+    };
+    Some(match (set, payload.name()) {
+        (false, "blocklist") => Ok(Request::Blocklist(BlocklistRequest { from, to, id })),
+        (true, "block") => jids().and_then(|jids| {
+            if jids.is_empty() {
+                return Err(reject::bad_request());
+            }
+            Ok(Request::Block(Block { from, to, jids, id }))
+        }),
+        (true, "unblock") => jids().map(|jids| Request::Unblock(Unblock { from, to, jids, id })),
+        _ => return None,
+    })
+}
+
+fn items(name: &str, jids: impl IntoIterator<Item = Jid>) -> Element {
+    Element::builder(name, NS)
+        .append_all(jids.into_iter().map(|jid| {
+            Element::builder("item", NS)
+                .attr("jid", jid.to_string())
+                .build()
+        }))
+        .build()
+}
+
+fn success(from: Option<Jid>, to: Option<Jid>, id: String) -> Iq {
+    Iq::Result {
+        from: to,
+        to: from,
+        id,
+        payload: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{KvStore, MemoryStore};
+
+    fn jid(jid: &str) -> Jid {
+        Jid::new(jid).unwrap()
+    }
+
+    #[tokio::test]
+    async fn namespace_stores_blocklists() {
+        let store = MemoryStore::new().namespace("blocking");
+        let juliet = BareJid::new("juliet@capulet.lit").unwrap();
+        let romeo = BareJid::new("romeo@montague.lit").unwrap();
+
+        assert_eq!(store.blocklist(&juliet).await.unwrap(), vec![]);
+
+        let blocked = [jid("romeo@montague.lit"), jid("tybalt@capulet.lit/sword")];
+        store.block(&juliet, &blocked).await.unwrap();
+        store.block(&juliet, &blocked[..1]).await.unwrap();
+        assert_eq!(store.blocklist(&juliet).await.unwrap(), blocked.to_vec());
+        assert_eq!(store.blocklist(&romeo).await.unwrap(), vec![]);
+
+        store.unblock(&juliet, &blocked[..1]).await.unwrap();
+        assert_eq!(
+            store.blocklist(&juliet).await.unwrap(),
+            blocked[1..].to_vec()
+        );
+    }
+
+    #[tokio::test]
+    async fn unblocking_nothing_clears_the_blocklist() {
+        let store = MemoryStore::new().namespace("blocking");
+        let juliet = BareJid::new("juliet@capulet.lit").unwrap();
+
+        store
+            .block(&juliet, &[jid("romeo@montague.lit"), jid("capulet.lit")])
+            .await
+            .unwrap();
+        store.unblock(&juliet, &[]).await.unwrap();
+        assert_eq!(store.blocklist(&juliet).await.unwrap(), vec![]);
+        assert!(store.list().await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,360 @@
+//! IQ response caching.
+//!
+//! Wrapping a route with `wax::cache::iq(ttl)` memoizes the result of
+//! idempotent IQ gets, so expensive lookups (disco against upstream
+//! services, vCard fetches) aren't recomputed for every identical query.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use std::time::Duration;
+//! use wax::Filter;
+//!
+//! let route = wax::iq()
+//!     .get()
+//!     .map(|| expensive_lookup())
+//!     .with(wax::cache::iq(Duration::from_secs(60)));
+//! ```
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::jid::{BareJid, Jid};
+
+use crate::filter::{Filter, WrapSealed};
+use crate::reject::IsReject;
+use crate::reply::Reply;
+
+use self::internal::WithCache;
+
+/// Create a wrapping [`Filter`] that caches IQ get results for `ttl`.
+///
+/// Entries are keyed by the sender's bare JID, the recipient JID and the
+/// serialized payload. Only successful
+/// `result` replies are cached; errors and rejections always go through the
+/// wrapped filter again. A cached reply is re-addressed to the current
+/// sender and carries the id of the current request.
+pub fn iq(ttl: Duration) -> IqCache {
+    IqCache {
+        ttl,
+        entries: Arc::new(DashMap::new()),
+    }
+}
+
+/// Decorates a [`Filter`] to cache IQ results.
+///
+/// Clones share the same cache.
+#[derive(Clone, Debug)]
+pub struct IqCache {
+    ttl: Duration,
+    entries: Arc<DashMap<CacheKey, Entry>>,
+}
+
+impl IqCache {
+    /// Drop every cached entry.
+    pub fn clear(&self) {
+        self.entries.clear();
+    }
+
+    /// Drop every expired entry.
+    ///
+    /// Expired entries are otherwise only dropped when they are looked up
+    /// again.
+    pub fn purge_expired(&self) {
+        let now = Instant::now();
+        self.entries.retain(|_, entry| entry.expires > now);
+    }
+}
+
+impl<F> WrapSealed<F> for IqCache
+where
+    F: Filter + Clone + Send,
+    F::Extract: Reply,
+    F::Error: IsReject,
+{
+    type Wrapped = WithCache<F>;
+
+    fn wrap(&self, filter: F) -> Self::Wrapped {
+        WithCache {
+            filter,
+            cache: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct CacheKey {
+    from: Option<BareJid>,
+    to: Option<Jid>,
+    // The whole payload rather than a hash of it, so that colliding
+    // requests are never served each other's replies.
+    payload: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    reply: Stanza,
+    expires: Instant,
+}
+
+/// The parts of an IQ get needed to look it up and re-address a hit.
+struct Request {
+    key: CacheKey,
+    id: String,
+    from: Option<Jid>,
+    to: Option<Jid>,
+}
+
+fn request(stanza: &Stanza) -> Option<Request> {
+    let Stanza::Iq(Iq::Get {
+        from,
+        to,
+        id,
+        payload,
+    }) = stanza
+    else {
+        return None;
+    };
+
+    let mut serialized = Vec::new();
+    if let Err(err) = payload.write_to(&mut serialized) {
+        tracing::debug!("not caching unserializable payload: {:?}", err);
+        return None;
+    }
+
+    Some(Request {
+        key: CacheKey {
+            from: from.as_ref().map(Jid::to_bare),
+            to: to.clone(),
+            payload: serialized,
+        },
+        id: id.clone(),
+        from: from.clone(),
+        to: to.clone(),
+    })
+}
+
+fn readdress(reply: &Stanza, request: &Request) -> Stanza {
+    match reply {
+        Stanza::Iq(Iq::Result { payload, .. }) => Stanza::Iq(Iq::Result {
+            from: request.to.clone(),
+            to: request.from.clone(),
+            id: request.id.clone(),
+            payload: payload.clone(),
+        }),
+        other => other.clone(),
+    }
+}
+
+mod internal {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use std::time::Instant;
+
+    use futures_util::{ready, TryFuture};
+    use pin_project::pin_project;
+    use tokio_xmpp::Stanza;
+    use xmpp_parsers::iq::Iq;
+
+    use super::{readdress, request, Entry, IqCache, Request};
+    use crate::disco::Features;
+    use crate::filter::{Filter, FilterBase, Internal};
+    use crate::filtered_stanza;
+    use crate::reject::IsReject;
+    use crate::reply::{Reply, ReplySealed, Response};
+
+    #[allow(missing_debug_implementations)]
+    pub struct Cached(Response);
+
+    impl Reply for Cached {
+        #[inline]
+        fn into_response(self) -> Response {
+            self.0
+        }
+    }
+
+    impl ReplySealed for Cached {}
+
+    #[allow(missing_debug_implementations)]
+    #[derive(Clone)]
+    pub struct WithCache<F> {
+        pub(super) filter: F,
+        pub(super) cache: IqCache,
+    }
+
+    impl<F> FilterBase for WithCache<F>
+    where
+        F: Filter + Clone + Send,
+        F::Extract: Reply,
+        F::Error: IsReject,
+    {
+        type Extract = (Cached,);
+        type Error = F::Error;
+        type Future = WithCacheFuture<F::Future>;
+
+        fn filter(&self, _: Internal) -> Self::Future {
+            let request = filtered_stanza::with(|stanza| request(stanza));
+
+            if let Some(ref request) = request {
+                let now = Instant::now();
+                let entries = &self.cache.entries;
+                entries.remove_if(&request.key, |_, entry| entry.expires <= now);
+                if let Some(entry) = entries.get(&request.key) {
+                    return WithCacheFuture {
+                        future: None,
+                        hit: Some(readdress(&entry.reply, request)),
+                        request: None,
+                        cache: self.cache.clone(),
+                    };
+                }
+            }
+
+            WithCacheFuture {
+                future: Some(self.filter.filter(Internal)),
+                hit: None,
+                request,
+                cache: self.cache.clone(),
+            }
+        }
+
+        fn advertise(&self, features: &mut Features) {
+            self.filter.advertise(features);
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    #[pin_project]
+    pub struct WithCacheFuture<F> {
+        #[pin]
+        future: Option<F>,
+        hit: Option<Stanza>,
+        request: Option<Request>,
+        cache: IqCache,
+    }
+
+    impl<F> Future for WithCacheFuture<F>
+    where
+        F: TryFuture,
+        F::Ok: Reply,
+        F::Error: IsReject,
+    {
+        type Output = Result<(Cached,), F::Error>;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            let pin = self.project();
+            let future = match pin.future.as_pin_mut() {
+                Some(future) => future,
+                None => return Poll::Ready(Ok((Cached(pin.hit.take().into()),))),
+            };
+
+            let resp = match ready!(future.try_poll(cx)) {
+                Ok(reply) => reply.into_response(),
+                Err(reject) => return Poll::Ready(Err(reject)),
+            };
+            if let (Some(request), [reply @ Stanza::Iq(Iq::Result { .. })], false) =
+                (pin.request.take(), resp.stanzas(), resp.is_streamed())
+            {
+                pin.cache.entries.insert(
+                    request.key,
+                    Entry {
+                        reply: reply.clone(),
+                        expires: Instant::now() + pin.cache.ttl,
+                    },
+                );
+            }
+            Poll::Ready(Ok((Cached(resp),)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use xmpp_parsers::minidom::Element;
+
+    use super::*;
+
+    fn get(id: &str, from: &str, node: &str) -> Stanza {
+        Stanza::Iq(Iq::Get {
+            from: Some(from.parse().unwrap()),
+            to: Some("cache.example.org".parse().unwrap()),
+            id: id.to_owned(),
+            payload: Element::builder("query", "urn:example:cache")
+                .attr("node", node)
+                .build(),
+        })
+    }
+
+    // A route counting its calls, wrapped in a cache with `ttl`.
+    fn counted(
+        ttl: Duration,
+    ) -> (
+        impl Filter<Extract = (internal::Cached,), Error = Infallible> + Clone + Send + Sync,
+        Arc<AtomicUsize>,
+    ) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let route = crate::any()
+            .map(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                crate::reply::iq_empty_result()
+            })
+            .with(iq(ttl));
+        (route, calls)
+    }
+
+    async fn call<F>(route: &F, stanza: Stanza) -> Vec<Stanza>
+    where
+        F: Filter + Clone + Send + Sync + 'static,
+        <F::Future as futures_util::TryFuture>::Ok: Reply,
+        <F::Future as futures_util::TryFuture>::Error: IsReject,
+    {
+        let response = crate::service(route.clone())
+            .call_stanza(stanza)
+            .await
+            .unwrap();
+        response.stanzas().to_vec()
+    }
+
+    #[tokio::test]
+    async fn hits_are_readdressed() {
+        let (route, calls) = counted(Duration::from_secs(60));
+
+        call(&route, get("1", "juliet@capulet.lit/balcony", "a")).await;
+        let hit = call(&route, get("2", "juliet@capulet.lit/chamber", "a")).await;
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        let [Stanza::Iq(Iq::Result { id, to, .. })] = &hit[..] else {
+            panic!("expected a result, got {:?}", hit);
+        };
+        assert_eq!(id, "2");
+        assert_eq!(to, &Some("juliet@capulet.lit/chamber".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn misses_on_other_requests() {
+        let (route, calls) = counted(Duration::from_secs(60));
+
+        call(&route, get("1", "juliet@capulet.lit/balcony", "a")).await;
+        call(&route, get("2", "juliet@capulet.lit/balcony", "b")).await;
+        call(&route, get("3", "romeo@montague.lit/orchard", "a")).await;
+
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn expired_entries_are_recomputed() {
+        let (route, calls) = counted(Duration::ZERO);
+
+        call(&route, get("1", "juliet@capulet.lit/balcony", "a")).await;
+        call(&route, get("2", "juliet@capulet.lit/balcony", "a")).await;
+
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+}
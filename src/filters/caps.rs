@@ -0,0 +1,223 @@
+//! Entity Capabilities (XEP-0115).
+//!
+//! [`Caps`] computes the verification string of the component's identity and
+//! features, the same ones served by `disco#info`, and caches it until the
+//! features change.
+//!
+//! - `.with(caps.clone())` - Attach `<c/>` to presence replies
+//! - `caps.info()` - Answer `disco#info` queries about the `node#ver` node
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::caps::Caps;
+//! use wax::disco::Identity;
+//! use wax::Filter;
+//!
+//! let identity = Identity::new("gateway", "sms");
+//! let caps = Caps::of("https://example.com/sms-gateway", identity, &routes);
+//!
+//! let routes = caps.info().or(routes).with(caps.clone());
+//! ```
+
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use base64::Engine;
+use futures_util::future;
+use sha1::{Digest, Sha1};
+use tokio_xmpp::Stanza;
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::jid::Jid;
+use xmpp_parsers::minidom::Element;
+
+use crate::disco::{self, Features, Identity, Info};
+use crate::filter::{filter_fn_one, Filter, WrapSealed};
+use crate::generic::One;
+use crate::reject::{self, IsReject, Rejection};
+use crate::reply::internal::{Transform, WithTransform};
+use crate::reply::Reply;
+
+/// The `http://jabber.org/protocol/caps` namespace.
+pub const NS: &str = "http://jabber.org/protocol/caps";
+
+/// The capabilities of the component.
+///
+/// Clones share the same features and cached verification string.
+#[derive(Clone)]
+pub struct Caps {
+    node: Arc<str>,
+    state: Arc<RwLock<State>>,
+}
+
+struct State {
+    identity: Identity,
+    features: Features,
+    ver: Option<String>,
+}
+
+impl Caps {
+    /// Capabilities with `identity` and `features`, published under
+    /// `node`, usually a URL identifying the software.
+    pub fn new(node: impl Into<String>, identity: Identity, mut features: Features) -> Self {
+        features.insert(disco::NS_INFO);
+        features.insert(NS);
+        Caps {
+            node: node.into().into(),
+            state: Arc::new(RwLock::new(State {
+                identity,
+                features,
+                ver: None,
+            })),
+        }
+    }
+
+    /// Capabilities with `identity` and the features advertised by
+    /// `filter`.
+    pub fn of<F: Filter>(node: impl Into<String>, identity: Identity, filter: &F) -> Self {
+        Caps::new(node, identity, disco::features(filter))
+    }
+
+    /// Replace the features, e.g. after enabling a protocol at runtime.
+    pub fn set_features(&self, mut features: Features) {
+        features.insert(disco::NS_INFO);
+        features.insert(NS);
+        let mut state = self.state.write().expect("caps lock poisoned");
+        if state.features != features {
+            state.features = features;
+            state.ver = None;
+        }
+    }
+
+    /// The verification string, computed with SHA-1.
+    pub fn ver(&self) -> String {
+        if let Some(ref ver) = self.state.read().expect("caps lock poisoned").ver {
+            return ver.clone();
+        }
+        let mut state = self.state.write().expect("caps lock poisoned");
+        let ver = verification(&state.identity, &state.features);
+        state.ver = Some(ver.clone());
+        ver
+    }
+
+    /// The `<c/>` element to attach to presence.
+    pub fn element(&self) -> Element {
+        Element::builder("c", NS)
+            .attr("hash", "sha-1")
+            .attr("node", &*self.node)
+            .attr("ver", self.ver())
+            .build()
+    }
+
+    /// Answer `disco#info` queries about `node#ver`, the node other
+    /// entities use to check the verification string.
+    ///
+    /// Rejects with `item-not-found` for other stanzas.
+    pub fn info(&self) -> impl Filter<Extract = One<Iq>, Error = Rejection> + Clone {
+        let caps = self.clone();
+        filter_fn_one(|stanza: &Stanza| match disco::node_info_query(stanza) {
+            Some(((from, to, id), Some(node))) => {
+                future::ok((from.clone(), to.clone(), id.clone(), node.to_owned()))
+            }
+            _ => future::err(reject::item_not_found()),
+        })
+        .and_then(
+            move |(from, to, id, node): (Option<Jid>, Option<Jid>, String, String)| {
+                let answer = if node == format!("{}#{}", caps.node, caps.ver()) {
+                    let state = caps.state.read().expect("caps lock poisoned");
+                    let info = Info::new(state.identity.clone(), state.features.clone());
+                    Ok(info.result(from, to, id, Some(node)))
+                } else {
+                    Err(reject::item_not_found())
+                };
+                future::ready(answer)
+            },
+        )
+        .advertises(NS)
+    }
+}
+
+impl fmt::Debug for Caps {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Caps").field("node", &self.node).finish()
+    }
+}
+
+impl<F> WrapSealed<F> for Caps
+where
+    F: Filter + Clone + Send,
+    F::Extract: Reply,
+    F::Error: IsReject,
+{
+    type Wrapped = WithTransform<Caps, F>;
+
+    fn wrap(&self, filter: F) -> Self::Wrapped {
+        WithTransform::new(self.clone(), filter)
+    }
+}
+
+impl Transform for Caps {
+    fn apply(&self, mut stanza: Stanza) -> Stanza {
+        if let Stanza::Presence(ref mut pres) = stanza {
+            pres.payloads.retain(|payload| !payload.is("c", NS));
+            pres.payloads.push(self.element());
+        }
+        stanza
+    }
+}
+
+// The verification string of XEP-0115 §5.1, for a single identity without
+// language, followed by its extended forms sorted by `FORM_TYPE`.
+fn verification(identity: &Identity, features: &Features) -> String {
+    let mut input = format!(
+        "{}/{}//{}<",
+        identity.category,
+        identity.type_,
+        identity.name.as_deref().unwrap_or_default()
+    );
+    for var in features.iter() {
+        input.push_str(var);
+        input.push('<');
+    }
+    let mut forms: Vec<_> = identity.forms.iter().collect();
+    forms.sort_by_key(|form| form.get_form_type());
+    for form in forms {
+        input.push_str(form.get_form_type().unwrap_or_default());
+        input.push('<');
+        let mut fields: Vec<_> = form
+            .fields
+            .iter()
+            .filter(|field| field.var != "FORM_TYPE")
+            .collect();
+        fields.sort_by(|a, b| a.var.cmp(&b.var));
+        for field in fields {
+            input.push_str(&field.var);
+            input.push('<');
+            let mut values: Vec<_> = field.values.iter().collect();
+            values.sort();
+            for value in values {
+                input.push_str(value);
+                input.push('<');
+            }
+        }
+    }
+    base64::engine::general_purpose::STANDARD.encode(Sha1::digest(input.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verification_string() {
+        // XEP-0115 §5.2, with the identity of the example.
+        let identity = Identity::new("client", "pc").name("Exodus 0.9.1");
+        let mut features = Features::new();
+        features.extend([
+            "http://jabber.org/protocol/disco#items",
+            "http://jabber.org/protocol/muc",
+        ]);
+        let caps = Caps::new("http://code.google.com/p/exodus", identity, features);
+        assert_eq!(caps.ver(), "QgayPKawpkPSDYmwT/WM94uAlu0=");
+    }
+}
@@ -0,0 +1,140 @@
+//! Message Carbons (XEP-0280).
+//!
+//! - `wax::carbons::param()` - Extract a [`Carbon`], whichever its direction
+//! - `wax::carbons::sent()` - Extract the message of a carbon of a sent message
+//! - `wax::carbons::received()` - Extract the message of a carbon of a received message
+//!
+//! Carbons are only trustworthy when they come from the bare JID of the
+//! account they were copied to; check [`Carbon::from`] before acting on
+//! them.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::carbons::{self, Carbon, Direction};
+//! use wax::Filter;
+//!
+//! let mirror = carbons::param().map(|carbon: Carbon| {
+//!     match carbon.direction {
+//!         Direction::Sent => { /* the user wrote carbon.message from another device... */ }
+//!         Direction::Received => { /* another device received carbon.message... */ }
+//!     }
+//!     wax::sink()
+//! });
+//! ```
+
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::jid::Jid;
+use xmpp_parsers::message::Message;
+
+use crate::filter::{filter_fn_one, Filter};
+use crate::forwarded;
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+
+/// The `urn:xmpp:carbons:2` namespace.
+pub const NS: &str = "urn:xmpp:carbons:2";
+
+/// Which way the copied message went.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// Sent by another resource of the account.
+    Sent,
+    /// Received by another resource of the account.
+    Received,
+}
+
+impl Direction {
+    fn as_str(self) -> &'static str {
+        match self {
+            Direction::Sent => "sent",
+            Direction::Received => "received",
+        }
+    }
+}
+
+/// A carbon copy of a message.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Carbon {
+    /// Which way the message went.
+    pub direction: Direction,
+    /// The account that the copy was made for.
+    pub from: Option<Jid>,
+    /// The copied message.
+    pub message: Message,
+}
+
+/// Extract a carbon copy.
+///
+/// Rejects with `item-not-found` for other stanzas, and with `bad-request`
+/// if the copied message is missing or malformed.
+pub fn param() -> impl Filter<Extract = One<Carbon>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(match carbon_of(stanza) {
+            Some(carbon) => carbon,
+            None => Err(reject::item_not_found()),
+        })
+    })
+    .advertises(NS)
+}
+
+/// Extract the message of a carbon of a message sent by another resource.
+///
+/// Rejects with `item-not-found` for other stanzas, and with `bad-request`
+/// if the copied message is missing or malformed.
+pub fn sent() -> impl Filter<Extract = One<Message>, Error = Rejection> + Copy {
+    direction(Direction::Sent)
+}
+
+/// Extract the message of a carbon of a message received by another
+/// resource.
+///
+/// Rejects with `item-not-found` for other stanzas, and with `bad-request`
+/// if the copied message is missing or malformed.
+pub fn received() -> impl Filter<Extract = One<Message>, Error = Rejection> + Copy {
+    direction(Direction::Received)
+}
+
+fn direction(
+    direction: Direction,
+) -> impl Filter<Extract = One<Message>, Error = Rejection> + Copy {
+    filter_fn_one(move |stanza: &Stanza| {
+        future::ready(match carbon_of(stanza) {
+            Some(Ok(carbon)) if carbon.direction == direction => Ok(carbon.message),
+            Some(Err(rejection)) => Err(rejection),
+            _ => Err(reject::item_not_found()),
+        })
+    })
+    .advertises(NS)
+}
+
+fn carbon_of(stanza: &Stanza) -> Option<Result<Carbon, Rejection>> {
+    let Stanza::Message(msg) = stanza else {
+        return None;
+    };
+    let (direction, wrapper) = [Direction::Sent, Direction::Received]
+        .into_iter()
+        .find_map(|direction| {
+            msg.payloads
+                .iter()
+                .find(|payload| payload.is(direction.as_str(), NS))
+                .map(|wrapper| (direction, wrapper))
+        })?;
+    let message = wrapper
+        .get_child("forwarded", forwarded::NS)
+        .map(forwarded::parse)
+        .and_then(|forwarded| forwarded.ok()?.message());
+    Some(
+        message
+            .map(|message| Carbon {
+                direction,
+                from: msg.from.clone(),
+                message,
+            })
+            .ok_or_else(|| {
+                tracing::debug!("invalid {} carbon", direction.as_str());
+                reject::bad_request()
+            }),
+    )
+}
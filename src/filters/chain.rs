@@ -0,0 +1,192 @@
+//! Flat route chains.
+//!
+//! Combining routes with [`Filter::or`] nests their types: the 30th route of
+//! a component is 29 `Or`s deep, and so is its future, which hurts compile
+//! times, stack usage, and poll depth. [`chain!`](crate::chain) builds a
+//! single [`Chain`] instead, which tries its routes in order from one flat
+//! future, the same way `or` would.
+//!
+//! ```
+//! use wax::Filter;
+//!
+//! let routes = wax::chain![
+//!     wax::id("ping").map(wax::sink),
+//!     wax::message::body::param().map(|_body: String| wax::sink()),
+//!     wax::any().map(wax::sink),
+//! ];
+//! # drop(routes);
+//! ```
+//!
+//! Each route is boxed, so a chain costs one allocation per route tried;
+//! it pays off for long chains rather than a handful of routes.
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures_util::{ready, TryFuture, TryFutureExt};
+
+use crate::disco::Features;
+use crate::filter::{BoxedFilter, Filter, FilterBase, Internal};
+use crate::reject::{CombineRejection, Rejection};
+use crate::reply::{Reply, Response};
+
+/// Try each route in order, replying with the first that matches.
+///
+/// Routes may extract any [`Reply`]; they are converted to the stanzas they
+/// send as soon as they match.
+///
+/// ```
+/// use wax::Filter;
+///
+/// let routes = wax::chain![
+///     wax::id("a").map(wax::sink),
+///     wax::id("b").map(wax::sink),
+/// ];
+/// # drop(routes);
+/// ```
+#[macro_export]
+macro_rules! chain {
+    ($($route:expr),+ $(,)?) => {
+        $crate::filters::chain::Chain::new(::std::vec![
+            $($crate::filters::chain::route($route)),+
+        ])
+    };
+}
+
+/// A flat chain of routes, built by [`chain!`](crate::chain).
+pub struct Chain {
+    routes: Arc<[Route]>,
+}
+
+type Route = BoxedFilter<(Response,)>;
+
+impl Chain {
+    #[doc(hidden)]
+    pub fn new(routes: Vec<Route>) -> Self {
+        assert!(!routes.is_empty(), "a chain needs at least one route");
+        Chain {
+            routes: routes.into(),
+        }
+    }
+}
+
+/// Box a route for a [`Chain`].
+#[doc(hidden)]
+pub fn route<F>(filter: F) -> Route
+where
+    F: Filter + Send + Sync + 'static,
+    F::Extract: Reply,
+    F::Error: Into<Rejection>,
+{
+    Respond { filter }.boxed()
+}
+
+impl Clone for Chain {
+    fn clone(&self) -> Self {
+        Chain {
+            routes: self.routes.clone(),
+        }
+    }
+}
+
+impl fmt::Debug for Chain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Chain")
+            .field("routes", &self.routes.len())
+            .finish()
+    }
+}
+
+impl FilterBase for Chain {
+    type Extract = (Response,);
+    type Error = Rejection;
+    type Future = ChainFuture;
+
+    fn filter(&self, _: Internal) -> Self::Future {
+        ChainFuture {
+            current: self.routes.first().map(|route| route.filter(Internal)),
+            next: 1,
+            routes: self.routes.clone(),
+            rejection: None,
+        }
+    }
+
+    fn advertise(&self, features: &mut Features) {
+        for route in self.routes.iter() {
+            route.advertise(features);
+        }
+    }
+}
+
+#[allow(missing_debug_implementations)]
+pub struct ChainFuture {
+    routes: Arc<[Route]>,
+    next: usize,
+    current: Option<<Route as FilterBase>::Future>,
+    rejection: Option<Rejection>,
+}
+
+impl Future for ChainFuture {
+    type Output = Result<(Response,), Rejection>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        loop {
+            let current = this.current.as_mut().expect("polled after complete");
+            let err = match ready!(current.as_mut().try_poll(cx)) {
+                Ok(reply) => {
+                    this.current = None;
+                    return Poll::Ready(Ok(reply));
+                }
+                Err(err) => err,
+            };
+            let err = match this.rejection.take() {
+                Some(previous) => err.combine(previous),
+                None => err,
+            };
+            match this.routes.get(this.next) {
+                Some(route) => {
+                    this.current = Some(route.filter(Internal));
+                    this.next += 1;
+                    this.rejection = Some(err);
+                }
+                None => {
+                    this.current = None;
+                    return Poll::Ready(Err(err));
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Respond<F> {
+    filter: F,
+}
+
+impl<F> FilterBase for Respond<F>
+where
+    F: Filter,
+    F::Extract: Reply,
+{
+    type Extract = (Response,);
+    type Error = F::Error;
+    type Future = futures_util::future::MapOk<F::Future, fn(F::Extract) -> (Response,)>;
+
+    fn filter(&self, _: Internal) -> Self::Future {
+        self.filter
+            .filter(Internal)
+            .map_ok(respond::<F::Extract> as fn(_) -> _)
+    }
+
+    fn advertise(&self, features: &mut Features) {
+        self.filter.advertise(features);
+    }
+}
+
+fn respond<T: Reply>(reply: T) -> (Response,) {
+    (reply.into_response(),)
+}
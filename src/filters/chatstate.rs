@@ -0,0 +1,142 @@
+//! Chat State Notifications (XEP-0085).
+//!
+//! - `wax::chatstate::param()` - Extract the [`ChatState`] of a message
+//! - `wax::chatstate::optional()` - Same, but yields `None` for messages without one
+//! - `wax::chatstate::composing()` and friends - Match messages in one state
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::Filter;
+//!
+//! let typing = wax::chatstate::composing()
+//!     .and(wax::require_from())
+//!     .map(|user: Jid| {
+//!         // show that user is typing...
+//!         wax::sink()
+//!     });
+//! ```
+
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::minidom::Element;
+
+use crate::filter::{filter_fn, filter_fn_one, Filter};
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+
+/// The `http://jabber.org/protocol/chatstates` namespace.
+pub const NS: &str = "http://jabber.org/protocol/chatstates";
+
+/// The chat state of a conversation partner.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ChatState {
+    /// Actively participating.
+    Active,
+    /// Typing a message.
+    Composing,
+    /// Stopped typing.
+    Paused,
+    /// Not paying attention.
+    Inactive,
+    /// Left the conversation.
+    Gone,
+}
+
+impl ChatState {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "active" => Some(ChatState::Active),
+            "composing" => Some(ChatState::Composing),
+            "paused" => Some(ChatState::Paused),
+            "inactive" => Some(ChatState::Inactive),
+            "gone" => Some(ChatState::Gone),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            ChatState::Active => "active",
+            ChatState::Composing => "composing",
+            ChatState::Paused => "paused",
+            ChatState::Inactive => "inactive",
+            ChatState::Gone => "gone",
+        }
+    }
+}
+
+impl From<ChatState> for Element {
+    fn from(state: ChatState) -> Element {
+        Element::builder(state.as_str(), NS).build()
+    }
+}
+
+/// Extract the chat state of a message.
+///
+/// Rejects with `item-not-found` if the stanza is not a message with a chat
+/// state.
+pub fn param() -> impl Filter<Extract = One<ChatState>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(state_of(stanza).ok_or_else(reject::item_not_found))
+    })
+    .advertises(NS)
+}
+
+/// Extract the chat state of a message, if it has one.
+///
+/// Rejects with `item-not-found` if the stanza is not a message.
+pub fn optional() -> impl Filter<Extract = One<Option<ChatState>>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| match stanza {
+        Stanza::Message(_) => future::ok(state_of(stanza)),
+        _ => future::err(reject::item_not_found()),
+    })
+    .advertises(NS)
+}
+
+macro_rules! state_filter {
+    ($(#[$doc:meta])* $name:ident => $state:ident) => {
+        $(#[$doc])*
+        ///
+        /// Rejects with `item-not-found` otherwise.
+        pub fn $name() -> impl Filter<Extract = (), Error = Rejection> + Copy {
+            filter_fn(|stanza: &Stanza| match state_of(stanza) {
+                Some(ChatState::$state) => future::ok(()),
+                _ => future::err(reject::item_not_found()),
+            })
+            .advertises(NS)
+        }
+    };
+}
+
+state_filter!(
+    /// Match messages saying the sender is active.
+    active => Active
+);
+state_filter!(
+    /// Match messages saying the sender is typing.
+    composing => Composing
+);
+state_filter!(
+    /// Match messages saying the sender stopped typing.
+    paused => Paused
+);
+state_filter!(
+    /// Match messages saying the sender is not paying attention.
+    inactive => Inactive
+);
+state_filter!(
+    /// Match messages saying the sender left the conversation.
+    gone => Gone
+);
+
+fn state_of(stanza: &Stanza) -> Option<ChatState> {
+    match stanza {
+        Stanza::Message(msg) => msg
+            .payloads
+            .iter()
+            .filter(|payload| payload.ns() == NS)
+            .find_map(|payload| ChatState::from_name(payload.name())),
+        _ => None,
+    }
+}
@@ -0,0 +1,137 @@
+//! Chat commands.
+//!
+//! - `wax::command("!weather")` - Match messages running a command, extracting its arguments
+//! - `wax::command_as::<T>("!weather")` - Same, parsing the arguments as `T`
+//!
+//! A command is a message whose body starts with a prefix, followed by
+//! arguments separated by whitespace. The prefix must be a whole word:
+//! `!weather` does not match `!weatherman`.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::Filter;
+//!
+//! let weather = wax::command_as::<(String,)>("!weather")
+//!     .map(|(city,): (String,)| wax::reply::message().body(forecast(&city)));
+//! let roll = wax::command_as::<(u32, u32)>("!roll")
+//!     .map(|(count, sides): (u32, u32)| wax::reply::message().body(roll(count, sides)));
+//!
+//! let bot = weather.or(roll);
+//! ```
+
+use std::str::FromStr;
+
+use futures_util::future;
+use tokio_xmpp::Stanza;
+
+use crate::filter::{filter_fn_one, Filter};
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+
+/// Arguments of a command, parsed from the words following its prefix.
+///
+/// Implemented for:
+///
+/// - `String`, the rest of the body as is
+/// - `Vec<T>`, every word parsed as `T`
+/// - tuples of up to four [`FromStr`] types, one word each, with exactly as
+///   many words as elements
+pub trait FromArgs: Sized {
+    /// Parse `rest`, the body after the prefix and whitespace.
+    fn from_args(rest: &str) -> Option<Self>;
+}
+
+impl FromArgs for String {
+    fn from_args(rest: &str) -> Option<Self> {
+        Some(rest.to_owned())
+    }
+}
+
+impl<T: FromStr> FromArgs for Vec<T> {
+    fn from_args(rest: &str) -> Option<Self> {
+        rest.split_whitespace()
+            .map(|arg| arg.parse().ok())
+            .collect()
+    }
+}
+
+macro_rules! tuple_args {
+    ($($ty:ident),+) => {
+        impl<$($ty: FromStr),+> FromArgs for ($($ty,)+) {
+            fn from_args(rest: &str) -> Option<Self> {
+                let mut args = rest.split_whitespace();
+                let parsed = ($(args.next()?.parse::<$ty>().ok()?,)+);
+                match args.next() {
+                    Some(_) => None,
+                    None => Some(parsed),
+                }
+            }
+        }
+    };
+}
+
+tuple_args!(A);
+tuple_args!(A, B);
+tuple_args!(A, B, C);
+tuple_args!(A, B, C, D);
+
+/// Match messages running the command `prefix`, extracting its arguments
+/// split on whitespace.
+///
+/// Rejects with `item-not-found` for other stanzas.
+pub fn command(
+    prefix: &'static str,
+) -> impl Filter<Extract = One<Vec<String>>, Error = Rejection> + Copy {
+    command_as(prefix)
+}
+
+/// Match messages running the command `prefix`, extracting its arguments
+/// as `T`.
+///
+/// Rejects with `item-not-found` for other stanzas, and with `bad-request`
+/// if the arguments do not parse.
+pub fn command_as<T>(
+    prefix: &'static str,
+) -> impl Filter<Extract = One<T>, Error = Rejection> + Copy
+where
+    T: FromArgs + Send + 'static,
+{
+    filter_fn_one(move |stanza: &Stanza| {
+        future::ready(match rest_of(stanza, prefix) {
+            Some(rest) => T::from_args(&rest).ok_or_else(reject::bad_request),
+            None => Err(reject::item_not_found()),
+        })
+    })
+}
+
+// The body after `prefix`, if the message runs that command.
+fn rest_of(stanza: &Stanza, prefix: &str) -> Option<String> {
+    let Stanza::Message(msg) = stanza else {
+        return None;
+    };
+    let (_, body) = msg.get_best_body_cloned(vec![])?;
+    let rest = body.trim().strip_prefix(prefix)?;
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    Some(rest.trim_start().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_arguments() {
+        assert_eq!(<(u32, u32)>::from_args("3 6"), Some((3, 6)));
+        assert_eq!(<(u32, u32)>::from_args("3"), None);
+        assert_eq!(<(u32,)>::from_args("3 6"), None);
+        assert_eq!(<(u32,)>::from_args("three"), None);
+        assert_eq!(Vec::<String>::from_args(""), Some(vec![]));
+        assert_eq!(
+            String::from_args("Verona  west"),
+            Some("Verona  west".into())
+        );
+    }
+}
@@ -0,0 +1,93 @@
+//! Last Message Correction (XEP-0308).
+//!
+//! - `wax::correction::param()` - Extract a [`Correction`] of an earlier message
+//! - `wax::correction::none()` - Match messages that are not corrections
+//!
+//! Put the correction route before the one handling new messages, or guard
+//! the latter with `none()`, so that edits are not taken for new messages.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::correction::Correction;
+//! use wax::Filter;
+//!
+//! let edit = wax::correction::param().map(|correction: Correction| {
+//!     // replace message correction.id with correction.body...
+//!     wax::sink()
+//! });
+//!
+//! let routes = edit.or(new_message);
+//! ```
+
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::jid::Jid;
+use xmpp_parsers::message::Message;
+use xmpp_parsers::minidom::Element;
+
+use crate::filter::{filter_fn, filter_fn_one, Filter};
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+
+/// The `urn:xmpp:message-correct:0` namespace.
+pub const NS: &str = "urn:xmpp:message-correct:0";
+
+/// A correction of an earlier message.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Correction {
+    /// The sender of both messages.
+    pub from: Option<Jid>,
+    /// The id of the corrected message.
+    pub id: String,
+    /// The corrected body, empty if the message was retracted this way.
+    pub body: String,
+}
+
+/// Mark `msg` as a correction of the message with `id`.
+pub fn replace(mut msg: Message, id: impl Into<String>) -> Message {
+    msg.payloads.push(
+        Element::builder("replace", NS)
+            .attr("id", id.into())
+            .build(),
+    );
+    msg
+}
+
+/// Extract the correction of an earlier message.
+///
+/// Rejects with `item-not-found` for other stanzas.
+pub fn param() -> impl Filter<Extract = One<Correction>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        let correction = match stanza {
+            Stanza::Message(msg) => target_of(msg).map(|id| Correction {
+                from: msg.from.clone(),
+                id: id.to_owned(),
+                body: msg
+                    .get_best_body_cloned(vec![])
+                    .map(|(_lang, body)| body)
+                    .unwrap_or_default(),
+            }),
+            _ => None,
+        };
+        future::ready(correction.ok_or_else(reject::item_not_found))
+    })
+    .advertises(NS)
+}
+
+/// Match messages that do not correct an earlier one.
+///
+/// Rejects with `item-not-found` for corrections and other stanzas.
+pub fn none() -> impl Filter<Extract = (), Error = Rejection> + Copy {
+    filter_fn(|stanza: &Stanza| match stanza {
+        Stanza::Message(msg) if target_of(msg).is_none() => future::ok(()),
+        _ => future::err(reject::item_not_found()),
+    })
+}
+
+fn target_of(msg: &Message) -> Option<&str> {
+    msg.payloads
+        .iter()
+        .find(|payload| payload.is("replace", NS))
+        .and_then(|replace| replace.attr("id"))
+}
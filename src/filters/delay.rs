@@ -0,0 +1,128 @@
+//! Delayed Delivery (XEP-0203).
+//!
+//! - `wax::delay::param()` - Extract the [`Delay`] of a stanza
+//! - `wax::delay::optional()` - Same, but yields `None` for stanzas delivered on time
+//!
+//! Stamp replies of your own with `wax::reply::with::delay(stamp)`, e.g.
+//! when replaying MUC history or archived messages.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::delay::Delay;
+//! use wax::Filter;
+//!
+//! let offline = wax::message()
+//!     .and(wax::delay::optional())
+//!     .map(|delay: Option<Delay>| {
+//!         if let Some(delay) = delay {
+//!             // sent while we were away, at delay.stamp...
+//!         }
+//!         wax::sink()
+//!     });
+//! ```
+
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::date::DateTime;
+use xmpp_parsers::jid::Jid;
+use xmpp_parsers::minidom::Element;
+
+use crate::filter::{filter_fn_one, Filter};
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+
+/// The `urn:xmpp:delay` namespace.
+pub const NS: &str = "urn:xmpp:delay";
+
+/// When, and by whom, delivery of a stanza was delayed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Delay {
+    /// When the stanza was originally sent.
+    pub stamp: DateTime,
+    /// The entity that delayed the stanza, if known.
+    pub from: Option<Jid>,
+    /// Why the stanza was delayed, if given.
+    pub reason: Option<String>,
+}
+
+impl Delay {
+    /// A delay since `stamp`.
+    pub fn new(stamp: DateTime) -> Self {
+        Delay {
+            stamp,
+            from: None,
+            reason: None,
+        }
+    }
+
+    /// Set the entity that delayed the stanza.
+    pub fn from(mut self, from: Jid) -> Self {
+        self.from = Some(from);
+        self
+    }
+
+    /// Set the reason.
+    pub fn reason(mut self, reason: impl Into<String>) -> Self {
+        self.reason = Some(reason.into());
+        self
+    }
+}
+
+impl From<Delay> for Element {
+    fn from(delay: Delay) -> Element {
+        let builder = Element::builder("delay", NS)
+            .attr("stamp", delay.stamp)
+            .attr("from", delay.from.map(|from| from.to_string()));
+        match delay.reason {
+            Some(reason) => builder.append(reason).build(),
+            None => builder.build(),
+        }
+    }
+}
+
+/// Extract the delay of a message or presence.
+///
+/// Rejects with `item-not-found` if there is none, and with `bad-request` if
+/// it is malformed.
+pub fn param() -> impl Filter<Extract = One<Delay>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(match delay_of(stanza) {
+            Some(delay) => delay,
+            None => Err(reject::item_not_found()),
+        })
+    })
+}
+
+/// Extract the delay of a message or presence, if any.
+///
+/// Rejects with `bad-request` if it is malformed.
+pub fn optional() -> impl Filter<Extract = One<Option<Delay>>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| future::ready(delay_of(stanza).transpose()))
+}
+
+fn delay_of(stanza: &Stanza) -> Option<Result<Delay, Rejection>> {
+    let payloads = match stanza {
+        Stanza::Message(msg) => &msg.payloads,
+        Stanza::Presence(pres) => &pres.payloads,
+        Stanza::Iq(_) => return None,
+    };
+    let elem = payloads.iter().find(|payload| payload.is("delay", NS))?;
+    Some(parse(elem).ok_or_else(|| {
+        tracing::debug!("invalid delay: {:?}", elem.attr("stamp"));
+        reject::bad_request()
+    }))
+}
+
+pub(crate) fn parse(elem: &Element) -> Option<Delay> {
+    let from = match elem.attr("from") {
+        Some(from) => Some(from.parse().ok()?),
+        None => None,
+    };
+    let reason = elem.text();
+    Some(Delay {
+        stamp: elem.attr("stamp")?.parse().ok()?,
+        from,
+        reason: (!reason.is_empty()).then_some(reason),
+    })
+}
@@ -0,0 +1,491 @@
+//! Service Discovery (XEP-0030).
+//!
+//! - `wax::disco::items(provider)` - Answer `disco#items` queries from an [`ItemProvider`]
+//! - `wax::disco::info(identity, features)` - Answer `disco#info` queries about the component
+//! - `wax::disco::node_info(node, identity, features)` - Answer `disco#info` queries about a node
+//! - [`features`] - Collect the features advertised by a filter
+//!
+//! Filters implementing a protocol advertise its namespace, and
+//! [`Filter::advertises`] adds features of your own. Give the server an
+//! [`Identity`] with `Server::disco` and it answers `disco#info` queries
+//! about the component with everything the served routes advertise.
+//!
+//! An identity may carry data forms extending the information returned
+//! with it (XEP-0128), such as `muc#roominfo` or server contact
+//! addresses; each node answered by [`node_info`] has its own.
+//!
+//! Items are produced per query, so they can come from anywhere: a static
+//! list, the rooms of a MUC service, or the contacts a gateway user has on
+//! the legacy network.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use futures_util::future::BoxFuture;
+//! use wax::disco::{self, Item, ItemProvider, ItemsQuery};
+//! use wax::Rejection;
+//!
+//! struct Rooms(Db);
+//!
+//! impl ItemProvider for Rooms {
+//!     fn items<'a>(&'a self, query: &'a ItemsQuery) -> BoxFuture<'a, Result<Vec<Item>, Rejection>> {
+//!         Box::pin(async move {
+//!             let rooms = self.0.public_rooms().await?;
+//!             Ok(rooms.into_iter().map(|room| Item::new(room.jid).name(room.title)).collect())
+//!         })
+//!     }
+//! }
+//!
+//! let route = disco::items(Rooms(db));
+//! ```
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use futures_util::future::{self, BoxFuture};
+use tokio_xmpp::Stanza;
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::jid::Jid;
+use xmpp_parsers::minidom::Element;
+
+use crate::filter::{filter_fn_one, Filter};
+use crate::form::DataForm;
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+use crate::rsm;
+
+/// The `http://jabber.org/protocol/disco#info` namespace.
+pub const NS_INFO: &str = "http://jabber.org/protocol/disco#info";
+
+/// The `http://jabber.org/protocol/disco#items` namespace.
+pub const NS_ITEMS: &str = "http://jabber.org/protocol/disco#items";
+
+/// An incoming `disco#items` query.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ItemsQuery {
+    /// The entity asking.
+    pub from: Option<Jid>,
+    /// The entity being asked about.
+    pub to: Option<Jid>,
+    /// The node being asked about, if any.
+    pub node: Option<String>,
+    /// The page of items asked for, if any.
+    pub page: Option<rsm::Request>,
+    id: String,
+}
+
+/// A discoverable item.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Item {
+    /// The JID of the item.
+    pub jid: Jid,
+    /// The node of the item, if it is not addressed by JID alone.
+    pub node: Option<String>,
+    /// A human-readable name.
+    pub name: Option<String>,
+}
+
+impl Item {
+    /// Create an item for `jid`.
+    pub fn new(jid: Jid) -> Self {
+        Item {
+            jid,
+            node: None,
+            name: None,
+        }
+    }
+
+    /// Set the node of the item.
+    pub fn node(mut self, node: impl Into<String>) -> Self {
+        self.node = Some(node.into());
+        self
+    }
+
+    /// Set the human-readable name of the item.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+}
+
+impl From<Item> for Element {
+    fn from(item: Item) -> Element {
+        Element::builder("item", NS_ITEMS)
+            .attr("jid", item.jid.to_string())
+            .attr("node", item.node)
+            .attr("name", item.name)
+            .build()
+    }
+}
+
+/// Produces the items returned for a `disco#items` query.
+///
+/// Reject with `item-not-found` for nodes that do not exist.
+pub trait ItemProvider: Send + Sync + 'static {
+    /// The items for `query`.
+    fn items<'a>(&'a self, query: &'a ItemsQuery) -> BoxFuture<'a, Result<Vec<Item>, Rejection>>;
+}
+
+/// A fixed list of items, returned for queries without a node.
+impl ItemProvider for Vec<Item> {
+    fn items<'a>(&'a self, query: &'a ItemsQuery) -> BoxFuture<'a, Result<Vec<Item>, Rejection>> {
+        Box::pin(future::ready(match query.node {
+            None => Ok(self.clone()),
+            Some(_) => Err(reject::item_not_found()),
+        }))
+    }
+}
+
+impl<P: ItemProvider + ?Sized> ItemProvider for Arc<P> {
+    fn items<'a>(&'a self, query: &'a ItemsQuery) -> BoxFuture<'a, Result<Vec<Item>, Rejection>> {
+        (**self).items(query)
+    }
+}
+
+/// Extract an incoming `disco#items` query.
+///
+/// Rejects with `item-not-found` if the stanza is not a `disco#items` get,
+/// and with `bad-request` if its paging request is malformed.
+pub fn items_query() -> impl Filter<Extract = One<ItemsQuery>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(match stanza {
+            Stanza::Iq(Iq::Get {
+                from,
+                to,
+                id,
+                payload,
+            }) if payload.is("query", NS_ITEMS) => {
+                rsm::parse(payload).transpose().map(|page| ItemsQuery {
+                    from: from.clone(),
+                    to: to.clone(),
+                    node: payload.attr("node").map(str::to_owned),
+                    page,
+                    id: id.clone(),
+                })
+            }
+            _ => Err(reject::item_not_found()),
+        })
+    })
+}
+
+/// Answer `disco#items` queries with the items of `provider`.
+///
+/// Paged queries get the requested page of the items, with its `<set/>`.
+pub fn items(
+    provider: impl ItemProvider,
+) -> impl Filter<Extract = One<Iq>, Error = Rejection> + Clone {
+    let provider = Arc::new(provider);
+    items_query()
+        .and_then(move |query: ItemsQuery| {
+            let provider = provider.clone();
+            async move {
+                let items = provider.items(&query).await?;
+                let Some(ref request) = query.page else {
+                    return Ok(items_result(query, items));
+                };
+                let (items, page) = request.paginate(&items, |item| match item.node {
+                    Some(ref node) => format!("{} {}", item.jid, node),
+                    None => item.jid.to_string(),
+                })?;
+                let items = items.to_vec();
+                let mut result = items_result(query, items);
+                if let Iq::Result {
+                    payload: Some(ref mut query),
+                    ..
+                } = result
+                {
+                    query.append_child(Element::from(page));
+                }
+                Ok::<_, Rejection>(result)
+            }
+        })
+        .advertises(NS_ITEMS)
+}
+
+/// Build the result of `query` listing `items`.
+pub fn items_result(query: ItemsQuery, items: impl IntoIterator<Item = Item>) -> Iq {
+    Iq::Result {
+        from: query.to,
+        to: query.from,
+        id: query.id,
+        payload: Some(
+            Element::builder("query", NS_ITEMS)
+                .attr("node", query.node)
+                .append_all(items.into_iter().map(Element::from))
+                .build(),
+        ),
+    }
+}
+
+/// The service discovery features handled by a set of routes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Features {
+    vars: BTreeSet<&'static str>,
+}
+
+impl Features {
+    /// An empty set of features.
+    pub fn new() -> Self {
+        Features::default()
+    }
+
+    /// Add a feature.
+    pub fn insert(&mut self, var: &'static str) {
+        self.vars.insert(var);
+    }
+
+    /// Whether `var` is advertised.
+    pub fn contains(&self, var: &str) -> bool {
+        self.vars.contains(var)
+    }
+
+    /// The advertised features, in order.
+    pub fn iter(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.vars.iter().copied()
+    }
+}
+
+impl Extend<&'static str> for Features {
+    fn extend<I: IntoIterator<Item = &'static str>>(&mut self, iter: I) {
+        self.vars.extend(iter);
+    }
+}
+
+/// Collect the features advertised by `filter` and everything it combines.
+pub fn features<F: Filter>(filter: &F) -> Features {
+    let mut features = Features::new();
+    filter.advertise(&mut features);
+    features
+}
+
+/// The identity of an entity, as returned in `disco#info` results.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Identity {
+    /// The category, e.g. `gateway` or `conference`.
+    pub category: String,
+    /// The type within the category, e.g. `sms` or `text`.
+    pub type_: String,
+    /// A human-readable name.
+    pub name: Option<String>,
+    /// The data forms extending the information (XEP-0128), each with its
+    /// own `FORM_TYPE`.
+    pub forms: Vec<DataForm>,
+}
+
+impl Identity {
+    /// Create an identity of `category` and `type_`.
+    pub fn new(category: impl Into<String>, type_: impl Into<String>) -> Self {
+        Identity {
+            category: category.into(),
+            type_: type_.into(),
+            name: None,
+            forms: Vec::new(),
+        }
+    }
+
+    /// Set the human-readable name.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Extend the information with `form`, of type `result`.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use wax::disco::Identity;
+    /// use wax::form::{DataForm, Field, FormType};
+    ///
+    /// let identity = Identity::new("conference", "text").form(
+    ///     DataForm::new(FormType::Result)
+    ///         .form_type("http://jabber.org/protocol/muc#roominfo")
+    ///         .field(Field::new("muc#roominfo_occupants").value("3")),
+    /// );
+    /// ```
+    pub fn form(mut self, form: DataForm) -> Self {
+        self.forms.push(form);
+        self
+    }
+}
+
+impl From<Identity> for Element {
+    fn from(identity: Identity) -> Element {
+        Element::builder("identity", NS_INFO)
+            .attr("category", identity.category)
+            .attr("type", identity.type_)
+            .attr("name", identity.name)
+            .build()
+    }
+}
+
+/// Answer `disco#info` queries without a node with `identity` and
+/// `features`.
+///
+/// `disco#info` itself is always included in the features, and the forms
+/// of `identity` follow them.
+pub fn info(
+    identity: Identity,
+    features: Features,
+) -> impl Filter<Extract = One<Iq>, Error = Rejection> + Clone {
+    let info = Arc::new(Info::new(identity, features));
+    filter_fn_one(|stanza: &Stanza| match info_query(stanza) {
+        Some((from, to, id)) => future::ok((from.clone(), to.clone(), id.clone())),
+        None => future::err(reject::item_not_found()),
+    })
+    .map(move |(from, to, id): (Option<Jid>, Option<Jid>, String)| info.result(from, to, id, None))
+    .advertises(NS_INFO)
+}
+
+/// Answer `disco#info` queries about `node` with `identity` and
+/// `features`.
+///
+/// Rejects with `item-not-found` for queries about other nodes, so several
+/// can be combined with [`Filter::or`].
+pub fn node_info(
+    node: impl Into<String>,
+    identity: Identity,
+    features: Features,
+) -> impl Filter<Extract = One<Iq>, Error = Rejection> + Clone {
+    let node: Arc<str> = node.into().into();
+    let info = Arc::new(Info::new(identity, features));
+    filter_fn_one(|stanza: &Stanza| match node_info_query(stanza) {
+        Some(((from, to, id), Some(node))) => {
+            future::ok((from.clone(), to.clone(), id.clone(), node.to_owned()))
+        }
+        _ => future::err(reject::item_not_found()),
+    })
+    .and_then(
+        move |(from, to, id, asked): (Option<Jid>, Option<Jid>, String, String)| {
+            future::ready(if *asked == *node {
+                Ok(info.result(from, to, id, Some(asked)))
+            } else {
+                Err(reject::item_not_found())
+            })
+        },
+    )
+    .advertises(NS_INFO)
+}
+
+pub(crate) type Addressing<'a> = (&'a Option<Jid>, &'a Option<Jid>, &'a String);
+
+fn info_query(stanza: &Stanza) -> Option<Addressing<'_>> {
+    match node_info_query(stanza)? {
+        (addressing, None) => Some(addressing),
+        (_, Some(_)) => None,
+    }
+}
+
+/// A `disco#info` query, with the node asked about.
+pub(crate) fn node_info_query(stanza: &Stanza) -> Option<(Addressing<'_>, Option<&str>)> {
+    match stanza {
+        Stanza::Iq(Iq::Get {
+            from,
+            to,
+            id,
+            payload,
+        }) if payload.is("query", NS_INFO) => Some(((from, to, id), payload.attr("node"))),
+        _ => None,
+    }
+}
+
+/// A prepared `disco#info` answer.
+#[derive(Debug)]
+pub(crate) struct Info {
+    identity: Identity,
+    features: Features,
+}
+
+impl Info {
+    pub(crate) fn new(identity: Identity, mut features: Features) -> Self {
+        features.insert(NS_INFO);
+        Info { identity, features }
+    }
+
+    /// Answer `stanza` if it is a `disco#info` query about `jid` itself.
+    pub(crate) fn answer(&self, stanza: &Stanza, jid: &Jid) -> Option<Stanza> {
+        let (from, to, id) = info_query(stanza)?;
+        if to.as_ref() != Some(jid) {
+            return None;
+        }
+        Some(Stanza::Iq(self.result(
+            from.clone(),
+            to.clone(),
+            id.clone(),
+            None,
+        )))
+    }
+
+    /// The answer to a query about `node`, or the entity itself.
+    pub(crate) fn result(
+        &self,
+        from: Option<Jid>,
+        to: Option<Jid>,
+        id: String,
+        node: Option<String>,
+    ) -> Iq {
+        Iq::Result {
+            from: to,
+            to: from,
+            id,
+            payload: Some(
+                Element::builder("query", NS_INFO)
+                    .attr("node", node)
+                    .append(Element::from(self.identity.clone()))
+                    .append_all(self.features.iter().map(|var| {
+                        Element::builder("feature", NS_INFO)
+                            .attr("var", var)
+                            .build()
+                    }))
+                    .append_all(self.identity.forms.iter().cloned().map(Element::from))
+                    .build(),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn features_are_collected_through_combinators() {
+        let routes = items(Vec::new())
+            .or(crate::any().map(crate::sink).advertises("urn:example:a"))
+            .with(crate::log("disco"))
+            .boxed();
+
+        let features = features(&routes);
+        assert_eq!(
+            features.iter().collect::<Vec<_>>(),
+            [NS_ITEMS, "urn:example:a"]
+        );
+    }
+
+    #[test]
+    fn node_info_lists_the_forms_of_the_node() {
+        use crate::form::{Field, FormType};
+
+        let identity = Identity::new("conference", "text").form(
+            DataForm::new(FormType::Result)
+                .form_type("http://jabber.org/protocol/muc#roominfo")
+                .field(Field::new("muc#roominfo_occupants").value("3")),
+        );
+        let info = Info::new(identity, Features::new());
+        let Iq::Result {
+            payload: Some(query),
+            ..
+        } = info.result(None, None, "info1".into(), Some("room".into()))
+        else {
+            panic!("not a result");
+        };
+
+        let children: Vec<_> = query.children().map(Element::name).collect();
+        assert_eq!(children, ["identity", "feature", "x"]);
+        let form = DataForm::try_from(query.get_child("x", crate::form::NS).unwrap()).unwrap();
+        assert_eq!(
+            form.get_form_type(),
+            Some("http://jabber.org/protocol/muc#roominfo")
+        );
+    }
+}
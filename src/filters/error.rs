@@ -0,0 +1,77 @@
+//! Error stanza extraction.
+//!
+//! - `wax::error::param()` - Extract the [`Bounce`] of an error stanza
+//!
+//! Stanzas a component sends can come back as errors, e.g. when the
+//! recipient is offline or unknown. Matching them lets a component retry,
+//! notify a user, or clean up state instead of silently dropping them.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::error::Bounce;
+//! use wax::Filter;
+//!
+//! let bounces = wax::error::param().map(|bounce: Bounce| {
+//!     tracing::warn!(id = ?bounce.id, "delivery failed: {:?}", bounce.error.defined_condition);
+//!     wax::sink()
+//! });
+//! let routes = bounces.or(other_routes);
+//! ```
+
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::jid::Jid;
+use xmpp_parsers::message::MessageType;
+use xmpp_parsers::minidom::Element;
+use xmpp_parsers::presence::Type as PresenceType;
+use xmpp_parsers::stanza_error::StanzaError;
+
+use crate::correlation::GetStanzaId;
+use crate::filter::{filter_fn_one, Filter};
+use crate::filters::stanza::from_of;
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+
+/// A stanza bounced back with an error.
+#[derive(Clone, Debug)]
+pub struct Bounce {
+    /// The entity reporting the error.
+    pub from: Option<Jid>,
+    /// The id of the stanza that failed, if it had one.
+    pub id: Option<String>,
+    /// The error.
+    pub error: StanzaError,
+}
+
+/// Extract the error of an IQ, message or presence error.
+///
+/// Rejects with `item-not-found` for other stanzas, including errors that
+/// do not parse: an error must never be answered with another one.
+pub fn param() -> impl Filter<Extract = One<Bounce>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        let error = match stanza {
+            Stanza::Iq(Iq::Error { error, .. }) => Some(error.clone()),
+            Stanza::Message(msg) if msg.type_ == MessageType::Error => parse(&msg.payloads),
+            Stanza::Presence(pres) if pres.type_ == PresenceType::Error => parse(&pres.payloads),
+            _ => None,
+        };
+        future::ready(
+            error
+                .map(|error| Bounce {
+                    from: from_of(stanza).cloned(),
+                    id: stanza.get_stanza_id().map(|id| id.as_str().to_owned()),
+                    error,
+                })
+                .ok_or_else(reject::item_not_found),
+        )
+    })
+}
+
+fn parse(payloads: &[Element]) -> Option<StanzaError> {
+    payloads
+        .iter()
+        .find(|payload| payload.name() == "error")
+        .and_then(|error| StanzaError::try_from(error.clone()).ok())
+}
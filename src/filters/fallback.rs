@@ -0,0 +1,98 @@
+//! A filter terminating a chain of routes.
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::iq::Iq;
+
+use crate::filter::{filter_fn_one, Filter};
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+
+/// A [`Filter`] handling whatever the routes before it did not, as RFC 6120
+/// requires.
+///
+/// IQ requests are rejected with `service-unavailable`, so that the sender
+/// gets an error rather than the default `item-not-found`; every other
+/// stanza is dropped without a reply.
+///
+/// Put it last in an `or` chain. This rejection ranks just above
+/// `item-not-found`, so a route rejecting an IQ with another condition,
+/// such as `forbidden`, still has its error sent. Messages and presences
+/// are dropped whatever the routes before rejected them with.
+///
+/// # Example
+///
+/// ```ignore
+/// use wax::Filter;
+///
+/// let routes = ping.or(disco).or(wax::fallback());
+/// ```
+pub fn fallback() -> impl Filter<Extract = One<Option<Stanza>>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| match stanza {
+        Stanza::Iq(Iq::Get { .. } | Iq::Set { .. }) => future::err(unhandled()),
+        _ => future::ok(None),
+    })
+}
+
+/// The `service-unavailable` rejection of [`fallback`], which ranks below
+/// every rejection but `item-not-found`.
+pub(crate) fn unhandled() -> Rejection {
+    reject::known(Unhandled { _p: () })
+}
+
+crate::unit_error! {
+    /// No route handled the request.
+    pub Unhandled: "no route handled the request"
+}
+
+#[cfg(test)]
+mod tests {
+    use xmpp_parsers::jid::Jid;
+    use xmpp_parsers::minidom::Element;
+    use xmpp_parsers::stanza_error::DefinedCondition;
+
+    use super::*;
+    use crate::reply::Response;
+
+    fn ping(id: &str) -> Stanza {
+        Stanza::Iq(Iq::Get {
+            from: Some(Jid::new("juliet@capulet.lit/balcony").unwrap()),
+            to: Some(Jid::new("sms.example.org").unwrap()),
+            id: id.to_owned(),
+            payload: Element::builder("ping", "urn:xmpp:ping").build(),
+        })
+    }
+
+    fn condition(response: Response) -> Option<DefinedCondition> {
+        match response.stanzas() {
+            [Stanza::Iq(Iq::Error { error, .. })] => Some(error.defined_condition.clone()),
+            [] => None,
+            other => panic!("expected an error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn answers_unhandled_requests() {
+        let unhandled = crate::id::param()
+            .and_then(|_: String| future::err::<Option<Stanza>, _>(reject::item_not_found()))
+            .or(fallback())
+            .unify();
+        let response = crate::service(unhandled).call_stanza(ping("p")).await;
+        assert_eq!(
+            condition(response.unwrap()),
+            Some(DefinedCondition::ServiceUnavailable)
+        );
+    }
+
+    #[tokio::test]
+    async fn keeps_earlier_rejections() {
+        let forbidden = crate::id::param()
+            .and_then(|_: String| future::err::<Option<Stanza>, _>(reject::forbidden()))
+            .or(fallback())
+            .unify();
+        let response = crate::service(forbidden).call_stanza(ping("p")).await;
+        assert_eq!(
+            condition(response.unwrap()),
+            Some(DefinedCondition::Forbidden)
+        );
+    }
+}
@@ -0,0 +1,535 @@
+//! Data Forms (XEP-0004).
+//!
+//! - `wax::form::param::<T>()` - Extract the data form of a stanza as `T`
+//! - `wax::form::optional::<T>()` - Same, but yields `None` when there is no form
+//!
+//! `T` implements [`FromDataForm`], usually derived with the `derive`
+//! feature:
+//!
+//! ```ignore
+//! use wax::form::FromDataForm;
+//! use wax::Filter;
+//!
+//! #[derive(FromDataForm)]
+//! #[form(form_type = "urn:example:signup")]
+//! struct Signup {
+//!     #[form(var = "phone-number")]
+//!     phone: String,
+//!     nick: Option<String>,
+//!     #[form(var = "accept-terms")]
+//!     accepted: bool,
+//! }
+//!
+//! let route = wax::iq()
+//!     .set()
+//!     .and(wax::form::param::<Signup>())
+//!     .map(|signup: Signup| wax::sink());
+//! ```
+//!
+//! Fields of type `Option<_>` may be missing; any other missing field, or a
+//! value that does not parse, rejects with `bad-request`.
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::str::FromStr;
+
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::minidom::Element;
+
+use crate::filter::{filter_fn_one, Filter};
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+
+#[cfg(feature = "derive")]
+pub use wax_macros::FromDataForm;
+
+/// The `jabber:x:data` namespace.
+pub const NS: &str = "jabber:x:data";
+
+/// The type of a data form.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FormType {
+    /// A form to fill in.
+    Form,
+    /// A filled in form.
+    Submit,
+    /// A cancelled form.
+    Cancel,
+    /// Results, e.g. of a search.
+    Result,
+}
+
+impl FormType {
+    fn as_str(self) -> &'static str {
+        match self {
+            FormType::Form => "form",
+            FormType::Submit => "submit",
+            FormType::Cancel => "cancel",
+            FormType::Result => "result",
+        }
+    }
+}
+
+/// A single form field.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Field {
+    /// The field name.
+    pub var: String,
+    /// The field type, e.g. `text-single` or `list-multi`.
+    pub type_: Option<String>,
+    /// A human-readable label.
+    pub label: Option<String>,
+    /// Whether the field must be filled in.
+    pub required: bool,
+    /// The values.
+    pub values: Vec<String>,
+    /// The options of a list field, as `(label, value)`.
+    pub options: Vec<(Option<String>, String)>,
+}
+
+impl Field {
+    /// A field named `var`.
+    pub fn new(var: impl Into<String>) -> Self {
+        Field {
+            var: var.into(),
+            type_: None,
+            label: None,
+            required: false,
+            values: Vec::new(),
+            options: Vec::new(),
+        }
+    }
+
+    /// Set the field type.
+    pub fn type_(mut self, type_: impl Into<String>) -> Self {
+        self.type_ = Some(type_.into());
+        self
+    }
+
+    /// Set the label.
+    pub fn label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    /// Mark the field as required.
+    pub fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+
+    /// Add a value.
+    pub fn value(mut self, value: impl Into<String>) -> Self {
+        self.values.push(value.into());
+        self
+    }
+
+    /// Add an option to choose from.
+    pub fn option(mut self, label: Option<String>, value: impl Into<String>) -> Self {
+        self.options.push((label, value.into()));
+        self
+    }
+}
+
+impl From<Field> for Element {
+    fn from(field: Field) -> Element {
+        let mut builder = Element::builder("field", NS)
+            .attr("var", field.var)
+            .attr("type", field.type_)
+            .attr("label", field.label);
+        if field.required {
+            builder = builder.append(Element::builder("required", NS).build());
+        }
+        for value in field.values {
+            builder = builder.append(Element::builder("value", NS).append(value).build());
+        }
+        for (label, value) in field.options {
+            builder = builder.append(
+                Element::builder("option", NS)
+                    .attr("label", label)
+                    .append(Element::builder("value", NS).append(value).build())
+                    .build(),
+            );
+        }
+        builder.build()
+    }
+}
+
+/// A data form.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DataForm {
+    /// The form type.
+    pub type_: FormType,
+    /// The title.
+    pub title: Option<String>,
+    /// The instructions.
+    pub instructions: Option<String>,
+    /// The fields, in document order, including the hidden `FORM_TYPE`.
+    pub fields: Vec<Field>,
+}
+
+impl DataForm {
+    /// An empty form of type `type_`.
+    pub fn new(type_: FormType) -> Self {
+        DataForm {
+            type_,
+            title: None,
+            instructions: None,
+            fields: Vec::new(),
+        }
+    }
+
+    /// Set the `FORM_TYPE` of the form.
+    pub fn form_type(self, form_type: impl Into<String>) -> Self {
+        self.field(Field::new("FORM_TYPE").type_("hidden").value(form_type))
+    }
+
+    /// Set the title.
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    /// Set the instructions.
+    pub fn instructions(mut self, instructions: impl Into<String>) -> Self {
+        self.instructions = Some(instructions.into());
+        self
+    }
+
+    /// Add a field.
+    pub fn field(mut self, field: Field) -> Self {
+        self.fields.push(field);
+        self
+    }
+
+    /// The field named `var`.
+    pub fn get(&self, var: &str) -> Option<&Field> {
+        self.fields.iter().find(|field| field.var == var)
+    }
+
+    /// The values of the field named `var`, if present.
+    pub fn values(&self, var: &str) -> Option<&[String]> {
+        self.get(var).map(|field| &field.values[..])
+    }
+
+    /// The `FORM_TYPE` of the form, if any.
+    pub fn get_form_type(&self) -> Option<&str> {
+        self.values("FORM_TYPE")
+            .and_then(|values| values.first())
+            .map(String::as_str)
+    }
+}
+
+impl TryFrom<&Element> for DataForm {
+    type Error = FormError;
+
+    fn try_from(elem: &Element) -> Result<Self, FormError> {
+        if !elem.is("x", NS) {
+            return Err(FormError::new("not a data form"));
+        }
+        let type_ = match elem.attr("type") {
+            Some("form") => FormType::Form,
+            Some("submit") => FormType::Submit,
+            Some("cancel") => FormType::Cancel,
+            Some("result") => FormType::Result,
+            _ => return Err(FormError::new("invalid form type")),
+        };
+        let mut form = DataForm::new(type_);
+        for child in elem.children().filter(|child| child.ns() == NS) {
+            match child.name() {
+                "title" => form.title = Some(child.text()),
+                "instructions" => form.instructions = Some(child.text()),
+                "field" => form.fields.push(Field {
+                    var: child.attr("var").unwrap_or_default().to_owned(),
+                    type_: child.attr("type").map(str::to_owned),
+                    label: child.attr("label").map(str::to_owned),
+                    required: child.has_child("required", NS),
+                    values: child
+                        .children()
+                        .filter(|value| value.is("value", NS))
+                        .map(Element::text)
+                        .collect(),
+                    options: child
+                        .children()
+                        .filter(|option| option.is("option", NS))
+                        .map(|option| {
+                            (
+                                option.attr("label").map(str::to_owned),
+                                option
+                                    .get_child("value", NS)
+                                    .map(Element::text)
+                                    .unwrap_or_default(),
+                            )
+                        })
+                        .collect(),
+                }),
+                _ => {}
+            }
+        }
+        Ok(form)
+    }
+}
+
+impl From<DataForm> for Element {
+    fn from(form: DataForm) -> Element {
+        let mut builder = Element::builder("x", NS).attr("type", form.type_.as_str());
+        if let Some(title) = form.title {
+            builder = builder.append(Element::builder("title", NS).append(title).build());
+        }
+        if let Some(instructions) = form.instructions {
+            builder = builder.append(
+                Element::builder("instructions", NS)
+                    .append(instructions)
+                    .build(),
+            );
+        }
+        builder
+            .append_all(form.fields.into_iter().map(Element::from))
+            .build()
+    }
+}
+
+/// Why a data form could not be converted.
+#[derive(Debug)]
+pub struct FormError {
+    message: String,
+}
+
+impl FormError {
+    /// An error with `message`.
+    pub fn new(message: impl Into<String>) -> Self {
+        FormError {
+            message: message.into(),
+        }
+    }
+
+    /// Required field `var` is missing.
+    pub fn missing(var: &str) -> Self {
+        FormError::new(format!("missing required field `{}`", var))
+    }
+
+    /// Prefix the message with the field it concerns.
+    pub fn in_field(self, var: &str) -> Self {
+        FormError::new(format!("field `{}`: {}", var, self.message))
+    }
+}
+
+impl fmt::Display for FormError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl StdError for FormError {}
+
+/// A type that can be read from a data form.
+///
+/// With the `derive` feature, `#[derive(FromDataForm)]` implements it for
+/// structs, reading each field from the form field of the same name
+/// (`#[form(var = "...")]` to rename) through [`FromField`], and checking
+/// the `FORM_TYPE` given with `#[form(form_type = "...")]`.
+pub trait FromDataForm: Sized {
+    /// The `FORM_TYPE` expected, if any.
+    fn form_type() -> Option<&'static str> {
+        None
+    }
+
+    /// Read `form`.
+    fn from_data_form(form: &DataForm) -> Result<Self, FormError>;
+}
+
+impl FromDataForm for DataForm {
+    fn from_data_form(form: &DataForm) -> Result<Self, FormError> {
+        Ok(form.clone())
+    }
+}
+
+/// A type that can be read from the values of a form field.
+pub trait FromField: Sized {
+    /// Read the values of field `var`, `None` if the form lacks it.
+    fn from_field(var: &str, values: Option<&[String]>) -> Result<Self, FormError>;
+}
+
+impl<T: FromField> FromField for Option<T> {
+    fn from_field(var: &str, values: Option<&[String]>) -> Result<Self, FormError> {
+        match values {
+            None | Some([]) => Ok(None),
+            Some(values) => T::from_field(var, Some(values)).map(Some),
+        }
+    }
+}
+
+impl FromField for Vec<String> {
+    fn from_field(_: &str, values: Option<&[String]>) -> Result<Self, FormError> {
+        Ok(values.map(<[String]>::to_vec).unwrap_or_default())
+    }
+}
+
+impl FromField for bool {
+    fn from_field(var: &str, values: Option<&[String]>) -> Result<Self, FormError> {
+        match single(var, values)? {
+            "1" | "true" => Ok(true),
+            "0" | "false" => Ok(false),
+            _ => Err(FormError::new("not a boolean").in_field(var)),
+        }
+    }
+}
+
+macro_rules! from_str_field {
+    ($($ty:ty),*) => {
+        $(
+            impl FromField for $ty {
+                fn from_field(var: &str, values: Option<&[String]>) -> Result<Self, FormError> {
+                    <$ty as FromStr>::from_str(single(var, values)?)
+                        .map_err(|err| FormError::new(err.to_string()).in_field(var))
+                }
+            }
+        )*
+    };
+}
+
+from_str_field!(
+    String,
+    xmpp_parsers::jid::Jid,
+    u8,
+    u16,
+    u32,
+    u64,
+    usize,
+    i8,
+    i16,
+    i32,
+    i64,
+    isize,
+    f32,
+    f64
+);
+
+fn single<'a>(var: &str, values: Option<&'a [String]>) -> Result<&'a str, FormError> {
+    match values {
+        None | Some([]) => Err(FormError::missing(var)),
+        Some([value]) => Ok(value),
+        Some(_) => Err(FormError::new("expected a single value").in_field(var)),
+    }
+}
+
+/// Extract the data form of the stanza as `T`.
+///
+/// The form is looked for among message payloads, and in the IQ payload
+/// and its children (such as an ad-hoc command or a registration query).
+/// Forms whose `FORM_TYPE` does not match `T::form_type()` are skipped.
+///
+/// Rejects with `item-not-found` if there is no such form, and with
+/// `bad-request` if it cannot be read as `T`.
+pub fn param<T>() -> impl Filter<Extract = One<T>, Error = Rejection> + Copy
+where
+    T: FromDataForm + Send + 'static,
+{
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(match extract::<T>(stanza) {
+            Some(result) => result,
+            None => Err(reject::item_not_found()),
+        })
+    })
+}
+
+/// Extract the data form of the stanza as `T`, if there is one.
+///
+/// Rejects with `bad-request` if a form is present but cannot be read as `T`.
+pub fn optional<T>() -> impl Filter<Extract = One<Option<T>>, Error = Rejection> + Copy
+where
+    T: FromDataForm + Send + 'static,
+{
+    filter_fn_one(|stanza: &Stanza| future::ready(extract::<T>(stanza).transpose()))
+}
+
+fn extract<T: FromDataForm>(stanza: &Stanza) -> Option<Result<T, Rejection>> {
+    let elem = forms(stanza).find(|elem| match T::form_type() {
+        Some(expected) => form_type_of(elem).as_deref() == Some(expected),
+        None => true,
+    })?;
+    Some(
+        DataForm::try_from(elem)
+            .and_then(|form| T::from_data_form(&form))
+            .map_err(|err| {
+                tracing::debug!("invalid data form: {}", err);
+                reject::bad_request()
+            }),
+    )
+}
+
+/// The data forms carried by `stanza`.
+pub(crate) fn forms(stanza: &Stanza) -> Box<dyn Iterator<Item = &Element> + '_> {
+    match stanza {
+        Stanza::Message(msg) => Box::new(msg.payloads.iter().filter(|p| p.is("x", NS))),
+        Stanza::Iq(Iq::Get { payload, .. } | Iq::Set { payload, .. }) => Box::new(
+            std::iter::once(payload)
+                .chain(payload.children())
+                .filter(|p| p.is("x", NS)),
+        ),
+        Stanza::Iq(Iq::Result {
+            payload: Some(payload),
+            ..
+        }) => Box::new(
+            std::iter::once(payload)
+                .chain(payload.children())
+                .filter(|p| p.is("x", NS)),
+        ),
+        Stanza::Iq(_) => Box::new(std::iter::empty()),
+        Stanza::Presence(pres) => Box::new(pres.payloads.iter().filter(|p| p.is("x", NS))),
+    }
+}
+
+fn form_type_of(elem: &Element) -> Option<String> {
+    elem.children()
+        .find(|field| field.is("field", NS) && field.attr("var") == Some("FORM_TYPE"))
+        .and_then(|field| field.get_child("value", NS))
+        .map(Element::text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Signup {
+        phone: String,
+        nick: Option<String>,
+        accepted: bool,
+    }
+
+    impl FromDataForm for Signup {
+        fn form_type() -> Option<&'static str> {
+            Some("urn:example:signup")
+        }
+
+        fn from_data_form(form: &DataForm) -> Result<Self, FormError> {
+            Ok(Signup {
+                phone: FromField::from_field("phone", form.values("phone"))?,
+                nick: FromField::from_field("nick", form.values("nick"))?,
+                accepted: FromField::from_field("accepted", form.values("accepted"))?,
+            })
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let elem = Element::from(
+            DataForm::new(FormType::Submit)
+                .form_type("urn:example:signup")
+                .field(Field::new("phone").value("+15555550100"))
+                .field(Field::new("accepted").value("1")),
+        );
+        assert_eq!(form_type_of(&elem).as_deref(), Some("urn:example:signup"));
+
+        let form = DataForm::try_from(&elem).unwrap();
+        let signup = Signup::from_data_form(&form).unwrap();
+        assert_eq!(signup.phone, "+15555550100");
+        assert_eq!(signup.nick, None);
+        assert!(signup.accepted);
+
+        let incomplete = DataForm::new(FormType::Submit).field(Field::new("nick").value("juliet"));
+        assert!(Signup::from_data_form(&incomplete).is_err());
+    }
+}
@@ -0,0 +1,151 @@
+//! Stanza Forwarding (XEP-0297).
+//!
+//! - `wax::forwarded::param()` - Extract the [`Forwarded`] stanza of a message
+//! - `wax::forwarded::optional()` - Same, but yields `None` for messages forwarding nothing
+//!
+//! The `<forwarded/>` element is found either directly in the message or in
+//! one of its payloads, the way MAM results and carbons wrap it.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::forwarded::Forwarded;
+//! use wax::{Filter, Stanza};
+//!
+//! let history = wax::forwarded::param().map(|forwarded: Forwarded| {
+//!     if let Stanza::Message(msg) = forwarded.stanza {
+//!         // replay msg, sent at forwarded.delay...
+//!     }
+//!     wax::sink()
+//! });
+//! ```
+
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::message::Message;
+use xmpp_parsers::minidom::Element;
+use xmpp_parsers::presence::Presence;
+
+use crate::delay::{self, Delay};
+use crate::filter::{filter_fn_one, Filter};
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+
+/// The `urn:xmpp:forward:0` namespace.
+pub const NS: &str = "urn:xmpp:forward:0";
+
+/// A forwarded stanza.
+#[derive(Clone, Debug)]
+pub struct Forwarded {
+    /// When the stanza was originally sent, if known.
+    pub delay: Option<Delay>,
+    /// The stanza.
+    pub stanza: Stanza,
+}
+
+impl Forwarded {
+    /// Forward `stanza`.
+    pub fn new(stanza: Stanza) -> Self {
+        Forwarded {
+            delay: None,
+            stanza,
+        }
+    }
+
+    /// Set when the stanza was originally sent.
+    pub fn delay(mut self, delay: Delay) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+
+    /// The forwarded message, if it is one.
+    pub fn message(self) -> Option<Message> {
+        match self.stanza {
+            Stanza::Message(msg) => Some(msg),
+            _ => None,
+        }
+    }
+}
+
+impl From<Forwarded> for Element {
+    fn from(forwarded: Forwarded) -> Element {
+        let stanza = match forwarded.stanza {
+            Stanza::Iq(iq) => Element::from(iq),
+            Stanza::Message(msg) => Element::from(msg),
+            Stanza::Presence(pres) => Element::from(pres),
+        };
+        Element::builder("forwarded", NS)
+            .append_all(forwarded.delay.map(Element::from))
+            .append(stanza)
+            .build()
+    }
+}
+
+/// Extract the stanza forwarded by a message.
+///
+/// Rejects with `item-not-found` if there is none, and with `bad-request` if
+/// it is malformed.
+pub fn param() -> impl Filter<Extract = One<Forwarded>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(match forwarded_of(stanza) {
+            Some(forwarded) => forwarded,
+            None => Err(reject::item_not_found()),
+        })
+    })
+    .advertises(NS)
+}
+
+/// Extract the stanza forwarded by a message, if any.
+///
+/// Rejects with `bad-request` if it is malformed.
+pub fn optional() -> impl Filter<Extract = One<Option<Forwarded>>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| future::ready(forwarded_of(stanza).transpose())).advertises(NS)
+}
+
+fn forwarded_of(stanza: &Stanza) -> Option<Result<Forwarded, Rejection>> {
+    let Stanza::Message(msg) = stanza else {
+        return None;
+    };
+    let elem = msg
+        .payloads
+        .iter()
+        .find(|payload| payload.is("forwarded", NS))
+        .or_else(|| {
+            msg.payloads
+                .iter()
+                .find_map(|payload| payload.get_child("forwarded", NS))
+        })?;
+    Some(parse(elem))
+}
+
+/// Parse a `<forwarded/>` element.
+pub(crate) fn parse(elem: &Element) -> Result<Forwarded, Rejection> {
+    let delay = match elem.get_child("delay", delay::NS) {
+        Some(delay) => Some(delay::parse(delay).ok_or_else(|| {
+            tracing::debug!("invalid forwarded delay: {:?}", delay.attr("stamp"));
+            reject::bad_request()
+        })?),
+        None => None,
+    };
+    let inner = elem
+        .children()
+        .find(|child| !child.is("delay", delay::NS))
+        .ok_or_else(|| {
+            tracing::debug!("forwarded nothing");
+            reject::bad_request()
+        })?;
+    let stanza = match inner.name() {
+        "message" => Message::try_from(inner.clone()).map(Stanza::Message).ok(),
+        "presence" => Presence::try_from(inner.clone()).map(Stanza::Presence).ok(),
+        "iq" => Iq::try_from(inner.clone()).map(Stanza::Iq).ok(),
+        _ => None,
+    };
+    match stanza {
+        Some(stanza) => Ok(Forwarded { delay, stanza }),
+        None => {
+            tracing::debug!("invalid forwarded <{}/>", inner.name());
+            Err(reject::bad_request())
+        }
+    }
+}
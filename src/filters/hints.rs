@@ -0,0 +1,105 @@
+//! Message Processing Hints (XEP-0334).
+//!
+//! - `wax::hints::param()` - Extract the [`Hint`]s of a message
+//! - `wax::hints::hint(hint)` - Match messages carrying `hint`
+//!
+//! Stamp replies with [`wax::reply::with::hint`](crate::reply::with::hint).
+//!
+//! Hints are advisory: an archive honors `no-store` and `no-permanent-store`
+//! by not archiving the message, and `store` by archiving one it otherwise
+//! would not, e.g. because it has no body.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::hints::Hint;
+//! use wax::Filter;
+//!
+//! let transient = wax::hints::hint(Hint::NoStore).map(|| wax::sink());
+//! let routes = transient.or(archive_route);
+//! ```
+
+use std::convert::Infallible;
+
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::minidom::Element;
+
+use crate::filter::{filter_fn, filter_fn_one, Filter};
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+
+/// The `urn:xmpp:hints` namespace.
+pub const NS: &str = "urn:xmpp:hints";
+
+/// A processing hint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Hint {
+    /// Do not store the message permanently, e.g. in an archive.
+    NoPermanentStore,
+    /// Do not store the message at all, even for offline delivery.
+    NoStore,
+    /// Do not copy the message to other resources, e.g. as a carbon.
+    NoCopy,
+    /// Store the message, even if it would not be otherwise.
+    Store,
+}
+
+impl Hint {
+    const ALL: [Hint; 4] = [
+        Hint::NoPermanentStore,
+        Hint::NoStore,
+        Hint::NoCopy,
+        Hint::Store,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Hint::NoPermanentStore => "no-permanent-store",
+            Hint::NoStore => "no-store",
+            Hint::NoCopy => "no-copy",
+            Hint::Store => "store",
+        }
+    }
+}
+
+impl From<Hint> for Element {
+    fn from(hint: Hint) -> Element {
+        Element::builder(hint.as_str(), NS).build()
+    }
+}
+
+/// Extract the hints of a message, in order.
+///
+/// Other stanzas, and messages without hints, yield an empty list.
+pub fn param() -> impl Filter<Extract = One<Vec<Hint>>, Error = Infallible> + Copy {
+    filter_fn_one(|stanza: &Stanza| future::ok(hints_of(stanza)))
+}
+
+/// Match messages carrying `hint`.
+///
+/// Rejects with `item-not-found` for other stanzas.
+pub fn hint(hint: Hint) -> impl Filter<Extract = (), Error = Rejection> + Copy {
+    filter_fn(move |stanza: &Stanza| {
+        if hints_of(stanza).contains(&hint) {
+            future::ok(())
+        } else {
+            future::err(reject::item_not_found())
+        }
+    })
+}
+
+fn hints_of(stanza: &Stanza) -> Vec<Hint> {
+    let Stanza::Message(msg) = stanza else {
+        return Vec::new();
+    };
+    msg.payloads
+        .iter()
+        .filter(|payload| payload.ns() == NS)
+        .filter_map(|payload| {
+            Hint::ALL
+                .into_iter()
+                .find(|hint| hint.as_str() == payload.name())
+        })
+        .collect()
+}
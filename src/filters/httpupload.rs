@@ -0,0 +1,247 @@
+//! HTTP File Upload (XEP-0363).
+//!
+//! - `wax::httpupload::request()` - Extract a [`SlotRequest`]
+//! - `wax::httpupload::Upload` - Answer slot requests from a [`SlotBackend`]
+//!
+//! The component never sees the files: it hands out a PUT URL to upload to
+//! and a GET URL to share, usually presigned URLs of an object store. A
+//! [`SlotBackend`] only has to sign them; size limits and quotas are checked
+//! beforehand by the [`Policy`] hooks of [`Upload`].
+//!
+//! # Example
+//!
+//! ```ignore
+//! use futures_util::future::BoxFuture;
+//! use wax::httpupload::{Slot, SlotBackend, SlotRequest, Upload};
+//! use wax::Rejection;
+//!
+//! struct Bucket(S3Client);
+//!
+//! impl SlotBackend for Bucket {
+//!     fn slot<'a>(&'a self, request: &'a SlotRequest) -> BoxFuture<'a, Result<Slot, Rejection>> {
+//!         Box::pin(async move {
+//!             let key = format!("{}/{}", uuid(), request.filename);
+//!             Ok(Slot::new(self.0.presign_put(&key).await?, self.0.public_url(&key)))
+//!         })
+//!     }
+//! }
+//!
+//! let route = Upload::new(Bucket(client))
+//!     .max_size(50 * 1024 * 1024)
+//!     .filter();
+//! ```
+
+use std::fmt;
+use std::sync::Arc;
+
+use futures_util::future::{self, BoxFuture};
+use tokio_xmpp::Stanza;
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::jid::Jid;
+use xmpp_parsers::minidom::Element;
+
+use crate::filter::{filter_fn_one, Filter};
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+
+/// The `urn:xmpp:http:upload:0` namespace.
+pub const NS: &str = "urn:xmpp:http:upload:0";
+
+// The only headers clients are allowed to pass on with the PUT request.
+const ALLOWED_HEADERS: &[&str] = &["Authorization", "Cookie", "Expires"];
+
+/// A request for an upload slot.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SlotRequest {
+    /// The uploader.
+    pub from: Option<Jid>,
+    /// The upload service.
+    pub to: Option<Jid>,
+    /// The name of the file.
+    pub filename: String,
+    /// The size of the file, in bytes.
+    pub size: u64,
+    /// The MIME type of the file, if given.
+    pub content_type: Option<String>,
+    id: String,
+}
+
+impl SlotRequest {
+    /// Answer with `slot`.
+    pub fn slot(self, slot: Slot) -> Iq {
+        let put = Element::builder("put", NS)
+            .attr("url", slot.put)
+            .append_all(slot.headers.into_iter().map(|(name, value)| {
+                Element::builder("header", NS)
+                    .attr("name", name)
+                    .append(value)
+                    .build()
+            }))
+            .build();
+        let get = Element::builder("get", NS).attr("url", slot.get).build();
+        Iq::Result {
+            from: self.to,
+            to: self.from,
+            id: self.id,
+            payload: Some(Element::builder("slot", NS).append(put).append(get).build()),
+        }
+    }
+}
+
+/// An upload slot.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Slot {
+    /// The URL to upload the file to.
+    pub put: String,
+    /// The URL to download the file from.
+    pub get: String,
+    /// Headers the uploader must send with the PUT request.
+    pub headers: Vec<(String, String)>,
+}
+
+impl Slot {
+    /// A slot uploading to `put`, downloaded from `get`.
+    pub fn new(put: impl Into<String>, get: impl Into<String>) -> Self {
+        Slot {
+            put: put.into(),
+            get: get.into(),
+            headers: Vec::new(),
+        }
+    }
+
+    /// Require the header `name` on the PUT request.
+    ///
+    /// Only `Authorization`, `Cookie` and `Expires` are allowed; other
+    /// headers are ignored.
+    pub fn header(mut self, name: &str, value: impl Into<String>) -> Self {
+        match ALLOWED_HEADERS
+            .iter()
+            .find(|allowed| allowed.eq_ignore_ascii_case(name))
+        {
+            Some(allowed) => self.headers.push(((*allowed).to_owned(), value.into())),
+            None => tracing::warn!("ignoring disallowed upload header {:?}", name),
+        }
+        self
+    }
+}
+
+/// Hands out upload slots, usually by signing URLs of an object store.
+pub trait SlotBackend: Send + Sync + 'static {
+    /// A slot for `request`.
+    fn slot<'a>(&'a self, request: &'a SlotRequest) -> BoxFuture<'a, Result<Slot, Rejection>>;
+}
+
+impl<B: SlotBackend + ?Sized> SlotBackend for Arc<B> {
+    fn slot<'a>(&'a self, request: &'a SlotRequest) -> BoxFuture<'a, Result<Slot, Rejection>> {
+        (**self).slot(request)
+    }
+}
+
+/// Decides whether a slot request is allowed, e.g. to enforce quotas.
+///
+/// Reject with `not-acceptable` for files that are too large, and with
+/// `resource-constraint` or `forbidden` for users over quota or not allowed
+/// to upload.
+pub trait Policy: Send + Sync + 'static {
+    /// Check `request`.
+    fn check<'a>(&'a self, request: &'a SlotRequest) -> BoxFuture<'a, Result<(), Rejection>>;
+}
+
+impl<F> Policy for F
+where
+    F: Fn(&SlotRequest) -> Result<(), Rejection> + Send + Sync + 'static,
+{
+    fn check<'a>(&'a self, request: &'a SlotRequest) -> BoxFuture<'a, Result<(), Rejection>> {
+        Box::pin(future::ready(self(request)))
+    }
+}
+
+/// Extract a request for an upload slot.
+///
+/// Rejects with `item-not-found` for other stanzas, and with `bad-request`
+/// if the request lacks a filename or size.
+pub fn request() -> impl Filter<Extract = One<SlotRequest>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(match stanza {
+            Stanza::Iq(Iq::Get {
+                from,
+                to,
+                id,
+                payload,
+            }) if payload.is("request", NS) => {
+                let size = payload.attr("size").and_then(|size| size.parse().ok());
+                match (payload.attr("filename"), size) {
+                    (Some(filename), Some(size)) => Ok(SlotRequest {
+                        from: from.clone(),
+                        to: to.clone(),
+                        filename: filename.to_owned(),
+                        size,
+                        content_type: payload.attr("content-type").map(str::to_owned),
+                        id: id.clone(),
+                    }),
+                    _ => Err(reject::bad_request()),
+                }
+            }
+            _ => Err(reject::item_not_found()),
+        })
+    })
+    .advertises(NS)
+}
+
+/// An upload service answering slot requests from a [`SlotBackend`].
+pub struct Upload<B> {
+    backend: Arc<B>,
+    max_size: Option<u64>,
+    policies: Vec<Arc<dyn Policy>>,
+}
+
+impl<B: SlotBackend> Upload<B> {
+    /// A service handing out slots from `backend`.
+    pub fn new(backend: B) -> Self {
+        Upload {
+            backend: Arc::new(backend),
+            max_size: None,
+            policies: Vec::new(),
+        }
+    }
+
+    /// Reject files larger than `bytes` with `not-acceptable`.
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Check requests with `policy` before asking the backend, after the
+    /// size limit and any policy added before.
+    pub fn policy(mut self, policy: impl Policy) -> Self {
+        self.policies.push(Arc::new(policy));
+        self
+    }
+
+    /// Serve slot requests.
+    pub fn filter(self) -> impl Filter<Extract = One<Iq>, Error = Rejection> + Clone {
+        let upload = Arc::new(self);
+        request().and_then(move |request: SlotRequest| {
+            let upload = upload.clone();
+            async move {
+                if upload.max_size.map_or(false, |max| request.size > max) {
+                    return Err(reject::not_acceptable());
+                }
+                for policy in &upload.policies {
+                    policy.check(&request).await?;
+                }
+                let slot = upload.backend.slot(&request).await?;
+                Ok(request.slot(slot))
+            }
+        })
+    }
+}
+
+impl<B> fmt::Debug for Upload<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Upload")
+            .field("max_size", &self.max_size)
+            .field("policies", &self.policies.len())
+            .finish()
+    }
+}
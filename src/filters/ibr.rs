@@ -0,0 +1,555 @@
+//! In-Band Registration (XEP-0077).
+//!
+//! - `wax::ibr::get()` - Match requests for the registration form
+//! - `wax::ibr::set()` - Extract a registration [`Submission`]
+//! - `wax::ibr::remove()` - Match requests to cancel a registration
+//!
+//! Answer a [`Request`] with a [`Form`], and a [`Submission`] with
+//! [`Submission::success`] or a rejection: `wax::reject::conflict()` if the
+//! username is taken, `wax::reject::not_acceptable()` if required fields are
+//! missing.
+//!
+//! [`Registrations`] does all of that over a [`Namespace`] of the shared
+//! [store](crate::store).
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::ibr::{self, Form, Request, Submission};
+//! use wax::Filter;
+//!
+//! let form = ibr::get().map(|request: Request| {
+//!     request.form(
+//!         Form::new()
+//!             .instructions("Enter your phone number and API token.")
+//!             .field("username")
+//!             .field("password"),
+//!     )
+//! });
+//!
+//! let submit = ibr::set().and_then(|submission: Submission| async move {
+//!     let phone = submission.username().ok_or_else(wax::reject::not_acceptable)?;
+//!     // store the registration...
+//!     Ok::<_, wax::Rejection>(submission.success())
+//! });
+//!
+//! let routes = form.or(submit);
+//! ```
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::jid::{BareJid, Jid};
+use xmpp_parsers::minidom::Element;
+
+use crate::filter::{filter_fn_one, Filter};
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+use crate::store::Namespace;
+
+/// The `jabber:iq:register` namespace.
+pub const NS: &str = "jabber:iq:register";
+
+/// The `jabber:x:data` namespace of data forms.
+pub const NS_DATA: &str = "jabber:x:data";
+
+/// A request for the registration form.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Request {
+    /// The entity registering.
+    pub from: Option<Jid>,
+    /// The entity registered with.
+    pub to: Option<Jid>,
+    id: String,
+}
+
+impl Request {
+    /// Answer with `form`.
+    pub fn form(self, form: Form) -> Iq {
+        result(self.from, self.to, self.id, Some(form.into()))
+    }
+}
+
+/// A submitted registration.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Submission {
+    /// The entity registering.
+    pub from: Option<Jid>,
+    /// The entity registered with.
+    pub to: Option<Jid>,
+    /// The submitted fields, by element name (`username`, `password`, ...).
+    pub fields: BTreeMap<String, String>,
+    /// The submitted data form, if the form was extended with one.
+    pub form: Option<Element>,
+    id: String,
+}
+
+impl Submission {
+    /// The value of the field `name`, if submitted.
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields.get(name).map(String::as_str)
+    }
+
+    /// The `username` field.
+    pub fn username(&self) -> Option<&str> {
+        self.field("username")
+    }
+
+    /// The `password` field.
+    pub fn password(&self) -> Option<&str> {
+        self.field("password")
+    }
+
+    /// The `email` field.
+    pub fn email(&self) -> Option<&str> {
+        self.field("email")
+    }
+
+    /// Acknowledge the registration.
+    pub fn success(self) -> Iq {
+        result(self.from, self.to, self.id, None)
+    }
+}
+
+/// A request to cancel a registration.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Removal {
+    /// The entity unregistering.
+    pub from: Option<Jid>,
+    /// The entity unregistered from.
+    pub to: Option<Jid>,
+    id: String,
+}
+
+impl Removal {
+    /// Acknowledge the cancellation.
+    pub fn success(self) -> Iq {
+        result(self.from, self.to, self.id, None)
+    }
+}
+
+/// The registration form sent in answer to a [`Request`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Form {
+    instructions: Option<String>,
+    registered: bool,
+    fields: Vec<(String, Option<String>)>,
+    data_form: Option<Element>,
+}
+
+impl Form {
+    /// An empty form.
+    pub fn new() -> Self {
+        Form::default()
+    }
+
+    /// Set the instructions shown to the user.
+    pub fn instructions(mut self, instructions: impl Into<String>) -> Self {
+        self.instructions = Some(instructions.into());
+        self
+    }
+
+    /// Ask for the field `name`, e.g. `username` or `password`.
+    pub fn field(mut self, name: impl Into<String>) -> Self {
+        self.fields.push((name.into(), None));
+        self
+    }
+
+    /// Include the field `name` with its current value.
+    pub fn field_value(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.fields.push((name.into(), Some(value.into())));
+        self
+    }
+
+    /// Mark the requester as already registered.
+    pub fn registered(mut self) -> Self {
+        self.registered = true;
+        self
+    }
+
+    /// Extend the form with a `jabber:x:data` form.
+    pub fn data_form(mut self, form: Element) -> Self {
+        self.data_form = Some(form);
+        self
+    }
+}
+
+impl From<Form> for Element {
+    fn from(form: Form) -> Element {
+        let mut query = Element::builder("query", NS);
+        if let Some(instructions) = form.instructions {
+            query = query.append(
+                Element::builder("instructions", NS)
+                    .append(instructions)
+                    .build(),
+            );
+        }
+        if form.registered {
+            query = query.append(Element::builder("registered", NS).build());
+        }
+        for (name, value) in form.fields {
+            let field = Element::builder(name, NS);
+            query = query.append(match value {
+                Some(value) => field.append(value).build(),
+                None => field.build(),
+            });
+        }
+        if let Some(data_form) = form.data_form {
+            query = query.append(data_form);
+        }
+        query.build()
+    }
+}
+
+impl Form {
+    // This form, marked as registered and filled with `fields`.
+    fn filled(&self, fields: &BTreeMap<String, String>) -> Form {
+        let mut form = self.clone().registered();
+        for (name, value) in &mut form.fields {
+            if let Some(registered) = fields.get(name.as_str()) {
+                *value = Some(registered.clone());
+            }
+        }
+        form
+    }
+}
+
+/// Registrations kept in a [`Namespace`] of the shared store: the fields of
+/// each registered entity, under its bare JID. Clones share the same store.
+#[derive(Clone, Debug)]
+pub struct Registrations {
+    store: Namespace,
+    form: Arc<Form>,
+}
+
+impl Registrations {
+    /// Keep registrations in `store`, asking for the fields of `form`.
+    ///
+    /// Every field of `form` is required, and only those are stored.
+    pub fn new(store: Namespace, form: Form) -> Self {
+        Registrations {
+            store,
+            form: Arc::new(form),
+        }
+    }
+
+    /// The fields `jid` registered with, if it is registered.
+    pub async fn get(&self, jid: &BareJid) -> Result<Option<BTreeMap<String, String>>, Rejection> {
+        self.store.get(jid.as_str()).await
+    }
+
+    /// Register `jid` with `fields`, replacing its previous registration.
+    ///
+    /// Rejects with `conflict` if another entity registered the same
+    /// `username`. Checking that loads every registration.
+    pub async fn register(
+        &self,
+        jid: &BareJid,
+        fields: &BTreeMap<String, String>,
+    ) -> Result<(), Rejection> {
+        if let Some(username) = fields.get("username") {
+            for other in self.store.list().await? {
+                if other == jid.as_str() {
+                    continue;
+                }
+                let registered = self.store.get::<BTreeMap<String, String>>(&other).await?;
+                if registered
+                    .as_ref()
+                    .and_then(|fields| fields.get("username"))
+                    == Some(username)
+                {
+                    return Err(reject::conflict());
+                }
+            }
+        }
+        self.store.put(jid.as_str(), fields).await
+    }
+
+    /// Cancel the registration of `jid`.
+    ///
+    /// Rejects with `registration-required` if it is not registered.
+    pub async fn unregister(&self, jid: &BareJid) -> Result<(), Rejection> {
+        if self.get(jid).await?.is_none() {
+            return Err(reject::registration_required());
+        }
+        self.store.delete(jid.as_str()).await
+    }
+
+    /// Serve registration requests on behalf of the bare JID of their
+    /// sender.
+    ///
+    /// Registered entities get the form filled with their registration.
+    /// Submissions missing a field are rejected with `not-acceptable`.
+    pub fn filter(&self) -> impl Filter<Extract = One<Iq>, Error = Rejection> + Clone {
+        let getting = {
+            let registrations = self.clone();
+            get().and_then(move |request: Request| {
+                let registrations = registrations.clone();
+                async move {
+                    let from = request.from.clone().ok_or_else(reject::bad_request)?;
+                    let form = match registrations.get(&from.to_bare()).await? {
+                        Some(fields) => registrations.form.filled(&fields),
+                        None => (*registrations.form).clone(),
+                    };
+                    Ok::<_, Rejection>(request.form(form))
+                }
+            })
+        };
+        let submitting = {
+            let registrations = self.clone();
+            set().and_then(move |submission: Submission| {
+                let registrations = registrations.clone();
+                async move {
+                    let from = submission.from.clone().ok_or_else(reject::bad_request)?;
+                    let mut fields = BTreeMap::new();
+                    for (name, _) in &registrations.form.fields {
+                        match submission.field(name) {
+                            Some(value) if !value.is_empty() => {
+                                fields.insert(name.clone(), value.to_owned());
+                            }
+                            _ => return Err(reject::not_acceptable()),
+                        }
+                    }
+                    registrations.register(&from.to_bare(), &fields).await?;
+                    Ok(submission.success())
+                }
+            })
+        };
+        let registrations = self.clone();
+        let removing = remove().and_then(move |removal: Removal| {
+            let registrations = registrations.clone();
+            async move {
+                let from = removal.from.clone().ok_or_else(reject::bad_request)?;
+                registrations.unregister(&from.to_bare()).await?;
+                Ok::<_, Rejection>(removal.success())
+            }
+        });
+        getting.or(submitting).unify().or(removing).unify()
+    }
+}
+
+/// Match requests for the registration form.
+pub fn get() -> impl Filter<Extract = One<Request>, Error = Rejection> + Copy {
+    let get = filter_fn_one(|stanza: &Stanza| match stanza {
+        Stanza::Iq(Iq::Get {
+            from,
+            to,
+            id,
+            payload,
+        }) if payload.is("query", NS) => future::ok(Request {
+            from: from.clone(),
+            to: to.clone(),
+            id: id.clone(),
+        }),
+        _ => future::err(reject::item_not_found()),
+    });
+    get.advertises(NS)
+}
+
+/// Extract a submitted registration.
+///
+/// Cancellations are left to [`remove()`].
+pub fn set() -> impl Filter<Extract = One<Submission>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| match registration_set(stanza) {
+        Some((from, to, id, query)) if query.get_child("remove", NS).is_none() => {
+            future::ok(Submission {
+                from: from.clone(),
+                to: to.clone(),
+                fields: query
+                    .children()
+                    .filter(|child| child.ns() == NS && !NOT_FIELDS.contains(&child.name()))
+                    .map(|child| (child.name().to_owned(), child.text()))
+                    .collect(),
+                form: query.get_child("x", NS_DATA).cloned(),
+                id: id.clone(),
+            })
+        }
+        _ => future::err(reject::item_not_found()),
+    })
+}
+
+/// Match requests to cancel a registration.
+pub fn remove() -> impl Filter<Extract = One<Removal>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| match registration_set(stanza) {
+        Some((from, to, id, query)) if query.get_child("remove", NS).is_some() => {
+            future::ok(Removal {
+                from: from.clone(),
+                to: to.clone(),
+                id: id.clone(),
+            })
+        }
+        _ => future::err(reject::item_not_found()),
+    })
+}
+
+// Children of a registration query that are not fields.
+const NOT_FIELDS: &[&str] = &["instructions", "registered", "remove"];
+
+type Addressed<'a> = (&'a Option<Jid>, &'a Option<Jid>, &'a String, &'a Element);
+
+fn registration_set(stanza: &Stanza) -> Option<Addressed<'_>> {
+    match stanza {
+        Stanza::Iq(Iq::Set {
+            from,
+            to,
+            id,
+            payload,
+        }) if payload.is("query", NS) => Some((from, to, id, payload)),
+        _ => None,
+    }
+}
+
+fn result(from: Option<Jid>, to: Option<Jid>, id: String, payload: Option<Element>) -> Iq {
+    Iq::Result {
+        from: to,
+        to: from,
+        id,
+        payload,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use xmpp_parsers::stanza_error::DefinedCondition;
+
+    use super::*;
+    use crate::store::{KvStore, MemoryStore};
+
+    fn registrations() -> Registrations {
+        Registrations::new(
+            MemoryStore::new().namespace("registrations"),
+            Form::new().field("username").field("password"),
+        )
+    }
+
+    fn iq(from: &str, set: bool, children: &[(&str, &str)]) -> Stanza {
+        let payload = children
+            .iter()
+            .fold(Element::builder("query", NS), |query, (name, value)| {
+                query.append(Element::builder(*name, NS).append(*value).build())
+            })
+            .build();
+        let from = Some(from.parse().unwrap());
+        let to = Some("gateway.example.org".parse().unwrap());
+        let id = "reg".to_owned();
+        Stanza::Iq(if set {
+            Iq::Set {
+                from,
+                to,
+                id,
+                payload,
+            }
+        } else {
+            Iq::Get {
+                from,
+                to,
+                id,
+                payload,
+            }
+        })
+    }
+
+    async fn call(registrations: &Registrations, stanza: Stanza) -> Iq {
+        let response = crate::service(registrations.filter())
+            .call_stanza(stanza)
+            .await
+            .unwrap();
+        match response.stanzas() {
+            [Stanza::Iq(iq)] => iq.clone(),
+            other => panic!("expected an iq, got {:?}", other),
+        }
+    }
+
+    fn condition(iq: &Iq) -> Option<&DefinedCondition> {
+        match iq {
+            Iq::Error { error, .. } => Some(&error.defined_condition),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn registers_and_fills_the_form() {
+        let registrations = registrations();
+        let juliet = "juliet@capulet.lit/balcony";
+
+        let Iq::Result {
+            payload: Some(form),
+            ..
+        } = call(&registrations, iq(juliet, false, &[])).await
+        else {
+            panic!("expected the form");
+        };
+        assert!(!form.has_child("registered", NS));
+        assert_eq!(
+            form.get_child("username", NS).map(Element::text),
+            Some(String::new())
+        );
+
+        let submission = [
+            ("username", "juliet"),
+            ("password", "R0m30"),
+            ("email", "x"),
+        ];
+        let result = call(&registrations, iq(juliet, true, &submission)).await;
+        assert!(matches!(result, Iq::Result { payload: None, .. }));
+        let stored = registrations
+            .get(&"juliet@capulet.lit".parse().unwrap())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.keys().collect::<Vec<_>>(), ["password", "username"]);
+
+        let Iq::Result {
+            payload: Some(form),
+            ..
+        } = call(&registrations, iq("juliet@capulet.lit/chamber", false, &[])).await
+        else {
+            panic!("expected the form");
+        };
+        assert!(form.has_child("registered", NS));
+        assert_eq!(
+            form.get_child("username", NS).map(Element::text),
+            Some("juliet".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_incomplete_and_conflicting_submissions() {
+        let registrations = registrations();
+
+        let incomplete = iq("juliet@capulet.lit", true, &[("username", "juliet")]);
+        let error = call(&registrations, incomplete).await;
+        assert_eq!(condition(&error), Some(&DefinedCondition::NotAcceptable));
+
+        let submission = [("username", "juliet"), ("password", "R0m30")];
+        call(&registrations, iq("juliet@capulet.lit", true, &submission)).await;
+        let again = call(&registrations, iq("juliet@capulet.lit", true, &submission)).await;
+        assert_eq!(condition(&again), None);
+        let taken = call(&registrations, iq("nurse@capulet.lit", true, &submission)).await;
+        assert_eq!(condition(&taken), Some(&DefinedCondition::Conflict));
+    }
+
+    #[tokio::test]
+    async fn cancels_registrations() {
+        let registrations = registrations();
+        let juliet = "juliet@capulet.lit";
+        let submission = [("username", "juliet"), ("password", "R0m30")];
+        call(&registrations, iq(juliet, true, &submission)).await;
+
+        let removed = call(&registrations, iq(juliet, true, &[("remove", "")])).await;
+        assert_eq!(condition(&removed), None);
+        assert_eq!(
+            registrations.get(&juliet.parse().unwrap()).await.unwrap(),
+            None
+        );
+
+        let again = call(&registrations, iq(juliet, true, &[("remove", "")])).await;
+        assert_eq!(
+            condition(&again),
+            Some(&DefinedCondition::RegistrationRequired)
+        );
+    }
+}
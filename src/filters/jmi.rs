@@ -0,0 +1,231 @@
+//! Jingle Message Initiation (XEP-0353).
+//!
+//! - `wax::jmi::param()` - Extract a [`Jmi`] message, whichever its action
+//! - `wax::jmi::propose()` - Extract a call proposal
+//! - `wax::jmi::accept()` - Extract the acceptance of a call by another device
+//! - `wax::jmi::reject()` - Extract the rejection of a call
+//! - `wax::jmi::retract()` - Extract the retraction of a proposal
+//!
+//! A call gateway answers a proposal with [`Action::Proceed`] and then waits
+//! for the Jingle `session-initiate` of the caller, or with
+//! [`Action::Reject`].
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::jmi::{Action, Jmi};
+//! use wax::Filter;
+//!
+//! let ring = wax::jmi::propose().map(|call: Jmi| {
+//!     // ring the phone...
+//!     Jmi::new(call.sid, Action::Proceed).to(call.from.unwrap())
+//! });
+//! ```
+
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::jid::Jid;
+use xmpp_parsers::message::{Message, MessageType};
+use xmpp_parsers::minidom::Element;
+
+use crate::filter::{filter_fn_one, Filter};
+use crate::generic::One;
+use crate::hints::Hint;
+use crate::reject::{self, Rejection};
+
+/// The `urn:xmpp:jingle-message:0` namespace.
+pub const NS: &str = "urn:xmpp:jingle-message:0";
+
+/// What a message does to a call.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    /// The caller proposes a call.
+    Propose,
+    /// The caller withdraws its proposal.
+    Retract,
+    /// A device of the callee accepts the call.
+    Accept,
+    /// The device accepting the call lets the caller go on.
+    Proceed,
+    /// The callee declines the call.
+    Reject,
+}
+
+impl Action {
+    const ALL: [Action; 5] = [
+        Action::Propose,
+        Action::Retract,
+        Action::Accept,
+        Action::Proceed,
+        Action::Reject,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Action::Propose => "propose",
+            Action::Retract => "retract",
+            Action::Accept => "accept",
+            Action::Proceed => "proceed",
+            Action::Reject => "reject",
+        }
+    }
+}
+
+/// The description of media proposed for a call.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Description {
+    /// The namespace of the application, e.g. `urn:xmpp:jingle:apps:rtp:1`.
+    pub ns: String,
+    /// The kind of media, e.g. `audio` or `video`, if given.
+    pub media: Option<String>,
+}
+
+impl Description {
+    /// An RTP session (XEP-0167) for `media`.
+    pub fn rtp(media: impl Into<String>) -> Self {
+        Description {
+            ns: "urn:xmpp:jingle:apps:rtp:1".to_owned(),
+            media: Some(media.into()),
+        }
+    }
+}
+
+/// A Jingle message initiation message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Jmi {
+    /// The sender, when extracted from a stanza.
+    pub from: Option<Jid>,
+    /// What the message does to the call.
+    pub action: Action,
+    /// The id of the Jingle session.
+    pub sid: String,
+    /// The media proposed, empty unless proposing.
+    pub descriptions: Vec<Description>,
+}
+
+impl Jmi {
+    /// A message doing `action` to the session with `sid`.
+    pub fn new(sid: impl Into<String>, action: Action) -> Self {
+        Jmi {
+            from: None,
+            action,
+            sid: sid.into(),
+            descriptions: Vec::new(),
+        }
+    }
+
+    /// Propose `description`.
+    pub fn description(mut self, description: Description) -> Self {
+        self.descriptions.push(description);
+        self
+    }
+
+    /// A chat message sending this to `to`.
+    ///
+    /// The message carries a `store` hint so that archives keep it despite
+    /// its lack of body.
+    pub fn to(self, to: Jid) -> Message {
+        let mut msg = Message::new(Some(to));
+        msg.type_ = MessageType::Chat;
+        msg.payloads.push(Element::from(self));
+        msg.payloads.push(Element::from(Hint::Store));
+        msg
+    }
+}
+
+impl From<Jmi> for Element {
+    fn from(jmi: Jmi) -> Element {
+        Element::builder(jmi.action.as_str(), NS)
+            .attr("id", jmi.sid)
+            .append_all(jmi.descriptions.into_iter().map(|description| {
+                Element::builder("description", description.ns)
+                    .attr("media", description.media)
+                    .build()
+            }))
+            .build()
+    }
+}
+
+/// Extract a Jingle message initiation message.
+///
+/// Rejects with `item-not-found` for other stanzas, and with `bad-request`
+/// if the session id is missing.
+pub fn param() -> impl Filter<Extract = One<Jmi>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(jmi_of(stanza).unwrap_or_else(|| Err(reject::item_not_found())))
+    })
+    .advertises(NS)
+}
+
+/// Extract a call proposal.
+///
+/// Rejects with `item-not-found` for other stanzas, and with `bad-request`
+/// if the session id is missing.
+pub fn propose() -> impl Filter<Extract = One<Jmi>, Error = Rejection> + Copy {
+    action(Action::Propose)
+}
+
+/// Extract the acceptance of a call by a device of the callee.
+///
+/// Rejects with `item-not-found` for other stanzas, and with `bad-request`
+/// if the session id is missing.
+pub fn accept() -> impl Filter<Extract = One<Jmi>, Error = Rejection> + Copy {
+    action(Action::Accept)
+}
+
+/// Extract the rejection of a call.
+///
+/// Rejects with `item-not-found` for other stanzas, and with `bad-request`
+/// if the session id is missing.
+pub fn reject() -> impl Filter<Extract = One<Jmi>, Error = Rejection> + Copy {
+    action(Action::Reject)
+}
+
+/// Extract the retraction of a call proposal.
+///
+/// Rejects with `item-not-found` for other stanzas, and with `bad-request`
+/// if the session id is missing.
+pub fn retract() -> impl Filter<Extract = One<Jmi>, Error = Rejection> + Copy {
+    action(Action::Retract)
+}
+
+fn action(action: Action) -> impl Filter<Extract = One<Jmi>, Error = Rejection> + Copy {
+    filter_fn_one(move |stanza: &Stanza| {
+        future::ready(match jmi_of(stanza) {
+            Some(Ok(jmi)) if jmi.action == action => Ok(jmi),
+            Some(Err(rejection)) => Err(rejection),
+            _ => Err(reject::item_not_found()),
+        })
+    })
+    .advertises(NS)
+}
+
+fn jmi_of(stanza: &Stanza) -> Option<Result<Jmi, Rejection>> {
+    let Stanza::Message(msg) = stanza else {
+        return None;
+    };
+    let (action, elem) = msg.payloads.iter().find_map(|payload| {
+        Action::ALL
+            .into_iter()
+            .find(|action| payload.is(action.as_str(), NS))
+            .map(|action| (action, payload))
+    })?;
+    let Some(sid) = elem.attr("id") else {
+        tracing::debug!("{} without session id", action.as_str());
+        return Some(Err(reject::bad_request()));
+    };
+    let descriptions = elem
+        .children()
+        .filter(|child| child.name() == "description")
+        .map(|description| Description {
+            ns: description.ns(),
+            media: description.attr("media").map(str::to_owned),
+        })
+        .collect();
+    Some(Ok(Jmi {
+        from: msg.from.clone(),
+        action,
+        sid: sid.to_owned(),
+        descriptions,
+    }))
+}
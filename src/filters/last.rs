@@ -0,0 +1,448 @@
+//! Last Activity (XEP-0012).
+//!
+//! - `wax::last::request()` - Extract a [`LastRequest`]
+//!
+//! To answer queries without writing a handler, give [`LastActivity`] to
+//! `Server::last_activity`: the server then records when each JID was last
+//! seen, and [`LastActivity::filter`] answers from those records. Queries to
+//! the component itself get its uptime; queries about other JIDs are
+//! forbidden unless allowed with [`LastActivity::authorize`].
+//!
+//! Records live in a [`Namespace`] of the shared
+//! [`KvStore`](crate::store::KvStore), keyed by bare JID. The server keeps
+//! the latest records in memory and writes them to the store at most once
+//! per [`LastActivity::flush_interval`].
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::last::LastActivity;
+//! use wax::store::{KvStore, MemoryStore};
+//! use wax::Filter;
+//!
+//! let last = LastActivity::new(MemoryStore::new().namespace("last"))
+//!     .authorize(|asker, jid| asker.to_bare() == *jid);
+//!
+//! component
+//!     .serve(last.filter().or(other_routes))
+//!     .last_activity(last)
+//!     .run()
+//!     .await?;
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use futures_util::future::{self, BoxFuture};
+use tokio_xmpp::Stanza;
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::jid::{BareJid, Jid};
+use xmpp_parsers::minidom::Element;
+use xmpp_parsers::presence::Type as PresenceType;
+
+use crate::filter::{filter_fn_one, Filter};
+use crate::filters::stanza::from_of;
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+use crate::store::Namespace;
+
+/// The `jabber:iq:last` namespace.
+pub const NS: &str = "jabber:iq:last";
+
+/// A last activity query.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LastRequest {
+    /// The entity asking.
+    pub from: Option<Jid>,
+    /// The entity asked about.
+    pub to: Option<Jid>,
+    id: String,
+}
+
+impl LastRequest {
+    /// Answer that the entity was last active `seconds` ago, with the status
+    /// it left with, if any.
+    pub fn result(self, seconds: u64, status: Option<String>) -> Iq {
+        Iq::Result {
+            from: self.to,
+            to: self.from,
+            id: self.id,
+            payload: Some(
+                Element::builder("query", NS)
+                    .attr("seconds", seconds.to_string())
+                    .append_all(status)
+                    .build(),
+            ),
+        }
+    }
+}
+
+/// Extract a last activity query.
+///
+/// Rejects with `item-not-found` for other stanzas.
+pub fn request() -> impl Filter<Extract = One<LastRequest>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(match stanza {
+            Stanza::Iq(Iq::Get {
+                from,
+                to,
+                id,
+                payload,
+            }) if payload.is("query", NS) => Ok(LastRequest {
+                from: from.clone(),
+                to: to.clone(),
+                id: id.clone(),
+            }),
+            _ => Err(reject::item_not_found()),
+        })
+    })
+    .advertises(NS)
+}
+
+// When a JID was last seen, in seconds since the epoch, and the status it
+// left with.
+type Record = (u64, Option<String>);
+
+type Authorize = Arc<dyn Fn(&Jid, &BareJid) -> bool + Send + Sync>;
+
+// Records observed since the last write to the store.
+struct Pending {
+    records: HashMap<BareJid, Record>,
+    flushing: bool,
+    flushed_at: Instant,
+}
+
+/// A tracker of when JIDs were last seen, shared by clones.
+#[derive(Clone)]
+pub struct LastActivity {
+    store: Namespace,
+    started: Arc<SystemTime>,
+    authorize: Option<Authorize>,
+    pending: Arc<Mutex<Pending>>,
+    flush_interval: Duration,
+}
+
+impl LastActivity {
+    /// Track activity in `store`, counting uptime from now.
+    pub fn new(store: Namespace) -> Self {
+        LastActivity {
+            store,
+            started: Arc::new(SystemTime::now()),
+            authorize: None,
+            pending: Arc::new(Mutex::new(Pending {
+                records: HashMap::new(),
+                flushing: false,
+                flushed_at: Instant::now(),
+            })),
+            flush_interval: Duration::from_secs(10),
+        }
+    }
+
+    /// Let [`filter`](Self::filter) answer when `allow` returns true for the
+    /// asking JID and the bare JID asked about.
+    ///
+    /// Without it, every query about a JID other than the component is
+    /// rejected with `forbidden`.
+    pub fn authorize(
+        mut self,
+        allow: impl Fn(&Jid, &BareJid) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.authorize = Some(Arc::new(allow));
+        self
+    }
+
+    /// Write observed activity to the store at most once per `interval`.
+    /// Defaults to ten seconds.
+    pub fn flush_interval(mut self, interval: Duration) -> Self {
+        self.flush_interval = interval;
+        self
+    }
+
+    /// Record that `jid` was active just now, leaving with `status` if it
+    /// went offline.
+    pub async fn seen(&self, jid: &BareJid, status: Option<String>) -> Result<(), Rejection> {
+        self.store
+            .put(jid.as_str(), &(unix_time(SystemTime::now()), status))
+            .await
+    }
+
+    /// How many seconds ago `jid` was last seen, and the status it left
+    /// with, if it was ever seen.
+    pub async fn last_seen(
+        &self,
+        jid: &BareJid,
+    ) -> Result<Option<(u64, Option<String>)>, Rejection> {
+        let pending = self.lock().records.get(jid).cloned();
+        let record = match pending {
+            Some(record) => Some(record),
+            None => self.store.get::<Record>(jid.as_str()).await?,
+        };
+        let now = unix_time(SystemTime::now());
+        Ok(record.map(|(at, status)| (now.saturating_sub(at), status)))
+    }
+
+    /// Seconds since this tracker was created.
+    pub fn uptime(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(*self.started)
+            .map_or(0, |uptime| uptime.as_secs())
+    }
+
+    /// Write the activity observed by the server to the store.
+    ///
+    /// The server flushes on its own as stanzas come in and when it shuts
+    /// down.
+    pub async fn flush(&self) {
+        // Records stay pending until written, so queries keep seeing them.
+        let records = self.lock().records.clone();
+        for (jid, record) in records {
+            if let Err(rejection) = self.store.put(jid.as_str(), &record).await {
+                tracing::warn!("failed to record activity of {}: {:?}", jid, rejection);
+                continue;
+            }
+            let mut pending = self.lock();
+            if pending.records.get(&jid) == Some(&record) {
+                pending.records.remove(&jid);
+            }
+        }
+    }
+
+    /// Answer last activity queries.
+    ///
+    /// Queries to a JID without a node get the uptime of the component.
+    /// Others are rejected with `forbidden` unless [`authorize`](Self::authorize)
+    /// allows the asker, then get the activity recorded for their bare JID,
+    /// and are rejected with `item-not-found` if there is none.
+    pub fn filter(&self) -> impl Filter<Extract = One<Iq>, Error = Rejection> + Clone {
+        let last = self.clone();
+        request().and_then(move |request: LastRequest| {
+            let last = last.clone();
+            async move {
+                let to = request.to.clone().ok_or_else(reject::bad_request)?;
+                if to.node().is_none() {
+                    let uptime = last.uptime();
+                    return Ok::<_, Rejection>(request.result(uptime, None));
+                }
+                let jid = to.to_bare();
+                let allowed = match (&last.authorize, &request.from) {
+                    (Some(allow), Some(from)) => allow(from, &jid),
+                    _ => false,
+                };
+                if !allowed {
+                    return Err(reject::forbidden());
+                }
+                match last.last_seen(&jid).await? {
+                    Some((seconds, status)) => Ok(request.result(seconds, status)),
+                    None => Err(reject::item_not_found()),
+                }
+            }
+        })
+    }
+
+    // Record the sender of `stanza` in memory, with the status of an
+    // unavailable presence. Returns the write of the pending records when
+    // one is due and no other is running.
+    pub(crate) fn observe(&self, stanza: &Stanza) -> Option<BoxFuture<'static, ()>> {
+        let from = from_of(stanza)?.to_bare();
+        let status = match stanza {
+            Stanza::Presence(presence) if presence.type_ == PresenceType::Unavailable => {
+                presence.statuses.values().next().cloned()
+            }
+            _ => None,
+        };
+        let mut pending = self.lock();
+        pending
+            .records
+            .insert(from, (unix_time(SystemTime::now()), status));
+        if pending.flushing || pending.flushed_at.elapsed() < self.flush_interval {
+            return None;
+        }
+        pending.flushing = true;
+        drop(pending);
+
+        let last = self.clone();
+        Some(Box::pin(async move {
+            last.flush().await;
+            let mut pending = last.lock();
+            pending.flushing = false;
+            pending.flushed_at = Instant::now();
+        }))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Pending> {
+        self.pending.lock().unwrap()
+    }
+}
+
+impl fmt::Debug for LastActivity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LastActivity")
+            .field("store", &self.store)
+            .field("uptime", &self.uptime())
+            .field("flush_interval", &self.flush_interval)
+            .finish()
+    }
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use xmpp_parsers::message::{Lang, Message};
+    use xmpp_parsers::presence::Presence;
+    use xmpp_parsers::stanza_error::DefinedCondition;
+
+    use super::*;
+    use crate::store::{KvStore, MemoryStore};
+
+    const JULIET: &str = "juliet@capulet.lit/balcony";
+    const ROMEO: &str = "romeo@montague.lit/orchard";
+
+    fn tracker() -> LastActivity {
+        LastActivity::new(MemoryStore::new().namespace("last"))
+    }
+
+    fn bare(jid: &str) -> BareJid {
+        jid.parse::<Jid>().unwrap().to_bare()
+    }
+
+    fn query(from: &str, to: &str) -> Stanza {
+        Stanza::Iq(Iq::Get {
+            from: Some(from.parse().unwrap()),
+            to: Some(to.parse().unwrap()),
+            id: "last".to_owned(),
+            payload: Element::builder("query", NS).build(),
+        })
+    }
+
+    fn message(from: &str) -> Stanza {
+        let mut msg = Message::new(None);
+        msg.from = Some(from.parse().unwrap());
+        Stanza::Message(msg)
+    }
+
+    async fn call(last: &LastActivity, stanza: Stanza) -> Iq {
+        let response = crate::service(last.filter())
+            .call_stanza(stanza)
+            .await
+            .unwrap();
+        match response.stanzas() {
+            [Stanza::Iq(iq)] => iq.clone(),
+            other => panic!("expected an iq, got {:?}", other),
+        }
+    }
+
+    fn condition(iq: &Iq) -> Option<&DefinedCondition> {
+        match iq {
+            Iq::Error { error, .. } => Some(&error.defined_condition),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn answers_uptime_to_anyone() {
+        let last = tracker();
+        let Iq::Result {
+            payload: Some(payload),
+            ..
+        } = call(&last, query(ROMEO, "last.capulet.lit")).await
+        else {
+            panic!("expected a result");
+        };
+        assert!(payload.attr("seconds").is_some());
+    }
+
+    #[tokio::test]
+    async fn forbids_queries_about_jids_by_default() {
+        let last = tracker();
+        last.seen(&bare("juliet@capulet.lit"), None).await.unwrap();
+
+        let answer = call(&last, query(JULIET, "juliet@capulet.lit")).await;
+        assert_eq!(condition(&answer), Some(&DefinedCondition::Forbidden));
+    }
+
+    #[tokio::test]
+    async fn answers_authorized_queries() {
+        let last = tracker().authorize(|asker, jid| asker.to_bare() == *jid);
+        last.seen(&bare("juliet@capulet.lit"), Some("Gone".to_owned()))
+            .await
+            .unwrap();
+
+        let Iq::Result {
+            payload: Some(payload),
+            ..
+        } = call(&last, query(JULIET, "juliet@capulet.lit")).await
+        else {
+            panic!("expected a result");
+        };
+        assert!(payload.attr("seconds").is_some());
+        assert_eq!(payload.text(), "Gone");
+
+        let denied = call(&last, query(ROMEO, "juliet@capulet.lit")).await;
+        assert_eq!(condition(&denied), Some(&DefinedCondition::Forbidden));
+
+        let unseen = call(&last, query(ROMEO, "romeo@montague.lit")).await;
+        assert_eq!(condition(&unseen), Some(&DefinedCondition::ItemNotFound));
+    }
+
+    #[tokio::test]
+    async fn keeps_activity_in_memory_until_flushed() {
+        let last = tracker().flush_interval(Duration::from_secs(60));
+        let juliet = bare(JULIET);
+
+        assert!(last.observe(&message(JULIET)).is_none());
+        assert_eq!(
+            last.store.get::<Record>(juliet.as_str()).await.unwrap(),
+            None
+        );
+        assert!(matches!(
+            last.last_seen(&juliet).await.unwrap(),
+            Some((_, None))
+        ));
+
+        last.flush().await;
+        assert!(last
+            .store
+            .get::<Record>(juliet.as_str())
+            .await
+            .unwrap()
+            .is_some());
+        assert!(last.lock().records.is_empty());
+    }
+
+    #[tokio::test]
+    async fn writes_one_batch_at_a_time() {
+        let last = tracker().flush_interval(Duration::ZERO);
+
+        let flush = last.observe(&message(JULIET)).expect("a flush is due");
+        // Only one write runs at a time; what is seen meanwhile stays
+        // pending for it.
+        assert!(last.observe(&message(ROMEO)).is_none());
+        flush.await;
+
+        for jid in [JULIET, ROMEO] {
+            let record = last.store.get::<Record>(bare(jid).as_str()).await.unwrap();
+            assert!(record.is_some(), "{jid} was not written");
+        }
+        assert!(last.observe(&message(JULIET)).is_some());
+    }
+
+    #[tokio::test]
+    async fn records_the_status_of_unavailable_presences() {
+        let last = tracker().flush_interval(Duration::from_secs(60));
+        let mut presence = Presence::new(PresenceType::Unavailable);
+        presence.from = Some(ROMEO.parse().unwrap());
+        presence
+            .statuses
+            .insert(Lang::default(), "Banished".to_owned());
+
+        assert!(last.observe(&Stanza::Presence(presence)).is_none());
+        let (_, status) = last.last_seen(&bare(ROMEO)).await.unwrap().unwrap();
+        assert_eq!(status.as_deref(), Some("Banished"));
+    }
+}
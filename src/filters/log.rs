@@ -180,20 +180,20 @@ pub(crate) mod internal {
 
     use futures_util::{ready, TryFuture};
     use pin_project::pin_project;
-    use tokio_xmpp::Stanza;
 
     use super::{Info, Log};
+    use crate::disco::Features;
     use crate::filter::{Filter, FilterBase, Internal};
     use crate::filtered_stanza;
     use crate::reject::IsReject;
-    use crate::reply::Reply;
+    use crate::reply::{Reply, Response};
 
     #[allow(missing_debug_implementations)]
-    pub struct Logged(pub(super) Option<Stanza>);
+    pub struct Logged(pub(super) Response);
 
     impl Reply for Logged {
         #[inline]
-        fn into_response(self) -> Option<Stanza> {
+        fn into_response(self) -> Response {
             self.0
         }
     }
@@ -224,6 +224,10 @@ pub(crate) mod internal {
                 started,
             }
         }
+
+        fn advertise(&self, features: &mut Features) {
+            self.filter.advertise(features);
+        }
     }
 
     #[allow(missing_debug_implementations)]
@@ -0,0 +1,207 @@
+//! JID matching filters.
+//!
+//! - `wax::from_is(jid)` - Match stanzas from one bare JID
+//! - `wax::to_is(jid)` - Match stanzas to one bare JID
+//! - `wax::from_matches("*@*.example.net")` - Match senders against a glob
+//! - `wax::from_regex(r"^\+1\d{10}@sms\.example\.net$")?` - Match senders against
+//!   a regular expression, with the `jid-regex` feature
+//!
+//! The sender filters express access rules in the route itself: a sender
+//! that does not match is rejected with `forbidden`, which is also what a
+//! stanza without a sender gets.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::Filter;
+//!
+//! let admin = wax::from_matches("*@admin.example.net").and(admin_routes);
+//! ```
+
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::jid::{BareJid, Jid};
+
+use crate::filter::{filter_fn, Filter};
+use crate::filters::stanza::{from_of, to_of};
+use crate::reject::{self, Rejection};
+
+/// Match stanzas sent by any resource of `jid`.
+///
+/// Rejects with `forbidden` for other stanzas.
+///
+/// # Example
+///
+/// ```ignore
+/// use wax::Filter;
+/// use xmpp_parsers::jid::BareJid;
+///
+/// let admin = BareJid::new("admin@example.net").unwrap();
+/// let shutdown = wax::from_is(admin).and(wax::message::body::param());
+/// ```
+pub fn from_is(jid: BareJid) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    filter_fn(move |stanza: &Stanza| {
+        if from_of(stanza).is_some_and(|from| same_bare(from, &jid)) {
+            future::ok(())
+        } else {
+            future::err(reject::forbidden())
+        }
+    })
+}
+
+/// Match stanzas sent to any resource of `jid`.
+///
+/// Rejects with `item-not-found` for other stanzas, so that the next
+/// route is tried.
+pub fn to_is(jid: BareJid) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    filter_fn(move |stanza: &Stanza| {
+        if to_of(stanza).is_some_and(|to| same_bare(to, &jid)) {
+            future::ok(())
+        } else {
+            future::err(reject::item_not_found())
+        }
+    })
+}
+
+/// Match stanzas whose sender matches the glob `pattern`.
+///
+/// `*` matches any run of characters and `?` any single one. The pattern
+/// is matched against the bare JID of the sender, or the full JID if it
+/// contains a `/`.
+///
+/// Rejects with `forbidden` for other stanzas.
+pub fn from_matches(pattern: &'static str) -> impl Filter<Extract = (), Error = Rejection> + Copy {
+    filter_fn(move |stanza: &Stanza| {
+        let matched = from_of(stanza).is_some_and(|from| {
+            if pattern.contains('/') {
+                glob(pattern, &from.to_string())
+            } else {
+                glob(pattern, from.to_bare().as_str())
+            }
+        });
+        if matched {
+            future::ok(())
+        } else {
+            future::err(reject::forbidden())
+        }
+    })
+}
+
+/// Match stanzas whose bare sender JID matches the regular expression
+/// `pattern`.
+///
+/// Available with the `jid-regex` feature. Rejects with `forbidden` for
+/// other stanzas.
+///
+/// # Errors
+///
+/// Fails if `pattern` is not a valid regular expression.
+#[cfg(feature = "jid-regex")]
+pub fn from_regex(
+    pattern: &str,
+) -> Result<impl Filter<Extract = (), Error = Rejection> + Clone, regex::Error> {
+    let regex = regex::Regex::new(pattern)?;
+    Ok(filter_fn(move |stanza: &Stanza| {
+        if from_of(stanza).is_some_and(|from| regex.is_match(from.to_bare().as_str())) {
+            future::ok(())
+        } else {
+            future::err(reject::forbidden())
+        }
+    }))
+}
+
+// Ignores ASCII case, in case either JID skipped normalization.
+fn same_bare(jid: &Jid, bare: &BareJid) -> bool {
+    jid.to_bare().as_str().eq_ignore_ascii_case(bare.as_str())
+}
+
+// Whether `text` matches `pattern`, backtracking to the last `*` on a
+// mismatch.
+fn glob(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut star = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use xmpp_parsers::iq::Iq;
+    use xmpp_parsers::minidom::Element;
+
+    use super::*;
+
+    // Whether `filter` lets through a request sent by `from`.
+    async fn passes<F>(filter: F, from: Option<&str>) -> bool
+    where
+        F: Filter<Extract = (), Error = Rejection> + Clone + Send + Sync + 'static,
+    {
+        let request = Stanza::Iq(Iq::Get {
+            from: from.map(|from| from.parse().unwrap()),
+            to: Some("gateway.example.net".parse().unwrap()),
+            id: "ping".to_owned(),
+            payload: Element::builder("ping", "urn:xmpp:ping").build(),
+        });
+        let response = crate::service(filter.map(crate::reply::iq_empty_result))
+            .call_stanza(request)
+            .await
+            .unwrap();
+        matches!(response.stanzas(), [Stanza::Iq(Iq::Result { .. })])
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob("*@*.example.net", "juliet@sms.example.net"));
+        assert!(glob("*@*.example.net", "@.example.net"));
+        assert!(!glob("*@*.example.net", "juliet@example.net"));
+        assert!(glob(
+            "juliet@capulet.lit/?alcony*",
+            "juliet@capulet.lit/balcony"
+        ));
+        assert!(!glob("juliet@capulet.lit", "juliet@capulet.lit.evil"));
+    }
+
+    #[tokio::test]
+    async fn matches_senders_against_globs() {
+        let sms = from_matches("*@sms.example.net");
+        assert!(passes(sms, Some("+15555550100@sms.example.net/phone")).await);
+        assert!(!passes(sms, Some("juliet@capulet.lit")).await);
+        assert!(!passes(sms, None).await);
+
+        let balcony = from_matches("juliet@capulet.lit/balcony");
+        assert!(passes(balcony, Some("juliet@capulet.lit/balcony")).await);
+        assert!(!passes(balcony, Some("juliet@capulet.lit/chamber")).await);
+    }
+
+    #[cfg(feature = "jid-regex")]
+    #[tokio::test]
+    async fn matches_senders_against_regexes() {
+        let us = from_regex(r"^\+1\d{10}@sms\.example\.net$").unwrap();
+        assert!(passes(us.clone(), Some("+15555550100@sms.example.net/phone")).await);
+        assert!(!passes(us.clone(), Some("+445555550100@sms.example.net")).await);
+        assert!(!passes(us, None).await);
+
+        assert!(from_regex(r"^(unclosed@example\.net$").is_err());
+    }
+}
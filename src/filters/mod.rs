@@ -3,10 +3,56 @@
 //! This module mostly serves as documentation to group together the list of
 //! built-in filters. Most of these are available at more convenient paths.
 
+pub mod addressing;
+pub mod amp;
 pub mod any;
+pub mod attention;
+pub mod avatar;
+pub mod blocking;
+pub mod cache;
+pub mod caps;
+pub mod carbons;
+pub mod chain;
+pub mod chatstate;
+pub mod command;
+pub mod correction;
+pub mod delay;
+pub mod disco;
+pub mod error;
+pub mod fallback;
+pub mod form;
+pub mod forwarded;
+pub mod hints;
+pub mod httpupload;
+pub mod ibr;
 pub mod id;
+pub mod jmi;
+pub mod last;
 pub mod log;
+pub mod matching;
+pub mod muc;
+pub mod nick;
+pub mod node;
+pub mod ns;
+pub mod oob;
+pub mod pep;
+pub mod private;
+pub mod reactions;
+pub mod receipts;
+pub mod relay;
+pub mod replies;
+pub mod resource;
+pub mod rosterx;
+pub mod rsm;
+pub mod search;
+pub mod sfs;
+pub mod shim;
 pub mod stanza;
+pub mod stanza_id;
+pub mod tel;
+pub mod vcard;
+#[cfg(feature = "webhook")]
+pub mod webhook;
 
 pub use crate::filter::BoxedFilter;
 pub use id::id;
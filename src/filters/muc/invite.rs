@@ -0,0 +1,223 @@
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::jid::{BareJid, Jid};
+use xmpp_parsers::message::Message;
+use xmpp_parsers::minidom::Element;
+
+use super::NS_USER;
+use crate::filter::{filter_fn_one, Filter};
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+
+/// The `jabber:x:conference` namespace of direct invitations (XEP-0249).
+pub const NS_CONFERENCE: &str = "jabber:x:conference";
+
+/// An invitation to a room, sent directly to the invitee (XEP-0249).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DirectInvite {
+    /// The entity inviting, when extracted from a stanza.
+    pub from: Option<Jid>,
+    /// The room.
+    pub room: BareJid,
+    /// The room password, if needed.
+    pub password: Option<String>,
+    /// Why the invitee is invited, if given.
+    pub reason: Option<String>,
+}
+
+impl DirectInvite {
+    /// An invitation to `room`.
+    pub fn new(room: BareJid) -> Self {
+        DirectInvite {
+            from: None,
+            room,
+            password: None,
+            reason: None,
+        }
+    }
+
+    /// Set the room password.
+    pub fn password(mut self, password: impl Into<String>) -> Self {
+        self.password = Some(password.into());
+        self
+    }
+
+    /// Set the reason.
+    pub fn reason(mut self, reason: impl Into<String>) -> Self {
+        self.reason = Some(reason.into());
+        self
+    }
+
+    /// A message inviting `to`.
+    pub fn to(self, to: Jid) -> Message {
+        let mut msg = Message::new(Some(to));
+        msg.payloads.push(Element::from(self));
+        msg
+    }
+}
+
+impl From<DirectInvite> for Element {
+    fn from(invite: DirectInvite) -> Element {
+        Element::builder("x", NS_CONFERENCE)
+            .attr("jid", invite.room.to_string())
+            .attr("password", invite.password)
+            .attr("reason", invite.reason)
+            .build()
+    }
+}
+
+/// Extract direct invitations to a room (XEP-0249).
+///
+/// Rejects with `item-not-found` for other stanzas, and with `bad-request`
+/// if the room is not a valid bare JID.
+pub fn direct_invite() -> impl Filter<Extract = One<DirectInvite>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        let invite = match stanza {
+            Stanza::Message(msg) => msg
+                .payloads
+                .iter()
+                .find(|payload| payload.is("x", NS_CONFERENCE))
+                .map(|x| {
+                    let room = x
+                        .attr("jid")
+                        .and_then(|room| room.parse::<BareJid>().ok())
+                        .ok_or_else(reject::bad_request)?;
+                    Ok(DirectInvite {
+                        from: msg.from.clone(),
+                        room,
+                        password: x.attr("password").map(str::to_owned),
+                        reason: x.attr("reason").map(str::to_owned),
+                    })
+                }),
+            _ => None,
+        };
+        future::ready(invite.unwrap_or_else(|| Err(reject::item_not_found())))
+    })
+    .advertises(NS_CONFERENCE)
+}
+
+/// An invitation to a room, sent through the room itself.
+///
+/// An invitation is sent to the room, which forwards it to the invitee
+/// with the room password, if any.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Invite {
+    /// The room.
+    pub room: BareJid,
+    /// The entity inviting.
+    pub from: Jid,
+    /// The invitee.
+    pub to: Jid,
+    /// Why the invitee is invited, if given.
+    pub reason: Option<String>,
+    /// The room password, if forwarded by the room.
+    pub password: Option<String>,
+}
+
+/// A declined invitation, sent through the room.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Decline {
+    /// The room.
+    pub room: BareJid,
+    /// The invitee declining.
+    pub from: Jid,
+    /// The entity that sent the invitation.
+    pub to: Jid,
+    /// Why the invitation is declined, if given.
+    pub reason: Option<String>,
+}
+
+/// Extract the invitations carried by a message, whether sent to the room
+/// or forwarded by it.
+///
+/// Rejects with `item-not-found` for other stanzas, and with `bad-request`
+/// if an invitation does not say who it is from or to.
+pub fn invites() -> impl Filter<Extract = One<Vec<Invite>>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(match mediated(stanza, "invite") {
+            Some((msg, x)) => {
+                let password = x.get_child("password", NS_USER).map(Element::text);
+                x.children()
+                    .filter(|child| child.is("invite", NS_USER))
+                    .map(|invite| {
+                        let (room, from, to) = parties(msg, invite)?;
+                        Ok(Invite {
+                            room,
+                            from,
+                            to,
+                            reason: reason(invite),
+                            password: password.clone(),
+                        })
+                    })
+                    .collect()
+            }
+            None => Err(reject::item_not_found()),
+        })
+    })
+}
+
+/// Extract a declined invitation, whether sent to the room or forwarded by
+/// it.
+///
+/// Rejects with `item-not-found` for other stanzas, and with `bad-request`
+/// if it does not say who it is from or to.
+pub fn decline() -> impl Filter<Extract = One<Decline>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(match mediated(stanza, "decline") {
+            Some((msg, x)) => {
+                let decline = x
+                    .get_child("decline", NS_USER)
+                    .expect("checked by mediated");
+                parties(msg, decline).map(|(room, from, to)| Decline {
+                    room,
+                    from,
+                    to,
+                    reason: reason(decline),
+                })
+            }
+            None => Err(reject::item_not_found()),
+        })
+    })
+}
+
+// The message and its `muc#user` payload, if it has a `name` child.
+fn mediated<'a>(stanza: &'a Stanza, name: &str) -> Option<(&'a Message, &'a Element)> {
+    let Stanza::Message(msg) = stanza else {
+        return None;
+    };
+    msg.payloads
+        .iter()
+        .find(|payload| payload.is("x", NS_USER) && payload.has_child(name, NS_USER))
+        .map(|x| (msg, x))
+}
+
+// The room, sender and recipient of an invitation or decline: a `to`
+// attribute means it is sent to the room, a `from` one that the room
+// forwards it.
+fn parties(msg: &Message, elem: &Element) -> Result<(BareJid, Jid, Jid), Rejection> {
+    let attr = |name| {
+        elem.attr(name)
+            .map(|jid| jid.parse::<Jid>().map_err(|_| reject::bad_request()))
+            .transpose()
+    };
+    let parties = match (attr("to")?, attr("from")?) {
+        (Some(to), _) => msg
+            .to
+            .as_ref()
+            .zip(msg.from.clone())
+            .map(|(room, from)| (room.to_bare(), from, to)),
+        (None, Some(from)) => msg
+            .from
+            .as_ref()
+            .zip(msg.to.clone())
+            .map(|(room, to)| (room.to_bare(), from, to)),
+        (None, None) => None,
+    };
+    parties.ok_or_else(reject::bad_request)
+}
+
+fn reason(elem: &Element) -> Option<String> {
+    elem.get_child("reason", NS_USER)
+        .map(Element::text)
+        .filter(|reason| !reason.is_empty())
+}
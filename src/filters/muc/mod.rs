@@ -0,0 +1,497 @@
+//! Multi-User Chat (XEP-0045).
+//!
+//! - `wax::muc::groupchat()` - Extract groupchat messages
+//! - `wax::muc::join()` - Extract requests to join a room
+//! - `wax::muc::leave()` - Extract occupants leaving a room
+//! - `wax::muc::room()` - Extract the room JID a stanza is addressed to
+//! - `wax::muc::nick()` - Extract the nickname a stanza is addressed to
+//! - `wax::muc::occupant()` - Extract both as an [`Occupant`]
+//! - `wax::muc::self_ping()` - Extract self-pings (XEP-0410)
+//! - `wax::muc::direct_invite()` - Extract a [`DirectInvite`] (XEP-0249)
+//! - `wax::muc::invites()` / `decline()` - Extract [`Invite`]s and [`Decline`]s
+//!   mediated by a room
+//!
+//! Rooms and occupants are addressed by JID: `room@service` is the room,
+//! `room@service/nick` the occupant `nick` in it.
+//!
+//! To run a MUC service rather than route around one, [`Rooms`] keeps a
+//! [`Room`] state machine per room and serves all of the above with
+//! [`Rooms::filter`].
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::muc::{self, Join};
+//! use wax::Filter;
+//!
+//! let join = muc::join().map(|join: Join| {
+//!     // admit join.from into join.room as join.nick...
+//!     wax::sink()
+//! });
+//!
+//! let chat = muc::groupchat()
+//!     .and(muc::room())
+//!     .map(|msg: Message, room: BareJid| {
+//!         // broadcast msg to the occupants of room...
+//!         wax::sink()
+//!     });
+//! ```
+
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::jid::{BareJid, Jid};
+use xmpp_parsers::message::{Message, MessageType};
+use xmpp_parsers::minidom::Element;
+use xmpp_parsers::presence::{Presence, Type as PresenceType};
+
+use crate::filter::{filter_fn_one, Filter};
+use crate::filters::stanza::to_of;
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+
+mod invite;
+mod room;
+
+pub use self::invite::{
+    decline, direct_invite, invites, Decline, DirectInvite, Invite, NS_CONFERENCE,
+};
+pub use self::room::{Affiliation, Role, Room, RoomConfig, RoomOccupant, Rooms};
+
+/// The `http://jabber.org/protocol/muc` namespace.
+pub const NS: &str = "http://jabber.org/protocol/muc";
+
+/// The `http://jabber.org/protocol/muc#user` namespace.
+pub const NS_USER: &str = "http://jabber.org/protocol/muc#user";
+
+/// The `urn:xmpp:ping` namespace.
+pub const NS_PING: &str = "urn:xmpp:ping";
+
+/// An occupant JID: a nickname in a room.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Occupant {
+    /// The room.
+    pub room: BareJid,
+    /// The nickname.
+    pub nick: String,
+}
+
+impl Occupant {
+    /// The full JID of the occupant, `room@service/nick`.
+    ///
+    /// `None` if `nick` is not a valid resource.
+    pub fn jid(&self) -> Option<Jid> {
+        self.room.with_resource_str(&self.nick).ok().map(Jid::from)
+    }
+}
+
+/// A request to join a room.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Join {
+    /// The real JID of the user joining.
+    pub from: Jid,
+    /// The room.
+    pub room: BareJid,
+    /// The requested nickname.
+    pub nick: String,
+    /// The room password, if given.
+    pub password: Option<String>,
+    /// How much discussion history the user wants.
+    pub history: History,
+    /// The presence of the user.
+    pub presence: Presence,
+}
+
+/// The discussion history requested when joining.
+///
+/// All limits are optional, and apply together.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct History {
+    /// The maximum number of characters.
+    pub max_chars: Option<u32>,
+    /// The maximum number of messages.
+    pub max_stanzas: Option<u32>,
+    /// Only messages from the last `seconds`.
+    pub seconds: Option<u32>,
+    /// Only messages since this timestamp.
+    pub since: Option<String>,
+}
+
+/// An occupant leaving a room.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Leave {
+    /// The real JID of the user leaving.
+    pub from: Jid,
+    /// The room.
+    pub room: BareJid,
+    /// The nickname of the occupant.
+    pub nick: String,
+    /// The status message, if any.
+    pub status: Option<String>,
+}
+
+/// Extract groupchat messages.
+///
+/// Rejects with `item-not-found` for other stanzas.
+pub fn groupchat() -> impl Filter<Extract = One<Message>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| match stanza {
+        Stanza::Message(msg) if msg.type_ == MessageType::Groupchat => future::ok(msg.clone()),
+        _ => future::err(reject::item_not_found()),
+    })
+}
+
+/// Extract the room a stanza is addressed to.
+///
+/// Rejects with `item-not-found` if the `to` JID has no local part.
+pub fn room() -> impl Filter<Extract = One<BareJid>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(room_of(stanza).ok_or_else(reject::item_not_found))
+    })
+}
+
+/// Extract the nickname a stanza is addressed to.
+///
+/// Rejects with `item-not-found` if the `to` JID has no resource.
+pub fn nick() -> impl Filter<Extract = One<String>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(
+            occupant_of(stanza)
+                .map(|occupant| occupant.nick)
+                .ok_or_else(reject::item_not_found),
+        )
+    })
+}
+
+/// Extract the occupant a stanza is addressed to.
+///
+/// Rejects with `item-not-found` if the `to` JID is not an occupant JID.
+pub fn occupant() -> impl Filter<Extract = One<Occupant>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(occupant_of(stanza).ok_or_else(reject::item_not_found))
+    })
+}
+
+/// Extract requests to join a room.
+///
+/// Matches available presence carrying `<x xmlns='http://jabber.org/protocol/muc'/>`
+/// sent to an occupant JID. Rejects with `item-not-found` otherwise, and
+/// with `bad-request` if the sender is unknown.
+pub fn join() -> impl Filter<Extract = One<Join>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(match (stanza, occupant_of(stanza)) {
+            (Stanza::Presence(pres), Some(occupant)) if pres.type_ == PresenceType::None => {
+                match pres.payloads.iter().find(|payload| payload.is("x", NS)) {
+                    Some(x) => match pres.from {
+                        Some(ref from) => Ok(Join {
+                            from: from.clone(),
+                            room: occupant.room,
+                            nick: occupant.nick,
+                            password: x.get_child("password", NS).map(Element::text),
+                            history: x.get_child("history", NS).map(history).unwrap_or_default(),
+                            presence: pres.clone(),
+                        }),
+                        None => Err(reject::bad_request()),
+                    },
+                    None => Err(reject::item_not_found()),
+                }
+            }
+            _ => Err(reject::item_not_found()),
+        })
+    })
+}
+
+/// Extract occupants leaving a room.
+///
+/// Matches unavailable presence sent to an occupant JID. Rejects with
+/// `item-not-found` otherwise, and with `bad-request` if the sender is
+/// unknown.
+pub fn leave() -> impl Filter<Extract = One<Leave>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(match (stanza, occupant_of(stanza)) {
+            (Stanza::Presence(pres), Some(occupant)) if pres.type_ == PresenceType::Unavailable => {
+                match pres.from {
+                    Some(ref from) => Ok(Leave {
+                        from: from.clone(),
+                        room: occupant.room,
+                        nick: occupant.nick,
+                        status: pres.statuses.values().next().cloned(),
+                    }),
+                    None => Err(reject::bad_request()),
+                }
+            }
+            _ => Err(reject::item_not_found()),
+        })
+    })
+}
+
+/// A ping sent by a client to its own occupant JID, to check that it is
+/// still joined (XEP-0410).
+#[derive(Clone, Debug, PartialEq)]
+pub struct SelfPing {
+    /// The real JID of the client.
+    pub from: Jid,
+    /// The occupant pinged.
+    pub occupant: Occupant,
+    id: String,
+}
+
+impl SelfPing {
+    /// Answer that `from` is still joined as `occupant`.
+    ///
+    /// Only answer after checking that it is; see [`self_ping`].
+    pub fn pong(self) -> Iq {
+        Iq::Result {
+            from: self.occupant.jid(),
+            to: Some(self.from),
+            id: self.id,
+            payload: None,
+        }
+    }
+}
+
+/// Extract pings addressed to an occupant JID.
+///
+/// Whether it is a self-ping depends on who the occupant is: only the
+/// service knows. Answer with [`SelfPing::pong`] if the sender is joined as
+/// the occupant, and reject with `not-acceptable` if not, as
+/// [`Rooms::self_ping`] does. Rejects with `item-not-found` for other
+/// stanzas.
+pub fn self_ping() -> impl Filter<Extract = One<SelfPing>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(match (stanza, occupant_of(stanza)) {
+            (
+                Stanza::Iq(Iq::Get {
+                    from: Some(from),
+                    id,
+                    payload,
+                    ..
+                }),
+                Some(occupant),
+            ) if payload.is("ping", NS_PING) => Ok(SelfPing {
+                from: from.clone(),
+                occupant,
+                id: id.clone(),
+            }),
+            _ => Err(reject::item_not_found()),
+        })
+    })
+}
+
+fn room_of(stanza: &Stanza) -> Option<BareJid> {
+    let to = to_of(stanza)?;
+    to.node()?;
+    Some(to.to_bare())
+}
+
+fn occupant_of(stanza: &Stanza) -> Option<Occupant> {
+    let to = to_of(stanza)?;
+    to.node()?;
+    Some(Occupant {
+        room: to.to_bare(),
+        nick: to.resource()?.as_str().to_owned(),
+    })
+}
+
+fn history(elem: &Element) -> History {
+    let number = |name| elem.attr(name).and_then(|value| value.parse().ok());
+    History {
+        max_chars: number("maxchars"),
+        max_stanzas: number("maxstanzas"),
+        seconds: number("seconds"),
+        since: elem.attr("since").map(str::to_owned),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::sync::Arc;
+
+    use futures_util::TryFuture;
+    use xmpp_parsers::message::{Id, Lang};
+    use xmpp_parsers::stanza_error::DefinedCondition;
+
+    use super::*;
+    use crate::filter::{FilterBase, Internal};
+    use crate::filtered_stanza;
+    use crate::reject::IsReject;
+
+    const WITCH: &str = "hag66@shakespeare.example/pda";
+    const OCCUPANT: &str = "coven@chat.example.com/firstwitch";
+    const ROOM: &str = "coven@chat.example.com";
+
+    // Run `filter` on `stanza`, as a server would.
+    async fn extract<F: Filter>(
+        filter: F,
+        stanza: Stanza,
+    ) -> Result<<F::Future as TryFuture>::Ok, <F::Future as TryFuture>::Error> {
+        let stanza = RefCell::new(Arc::new(stanza));
+        let mut fut = Box::pin(filtered_stanza::set(&stanza, || filter.filter(Internal)));
+        future::poll_fn(|cx| filtered_stanza::set(&stanza, || fut.as_mut().try_poll(cx))).await
+    }
+
+    fn presence(from: Option<&str>, to: &str, type_: PresenceType) -> Presence {
+        let mut presence = Presence::new(type_);
+        presence.from = from.map(|from| from.parse().unwrap());
+        presence.to = Some(to.parse().unwrap());
+        presence
+    }
+
+    fn muc_presence(from: Option<&str>, to: &str) -> Stanza {
+        let mut presence = presence(from, to, PresenceType::None);
+        presence.payloads.push(
+            format!(
+                "<x xmlns='{NS}'><password>cauldronburn</password>\
+                 <history maxstanzas='20' since='1970-01-01T00:00:00Z'/></x>"
+            )
+            .parse()
+            .unwrap(),
+        );
+        Stanza::Presence(presence)
+    }
+
+    fn message(from: &str, to: &str, type_: MessageType) -> Stanza {
+        let mut msg = Message::new(Some(to.parse().unwrap())).with_body(
+            Lang::default(),
+            "Thrice the brinded cat hath mew'd.".to_owned(),
+        );
+        msg.from = Some(from.parse().unwrap());
+        msg.id = Some(Id("mew".to_owned()));
+        msg.type_ = type_;
+        Stanza::Message(msg)
+    }
+
+    fn condition<T>(result: Result<T, Rejection>) -> DefinedCondition {
+        match result {
+            Ok(_) => panic!("expected a rejection"),
+            Err(rejection) => rejection.error_condition(),
+        }
+    }
+
+    #[tokio::test]
+    async fn extracts_joins() {
+        let (join,) = extract(join(), muc_presence(Some(WITCH), OCCUPANT))
+            .await
+            .unwrap();
+        assert_eq!(join.from, WITCH.parse::<Jid>().unwrap());
+        assert_eq!(join.room, ROOM.parse::<BareJid>().unwrap());
+        assert_eq!(join.nick, "firstwitch");
+        assert_eq!(join.password.as_deref(), Some("cauldronburn"));
+        assert_eq!(
+            join.history,
+            History {
+                max_stanzas: Some(20),
+                since: Some("1970-01-01T00:00:00Z".to_owned()),
+                ..History::default()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_other_presence_as_joins() {
+        // Without the MUC payload, it is plain presence to an occupant.
+        let plain = Stanza::Presence(presence(Some(WITCH), OCCUPANT, PresenceType::None));
+        assert_eq!(
+            condition(extract(join(), plain).await),
+            DefinedCondition::ItemNotFound
+        );
+        // A join needs a nickname.
+        assert_eq!(
+            condition(extract(join(), muc_presence(Some(WITCH), ROOM)).await),
+            DefinedCondition::ItemNotFound
+        );
+        assert_eq!(
+            condition(extract(join(), muc_presence(None, OCCUPANT)).await),
+            DefinedCondition::BadRequest
+        );
+    }
+
+    #[tokio::test]
+    async fn extracts_leaves() {
+        let mut unavailable = presence(Some(WITCH), OCCUPANT, PresenceType::Unavailable);
+        unavailable
+            .statuses
+            .insert(Lang::default(), "Fair is foul".to_owned());
+        let (left,) = extract(leave(), Stanza::Presence(unavailable))
+            .await
+            .unwrap();
+        assert_eq!(
+            left,
+            Leave {
+                from: WITCH.parse().unwrap(),
+                room: ROOM.parse().unwrap(),
+                nick: "firstwitch".to_owned(),
+                status: Some("Fair is foul".to_owned()),
+            }
+        );
+
+        assert_eq!(
+            condition(extract(leave(), muc_presence(Some(WITCH), OCCUPANT)).await),
+            DefinedCondition::ItemNotFound
+        );
+    }
+
+    #[tokio::test]
+    async fn extracts_groupchat_messages_and_their_room() {
+        let stanza = message(WITCH, ROOM, MessageType::Groupchat);
+        let (msg, room) = extract(groupchat().and(room()), stanza).await.unwrap();
+        assert_eq!(msg.type_, MessageType::Groupchat);
+        assert_eq!(room, ROOM.parse::<BareJid>().unwrap());
+
+        let chat = message(WITCH, OCCUPANT, MessageType::Chat);
+        assert_eq!(
+            condition(extract(groupchat(), chat).await),
+            DefinedCondition::ItemNotFound
+        );
+    }
+
+    #[tokio::test]
+    async fn rooms_serve_joins_messages_and_leaves() {
+        let rooms = Rooms::new();
+        let room: BareJid = ROOM.parse().unwrap();
+        let service = crate::service(rooms.filter());
+
+        let joined = service
+            .call_stanza(muc_presence(Some(WITCH), OCCUPANT))
+            .await
+            .unwrap();
+        assert!(joined.is_empty());
+        let occupant = rooms.with_room(&room, |room| {
+            room.occupant("firstwitch")
+                .map(|occupant| occupant.jid.clone())
+        });
+        assert_eq!(occupant, Some(Some(WITCH.parse().unwrap())));
+
+        let said = service
+            .call_stanza(message(WITCH, ROOM, MessageType::Groupchat))
+            .await
+            .unwrap();
+        assert!(said.is_empty());
+
+        // Only occupants may speak.
+        let outsider = service
+            .call_stanza(message(
+                "crone1@shakespeare.example/desktop",
+                ROOM,
+                MessageType::Groupchat,
+            ))
+            .await
+            .unwrap();
+        let [Stanza::Message(error)] = outsider.stanzas() else {
+            panic!("expected an error message");
+        };
+        assert_eq!(error.type_, MessageType::Error);
+
+        let left = service
+            .call_stanza(Stanza::Presence(presence(
+                Some(WITCH),
+                OCCUPANT,
+                PresenceType::Unavailable,
+            )))
+            .await
+            .unwrap();
+        assert!(left.is_empty());
+        // The room went away with its last occupant.
+        assert!(rooms.with_room(&room, |_| ()).is_none());
+    }
+}
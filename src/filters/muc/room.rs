@@ -0,0 +1,706 @@
+//! Room state for MUC services.
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
+use std::fmt;
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::jid::{BareJid, Jid};
+use xmpp_parsers::message::{Lang, Message, MessageType, Subject};
+use xmpp_parsers::minidom::Element;
+use xmpp_parsers::presence::{Presence, Type as PresenceType};
+
+use super::{groupchat, join, leave, self_ping, Join, Leave, SelfPing, NS, NS_PING, NS_USER};
+use crate::correlation::{self, Outbound};
+use crate::filter::{filter_fn_one, Filter};
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+
+/// The role of an occupant, which lasts as long as their visit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    /// May kick participants and change the subject.
+    Moderator,
+    /// May send messages to the room.
+    Participant,
+    /// May only read, in moderated rooms.
+    Visitor,
+    /// Not in the room.
+    None,
+}
+
+impl Role {
+    fn as_str(self) -> &'static str {
+        match self {
+            Role::Moderator => "moderator",
+            Role::Participant => "participant",
+            Role::Visitor => "visitor",
+            Role::None => "none",
+        }
+    }
+}
+
+/// The affiliation of a user with a room, which outlasts their visits.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Affiliation {
+    /// Owns the room.
+    Owner,
+    /// Administers the room.
+    Admin,
+    /// A member of the room.
+    Member,
+    /// No affiliation.
+    #[default]
+    None,
+    /// Banned from the room.
+    Outcast,
+}
+
+impl Affiliation {
+    fn as_str(self) -> &'static str {
+        match self {
+            Affiliation::Owner => "owner",
+            Affiliation::Admin => "admin",
+            Affiliation::Member => "member",
+            Affiliation::None => "none",
+            Affiliation::Outcast => "outcast",
+        }
+    }
+}
+
+/// The configuration of a room.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RoomConfig {
+    /// Only members, admins and owners may join.
+    pub members_only: bool,
+    /// Only occupants with a voice may send messages; others join as
+    /// visitors.
+    pub moderated: bool,
+    /// Real JIDs are only shown to moderators.
+    pub semi_anonymous: bool,
+    /// The password required to join, if any.
+    pub password: Option<String>,
+    /// The maximum number of occupants, not counting admins and owners.
+    pub max_occupants: Option<usize>,
+}
+
+impl Default for RoomConfig {
+    fn default() -> Self {
+        RoomConfig {
+            members_only: false,
+            moderated: false,
+            semi_anonymous: true,
+            password: None,
+            max_occupants: None,
+        }
+    }
+}
+
+/// An occupant of a [`Room`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct RoomOccupant {
+    /// The nickname in the room.
+    pub nick: String,
+    /// The real JID.
+    pub jid: Jid,
+    /// The role.
+    pub role: Role,
+    /// The affiliation.
+    pub affiliation: Affiliation,
+    presence: Presence,
+}
+
+/// A chat room.
+///
+/// Each transition checks the rules of the room and returns the stanzas to
+/// send, or the rejection to answer with.
+#[derive(Clone, Debug)]
+pub struct Room {
+    jid: BareJid,
+    config: RoomConfig,
+    subject: Option<String>,
+    affiliations: HashMap<BareJid, Affiliation>,
+    occupants: BTreeMap<String, RoomOccupant>,
+}
+
+impl Room {
+    /// An empty room `jid`.
+    pub fn new(jid: BareJid, config: RoomConfig) -> Self {
+        Room {
+            jid,
+            config,
+            subject: None,
+            affiliations: HashMap::new(),
+            occupants: BTreeMap::new(),
+        }
+    }
+
+    /// The room JID.
+    pub fn jid(&self) -> &BareJid {
+        &self.jid
+    }
+
+    /// The configuration.
+    pub fn config(&self) -> &RoomConfig {
+        &self.config
+    }
+
+    /// The configuration, for changes.
+    pub fn config_mut(&mut self) -> &mut RoomConfig {
+        &mut self.config
+    }
+
+    /// The subject, if set.
+    pub fn subject(&self) -> Option<&str> {
+        self.subject.as_deref()
+    }
+
+    /// The affiliation of `user`.
+    pub fn affiliation(&self, user: &BareJid) -> Affiliation {
+        self.affiliations.get(user).copied().unwrap_or_default()
+    }
+
+    /// Set the affiliation of `user`.
+    ///
+    /// Takes effect on their next visit.
+    pub fn set_affiliation(&mut self, user: BareJid, affiliation: Affiliation) {
+        match affiliation {
+            Affiliation::None => self.affiliations.remove(&user),
+            _ => self.affiliations.insert(user, affiliation),
+        };
+    }
+
+    /// The occupants, by nickname.
+    pub fn occupants(&self) -> impl Iterator<Item = &RoomOccupant> {
+        self.occupants.values()
+    }
+
+    /// The occupant with nickname `nick`.
+    pub fn occupant(&self, nick: &str) -> Option<&RoomOccupant> {
+        self.occupants.get(nick)
+    }
+
+    /// The occupant whose real JID is `jid`.
+    pub fn occupant_by_jid(&self, jid: &Jid) -> Option<&RoomOccupant> {
+        self.occupants
+            .values()
+            .find(|occupant| occupant.jid == *jid)
+    }
+
+    /// Whether nobody is in the room.
+    pub fn is_empty(&self) -> bool {
+        self.occupants.is_empty()
+    }
+
+    /// Admit a user.
+    ///
+    /// Returns the presence of every occupant for the newcomer, the
+    /// newcomer's presence for everyone, and the subject.
+    ///
+    /// Rejects with `forbidden` for outcasts, `registration-required` for
+    /// non-members of members-only rooms, `not-authorized` for a wrong
+    /// password, `conflict` if the nickname is taken and
+    /// `service-unavailable` if the room is full.
+    pub fn join(&mut self, join: Join) -> Result<Vec<Stanza>, Rejection> {
+        let affiliation = self.affiliation(&join.from.to_bare());
+        if affiliation == Affiliation::Outcast {
+            return Err(reject::forbidden());
+        }
+        if let Some(occupant) = self.occupants.get(&join.nick) {
+            if occupant.jid.to_bare() != join.from.to_bare() {
+                return Err(reject::conflict());
+            }
+        }
+        if self.config.members_only && affiliation == Affiliation::None {
+            return Err(reject::registration_required());
+        }
+        if self.config.password.is_some() && self.config.password != join.password {
+            return Err(reject::not_authorized());
+        }
+        let privileged = matches!(affiliation, Affiliation::Owner | Affiliation::Admin);
+        let counted = self
+            .occupants
+            .values()
+            .filter(|occupant| {
+                !matches!(
+                    occupant.affiliation,
+                    Affiliation::Owner | Affiliation::Admin
+                )
+            })
+            .count();
+        if !privileged
+            && self
+                .config
+                .max_occupants
+                .map_or(false, |max| counted >= max)
+        {
+            return Err(reject::service_unavailable());
+        }
+
+        let role = match affiliation {
+            Affiliation::Owner | Affiliation::Admin => Role::Moderator,
+            Affiliation::None if self.config.moderated => Role::Visitor,
+            _ => Role::Participant,
+        };
+        let mut presence = join.presence;
+        presence.payloads.retain(|payload| !payload.is("x", NS));
+        let newcomer = RoomOccupant {
+            nick: join.nick,
+            jid: join.from,
+            role,
+            affiliation,
+            presence,
+        };
+
+        let mut stanzas = Vec::new();
+        for occupant in self.occupants.values() {
+            if occupant.nick != newcomer.nick {
+                stanzas.push(self.presence_of(occupant, &newcomer, false));
+            }
+        }
+        self.occupants
+            .insert(newcomer.nick.clone(), newcomer.clone());
+        for occupant in self.occupants.values() {
+            stanzas.push(self.presence_of(&newcomer, occupant, false));
+        }
+
+        let mut subject = Message::new(Some(newcomer.jid.clone()));
+        subject.from = Some(Jid::from(self.jid.clone()));
+        subject.type_ = MessageType::Groupchat;
+        subject.subjects.insert(
+            Lang::default(),
+            Subject(self.subject.clone().unwrap_or_default()),
+        );
+        stanzas.push(Stanza::Message(subject));
+        Ok(stanzas)
+    }
+
+    /// Let an occupant go.
+    ///
+    /// Returns their unavailable presence for everyone, themselves
+    /// included, or nothing if `leave` does not come from the occupant.
+    pub fn leave(&mut self, leave: &Leave) -> Vec<Stanza> {
+        match self.occupants.get(&leave.nick) {
+            Some(occupant) if occupant.jid == leave.from => {}
+            _ => return Vec::new(),
+        }
+        let Some(mut gone) = self.occupants.remove(&leave.nick) else {
+            return Vec::new();
+        };
+        gone.role = Role::None;
+        gone.presence = Presence::new(PresenceType::Unavailable);
+        if let Some(ref status) = leave.status {
+            gone.presence
+                .statuses
+                .insert(Lang::default(), status.clone());
+        }
+
+        let mut stanzas = vec![self.presence_of(&gone, &gone, true)];
+        for occupant in self.occupants.values() {
+            stanzas.push(self.presence_of(&gone, occupant, false));
+        }
+        stanzas
+    }
+
+    /// Relay a groupchat message to every occupant.
+    ///
+    /// A message with a subject and no body changes the subject, which only
+    /// moderators may do.
+    ///
+    /// Rejects with `not-acceptable` if the sender is not an occupant, and
+    /// with `forbidden` if they may not speak or change the subject.
+    pub fn message(&mut self, msg: &Message) -> Result<Vec<Stanza>, Rejection> {
+        let from = msg.from.as_ref().ok_or_else(reject::bad_request)?;
+        let sender = self
+            .occupant_by_jid(from)
+            .ok_or_else(reject::not_acceptable)?;
+        let changes_subject = msg.bodies.is_empty() && !msg.subjects.is_empty();
+        match sender.role {
+            Role::Moderator => {}
+            Role::Participant if !changes_subject => {}
+            _ => return Err(reject::forbidden()),
+        }
+        let nick = sender.nick.clone();
+        if changes_subject {
+            self.subject = msg
+                .subjects
+                .values()
+                .next()
+                .map(|subject| subject.0.clone());
+        }
+
+        let from = self
+            .jid
+            .with_resource_str(&nick)
+            .map(Jid::from)
+            .map_err(|_| reject::internal_server_error())?;
+        Ok(self
+            .occupants
+            .values()
+            .map(|occupant| {
+                let mut relayed = msg.clone();
+                relayed.from = Some(from.clone());
+                relayed.to = Some(occupant.jid.clone());
+                relayed.type_ = MessageType::Groupchat;
+                Stanza::Message(relayed)
+            })
+            .collect())
+    }
+
+    /// Change the role of the occupant `nick`.
+    ///
+    /// Returns their updated presence for everyone. Setting [`Role::None`]
+    /// kicks them. Rejects with `item-not-found` if there is no such
+    /// occupant.
+    pub fn set_role(&mut self, nick: &str, role: Role) -> Result<Vec<Stanza>, Rejection> {
+        let occupant = self
+            .occupants
+            .get_mut(nick)
+            .ok_or_else(reject::item_not_found)?;
+        occupant.role = role;
+        if role != Role::None {
+            let changed = occupant.clone();
+            return Ok(self
+                .occupants
+                .values()
+                .map(|occupant| self.presence_of(&changed, occupant, false))
+                .collect());
+        }
+
+        let mut kicked = self.occupants.remove(nick).expect("found above");
+        kicked.presence = Presence::new(PresenceType::Unavailable);
+        let mut stanzas = vec![self.presence_of(&kicked, &kicked, true)];
+        for occupant in self.occupants.values() {
+            stanzas.push(self.presence_of(&kicked, occupant, false));
+        }
+        Ok(stanzas)
+    }
+
+    // The presence of `subject` as seen by `recipient`. `to_self` addresses
+    // it to `subject` even if they are no longer an occupant.
+    fn presence_of(
+        &self,
+        subject: &RoomOccupant,
+        recipient: &RoomOccupant,
+        to_self: bool,
+    ) -> Stanza {
+        let mut presence = subject.presence.clone();
+        presence.from = self
+            .jid
+            .with_resource_str(&subject.nick)
+            .ok()
+            .map(Jid::from);
+        presence.to = Some(recipient.jid.clone());
+
+        let reveal = !self.config.semi_anonymous || recipient.role == Role::Moderator;
+        let item = Element::builder("item", NS_USER)
+            .attr("affiliation", subject.affiliation.as_str())
+            .attr("role", subject.role.as_str())
+            .attr("jid", reveal.then(|| subject.jid.to_string()))
+            .build();
+        let mut x = Element::builder("x", NS_USER).append(item);
+        if to_self || subject.nick == recipient.nick {
+            if !self.config.semi_anonymous {
+                x = x.append(status(100));
+            }
+            x = x.append(status(110));
+        }
+        presence.payloads.push(x.build());
+        Stanza::Presence(presence)
+    }
+}
+
+fn status(code: u16) -> Element {
+    Element::builder("status", NS_USER)
+        .attr("code", code.to_string())
+        .build()
+}
+
+/// The rooms of a MUC service.
+///
+/// Clones share the same rooms. Rooms are created on first join, with the
+/// creator as owner, and dropped when the last occupant leaves unless they
+/// were added with [`Rooms::insert`].
+#[derive(Clone, Default)]
+pub struct Rooms {
+    rooms: Arc<DashMap<BareJid, (Room, bool)>>,
+    config: RoomConfig,
+}
+
+impl Rooms {
+    /// A service without rooms, creating them with the default
+    /// [`RoomConfig`].
+    pub fn new() -> Self {
+        Rooms::default()
+    }
+
+    /// Create new rooms with `config`.
+    pub fn default_config(mut self, config: RoomConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Add a persistent room, kept when empty.
+    pub fn insert(&self, room: Room) {
+        self.rooms.insert(room.jid().clone(), (room, true));
+    }
+
+    /// Remove the room `jid`.
+    pub fn remove(&self, jid: &BareJid) -> Option<Room> {
+        self.rooms.remove(jid).map(|(_, (room, _))| room)
+    }
+
+    /// Run `func` on the room `jid`, if it exists.
+    pub fn with_room<T>(&self, jid: &BareJid, func: impl FnOnce(&mut Room) -> T) -> Option<T> {
+        self.rooms.get_mut(jid).map(|mut entry| func(&mut entry.0))
+    }
+
+    /// Answer self-pings (XEP-0410).
+    ///
+    /// Rejects with `not-acceptable` if the sender is not joined as the
+    /// occupant pinged.
+    pub fn self_ping(&self) -> impl Filter<Extract = One<Iq>, Error = Rejection> + Clone {
+        let rooms = self.clone();
+        self_ping()
+            .and_then(move |ping: SelfPing| {
+                let joined = rooms
+                    .with_room(&ping.occupant.room, |room| {
+                        room.occupant(&ping.occupant.nick)
+                            .map_or(false, |occupant| occupant.jid == ping.from)
+                    })
+                    .unwrap_or(false);
+                future::ready(if joined {
+                    Ok(ping.pong())
+                } else {
+                    Err(reject::not_acceptable())
+                })
+            })
+            .advertises(NS_PING)
+    }
+
+    /// Serve joins, departures, groupchat messages and self-pings.
+    ///
+    /// The resulting stanzas are sent through the outbound queue of the
+    /// server, so the filter itself never replies.
+    pub fn filter(&self) -> impl Filter<Extract = One<Option<Stanza>>, Error = Rejection> + Clone {
+        let rooms = self.clone();
+        let joins =
+            join()
+                .and(outbound())
+                .and_then(move |join: Join, outbound: Option<Outbound>| {
+                    future::ready(rooms.join(join).map(|stanzas| send(outbound, stanzas)))
+                });
+        let rooms = self.clone();
+        let leaves =
+            leave()
+                .and(outbound())
+                .map(move |leave: Leave, outbound: Option<Outbound>| {
+                    send(outbound, rooms.leave(&leave))
+                });
+        let rooms = self.clone();
+        let messages = groupchat().and(super::room()).and(outbound()).and_then(
+            move |msg: Message, room: BareJid, outbound: Option<Outbound>| {
+                future::ready(
+                    rooms
+                        .with_room(&room, |room| room.message(&msg))
+                        .unwrap_or_else(|| Err(reject::item_not_found()))
+                        .map(|stanzas| send(outbound, stanzas)),
+                )
+            },
+        );
+        let pings = self.self_ping().map(|pong: Iq| Some(Stanza::Iq(pong)));
+        joins
+            .or(leaves)
+            .unify()
+            .or(messages)
+            .unify()
+            .or(pings)
+            .unify()
+            .advertises(NS)
+    }
+
+    fn join(&self, join: Join) -> Result<Vec<Stanza>, Rejection> {
+        let config = self.config.clone();
+        let mut entry = self.rooms.entry(join.room.clone()).or_insert_with(|| {
+            let mut room = Room::new(join.room.clone(), config);
+            room.set_affiliation(join.from.to_bare(), Affiliation::Owner);
+            (room, false)
+        });
+        let joined = entry.0.join(join);
+        let abandoned = !entry.1 && entry.0.is_empty();
+        let jid = entry.0.jid().clone();
+        drop(entry);
+        if abandoned {
+            self.rooms.remove(&jid);
+        }
+        joined
+    }
+
+    fn leave(&self, leave: &Leave) -> Vec<Stanza> {
+        let Some(mut entry) = self.rooms.get_mut(&leave.room) else {
+            return Vec::new();
+        };
+        let stanzas = entry.0.leave(leave);
+        let abandoned = !entry.1 && entry.0.is_empty();
+        drop(entry);
+        if abandoned {
+            self.rooms.remove(&leave.room);
+        }
+        stanzas
+    }
+}
+
+impl fmt::Debug for Rooms {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Rooms")
+            .field("rooms", &self.rooms.len())
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+fn outbound() -> impl Filter<Extract = One<Option<Outbound>>, Error = Infallible> + Copy {
+    filter_fn_one(|_: &Stanza| future::ok::<_, Infallible>(correlation::outbound()))
+}
+
+fn send(outbound: Option<Outbound>, stanzas: Vec<Stanza>) -> Option<Stanza> {
+    match outbound {
+        Some(outbound) => {
+            for stanza in stanzas {
+                if outbound.send(stanza).is_err() {
+                    tracing::warn!("dropped MUC broadcast");
+                }
+            }
+        }
+        None => tracing::debug!("no outbound queue, dropping {} MUC stanzas", stanzas.len()),
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn join(from: &str, nick: &str) -> Join {
+        Join {
+            from: from.parse().unwrap(),
+            room: "coven@chat.example.com".parse().unwrap(),
+            nick: nick.to_owned(),
+            password: None,
+            history: Default::default(),
+            presence: Presence::new(PresenceType::None),
+        }
+    }
+
+    #[test]
+    fn joins_and_leaves() {
+        let mut room = Room::new(
+            "coven@chat.example.com".parse().unwrap(),
+            RoomConfig::default(),
+        );
+        room.set_affiliation(
+            "hag66@shakespeare.example".parse().unwrap(),
+            Affiliation::Owner,
+        );
+
+        // Own presence and subject.
+        let stanzas = room
+            .join(join("hag66@shakespeare.example/pda", "firstwitch"))
+            .unwrap();
+        assert_eq!(stanzas.len(), 2);
+        assert_eq!(room.occupant("firstwitch").unwrap().role, Role::Moderator);
+
+        // The first witch's presence, own presence to both, and subject.
+        let stanzas = room
+            .join(join("wiccarocks@shakespeare.example/laptop", "secondwitch"))
+            .unwrap();
+        assert_eq!(stanzas.len(), 4);
+        assert_eq!(
+            room.occupant("secondwitch").unwrap().role,
+            Role::Participant
+        );
+
+        assert!(room
+            .join(join("crone1@shakespeare.example/desktop", "firstwitch"))
+            .is_err());
+
+        let stanzas = room.leave(&Leave {
+            from: "wiccarocks@shakespeare.example/laptop".parse().unwrap(),
+            room: room.jid().clone(),
+            nick: "secondwitch".to_owned(),
+            status: None,
+        });
+        assert_eq!(stanzas.len(), 2);
+        assert!(room.occupant("secondwitch").is_none());
+    }
+
+    fn self_ping(from: &str, nick: &str) -> Stanza {
+        Stanza::Iq(Iq::Get {
+            from: Some(from.parse().unwrap()),
+            to: Some(format!("coven@chat.example.com/{nick}").parse().unwrap()),
+            id: "ping".to_owned(),
+            payload: Element::builder("ping", NS_PING).build(),
+        })
+    }
+
+    async fn answer(rooms: &Rooms, ping: Stanza) -> Iq {
+        let response = crate::service(rooms.self_ping())
+            .call_stanza(ping)
+            .await
+            .unwrap();
+        match response.stanzas() {
+            [Stanza::Iq(iq)] => iq.clone(),
+            other => panic!("expected an iq, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn answers_self_pings_of_occupants() {
+        let rooms = Rooms::new();
+        rooms
+            .join(join("hag66@shakespeare.example/pda", "firstwitch"))
+            .unwrap();
+
+        let pong = answer(
+            &rooms,
+            self_ping("hag66@shakespeare.example/pda", "firstwitch"),
+        )
+        .await;
+        let Iq::Result { from, to, id, .. } = pong else {
+            panic!("expected a pong, got {:?}", pong);
+        };
+        assert_eq!(id, "ping");
+        assert_eq!(
+            from,
+            Some("coven@chat.example.com/firstwitch".parse().unwrap())
+        );
+        assert_eq!(to, Some("hag66@shakespeare.example/pda".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn rejects_self_pings_of_others() {
+        let rooms = Rooms::new();
+        rooms
+            .join(join("hag66@shakespeare.example/pda", "firstwitch"))
+            .unwrap();
+
+        for ping in [
+            // Another client of the same user is not joined.
+            self_ping("hag66@shakespeare.example/laptop", "firstwitch"),
+            self_ping("hag66@shakespeare.example/pda", "secondwitch"),
+            self_ping("crone1@shakespeare.example/desktop", "firstwitch"),
+        ] {
+            let Iq::Error { error, .. } = answer(&rooms, ping).await else {
+                panic!("expected an error");
+            };
+            assert_eq!(
+                error.defined_condition,
+                xmpp_parsers::stanza_error::DefinedCondition::NotAcceptable
+            );
+        }
+    }
+}
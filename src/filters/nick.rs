@@ -0,0 +1,62 @@
+//! User Nickname (XEP-0172).
+//!
+//! - `wax::nick::param()` - Extract the nickname carried by a presence or message
+//!
+//! Attach a nickname to replies with
+//! [`wax::reply::with::nick`](crate::reply::with::nick).
+//!
+//! Nicknames are sent in subscription requests and in the first message of
+//! a conversation, so a gateway can name its contacts before any vCard is
+//! fetched.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::Filter;
+//!
+//! let route = wax::presence()
+//!     .and(wax::nick::param())
+//!     .map(|nick: String| {
+//!         // rename the legacy contact...
+//!         wax::sink()
+//!     });
+//! ```
+
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::minidom::Element;
+
+use crate::filter::{filter_fn_one, Filter};
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+
+/// The `http://jabber.org/protocol/nick` namespace.
+pub const NS: &str = "http://jabber.org/protocol/nick";
+
+/// Extract the nickname of a presence or message.
+///
+/// Rejects with `item-not-found` for other stanzas, and for ones without a
+/// nickname or with an empty one.
+pub fn param() -> impl Filter<Extract = One<String>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        let payloads = match stanza {
+            Stanza::Message(msg) => &msg.payloads[..],
+            Stanza::Presence(presence) => &presence.payloads[..],
+            Stanza::Iq(_) => &[],
+        };
+        future::ready(
+            payloads
+                .iter()
+                .find(|payload| payload.is("nick", NS))
+                .map(|nick| nick.text().trim().to_owned())
+                .filter(|nick| !nick.is_empty())
+                .ok_or_else(reject::item_not_found),
+        )
+    })
+    .advertises(NS)
+}
+
+/// The `<nick/>` payload carrying `nick`.
+pub fn element(nick: impl Into<String>) -> Element {
+    Element::builder("nick", NS).append(nick.into()).build()
+}
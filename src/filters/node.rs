@@ -0,0 +1,46 @@
+//! Localpart filters.
+//!
+//! - `wax::node::param::<T>()` - Extract the localpart of the recipient, parsed as `T`
+//!
+//! A gateway addresses each contact on the legacy network as
+//! `contact@gateway`, so the localpart identifies the contact, much like a
+//! path parameter identifies a resource over HTTP.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::Filter;
+//!
+//! let route = wax::node::param::<PhoneNumber>()
+//!     .and(wax::message::body::param())
+//!     .map(|to: PhoneNumber, body: String| {
+//!         // send `body` as an SMS to `to`...
+//!         wax::sink()
+//!     });
+//! ```
+
+use std::str::FromStr;
+
+use futures_util::future;
+use tokio_xmpp::Stanza;
+
+use crate::filter::{filter_fn_one, Filter};
+use crate::filters::stanza::to_of;
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+
+/// Extract the localpart of the `to` JID, parsed as `T`.
+///
+/// Rejects with `item-not-found` if the recipient has no localpart, and
+/// with `jid-malformed` if it does not parse.
+pub fn param<T>() -> impl Filter<Extract = One<T>, Error = Rejection> + Copy
+where
+    T: FromStr + Send + 'static,
+{
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(match to_of(stanza).and_then(|to| to.node()) {
+            Some(node) => node.as_str().parse().map_err(|_| reject::jid_malformed()),
+            None => Err(reject::item_not_found()),
+        })
+    })
+}
@@ -0,0 +1,161 @@
+//! Namespace filters.
+//!
+//! - `wax::ns("urn:xmpp:ping")` - Match stanzas with a payload in a namespace
+//! - `wax::ns::named("ping", "urn:xmpp:ping")` - Same, for a payload of a given name
+//! - `wax::element("ping", "urn:xmpp:ping")` - Extract such an element as an [`Element`]
+//! - `wax::attr("items", NS_PUBSUB, "node")` - Extract an attribute of such an element
+//! - `wax::attr_as::<T>(name, ns, attr)` - Same, parsed as `T`
+//!
+//! The matching filters only look at the payloads, so routes can be
+//! partitioned by protocol before extracting anything. The extracting
+//! ones also look inside them, e.g. at the `<items/>` of a `<pubsub/>`
+//! request, and are the escape hatch for protocols without a typed parser.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::Filter;
+//!
+//! let pubsub = wax::ns("http://jabber.org/protocol/pubsub").and(pubsub_routes);
+//! let routes = pubsub.or(other_routes);
+//! ```
+
+use std::str::FromStr;
+
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::minidom::Element;
+
+use crate::filter::{filter_fn, filter_fn_one, Filter};
+use crate::filters::stanza::payloads_of;
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+
+/// Match stanzas with a payload in `ns`.
+///
+/// Rejects with `item-not-found` for other stanzas.
+pub fn ns(ns: &'static str) -> impl Filter<Extract = (), Error = Rejection> + Copy {
+    filter_fn(move |stanza: &Stanza| {
+        if payloads_of(stanza).iter().any(|payload| payload.ns() == ns) {
+            future::ok(())
+        } else {
+            future::err(reject::item_not_found())
+        }
+    })
+}
+
+/// Match stanzas with a payload named `name` in `ns`.
+///
+/// Rejects with `item-not-found` for other stanzas.
+pub fn named(
+    name: &'static str,
+    ns: &'static str,
+) -> impl Filter<Extract = (), Error = Rejection> + Copy {
+    filter_fn(move |stanza: &Stanza| {
+        if payloads_of(stanza)
+            .iter()
+            .any(|payload| payload.is(name, ns))
+        {
+            future::ok(())
+        } else {
+            future::err(reject::item_not_found())
+        }
+    })
+}
+
+/// Extract the first element named `name` in `ns`, among the payloads and
+/// their descendants.
+///
+/// Rejects with `item-not-found` for stanzas without one.
+///
+/// # Example
+///
+/// ```ignore
+/// use wax::Filter;
+/// use xmpp_parsers::minidom::Element;
+///
+/// let route = wax::element("x", "urn:example:legacy").map(|x: Element| {
+///     // walk the payload...
+///     wax::sink()
+/// });
+/// ```
+pub fn element(
+    name: &'static str,
+    ns: &'static str,
+) -> impl Filter<Extract = One<Element>, Error = Rejection> + Copy {
+    filter_fn_one(move |stanza: &Stanza| {
+        future::ready(
+            find(stanza, name, ns)
+                .cloned()
+                .ok_or_else(reject::item_not_found),
+        )
+    })
+}
+
+/// Extract the attribute `attr` of the first element named `name` in
+/// `ns`, among the payloads and their descendants.
+///
+/// Rejects with `item-not-found` if there is no such element or it does
+/// not have the attribute.
+///
+/// # Example
+///
+/// ```ignore
+/// use wax::Filter;
+///
+/// let route = wax::attr("query", "http://jabber.org/protocol/disco#items", "node")
+///     .map(|node: String| {
+///         // list the items of `node`...
+///         wax::sink()
+///     });
+/// ```
+pub fn attr(
+    name: &'static str,
+    ns: &'static str,
+    attr: &'static str,
+) -> impl Filter<Extract = One<String>, Error = Rejection> + Copy {
+    filter_fn_one(move |stanza: &Stanza| {
+        future::ready(
+            find(stanza, name, ns)
+                .and_then(|elem| elem.attr(attr))
+                .map(str::to_owned)
+                .ok_or_else(reject::item_not_found),
+        )
+    })
+}
+
+/// Extract the attribute `attr` of the first element named `name` in
+/// `ns`, parsed as `T`.
+///
+/// Rejects like [`attr`], and with `bad-request` if the attribute does not
+/// parse.
+pub fn attr_as<T>(
+    name: &'static str,
+    ns: &'static str,
+    attr: &'static str,
+) -> impl Filter<Extract = One<T>, Error = Rejection> + Copy
+where
+    T: FromStr + Send + 'static,
+{
+    filter_fn_one(move |stanza: &Stanza| {
+        future::ready(
+            match find(stanza, name, ns).and_then(|elem| elem.attr(attr)) {
+                Some(value) => value.parse().map_err(|_| reject::bad_request()),
+                None => Err(reject::item_not_found()),
+            },
+        )
+    })
+}
+
+// Depth-first, so that a payload matching itself comes before its children.
+fn find<'a>(stanza: &'a Stanza, name: &str, ns: &str) -> Option<&'a Element> {
+    fn descend<'a>(elem: &'a Element, name: &str, ns: &str) -> Option<&'a Element> {
+        if elem.is(name, ns) {
+            return Some(elem);
+        }
+        elem.children().find_map(|child| descend(child, name, ns))
+    }
+    payloads_of(stanza)
+        .iter()
+        .find_map(|payload| descend(payload, name, ns))
+}
@@ -0,0 +1,147 @@
+//! Out of Band Data (XEP-0066).
+//!
+//! - `wax::oob::param()` - Extract the first [`Oob`] URL of a message
+//! - `wax::oob::all()` - Extract every one of them
+//! - `wax::oob::reply(oob)` - Reply with a URL, shown inline by most clients
+//!
+//! Clients only show a file inline when the body of the message is the URL
+//! itself, which is what [`reply`] sends. Use [`Oob::attach`] to attach a
+//! URL to a message with a body of its own.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::oob::Oob;
+//! use wax::Filter;
+//!
+//! let mms = wax::oob::all().map(|attachments: Vec<Oob>| {
+//!     // forward attachments to the phone network...
+//!     wax::sink()
+//! });
+//!
+//! let cat = wax::oob::reply(Oob::new("https://example.com/cat.jpg"));
+//! ```
+
+use std::convert::Infallible;
+
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::jid::Jid;
+use xmpp_parsers::message::{Lang, Message};
+use xmpp_parsers::minidom::Element;
+
+use crate::filter::{filter_fn_one, Filter};
+use crate::filters::stanza::{from, to};
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+
+/// The `jabber:x:oob` namespace.
+pub const NS: &str = "jabber:x:oob";
+
+/// A URL sent out of band.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Oob {
+    /// The URL.
+    pub url: String,
+    /// A description of what it points to, if any.
+    pub desc: Option<String>,
+}
+
+impl Oob {
+    /// A URL without description.
+    pub fn new(url: impl Into<String>) -> Self {
+        Oob {
+            url: url.into(),
+            desc: None,
+        }
+    }
+
+    /// Set the description.
+    pub fn desc(mut self, desc: impl Into<String>) -> Self {
+        self.desc = Some(desc.into());
+        self
+    }
+
+    /// Attach the URL to `msg`, keeping its body.
+    pub fn attach(self, mut msg: Message) -> Message {
+        msg.payloads.push(Element::from(self));
+        msg
+    }
+}
+
+impl From<Oob> for Element {
+    fn from(oob: Oob) -> Element {
+        Element::builder("x", NS)
+            .append(Element::builder("url", NS).append(oob.url).build())
+            .append_all(
+                oob.desc
+                    .map(|desc| Element::builder("desc", NS).append(desc).build()),
+            )
+            .build()
+    }
+}
+
+/// Extract the first URL attached to a message.
+///
+/// Rejects with `item-not-found` if there is none.
+pub fn param() -> impl Filter<Extract = One<Oob>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(
+            oobs_of(stanza)
+                .into_iter()
+                .next()
+                .ok_or_else(reject::item_not_found),
+        )
+    })
+    .advertises(NS)
+}
+
+/// Extract every URL attached to a message.
+///
+/// Rejects with `item-not-found` if there is none.
+pub fn all() -> impl Filter<Extract = One<Vec<Oob>>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        let oobs = oobs_of(stanza);
+        future::ready(if oobs.is_empty() {
+            Err(reject::item_not_found())
+        } else {
+            Ok(oobs)
+        })
+    })
+    .advertises(NS)
+}
+
+/// Reply with a message whose body is the URL of `oob`, and which carries
+/// it out of band.
+pub fn reply(oob: Oob) -> impl Filter<Extract = One<Message>, Error = Infallible> + Clone {
+    from()
+        .and(to())
+        .map(move |sender: Option<Jid>, recipient: Option<Jid>| {
+            let mut msg = Message::new(sender);
+            msg.from = recipient;
+            let msg = msg.with_body(Lang::default(), oob.url.clone());
+            oob.clone().attach(msg)
+        })
+}
+
+fn oobs_of(stanza: &Stanza) -> Vec<Oob> {
+    let Stanza::Message(msg) = stanza else {
+        return Vec::new();
+    };
+    msg.payloads
+        .iter()
+        .filter(|payload| payload.is("x", NS))
+        .filter_map(|x| {
+            let url = x.get_child("url", NS)?.text();
+            let desc = x
+                .get_child("desc", NS)
+                .map(Element::text)
+                .filter(|desc| !desc.is_empty());
+            Some(Oob {
+                url: url.trim().to_owned(),
+                desc,
+            })
+        })
+        .filter(|oob| !oob.url.is_empty())
+        .collect()
+}
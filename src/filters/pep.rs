@@ -0,0 +1,169 @@
+//! Personal Eventing Protocol (XEP-0163).
+//!
+//! - `wax::pep::event()` - Extract the PEP [`Event`] carried by a message
+//! - `wax::pep::node(name)` - Same, only for events of node `name`
+//! - `wax::pep::items::<T>(name)` - Extract the item payloads of node `name` as `T`
+//!
+//! Events reach the component when it is subscribed to a user's nodes,
+//! usually through presence with entity capabilities or through the
+//! privileges granted by the server (XEP-0356). With those privileges,
+//! [`publish`] builds the request to publish an item on behalf of a user.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::xmpp_parsers::nick::Nick;
+//! use wax::Filter;
+//!
+//! let nicks = wax::pep::items::<Nick>("http://jabber.org/protocol/nick")
+//!     .and(wax::require_from())
+//!     .map(|nicks: Vec<Nick>, user: Jid| {
+//!         // remember the nickname of `user`...
+//!         wax::sink()
+//!     });
+//! ```
+
+use std::fmt;
+
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::jid::Jid;
+use xmpp_parsers::minidom::Element;
+
+use crate::correlation;
+use crate::filter::{filter_fn_one, Filter};
+use crate::generic::One;
+use crate::privilege;
+use crate::pubsub::{self, Item, NS_EVENT};
+use crate::reject::{self, Rejection};
+
+/// The `urn:xmpp:privilege:2` namespace of privileged entities.
+pub use crate::privilege::NS as NS_PRIVILEGE;
+
+/// A PEP event notification.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Event {
+    /// The user whose node changed.
+    pub from: Option<Jid>,
+    /// The recipient of the notification.
+    pub to: Option<Jid>,
+    /// The node.
+    pub node: String,
+    /// The published items.
+    pub items: Vec<Item>,
+    /// The ids of retracted items.
+    pub retracted: Vec<String>,
+}
+
+impl Event {
+    /// The payloads of the published items, converted to `T`.
+    ///
+    /// Items without payload are skipped.
+    pub fn payloads<T>(&self) -> Result<Vec<T>, T::Error>
+    where
+        T: TryFrom<Element>,
+    {
+        self.items
+            .iter()
+            .filter_map(|item| item.payload.clone())
+            .map(T::try_from)
+            .collect()
+    }
+}
+
+/// Extract the PEP event carried by a message.
+///
+/// Rejects with `item-not-found` if the stanza is not an event notification.
+pub fn event() -> impl Filter<Extract = One<Event>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(parse(stanza, None).ok_or_else(reject::item_not_found))
+    })
+}
+
+/// Extract the PEP event carried by a message, if it concerns `node`.
+///
+/// Rejects with `item-not-found` otherwise.
+pub fn node(node: &'static str) -> impl Filter<Extract = One<Event>, Error = Rejection> + Copy {
+    filter_fn_one(move |stanza: &Stanza| {
+        future::ready(parse(stanza, Some(node)).ok_or_else(reject::item_not_found))
+    })
+}
+
+/// Extract the payloads published to `node`, converted to `T`.
+///
+/// Rejects with `item-not-found` if the stanza is not an event of `node`,
+/// and with `bad-request` if a payload is not a valid `T`.
+pub fn items<T>(node: &'static str) -> impl Filter<Extract = One<Vec<T>>, Error = Rejection> + Copy
+where
+    T: TryFrom<Element> + Send + 'static,
+    T::Error: fmt::Debug,
+{
+    filter_fn_one(move |stanza: &Stanza| {
+        future::ready(match parse(stanza, Some(node)) {
+            Some(event) => event.payloads::<T>().map_err(|err| {
+                tracing::debug!("invalid PEP payload on {}: {:?}", node, err);
+                reject::bad_request()
+            }),
+            None => Err(reject::item_not_found()),
+        })
+    })
+}
+
+fn parse(stanza: &Stanza, only: Option<&str>) -> Option<Event> {
+    let Stanza::Message(msg) = stanza else {
+        return None;
+    };
+    let items = msg
+        .payloads
+        .iter()
+        .find(|payload| payload.is("event", NS_EVENT))?
+        .get_child("items", NS_EVENT)?;
+    let node = items.attr("node")?;
+    if only.map_or(false, |only| only != node) {
+        return None;
+    }
+    Some(Event {
+        from: msg.from.clone(),
+        to: msg.to.clone(),
+        node: node.to_owned(),
+        items: items
+            .children()
+            .filter(|child| child.is("item", NS_EVENT))
+            .map(|item| Item {
+                id: item.attr("id").unwrap_or_default().to_owned(),
+                payload: item.children().next().cloned(),
+            })
+            .collect(),
+        retracted: items
+            .children()
+            .filter(|child| child.is("retract", NS_EVENT))
+            .filter_map(|retract| retract.attr("id").map(str::to_owned))
+            .collect(),
+    })
+}
+
+/// Build a request publishing `item` to `node` of `user`.
+///
+/// The request is sent from the component `component` to the server of
+/// `user`, which performs it on the user's behalf if the component was
+/// granted the `pubsub` IQ permission (XEP-0356). To check that it was
+/// before sending, build the request through
+/// [`Privileges::iq`](crate::privilege::Privileges::iq) instead.
+pub fn publish(component: Jid, user: &Jid, node: impl Into<String>, item: Item) -> Iq {
+    let server = Jid::new(user.domain().as_str()).expect("a domain is a valid JID");
+    let user = Jid::from(user.to_bare());
+    let publish = Element::builder("publish", pubsub::NS)
+        .attr("node", node.into())
+        .append(item.to_element(pubsub::NS))
+        .build();
+    let iq = Iq::Set {
+        from: None,
+        to: None,
+        id: correlation::unique_id(),
+        payload: Element::builder("pubsub", pubsub::NS)
+            .append(publish)
+            .build(),
+    };
+    privilege::wrap_iq(component, server, &user, iq)
+}
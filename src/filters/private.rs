@@ -0,0 +1,321 @@
+//! Private XML Storage (XEP-0049).
+//!
+//! - `wax::private::get()` - Extract a [`PrivateGet`] request
+//! - `wax::private::set()` - Extract a [`PrivateSet`] request
+//! - `wax::private::serve(store)` - Answer both from a [`PrivateStore`]
+//!
+//! Each user stores at most one element per namespace; storing another
+//! element with the same namespace replaces it. A [`Namespace`] of the
+//! shared [store](crate::store) is a [`PrivateStore`].
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::Filter;
+//!
+//! let routes = wax::private::serve(store).or(other_routes);
+//! ```
+
+use std::sync::Arc;
+
+use futures_util::future::{self, BoxFuture};
+use tokio_xmpp::Stanza;
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::jid::{BareJid, Jid};
+use xmpp_parsers::minidom::Element;
+
+use crate::filter::{filter_fn_one, Filter};
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+use crate::store::Namespace;
+
+/// The `jabber:iq:private` namespace.
+pub const NS: &str = "jabber:iq:private";
+
+// Namespaces that cannot be stored.
+const RESERVED: [&str; 3] = [NS, "jabber:client", "jabber:server"];
+
+/// A request for a stored element.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PrivateGet {
+    /// The user asking.
+    pub from: Option<Jid>,
+    /// The service asked.
+    pub to: Option<Jid>,
+    /// The name of the element asked for.
+    pub name: String,
+    /// The namespace of the element asked for.
+    pub ns: String,
+    id: String,
+}
+
+impl PrivateGet {
+    /// Answer with `element`, or with an empty element if nothing is stored.
+    pub fn result(self, element: Option<Element>) -> Iq {
+        let element = element.unwrap_or_else(|| Element::builder(self.name, self.ns).build());
+        Iq::Result {
+            from: self.to,
+            to: self.from,
+            id: self.id,
+            payload: Some(Element::builder("query", NS).append(element).build()),
+        }
+    }
+}
+
+/// A request to store elements.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PrivateSet {
+    /// The user storing.
+    pub from: Option<Jid>,
+    /// The service asked.
+    pub to: Option<Jid>,
+    /// The elements to store, each in a different namespace.
+    pub elements: Vec<Element>,
+    id: String,
+}
+
+impl PrivateSet {
+    /// Acknowledge the request.
+    pub fn success(self) -> Iq {
+        Iq::Result {
+            from: self.to,
+            to: self.from,
+            id: self.id,
+            payload: None,
+        }
+    }
+}
+
+/// Storage for the private elements of users.
+pub trait PrivateStore: Send + Sync + 'static {
+    /// The element stored by `user` in namespace `ns`, if any.
+    fn get<'a>(
+        &'a self,
+        user: &'a BareJid,
+        ns: &'a str,
+    ) -> BoxFuture<'a, Result<Option<Element>, Rejection>>;
+
+    /// Store `element` for `user`, replacing the one in the same namespace.
+    fn set<'a>(
+        &'a self,
+        user: &'a BareJid,
+        element: Element,
+    ) -> BoxFuture<'a, Result<(), Rejection>>;
+}
+
+impl<S: PrivateStore + ?Sized> PrivateStore for Arc<S> {
+    fn get<'a>(
+        &'a self,
+        user: &'a BareJid,
+        ns: &'a str,
+    ) -> BoxFuture<'a, Result<Option<Element>, Rejection>> {
+        (**self).get(user, ns)
+    }
+
+    fn set<'a>(
+        &'a self,
+        user: &'a BareJid,
+        element: Element,
+    ) -> BoxFuture<'a, Result<(), Rejection>> {
+        (**self).set(user, element)
+    }
+}
+
+/// Elements kept in a [`Namespace`] of the shared store, as XML under the
+/// bare JID of their user and their namespace.
+impl PrivateStore for Namespace {
+    fn get<'a>(
+        &'a self,
+        user: &'a BareJid,
+        ns: &'a str,
+    ) -> BoxFuture<'a, Result<Option<Element>, Rejection>> {
+        Box::pin(async move {
+            let key = private_key(user, ns);
+            let Some(xml) = Namespace::get::<String>(self, &key).await? else {
+                return Ok(None);
+            };
+            xml.parse().map(Some).map_err(|err| {
+                tracing::error!("invalid private element stored under {}: {}", key, err);
+                reject::internal_server_error()
+            })
+        })
+    }
+
+    fn set<'a>(
+        &'a self,
+        user: &'a BareJid,
+        element: Element,
+    ) -> BoxFuture<'a, Result<(), Rejection>> {
+        Box::pin(async move {
+            let key = private_key(user, &element.ns());
+            let mut xml = Vec::new();
+            element.write_to(&mut xml).map_err(|err| {
+                tracing::error!("failed to encode private element {}: {}", key, err);
+                reject::internal_server_error()
+            })?;
+            self.put(&key, &String::from_utf8_lossy(&xml)).await
+        })
+    }
+}
+
+// Spaces cannot appear in JIDs, so keys of different users never collide.
+fn private_key(user: &BareJid, ns: &str) -> String {
+    format!("{} {}", user, ns)
+}
+
+/// Extract a request for a stored element.
+///
+/// Rejects with `item-not-found` for other stanzas, with `bad-request` if
+/// it does not name exactly one element, and with `not-acceptable` if that
+/// element is in a reserved namespace.
+pub fn get() -> impl Filter<Extract = One<PrivateGet>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(match stanza {
+            Stanza::Iq(Iq::Get {
+                from,
+                to,
+                id,
+                payload,
+            }) if payload.is("query", NS) => {
+                let mut children = payload.children();
+                match (children.next(), children.next()) {
+                    (Some(child), None) => check(child).map(|()| PrivateGet {
+                        from: from.clone(),
+                        to: to.clone(),
+                        name: child.name().to_owned(),
+                        ns: child.ns(),
+                        id: id.clone(),
+                    }),
+                    _ => Err(reject::bad_request()),
+                }
+            }
+            _ => Err(reject::item_not_found()),
+        })
+    })
+    .advertises(NS)
+}
+
+/// Extract a request to store elements.
+///
+/// Rejects with `item-not-found` for other stanzas, with `bad-request` if
+/// it carries no element, and with `not-acceptable` if one is in a reserved
+/// namespace.
+pub fn set() -> impl Filter<Extract = One<PrivateSet>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(match stanza {
+            Stanza::Iq(Iq::Set {
+                from,
+                to,
+                id,
+                payload,
+            }) if payload.is("query", NS) => {
+                let elements: Vec<Element> = payload.children().cloned().collect();
+                if elements.is_empty() {
+                    Err(reject::bad_request())
+                } else {
+                    elements.iter().try_for_each(check).map(|()| PrivateSet {
+                        from: from.clone(),
+                        to: to.clone(),
+                        elements,
+                        id: id.clone(),
+                    })
+                }
+            }
+            _ => Err(reject::item_not_found()),
+        })
+    })
+    .advertises(NS)
+}
+
+/// Answer private storage requests from `store`, on behalf of the bare JID
+/// of their sender.
+pub fn serve(
+    store: impl PrivateStore,
+) -> impl Filter<Extract = One<Iq>, Error = Rejection> + Clone {
+    let store = Arc::new(store);
+    let getting = {
+        let store = store.clone();
+        get().and_then(move |request: PrivateGet| {
+            let store = store.clone();
+            async move {
+                let user = request.from.clone().ok_or_else(reject::bad_request)?;
+                let element = store.get(&user.to_bare(), &request.ns).await?;
+                Ok::<_, Rejection>(request.result(element))
+            }
+        })
+    };
+    let setting = set().and_then(move |request: PrivateSet| {
+        let store = store.clone();
+        async move {
+            let user = request
+                .from
+                .clone()
+                .ok_or_else(reject::bad_request)?
+                .to_bare();
+            for element in request.elements.iter().cloned() {
+                store.set(&user, element).await?;
+            }
+            Ok::<_, Rejection>(request.success())
+        }
+    });
+    getting.or(setting).unify()
+}
+
+fn check(element: &Element) -> Result<(), Rejection> {
+    if RESERVED.contains(&element.ns().as_str()) {
+        tracing::debug!("refusing private storage in {}", element.ns());
+        Err(reject::not_acceptable())
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{KvStore, MemoryStore};
+
+    #[tokio::test]
+    async fn namespace_stores_one_element_per_namespace() {
+        let store = MemoryStore::new().namespace("private");
+        let juliet = BareJid::new("juliet@capulet.lit").unwrap();
+        let romeo = BareJid::new("romeo@montague.lit").unwrap();
+        let exodus = |text: &str| {
+            Element::builder("exodus", "exodus:prefs")
+                .append(
+                    Element::builder("defaultnick", "exodus:prefs")
+                        .append(text)
+                        .build(),
+                )
+                .build()
+        };
+
+        assert_eq!(
+            PrivateStore::get(&store, &juliet, "exodus:prefs")
+                .await
+                .unwrap(),
+            None
+        );
+
+        store.set(&juliet, exodus("Hamlet")).await.unwrap();
+        store.set(&juliet, exodus("Jules")).await.unwrap();
+        assert_eq!(
+            PrivateStore::get(&store, &juliet, "exodus:prefs")
+                .await
+                .unwrap(),
+            Some(exodus("Jules"))
+        );
+        assert_eq!(
+            PrivateStore::get(&store, &juliet, "storage:bookmarks")
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            PrivateStore::get(&store, &romeo, "exodus:prefs")
+                .await
+                .unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,122 @@
+//! Message Reactions (XEP-0444).
+//!
+//! - `wax::reactions::param()` - Extract the [`Reactions`] to a message
+//! - [`Reactions::to`] - Build a message reacting to one
+//!
+//! A reaction message carries the whole set of reactions of its sender to a
+//! message: an empty set removes them all.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::reactions::Reactions;
+//! use wax::Filter;
+//!
+//! let bridge = wax::reactions::param().map(|reactions: Reactions| {
+//!     // set the reactions of the sender to reactions.id on the remote network...
+//!     wax::sink()
+//! });
+//!
+//! let thumbs_up = Reactions::new(remote_id).emoji("👍").to(user_jid);
+//! ```
+
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::jid::Jid;
+use xmpp_parsers::message::{Message, MessageType};
+use xmpp_parsers::minidom::Element;
+
+use crate::filter::{filter_fn_one, Filter};
+use crate::generic::One;
+use crate::hints::Hint;
+use crate::reject::{self, Rejection};
+
+/// The `urn:xmpp:reactions:0` namespace.
+pub const NS: &str = "urn:xmpp:reactions:0";
+
+/// The reactions of an entity to a message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reactions {
+    /// The entity reacting, when extracted from a stanza.
+    pub from: Option<Jid>,
+    /// The id of the message reacted to.
+    pub id: String,
+    /// The emojis, without duplicates.
+    pub emojis: Vec<String>,
+}
+
+impl Reactions {
+    /// No reactions to the message with `id`.
+    pub fn new(id: impl Into<String>) -> Self {
+        Reactions {
+            from: None,
+            id: id.into(),
+            emojis: Vec::new(),
+        }
+    }
+
+    /// Add `emoji`, unless it is already there.
+    pub fn emoji(mut self, emoji: impl Into<String>) -> Self {
+        let emoji = emoji.into();
+        if !self.emojis.contains(&emoji) {
+            self.emojis.push(emoji);
+        }
+        self
+    }
+
+    /// A chat message sending the reactions to `to`.
+    ///
+    /// The message carries a `store` hint so that archives keep it despite
+    /// its lack of body.
+    pub fn to(self, to: Jid) -> Message {
+        let mut msg = Message::new(Some(to));
+        msg.type_ = MessageType::Chat;
+        msg.payloads.push(Element::from(self));
+        msg.payloads.push(Element::from(Hint::Store));
+        msg
+    }
+}
+
+impl From<Reactions> for Element {
+    fn from(reactions: Reactions) -> Element {
+        Element::builder("reactions", NS)
+            .attr("id", reactions.id)
+            .append_all(
+                reactions
+                    .emojis
+                    .into_iter()
+                    .map(|emoji| Element::builder("reaction", NS).append(emoji).build()),
+            )
+            .build()
+    }
+}
+
+/// Extract the reactions to a message.
+///
+/// Rejects with `item-not-found` for other stanzas, and with `bad-request`
+/// if the message reacted to is not named.
+pub fn param() -> impl Filter<Extract = One<Reactions>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        let reactions = match stanza {
+            Stanza::Message(msg) => msg
+                .payloads
+                .iter()
+                .find(|payload| payload.is("reactions", NS))
+                .map(|reactions| parse(msg.from.clone(), reactions)),
+            _ => None,
+        };
+        future::ready(reactions.unwrap_or_else(|| Err(reject::item_not_found())))
+    })
+    .advertises(NS)
+}
+
+fn parse(from: Option<Jid>, elem: &Element) -> Result<Reactions, Rejection> {
+    let id = elem.attr("id").ok_or_else(reject::bad_request)?;
+    let reactions = elem
+        .children()
+        .filter(|child| child.is("reaction", NS))
+        .map(|reaction| reaction.text().trim().to_owned())
+        .filter(|emoji| !emoji.is_empty())
+        .fold(Reactions::new(id), Reactions::emoji);
+    Ok(Reactions { from, ..reactions })
+}
@@ -0,0 +1,336 @@
+//! Message Delivery Receipts (XEP-0184).
+//!
+//! - `wax::receipts::received()` - Extract incoming receipts
+//! - `wax::receipts::auto_ack()` - Acknowledge handled messages that request a receipt
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::receipts::{self, Received};
+//! use wax::Filter;
+//!
+//! let receipts = receipts::received().map(|receipt: Received| {
+//!     // mark receipt.id as delivered...
+//!     wax::sink()
+//! });
+//!
+//! let routes = receipts.or(chat).with(receipts::auto_ack());
+//! ```
+
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::jid::Jid;
+use xmpp_parsers::message::{Message, MessageType};
+use xmpp_parsers::minidom::Element;
+
+use crate::filter::{filter_fn_one, Filter, WrapSealed};
+use crate::generic::One;
+use crate::reject::{self, IsReject, Rejection};
+use crate::reply::Reply;
+
+use self::internal::WithAutoAck;
+
+/// The `urn:xmpp:receipts` namespace.
+pub const NS: &str = "urn:xmpp:receipts";
+
+/// A receipt confirming that a message was delivered.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Received {
+    /// The recipient of the original message.
+    pub from: Option<Jid>,
+    /// The sender of the original message.
+    pub to: Option<Jid>,
+    /// The id of the original message.
+    pub id: String,
+}
+
+/// Extract incoming receipts.
+///
+/// Receipts without an `id` attribute fall back to the id of the receipt
+/// message itself, as sent by older clients. Rejects with `item-not-found`
+/// for other stanzas.
+pub fn received() -> impl Filter<Extract = One<Received>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        let receipt = match stanza {
+            Stanza::Message(msg) => msg
+                .payloads
+                .iter()
+                .find(|payload| payload.is("received", NS))
+                .and_then(|received| {
+                    received
+                        .attr("id")
+                        .map(str::to_owned)
+                        .or_else(|| msg.id.as_ref().map(|id| id.0.clone()))
+                })
+                .map(|id| Received {
+                    from: msg.from.clone(),
+                    to: msg.to.clone(),
+                    id,
+                }),
+            _ => None,
+        };
+        future::ready(receipt.ok_or_else(reject::item_not_found))
+    })
+    .advertises(NS)
+}
+
+/// The receipt acknowledging `msg`, if it requests one.
+///
+/// Messages without an id, and error messages, are never acknowledged.
+pub fn ack(msg: &Message) -> Option<Message> {
+    if msg.type_ == MessageType::Error
+        || !msg.payloads.iter().any(|payload| payload.is("request", NS))
+    {
+        return None;
+    }
+    let id = msg.id.as_ref()?;
+    let mut ack = Message::new(msg.from.clone());
+    ack.from = msg.to.clone();
+    ack.type_ = msg.type_.clone();
+    ack.payloads.push(
+        Element::builder("received", NS)
+            .attr("id", id.0.as_str())
+            .build(),
+    );
+    Some(ack)
+}
+
+/// Acknowledge the messages handled by the wrapped filter that request a
+/// receipt.
+///
+/// The receipt is only sent if the filter succeeds, as part of its
+/// response, ahead of the reply.
+pub fn auto_ack() -> AutoAck {
+    AutoAck { _p: () }
+}
+
+/// Decorates a [`Filter`] to acknowledge messages requesting a receipt.
+#[derive(Clone, Copy, Debug)]
+pub struct AutoAck {
+    _p: (),
+}
+
+impl<F> WrapSealed<F> for AutoAck
+where
+    F: Filter + Clone + Send,
+    F::Extract: Reply,
+    F::Error: IsReject,
+{
+    type Wrapped = WithAutoAck<F>;
+
+    fn wrap(&self, filter: F) -> Self::Wrapped {
+        WithAutoAck { filter }
+    }
+}
+
+pub(crate) mod internal {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use futures_util::{ready, TryFuture};
+    use pin_project::pin_project;
+    use tokio_xmpp::Stanza;
+    use xmpp_parsers::message::Message;
+
+    use super::ack;
+    use crate::disco::Features;
+    use crate::filter::{Filter, FilterBase, Internal};
+    use crate::filtered_stanza;
+    use crate::reject::IsReject;
+    use crate::reply::{Reply, Response};
+
+    #[allow(missing_debug_implementations)]
+    pub struct Acked(pub(super) Response);
+
+    impl Reply for Acked {
+        #[inline]
+        fn into_response(self) -> Response {
+            self.0
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    #[derive(Clone, Copy)]
+    pub struct WithAutoAck<F> {
+        pub(super) filter: F,
+    }
+
+    impl<F> FilterBase for WithAutoAck<F>
+    where
+        F: Filter + Clone + Send,
+        F::Extract: Reply,
+        F::Error: IsReject,
+    {
+        type Extract = (Acked,);
+        type Error = F::Error;
+        type Future = WithAutoAckFuture<F::Future>;
+
+        fn filter(&self, _: Internal) -> Self::Future {
+            let ack = filtered_stanza::with(|stanza| match stanza {
+                Stanza::Message(msg) => ack(msg),
+                _ => None,
+            });
+            WithAutoAckFuture {
+                ack,
+                future: self.filter.filter(Internal),
+            }
+        }
+
+        fn advertise(&self, features: &mut Features) {
+            features.insert(super::NS);
+            self.filter.advertise(features);
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    #[pin_project]
+    pub struct WithAutoAckFuture<F> {
+        ack: Option<Message>,
+        #[pin]
+        future: F,
+    }
+
+    impl<F> Future for WithAutoAckFuture<F>
+    where
+        F: TryFuture,
+        F::Ok: Reply,
+        F::Error: IsReject,
+    {
+        type Output = Result<(Acked,), F::Error>;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            let pin = self.project();
+            let reply = ready!(pin.future.try_poll(cx))?.into_response();
+            let Some(ack) = pin.ack.take() else {
+                return Poll::Ready(Ok((Acked(reply),)));
+            };
+            let mut response = Response::from(Stanza::Message(ack));
+            response.append(reply);
+            Poll::Ready(Ok((Acked(response),)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use xmpp_parsers::message::{Id, Lang};
+
+    use super::*;
+    use crate::reply::Response;
+
+    fn message(request: bool) -> Message {
+        let mut msg = Message::new(Some(
+            "kingrichard@royalty.england.lit/throne".parse().unwrap(),
+        ))
+        .with_body(Lang::default(), "My lord, dispatch".to_owned());
+        msg.from = Some(
+            "northumberland@shakespeare.lit/westminster"
+                .parse()
+                .unwrap(),
+        );
+        msg.id = Some(Id("richard2-4.1.247".to_owned()));
+        msg.type_ = MessageType::Chat;
+        if request {
+            msg.payloads.push(Element::builder("request", NS).build());
+        }
+        msg
+    }
+
+    // The id acknowledged by `stanza`, if it is a receipt.
+    fn acked(stanza: &Stanza) -> Option<&str> {
+        match stanza {
+            Stanza::Message(msg) => msg
+                .payloads
+                .iter()
+                .find(|payload| payload.is("received", NS))
+                .and_then(|received| received.attr("id")),
+            _ => None,
+        }
+    }
+
+    async fn respond(request: bool) -> Response {
+        let route = crate::any()
+            .map(|| {
+                let mut reply = Message::new(Some(
+                    "northumberland@shakespeare.lit/westminster"
+                        .parse()
+                        .unwrap(),
+                ));
+                reply.type_ = MessageType::Chat;
+                reply
+            })
+            .with(auto_ack());
+        crate::service(route)
+            .call_stanza(Stanza::Message(message(request)))
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn acks_requests_only() {
+        let receipt = ack(&message(true)).unwrap();
+        assert_eq!(receipt.to, message(true).from);
+        assert_eq!(receipt.from, message(true).to);
+        assert_eq!(acked(&Stanza::Message(receipt)), Some("richard2-4.1.247"));
+
+        assert!(ack(&message(false)).is_none());
+        let mut anonymous = message(true);
+        anonymous.id = None;
+        assert!(ack(&anonymous).is_none());
+        let mut error = message(true);
+        error.type_ = MessageType::Error;
+        assert!(ack(&error).is_none());
+    }
+
+    #[tokio::test]
+    async fn sends_the_receipt_ahead_of_the_reply() {
+        let response = respond(true).await;
+        let [receipt, Stanza::Message(reply)] = response.stanzas() else {
+            panic!("expected a receipt and a reply, got {:?}", response);
+        };
+        assert_eq!(acked(receipt), Some("richard2-4.1.247"));
+        assert!(reply
+            .payloads
+            .iter()
+            .all(|payload| !payload.is("received", NS)));
+
+        let unrequested = respond(false).await;
+        assert_eq!(unrequested.stanzas().len(), 1);
+        assert_eq!(acked(&unrequested.stanzas()[0]), None);
+    }
+
+    #[tokio::test]
+    async fn does_not_ack_rejected_messages() {
+        let route = received().map(|_: Received| crate::sink()).with(auto_ack());
+        let response = crate::service(route)
+            .call_stanza(Stanza::Message(message(true)))
+            .await
+            .unwrap();
+        assert!(response
+            .stanzas()
+            .iter()
+            .all(|stanza| acked(stanza).is_none()));
+    }
+
+    #[tokio::test]
+    async fn extracts_receipts() {
+        // Older clients leave out the id, and reuse the original one for the
+        // receipt message.
+        for id in [Some("richard2-4.1.247"), None] {
+            let mut receipt = message(false);
+            receipt
+                .payloads
+                .push(Element::builder("received", NS).attr("id", id).build());
+            let route = received().map(|receipt: Received| {
+                assert_eq!(receipt.id, "richard2-4.1.247");
+                crate::sink()
+            });
+            let response = crate::service(route)
+                .call_stanza(Stanza::Message(receipt))
+                .await
+                .unwrap();
+            assert!(response.is_empty());
+        }
+    }
+}
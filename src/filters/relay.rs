@@ -0,0 +1,138 @@
+//! Passthrough relaying.
+//!
+//! - `wax::relay::param()` - Extract a [`Relay`] of the incoming stanza
+//!
+//! Gateways and relays often only readdress a stanza before sending it on.
+//! Rebuilding it from extracted parts (or cloning it with
+//! `message::param()`) copies every payload; a [`Relay`] instead takes the
+//! incoming stanza back once filtering completes and rewrites its `to` and
+//! `from` in place, so it is forwarded without any copy.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::relay::Relay;
+//! use wax::Filter;
+//!
+//! let upstream: wax::xmpp_parsers::jid::Jid = "upstream.example.org".parse()?;
+//! let route = wax::message()
+//!     .and(wax::relay::param())
+//!     .map(move |relay: Relay| relay.to(upstream.clone()));
+//! ```
+
+use std::convert::Infallible;
+use std::fmt;
+use std::sync::Arc;
+
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::jid::Jid;
+
+use crate::filter::{Filter, FilterBase, Internal};
+use crate::filtered_stanza;
+use crate::generic::One;
+use crate::reply::{Reply, ReplySealed, Response};
+
+/// Extract a [`Relay`] of the incoming stanza.
+pub fn param() -> impl Filter<Extract = One<Relay>, Error = Infallible> + Copy {
+    Param
+}
+
+#[derive(Copy, Clone)]
+#[allow(missing_debug_implementations)]
+struct Param;
+
+impl FilterBase for Param {
+    type Extract = One<Relay>;
+    type Error = Infallible;
+    type Future = future::Ready<Result<Self::Extract, Self::Error>>;
+
+    fn filter(&self, _: Internal) -> Self::Future {
+        future::ok((Relay {
+            stanza: filtered_stanza::shared(),
+            to: None,
+            from: None,
+        },))
+    }
+}
+
+/// The incoming stanza, to be forwarded with new addresses.
+///
+/// As a [`Reply`], it sends the incoming stanza itself, with `to` and `from`
+/// replaced if set. The stanza is only copied if something else, such as a
+/// webhook still in flight, holds on to it too.
+pub struct Relay {
+    stanza: Arc<Stanza>,
+    to: Option<Jid>,
+    from: Option<Jid>,
+}
+
+impl Relay {
+    /// Forward the stanza to `to`.
+    pub fn to(mut self, to: Jid) -> Self {
+        self.to = Some(to);
+        self
+    }
+
+    /// Forward the stanza as coming from `from`.
+    pub fn from(mut self, from: Jid) -> Self {
+        self.from = Some(from);
+        self
+    }
+
+    /// The stanza being relayed, as it was received.
+    pub fn stanza(&self) -> &Stanza {
+        &self.stanza
+    }
+}
+
+impl fmt::Debug for Relay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Relay")
+            .field("to", &self.to)
+            .field("from", &self.from)
+            .finish()
+    }
+}
+
+impl Reply for Relay {
+    fn into_response(self) -> Response {
+        let mut stanza = Arc::try_unwrap(self.stanza).unwrap_or_else(|shared| (*shared).clone());
+        let (to, from) = match stanza {
+            Stanza::Message(ref mut msg) => (&mut msg.to, &mut msg.from),
+            Stanza::Presence(ref mut pres) => (&mut pres.to, &mut pres.from),
+            Stanza::Iq(
+                Iq::Get {
+                    ref mut to,
+                    ref mut from,
+                    ..
+                }
+                | Iq::Set {
+                    ref mut to,
+                    ref mut from,
+                    ..
+                }
+                | Iq::Result {
+                    ref mut to,
+                    ref mut from,
+                    ..
+                }
+                | Iq::Error {
+                    ref mut to,
+                    ref mut from,
+                    ..
+                },
+            ) => (to, from),
+        };
+        if self.to.is_some() {
+            *to = self.to;
+        }
+        if self.from.is_some() {
+            *from = self.from;
+        }
+        Response::from(stanza)
+    }
+}
+
+impl ReplySealed for Relay {}
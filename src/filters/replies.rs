@@ -0,0 +1,144 @@
+//! Message Replies (XEP-0461).
+//!
+//! - `wax::replies::param()` - Extract the [`ReplyTo`] reference of a message
+//!
+//! Clients that do not understand replies see a quote of the original
+//! message at the start of the body, which the sender marks as a fallback
+//! (XEP-0428). The extracted body has that quote trimmed.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::replies::ReplyTo;
+//! use wax::Filter;
+//!
+//! let threaded = wax::replies::param().map(|reply: ReplyTo| {
+//!     // post reply.body in the thread of reply.id...
+//!     wax::sink()
+//! });
+//! ```
+
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::jid::Jid;
+use xmpp_parsers::message::Message;
+use xmpp_parsers::minidom::Element;
+
+use crate::filter::{filter_fn_one, Filter};
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+
+/// The `urn:xmpp:reply:0` namespace.
+pub const NS: &str = "urn:xmpp:reply:0";
+
+/// The `urn:xmpp:fallback:0` namespace of fallback indications.
+pub const NS_FALLBACK: &str = "urn:xmpp:fallback:0";
+
+/// A message replying to an earlier one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReplyTo {
+    /// The sender of the reply.
+    pub from: Option<Jid>,
+    /// The sender of the message replied to, if given.
+    pub to: Option<Jid>,
+    /// The id of the message replied to.
+    pub id: String,
+    /// The body of the reply, without the fallback quote.
+    pub body: String,
+}
+
+/// Extract the reference to the message a message replies to.
+///
+/// Rejects with `item-not-found` for other stanzas, and with `bad-request`
+/// if the reference is malformed.
+pub fn param() -> impl Filter<Extract = One<ReplyTo>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        let reply = match stanza {
+            Stanza::Message(msg) => msg
+                .payloads
+                .iter()
+                .find(|payload| payload.is("reply", NS))
+                .map(|reply| parse(msg, reply)),
+            _ => None,
+        };
+        future::ready(reply.unwrap_or_else(|| Err(reject::item_not_found())))
+    })
+    .advertises(NS)
+}
+
+fn parse(msg: &Message, reply: &Element) -> Result<ReplyTo, Rejection> {
+    let id = reply.attr("id").ok_or_else(reject::bad_request)?;
+    let to = match reply.attr("to") {
+        Some(to) => Some(to.parse().map_err(|_| reject::bad_request())?),
+        None => None,
+    };
+    let body = msg
+        .get_best_body_cloned(vec![])
+        .map(|(_lang, body)| body)
+        .unwrap_or_default();
+    Ok(ReplyTo {
+        from: msg.from.clone(),
+        to,
+        id: id.to_owned(),
+        body: trim_fallback(msg, body),
+    })
+}
+
+// Remove the ranges of `body` marked as a reply fallback. Offsets count
+// Unicode code points.
+fn trim_fallback(msg: &Message, body: String) -> String {
+    let ranges: Vec<(usize, usize)> = msg
+        .payloads
+        .iter()
+        .filter(|payload| payload.is("fallback", NS_FALLBACK) && payload.attr("for") == Some(NS))
+        .flat_map(|fallback| {
+            fallback
+                .children()
+                .filter(|child| child.is("body", NS_FALLBACK))
+        })
+        .filter_map(|range| {
+            let start = range.attr("start")?.parse().ok()?;
+            let end = range.attr("end")?.parse().ok()?;
+            (start <= end).then_some((start, end))
+        })
+        .collect();
+    if ranges.is_empty() {
+        return body;
+    }
+    body.chars()
+        .enumerate()
+        .filter(|(i, _)| !ranges.iter().any(|&(start, end)| (start..end).contains(i)))
+        .map(|(_, c)| c)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xmpp_parsers::message::Lang;
+
+    #[test]
+    fn trims_the_quote() {
+        let body = "> Anna wrote:\n> We should bake a cake\nGreat idea!";
+        let mut msg = Message::new(None).with_body(Lang::default(), body.to_owned());
+        let reply = Element::builder("reply", NS)
+            .attr("to", "anna@example.com/laptop")
+            .attr("id", "message-id1")
+            .build();
+        msg.payloads.push(reply.clone());
+        msg.payloads.push(
+            Element::builder("fallback", NS_FALLBACK)
+                .attr("for", NS)
+                .append(
+                    Element::builder("body", NS_FALLBACK)
+                        .attr("start", "0")
+                        .attr("end", "38")
+                        .build(),
+                )
+                .build(),
+        );
+        let reply = parse(&msg, &reply).unwrap();
+        assert_eq!(reply.id, "message-id1");
+        assert_eq!(reply.body, "Great idea!");
+    }
+}
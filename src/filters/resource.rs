@@ -0,0 +1,54 @@
+//! Resource filters.
+//!
+//! - `wax::resource::param()` - Extract the resource of the recipient
+//! - `wax::resource_is("announce")` - Match stanzas sent to a resource
+//!
+//! A component can expose several endpoints as resources of its own JID,
+//! e.g. `component/announce` for broadcasts.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::Filter;
+//!
+//! let announce = wax::resource_is("announce")
+//!     .and(wax::message::body::param())
+//!     .map(|body: String| {
+//!         // broadcast `body` to every user...
+//!         wax::sink()
+//!     });
+//! ```
+
+use futures_util::future;
+use tokio_xmpp::Stanza;
+
+use crate::filter::{filter_fn, filter_fn_one, Filter};
+use crate::filters::stanza::to_of;
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+
+/// Extract the resource of the `to` JID.
+///
+/// Rejects with `item-not-found` if the recipient has no resource.
+pub fn param() -> impl Filter<Extract = One<String>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(
+            to_of(stanza)
+                .and_then(|to| to.resource())
+                .map(|resource| resource.as_str().to_owned())
+                .ok_or_else(reject::item_not_found),
+        )
+    })
+}
+
+/// Match stanzas sent to the resource `expected`.
+///
+/// Rejects with `item-not-found` for other stanzas.
+pub fn resource_is(expected: &'static str) -> impl Filter<Extract = (), Error = Rejection> + Copy {
+    filter_fn(
+        move |stanza: &Stanza| match to_of(stanza).and_then(|to| to.resource()) {
+            Some(resource) if resource.as_str() == expected => future::ok(()),
+            _ => future::err(reject::item_not_found()),
+        },
+    )
+}
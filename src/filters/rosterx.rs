@@ -0,0 +1,212 @@
+//! Roster Item Exchange (XEP-0144).
+//!
+//! - `wax::rosterx::param()` - Extract the [`Suggestion`] of a message or IQ
+//! - [`Suggestion::to`] - Build a message suggesting roster changes
+//!
+//! A gateway uses this to push the contact list of a legacy account into
+//! the roster of its user, who approves each change.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::rosterx::{Item, Suggestion};
+//!
+//! let contacts = Suggestion::new()
+//!     .item(Item::add(contact_jid).name("Tybalt").group("Legacy"))
+//!     .to(user_jid);
+//! ```
+
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::jid::Jid;
+use xmpp_parsers::message::Message;
+use xmpp_parsers::minidom::Element;
+
+use crate::filter::{filter_fn_one, Filter};
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+
+/// The `http://jabber.org/protocol/rosterx` namespace.
+pub const NS: &str = "http://jabber.org/protocol/rosterx";
+
+/// What to do with a roster item.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Action {
+    /// Add the item.
+    #[default]
+    Add,
+    /// Remove the item.
+    Delete,
+    /// Change the name or groups of the item.
+    Modify,
+}
+
+impl Action {
+    fn as_str(self) -> &'static str {
+        match self {
+            Action::Add => "add",
+            Action::Delete => "delete",
+            Action::Modify => "modify",
+        }
+    }
+}
+
+/// A suggested change to one roster item.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Item {
+    /// What to do.
+    pub action: Action,
+    /// The contact.
+    pub jid: Jid,
+    /// The name to show for the contact, if any.
+    pub name: Option<String>,
+    /// The groups to put the contact in.
+    pub groups: Vec<String>,
+}
+
+impl Item {
+    /// Suggest adding `jid`.
+    pub fn add(jid: Jid) -> Self {
+        Item::new(Action::Add, jid)
+    }
+
+    /// Suggest removing `jid`.
+    pub fn delete(jid: Jid) -> Self {
+        Item::new(Action::Delete, jid)
+    }
+
+    /// Suggest changing the name or groups of `jid`.
+    pub fn modify(jid: Jid) -> Self {
+        Item::new(Action::Modify, jid)
+    }
+
+    fn new(action: Action, jid: Jid) -> Self {
+        Item {
+            action,
+            jid,
+            name: None,
+            groups: Vec::new(),
+        }
+    }
+
+    /// Set the name.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Add a group.
+    pub fn group(mut self, group: impl Into<String>) -> Self {
+        self.groups.push(group.into());
+        self
+    }
+}
+
+impl From<Item> for Element {
+    fn from(item: Item) -> Element {
+        Element::builder("item", NS)
+            .attr("action", item.action.as_str())
+            .attr("jid", item.jid.to_string())
+            .attr("name", item.name)
+            .append_all(
+                item.groups
+                    .into_iter()
+                    .map(|group| Element::builder("group", NS).append(group).build()),
+            )
+            .build()
+    }
+}
+
+/// A set of suggested roster changes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Suggestion {
+    /// The entity suggesting, when extracted from a stanza.
+    pub from: Option<Jid>,
+    /// The changes.
+    pub items: Vec<Item>,
+}
+
+impl Suggestion {
+    /// No changes.
+    pub fn new() -> Self {
+        Suggestion::default()
+    }
+
+    /// Add a change.
+    pub fn item(mut self, item: Item) -> Self {
+        self.items.push(item);
+        self
+    }
+
+    /// A message sending the suggestion to `to`.
+    pub fn to(self, to: Jid) -> Message {
+        let mut msg = Message::new(Some(to));
+        msg.payloads.push(Element::from(self));
+        msg
+    }
+}
+
+impl From<Suggestion> for Element {
+    fn from(suggestion: Suggestion) -> Element {
+        Element::builder("x", NS)
+            .append_all(suggestion.items.into_iter().map(Element::from))
+            .build()
+    }
+}
+
+/// Extract the roster changes suggested by a message or an IQ.
+///
+/// Rejects with `item-not-found` for other stanzas, and with `bad-request`
+/// if an item is malformed.
+pub fn param() -> impl Filter<Extract = One<Suggestion>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        let found = match stanza {
+            Stanza::Message(msg) => msg
+                .payloads
+                .iter()
+                .find(|payload| payload.is("x", NS))
+                .map(|x| (&msg.from, x)),
+            Stanza::Iq(Iq::Set { from, payload, .. }) if payload.is("x", NS) => {
+                Some((from, payload))
+            }
+            _ => None,
+        };
+        future::ready(match found {
+            Some((from, x)) => parse(x).map(|items| Suggestion {
+                from: from.clone(),
+                items,
+            }),
+            None => Err(reject::item_not_found()),
+        })
+    })
+    .advertises(NS)
+}
+
+fn parse(x: &Element) -> Result<Vec<Item>, Rejection> {
+    x.children()
+        .filter(|child| child.is("item", NS))
+        .map(|item| {
+            let action = match item.attr("action") {
+                None | Some("add") => Action::Add,
+                Some("delete") => Action::Delete,
+                Some("modify") => Action::Modify,
+                Some(_) => return Err(reject::bad_request()),
+            };
+            let jid = item
+                .attr("jid")
+                .and_then(|jid| jid.parse().ok())
+                .ok_or_else(reject::bad_request)?;
+            Ok(Item {
+                action,
+                jid,
+                name: item.attr("name").map(str::to_owned),
+                groups: item
+                    .children()
+                    .filter(|group| group.is("group", NS))
+                    .map(Element::text)
+                    .collect(),
+            })
+        })
+        .collect()
+}
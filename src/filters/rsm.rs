@@ -0,0 +1,242 @@
+//! Result Set Management (XEP-0059).
+//!
+//! - `wax::rsm::param()` - Extract the paging [`Request`] of a query
+//! - `wax::rsm::optional()` - Same, but yields `None` for unpaged queries
+//!
+//! Answer with the `<set/>` of a [`Page`]: either built by
+//! [`Request::paginate`] over a list held in memory, or with [`Page::of`]
+//! from the items a backend returned for its cursor.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::rsm::{self, Page, Request};
+//! use wax::Filter;
+//!
+//! let route = wax::query("jabber:iq:search")
+//!     .and(rsm::optional())
+//!     .and_then(|query: Element, page: Option<Request>| async move {
+//!         let page = page.unwrap_or_default();
+//!         let rows = db.search(&query, page.after.as_deref(), page.max.unwrap_or(50)).await?;
+//!         let set = Page::of(&rows, |row| row.id.to_string()).count(db.count(&query).await?);
+//!         // answer with the rows and Element::from(set)...
+//!     });
+//! ```
+
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::minidom::Element;
+
+use crate::filter::{filter_fn_one, Filter};
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+
+/// The `http://jabber.org/protocol/rsm` namespace.
+pub const NS: &str = "http://jabber.org/protocol/rsm";
+
+/// The page of a result set asked for.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Request {
+    /// The maximum number of items; `Some(0)` asks for the count only.
+    pub max: Option<usize>,
+    /// Items after the one with this id.
+    pub after: Option<String>,
+    /// Items before the one with this id; `Some("")` for the last page.
+    pub before: Option<String>,
+    /// Items from this position on.
+    pub index: Option<usize>,
+}
+
+impl Request {
+    /// The requested page of `items`, identified by `key`, and its `<set/>`.
+    ///
+    /// Rejects with `item-not-found` if `after` or `before` is unknown.
+    pub fn paginate<'a, T, K>(
+        &self,
+        items: &'a [T],
+        key: impl Fn(&T) -> K,
+    ) -> Result<(&'a [T], Page), Rejection>
+    where
+        K: Into<String>,
+    {
+        let position = |id: &str| {
+            items
+                .iter()
+                .position(|item| key(item).into() == id)
+                .ok_or_else(reject::item_not_found)
+        };
+        let max = self.max.unwrap_or(items.len());
+        let (start, end) = match (&self.before, &self.after, self.index) {
+            (Some(before), _, _) => {
+                let end = match before.as_str() {
+                    "" => items.len(),
+                    before => position(before)?,
+                };
+                (end.saturating_sub(max), end)
+            }
+            (None, Some(after), _) => {
+                let start = position(after)? + 1;
+                (start, start.saturating_add(max).min(items.len()))
+            }
+            (None, None, index) => {
+                let start = index.unwrap_or(0).min(items.len());
+                (start, start.saturating_add(max).min(items.len()))
+            }
+        };
+        let slice = &items[start..end];
+        let page = Page::of(slice, &key).index(start).count(items.len());
+        Ok((slice, page))
+    }
+}
+
+/// The `<set/>` describing a page of a result set.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Page {
+    /// The id of the first item of the page.
+    pub first: Option<String>,
+    /// The position of the first item in the whole result set.
+    pub index: Option<usize>,
+    /// The id of the last item of the page.
+    pub last: Option<String>,
+    /// The number of items in the whole result set.
+    pub count: Option<usize>,
+}
+
+impl Page {
+    /// The page holding `items`, identified by `key`.
+    pub fn of<T, K>(items: &[T], key: impl Fn(&T) -> K) -> Self
+    where
+        K: Into<String>,
+    {
+        Page {
+            first: items.first().map(|item| key(item).into()),
+            index: None,
+            last: items.last().map(|item| key(item).into()),
+            count: None,
+        }
+    }
+
+    /// Set the position of the first item.
+    pub fn index(mut self, index: usize) -> Self {
+        self.index = Some(index);
+        self
+    }
+
+    /// Set the number of items in the whole result set.
+    pub fn count(mut self, count: usize) -> Self {
+        self.count = Some(count);
+        self
+    }
+}
+
+impl From<Page> for Element {
+    fn from(page: Page) -> Element {
+        let mut set = Element::builder("set", NS);
+        if let Some(first) = page.first {
+            set = set.append(
+                Element::builder("first", NS)
+                    .attr("index", page.index.map(|index| index.to_string()))
+                    .append(first)
+                    .build(),
+            );
+        }
+        if let Some(last) = page.last {
+            set = set.append(Element::builder("last", NS).append(last).build());
+        }
+        if let Some(count) = page.count {
+            set = set.append(
+                Element::builder("count", NS)
+                    .append(count.to_string())
+                    .build(),
+            );
+        }
+        set.build()
+    }
+}
+
+/// Extract the paging request of an IQ query.
+///
+/// Rejects with `item-not-found` if the query is not paged, and with
+/// `bad-request` if the request is malformed.
+pub fn param() -> impl Filter<Extract = One<Request>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(match request_of(stanza) {
+            Some(request) => request,
+            None => Err(reject::item_not_found()),
+        })
+    })
+    .advertises(NS)
+}
+
+/// Extract the paging request of an IQ query, if any.
+///
+/// Rejects with `bad-request` if the request is malformed.
+pub fn optional() -> impl Filter<Extract = One<Option<Request>>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| future::ready(request_of(stanza).transpose())).advertises(NS)
+}
+
+fn request_of(stanza: &Stanza) -> Option<Result<Request, Rejection>> {
+    match stanza {
+        Stanza::Iq(Iq::Get { payload, .. }) | Stanza::Iq(Iq::Set { payload, .. }) => parse(payload),
+        _ => None,
+    }
+}
+
+/// The paging request in `query`, if any.
+pub(crate) fn parse(query: &Element) -> Option<Result<Request, Rejection>> {
+    query.get_child("set", NS).map(parse_set)
+}
+
+fn parse_set(set: &Element) -> Result<Request, Rejection> {
+    let number = |name| match set.get_child(name, NS) {
+        Some(elem) => elem.text().trim().parse().map(Some).map_err(|_| {
+            tracing::debug!("invalid rsm <{}/>: {:?}", name, elem.text());
+            reject::bad_request()
+        }),
+        None => Ok(None),
+    };
+    Ok(Request {
+        max: number("max")?,
+        after: set.get_child("after", NS).map(Element::text),
+        before: set.get_child("before", NS).map(Element::text),
+        index: number("index")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paginates_both_ways() {
+        let items: Vec<u32> = (0..10).collect();
+        let key = |item: &u32| item.to_string();
+
+        let request = Request {
+            max: Some(3),
+            after: Some("4".to_owned()),
+            ..Request::default()
+        };
+        let (page, set) = request.paginate(&items, key).unwrap();
+        assert_eq!(page, &[5, 6, 7]);
+        assert_eq!(set.first.as_deref(), Some("5"));
+        assert_eq!(set.index, Some(5));
+        assert_eq!(set.count, Some(10));
+
+        let request = Request {
+            max: Some(4),
+            before: Some(String::new()),
+            ..Request::default()
+        };
+        let (page, set) = request.paginate(&items, key).unwrap();
+        assert_eq!(page, &[6, 7, 8, 9]);
+        assert_eq!(set.last.as_deref(), Some("9"));
+
+        let request = Request {
+            before: Some("42".to_owned()),
+            ..Request::default()
+        };
+        assert!(request.paginate(&items, key).is_err());
+    }
+}
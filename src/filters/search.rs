@@ -0,0 +1,404 @@
+//! Jabber Search (XEP-0055).
+//!
+//! - `wax::search::get()` - Match requests for the search form
+//! - `wax::search::set()` - Extract a submitted search [`Query`]
+//!
+//! Answer a [`FormRequest`] with a [`SearchForm`] of legacy fields, a data
+//! form, or both, and a [`Query`] with [`Query::results`]. Results come back
+//! in the format the query was submitted in, paginated when the query asked
+//! for a page (XEP-0059).
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::search::{self, FormRequest, Item, Query, SearchForm};
+//! use wax::Filter;
+//!
+//! let form = search::get().map(|request: FormRequest| {
+//!     request.form(
+//!         SearchForm::new()
+//!             .instructions("Find a contact by nickname.")
+//!             .field("nick"),
+//!     )
+//! });
+//!
+//! let results = search::set().and_then(|query: Query| async move {
+//!     let nick = query.nick().ok_or_else(wax::reject::not_acceptable)?;
+//!     let items: Vec<Item> = directory_lookup(nick).await;
+//!     query.results(&items)
+//! });
+//!
+//! let routes = form.or(results);
+//! ```
+
+use std::collections::BTreeMap;
+
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::jid::Jid;
+use xmpp_parsers::minidom::Element;
+
+use crate::filter::{filter_fn_one, Filter};
+use crate::form::{self, DataForm};
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+use crate::rsm;
+
+/// The `jabber:iq:search` namespace.
+pub const NS: &str = "jabber:iq:search";
+
+/// A request for the search form.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FormRequest {
+    /// The entity searching.
+    pub from: Option<Jid>,
+    /// The directory searched.
+    pub to: Option<Jid>,
+    id: String,
+}
+
+impl FormRequest {
+    /// Answer with `form`.
+    pub fn form(self, form: SearchForm) -> Iq {
+        Iq::Result {
+            from: self.to,
+            to: self.from,
+            id: self.id,
+            payload: Some(form.into()),
+        }
+    }
+}
+
+/// The search form sent in answer to a [`FormRequest`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SearchForm {
+    instructions: Option<String>,
+    fields: Vec<String>,
+    data_form: Option<DataForm>,
+}
+
+impl SearchForm {
+    /// An empty form.
+    pub fn new() -> Self {
+        SearchForm::default()
+    }
+
+    /// Set the instructions shown to the user.
+    pub fn instructions(mut self, instructions: impl Into<String>) -> Self {
+        self.instructions = Some(instructions.into());
+        self
+    }
+
+    /// Offer the legacy field `name`: `first`, `last`, `nick` or `email`.
+    pub fn field(mut self, name: impl Into<String>) -> Self {
+        self.fields.push(name.into());
+        self
+    }
+
+    /// Offer a data form instead of, or on top of, legacy fields.
+    pub fn data_form(mut self, form: DataForm) -> Self {
+        self.data_form = Some(form);
+        self
+    }
+}
+
+impl From<SearchForm> for Element {
+    fn from(form: SearchForm) -> Element {
+        Element::builder("query", NS)
+            .append_all(form.instructions.map(|instructions| {
+                Element::builder("instructions", NS)
+                    .append(instructions)
+                    .build()
+            }))
+            .append_all(
+                form.fields
+                    .into_iter()
+                    .map(|name| Element::builder(name, NS).build()),
+            )
+            .append_all(form.data_form.map(Element::from))
+            .build()
+    }
+}
+
+/// A submitted search.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Query {
+    /// The entity searching.
+    pub from: Option<Jid>,
+    /// The directory searched.
+    pub to: Option<Jid>,
+    /// The submitted legacy fields, by element name.
+    pub fields: BTreeMap<String, String>,
+    /// The submitted data form, if the search used one.
+    pub form: Option<DataForm>,
+    /// The page of results asked for, if any.
+    pub page: Option<rsm::Request>,
+    id: String,
+}
+
+impl Query {
+    /// The value of the field `name`, from the data form if there is one,
+    /// or else from the legacy fields. Empty values count as missing.
+    pub fn field(&self, name: &str) -> Option<&str> {
+        let value = match self.form {
+            Some(ref form) => form
+                .values(name)
+                .and_then(|values| values.first())
+                .map(String::as_str),
+            None => self.fields.get(name).map(String::as_str),
+        };
+        value.filter(|value| !value.is_empty())
+    }
+
+    /// The `first` name field.
+    pub fn first(&self) -> Option<&str> {
+        self.field("first")
+    }
+
+    /// The `last` name field.
+    pub fn last(&self) -> Option<&str> {
+        self.field("last")
+    }
+
+    /// The `nick` field.
+    pub fn nick(&self) -> Option<&str> {
+        self.field("nick")
+    }
+
+    /// The `email` field.
+    pub fn email(&self) -> Option<&str> {
+        self.field("email")
+    }
+
+    /// Read the submitted data form as `T`.
+    ///
+    /// Rejects with `bad-request` if there is no data form, or if it does
+    /// not convert.
+    pub fn form_as<T: form::FromDataForm>(&self) -> Result<T, Rejection> {
+        let form = self.form.as_ref().ok_or_else(reject::bad_request)?;
+        T::from_data_form(form).map_err(|err| {
+            tracing::debug!("invalid search form: {}", err);
+            reject::bad_request()
+        })
+    }
+
+    /// Answer with `items`, or the page of them asked for.
+    ///
+    /// Rejects with `item-not-found` if the page starts from an unknown
+    /// item.
+    pub fn results(self, items: &[Item]) -> Result<Iq, Rejection> {
+        let (items, page) = match self.page {
+            Some(ref request) => {
+                let (items, page) = request.paginate(items, |item| item.jid.to_string())?;
+                (items, Some(page))
+            }
+            None => (items, None),
+        };
+        let query = match self.form {
+            Some(_) => Element::builder("query", NS).append(data_form_results(items)),
+            None => Element::builder("query", NS).append_all(items.iter().map(Item::to_legacy)),
+        };
+        Ok(Iq::Result {
+            from: self.to,
+            to: self.from,
+            id: self.id,
+            payload: Some(query.append_all(page.map(Element::from)).build()),
+        })
+    }
+}
+
+/// A search result.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Item {
+    /// The entity found.
+    pub jid: Jid,
+    /// Its fields, in order, e.g. `("nick", "Jules")`.
+    pub fields: Vec<(String, String)>,
+}
+
+impl Item {
+    /// A result without fields.
+    pub fn new(jid: Jid) -> Self {
+        Item {
+            jid,
+            fields: Vec::new(),
+        }
+    }
+
+    /// Add the field `name`.
+    pub fn field(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.fields.push((name.into(), value.into()));
+        self
+    }
+
+    fn to_legacy(&self) -> Element {
+        Element::builder("item", NS)
+            .attr("jid", self.jid.to_string())
+            .append_all(self.fields.iter().map(|(name, value)| {
+                Element::builder(name.as_str(), NS)
+                    .append(value.as_str())
+                    .build()
+            }))
+            .build()
+    }
+}
+
+/// Match requests for the search form.
+pub fn get() -> impl Filter<Extract = One<FormRequest>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| match stanza {
+        Stanza::Iq(Iq::Get {
+            from,
+            to,
+            id,
+            payload,
+        }) if payload.is("query", NS) => future::ok(FormRequest {
+            from: from.clone(),
+            to: to.clone(),
+            id: id.clone(),
+        }),
+        _ => future::err(reject::item_not_found()),
+    })
+    .advertises(NS)
+}
+
+/// Extract a submitted search.
+///
+/// Rejects with `item-not-found` for other stanzas, and with `bad-request`
+/// if its data form or page request is malformed.
+pub fn set() -> impl Filter<Extract = One<Query>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(match stanza {
+            Stanza::Iq(Iq::Set {
+                from,
+                to,
+                id,
+                payload,
+            }) if payload.is("query", NS) => parse(payload).map(|(fields, form, page)| Query {
+                from: from.clone(),
+                to: to.clone(),
+                fields,
+                form,
+                page,
+                id: id.clone(),
+            }),
+            _ => Err(reject::item_not_found()),
+        })
+    })
+    .advertises(NS)
+}
+
+type Parsed = (
+    BTreeMap<String, String>,
+    Option<DataForm>,
+    Option<rsm::Request>,
+);
+
+fn parse(query: &Element) -> Result<Parsed, Rejection> {
+    let fields = query
+        .children()
+        .filter(|child| child.ns() == NS && child.name() != "instructions")
+        .map(|child| (child.name().to_owned(), child.text()))
+        .collect();
+    let form = match query.get_child("x", form::NS) {
+        Some(x) => Some(DataForm::try_from(x).map_err(|err| {
+            tracing::debug!("invalid search form: {}", err);
+            reject::bad_request()
+        })?),
+        None => None,
+    };
+    let page = rsm::parse(query).transpose()?;
+    Ok((fields, form, page))
+}
+
+// A `result` data form listing `items`, with every field name used by one
+// of them as a reported column.
+fn data_form_results(items: &[Item]) -> Element {
+    let mut columns = vec!["jid"];
+    for (name, _) in items.iter().flat_map(|item| &item.fields) {
+        if !columns.contains(&name.as_str()) {
+            columns.push(name);
+        }
+    }
+    let field = |var: &str, value: Option<&str>| {
+        Element::builder("field", form::NS)
+            .attr("var", var)
+            .append_all(
+                value.map(|value| Element::builder("value", form::NS).append(value).build()),
+            )
+            .build()
+    };
+    Element::builder("x", form::NS)
+        .attr("type", "result")
+        .append(
+            Element::builder("reported", form::NS)
+                .append_all(columns.iter().map(|var| field(var, None)))
+                .build(),
+        )
+        .append_all(items.iter().map(|item| {
+            let jid = item.jid.to_string();
+            Element::builder("item", form::NS)
+                .append(field("jid", Some(jid.as_str())))
+                .append_all(
+                    item.fields
+                        .iter()
+                        .map(|(name, value)| field(name, Some(value.as_str()))),
+                )
+                .build()
+        }))
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::form::FormType;
+
+    #[test]
+    fn answers_in_the_submitted_format() {
+        let items = [
+            Item::new(Jid::new("juliet@capulet.lit").unwrap()).field("nick", "Jules"),
+            Item::new(Jid::new("tybalt@capulet.lit").unwrap()).field("nick", "Ty"),
+        ];
+        let query = |form: Option<DataForm>| Query {
+            from: None,
+            to: None,
+            fields: BTreeMap::new(),
+            form,
+            page: Some(rsm::Request {
+                max: Some(1),
+                ..Default::default()
+            }),
+            id: "search1".to_owned(),
+        };
+
+        let Iq::Result {
+            payload: Some(legacy),
+            ..
+        } = query(None).results(&items).unwrap()
+        else {
+            panic!("not a result");
+        };
+        assert_eq!(legacy.children().filter(|c| c.is("item", NS)).count(), 1);
+        assert!(legacy.has_child("set", rsm::NS));
+
+        let Iq::Result {
+            payload: Some(data),
+            ..
+        } = query(Some(DataForm::new(FormType::Submit)))
+            .results(&items)
+            .unwrap()
+        else {
+            panic!("not a result");
+        };
+        let x = data.get_child("x", form::NS).unwrap();
+        assert_eq!(
+            x.get_child("reported", form::NS)
+                .unwrap()
+                .children()
+                .count(),
+            2
+        );
+        assert_eq!(x.children().filter(|c| c.is("item", form::NS)).count(), 1);
+    }
+}
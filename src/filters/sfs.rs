@@ -0,0 +1,302 @@
+//! Stateless File Sharing (XEP-0447).
+//!
+//! - `wax::sfs::param()` - Extract the first [`FileSharing`] of a message
+//! - `wax::sfs::all()` - Extract every one of them
+//!
+//! A shared file is described by its metadata (XEP-0446) and the sources
+//! it can be fetched from, usually HTTP URLs. Bridges relaying attachments
+//! can forward the sources, check the hashes once downloaded, and rebuild
+//! the element with [`FileSharing::attach`].
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::sfs::FileSharing;
+//! use wax::Filter;
+//!
+//! let mms = wax::sfs::all().map(|files: Vec<FileSharing>| {
+//!     let urls = files.iter().flat_map(|file| file.urls());
+//!     // forward attachments to the phone network...
+//!     wax::sink()
+//! });
+//! ```
+
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::message::Message;
+use xmpp_parsers::minidom::Element;
+
+use crate::filter::{filter_fn_one, Filter};
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+
+/// The `urn:xmpp:sfs:0` namespace.
+pub const NS: &str = "urn:xmpp:sfs:0";
+
+/// The `urn:xmpp:file:metadata:0` namespace of file metadata (XEP-0446).
+pub const NS_METADATA: &str = "urn:xmpp:file:metadata:0";
+
+const NS_HASHES: &str = "urn:xmpp:hashes:2";
+
+const NS_URL_DATA: &str = "http://jabber.org/protocol/url-data";
+
+/// How the sender wants the file presented.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Disposition {
+    /// Shown within the conversation, e.g. an image.
+    Inline,
+    /// Offered for download.
+    Attachment,
+}
+
+impl Disposition {
+    const ALL: [Disposition; 2] = [Disposition::Inline, Disposition::Attachment];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Disposition::Inline => "inline",
+            Disposition::Attachment => "attachment",
+        }
+    }
+}
+
+/// A hash of a file (XEP-0300).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hash {
+    /// The algorithm, e.g. `sha-256`.
+    pub algo: String,
+    /// The base64-encoded digest.
+    pub value: String,
+}
+
+/// Where a file can be fetched from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Source {
+    /// A URL, usually HTTP (XEP-0103).
+    Url(String),
+    /// Any other source, e.g. a Jingle publication, as is.
+    Other(Element),
+}
+
+impl From<Source> for Element {
+    fn from(source: Source) -> Element {
+        match source {
+            Source::Url(url) => Element::builder("url-data", NS_URL_DATA)
+                .attr("target", url)
+                .build(),
+            Source::Other(elem) => elem,
+        }
+    }
+}
+
+/// The metadata of a file (XEP-0446).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct File {
+    /// The media type, e.g. `image/jpeg`.
+    pub media_type: Option<String>,
+    /// The file name.
+    pub name: Option<String>,
+    /// The size in bytes.
+    pub size: Option<u64>,
+    /// A description of the file.
+    pub desc: Option<String>,
+    /// Hashes of the content.
+    pub hashes: Vec<Hash>,
+}
+
+impl From<File> for Element {
+    fn from(file: File) -> Element {
+        let text = |name, text: Option<String>| {
+            text.map(|text| Element::builder(name, NS_METADATA).append(text).build())
+        };
+        Element::builder("file", NS_METADATA)
+            .append_all(text("media-type", file.media_type))
+            .append_all(text("name", file.name))
+            .append_all(text("size", file.size.map(|size| size.to_string())))
+            .append_all(text("desc", file.desc))
+            .append_all(file.hashes.into_iter().map(|hash| {
+                Element::builder("hash", NS_HASHES)
+                    .attr("algo", hash.algo)
+                    .append(hash.value)
+                    .build()
+            }))
+            .build()
+    }
+}
+
+/// A file shared in a message.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileSharing {
+    /// The metadata of the file.
+    pub file: File,
+    /// Where to fetch the file, in order of preference.
+    pub sources: Vec<Source>,
+    /// How to present the file, if the sender said.
+    pub disposition: Option<Disposition>,
+    /// The id sources sent later refer to, if any.
+    pub id: Option<String>,
+}
+
+impl FileSharing {
+    /// A file without sources.
+    pub fn new(file: File) -> Self {
+        FileSharing {
+            file,
+            sources: Vec::new(),
+            disposition: None,
+            id: None,
+        }
+    }
+
+    /// Add a source.
+    pub fn source(mut self, source: Source) -> Self {
+        self.sources.push(source);
+        self
+    }
+
+    /// Set the disposition.
+    pub fn disposition(mut self, disposition: Disposition) -> Self {
+        self.disposition = Some(disposition);
+        self
+    }
+
+    /// The URLs the file can be fetched from.
+    pub fn urls(&self) -> impl Iterator<Item = &str> + '_ {
+        self.sources.iter().filter_map(|source| match source {
+            Source::Url(url) => Some(url.as_str()),
+            Source::Other(_) => None,
+        })
+    }
+
+    /// Attach the file to `msg`.
+    pub fn attach(self, mut msg: Message) -> Message {
+        msg.payloads.push(Element::from(self));
+        msg
+    }
+}
+
+impl From<FileSharing> for Element {
+    fn from(sharing: FileSharing) -> Element {
+        Element::builder("file-sharing", NS)
+            .attr("disposition", sharing.disposition.map(Disposition::as_str))
+            .attr("id", sharing.id)
+            .append(Element::from(sharing.file))
+            .append(
+                Element::builder("sources", NS)
+                    .append_all(sharing.sources.into_iter().map(Element::from))
+                    .build(),
+            )
+            .build()
+    }
+}
+
+/// Extract the first file shared in a message.
+///
+/// Rejects with `item-not-found` if there is none.
+pub fn param() -> impl Filter<Extract = One<FileSharing>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(
+            files_of(stanza)
+                .into_iter()
+                .next()
+                .ok_or_else(reject::item_not_found),
+        )
+    })
+    .advertises(NS)
+}
+
+/// Extract every file shared in a message.
+///
+/// Rejects with `item-not-found` if there is none.
+pub fn all() -> impl Filter<Extract = One<Vec<FileSharing>>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        let files = files_of(stanza);
+        future::ready(if files.is_empty() {
+            Err(reject::item_not_found())
+        } else {
+            Ok(files)
+        })
+    })
+    .advertises(NS)
+}
+
+// File sharings without metadata are skipped; unknown metadata is ignored.
+fn files_of(stanza: &Stanza) -> Vec<FileSharing> {
+    let Stanza::Message(msg) = stanza else {
+        return Vec::new();
+    };
+    msg.payloads
+        .iter()
+        .filter(|payload| payload.is("file-sharing", NS))
+        .filter_map(|sharing| {
+            let file = sharing.get_child("file", NS_METADATA)?;
+            let text = |name| {
+                file.get_child(name, NS_METADATA)
+                    .map(|child| child.text().trim().to_owned())
+                    .filter(|text| !text.is_empty())
+            };
+            let file = File {
+                media_type: text("media-type"),
+                name: text("name"),
+                size: text("size").and_then(|size| size.parse().ok()),
+                desc: text("desc"),
+                hashes: file
+                    .children()
+                    .filter(|hash| hash.is("hash", NS_HASHES))
+                    .filter_map(|hash| {
+                        Some(Hash {
+                            algo: hash.attr("algo")?.to_owned(),
+                            value: hash.text().trim().to_owned(),
+                        })
+                    })
+                    .collect(),
+            };
+            let sources = sharing
+                .get_child("sources", NS)
+                .into_iter()
+                .flat_map(Element::children)
+                .map(|source| match source.attr("target") {
+                    Some(url) if source.is("url-data", NS_URL_DATA) => Source::Url(url.to_owned()),
+                    _ => Source::Other(source.clone()),
+                })
+                .collect();
+            Some(FileSharing {
+                file,
+                sources,
+                disposition: sharing.attr("disposition").and_then(|disposition| {
+                    Disposition::ALL
+                        .into_iter()
+                        .find(|known| known.as_str() == disposition)
+                }),
+                id: sharing.attr("id").map(str::to_owned),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_a_message() {
+        let file = File {
+            media_type: Some("image/jpeg".into()),
+            name: Some("summit.jpg".into()),
+            size: Some(3032449),
+            desc: Some("Photo from the summit.".into()),
+            hashes: vec![Hash {
+                algo: "sha-256".into(),
+                value: "2XarmwTlNxDAMkvymloX3S5+VbylNrJt/l5QyPa+YoU=".into(),
+            }],
+        };
+        let sharing = FileSharing::new(file)
+            .source(Source::Url(
+                "https://download.montague.lit/summit.jpg".into(),
+            ))
+            .disposition(Disposition::Inline);
+
+        let msg = sharing.clone().attach(Message::new(None));
+        assert_eq!(files_of(&Stanza::Message(msg)), [sharing]);
+    }
+}
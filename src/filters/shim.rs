@@ -0,0 +1,89 @@
+//! Stanza Headers and Internet Metadata (XEP-0131).
+//!
+//! - `wax::shim::header(name)` - Extract the value of one header
+//! - `wax::shim::headers()` - Extract every header, in order
+//!
+//! Attach headers to replies with
+//! [`wax::reply::with::headers`](crate::reply::with::headers).
+//!
+//! Header names compare case-insensitively, as in HTTP.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::Filter;
+//!
+//! let route = wax::shim::header("Ticket")
+//!     .map(|ticket: String| {
+//!         // attach the message to the ticket...
+//!         wax::sink()
+//!     })
+//!     .with(wax::reply::with::headers([("Workflow", "triage")]));
+//! ```
+
+use std::convert::Infallible;
+
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::minidom::Element;
+
+use crate::filter::{filter_fn_one, Filter};
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+
+/// The `http://jabber.org/protocol/shim` namespace.
+pub const NS: &str = "http://jabber.org/protocol/shim";
+
+/// Extract the value of the header `name`, the first if it is repeated.
+///
+/// Rejects with `item-not-found` if the stanza does not have it.
+pub fn header(name: &'static str) -> impl Filter<Extract = One<String>, Error = Rejection> + Copy {
+    filter_fn_one(move |stanza: &Stanza| {
+        future::ready(
+            headers_of(stanza)
+                .into_iter()
+                .find(|(header, _)| header.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value)
+                .ok_or_else(reject::item_not_found),
+        )
+    })
+    .advertises(NS)
+}
+
+/// Extract every header of a stanza as `(name, value)` pairs, in order.
+///
+/// Stanzas without headers yield an empty list.
+pub fn headers() -> impl Filter<Extract = One<Vec<(String, String)>>, Error = Infallible> + Copy {
+    filter_fn_one(|stanza: &Stanza| future::ok(headers_of(stanza))).advertises(NS)
+}
+
+/// The `<headers/>` payload listing `headers`.
+pub fn element<N, V>(headers: impl IntoIterator<Item = (N, V)>) -> Element
+where
+    N: Into<String>,
+    V: Into<String>,
+{
+    Element::builder("headers", NS)
+        .append_all(headers.into_iter().map(|(name, value)| {
+            Element::builder("header", NS)
+                .attr("name", name.into())
+                .append(value.into())
+                .build()
+        }))
+        .build()
+}
+
+fn headers_of(stanza: &Stanza) -> Vec<(String, String)> {
+    let payloads = match stanza {
+        Stanza::Message(msg) => &msg.payloads,
+        Stanza::Presence(presence) => &presence.payloads,
+        Stanza::Iq(_) => return Vec::new(),
+    };
+    payloads
+        .iter()
+        .filter(|payload| payload.is("headers", NS))
+        .flat_map(Element::children)
+        .filter(|header| header.is("header", NS))
+        .filter_map(|header| Some((header.attr("name")?.to_owned(), header.text())))
+        .collect()
+}
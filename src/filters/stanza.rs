@@ -5,17 +5,22 @@
 
 use std::convert::Infallible;
 use std::marker::PhantomData;
+use std::sync::Arc;
 
 use futures_util::future;
 use tokio_xmpp::Stanza;
+use xmpp_parsers::iq::Iq;
 use xmpp_parsers::jid::Jid;
-use xmpp_parsers::message::{Lang, Message};
+use xmpp_parsers::message::Message;
+use xmpp_parsers::minidom::Element;
 
-use crate::filter::{filter_fn, filter_fn_one, Filter};
+use crate::filter::{filter_fn, filter_fn_one, Filter, FilterBase, Internal};
+use crate::filtered_stanza;
 use crate::generic::One;
 use crate::reject::Rejection;
 use crate::Reply;
 
+pub mod iq;
 pub mod message;
 pub mod presence;
 pub mod query;
@@ -24,7 +29,7 @@ use query::Query;
 
 /// Match incoming message stanzas without extracting.
 pub fn message() -> impl Filter<Extract = (), Error = Rejection> + Copy {
-    filter_fn(|stanza: &mut Stanza| match stanza {
+    filter_fn(|stanza: &Stanza| match stanza {
         Stanza::Message(_) => future::ok(()),
         _ => future::err(crate::reject::item_not_found()),
     })
@@ -32,9 +37,11 @@ pub fn message() -> impl Filter<Extract = (), Error = Rejection> + Copy {
 
 /// Match incoming IQ stanzas, returning a [`Query`] that supports
 /// type-state narrowing with `.get()` and `.set()`.
+///
+/// Extract typed payloads with [`iq::payload`](crate::iq::payload).
 pub fn iq() -> Query<query::state::IqAny, impl Filter<Extract = (), Error = Rejection> + Copy> {
     Query {
-        filter: filter_fn(|stanza: &mut Stanza| match stanza {
+        filter: filter_fn(|stanza: &Stanza| match stanza {
             Stanza::Iq(_) => future::ok(()),
             _ => future::err(crate::reject::item_not_found()),
         }),
@@ -44,112 +51,128 @@ pub fn iq() -> Query<query::state::IqAny, impl Filter<Extract = (), Error = Reje
 
 /// Match incoming presence stanzas without extracting.
 pub fn presence() -> impl Filter<Extract = (), Error = Rejection> + Copy {
-    filter_fn(|stanza: &mut Stanza| match stanza {
+    filter_fn(|stanza: &Stanza| match stanza {
         Stanza::Presence(_) => future::ok(()),
         _ => future::err(crate::reject::item_not_found()),
     })
 }
 
+/// Extract the whole incoming stanza.
+///
+/// The stanza is shared with the server rather than cloned, so extracting
+/// it is cheap however large it is. Prefer narrower filters when they
+/// suffice, as they document what a route depends on.
+///
+/// # Example
+///
+/// ```ignore
+/// use std::sync::Arc;
+/// use wax::{Filter, Stanza};
+///
+/// let route = wax::stanza().map(|stanza: Arc<Stanza>| {
+///     // archive the stanza as is...
+///     wax::sink()
+/// });
+/// ```
+pub fn stanza() -> impl Filter<Extract = One<Arc<Stanza>>, Error = Infallible> + Copy {
+    Shared
+}
+
+#[derive(Copy, Clone)]
+#[allow(missing_debug_implementations)]
+struct Shared;
+
+impl FilterBase for Shared {
+    type Extract = One<Arc<Stanza>>;
+    type Error = Infallible;
+    type Future = future::Ready<Result<Self::Extract, Self::Error>>;
+
+    fn filter(&self, _: Internal) -> Self::Future {
+        future::ok((filtered_stanza::shared(),))
+    }
+}
+
 /// Extract the `from` JID attribute from the incoming stanza.
 pub fn from() -> impl Filter<Extract = One<Option<Jid>>, Error = Infallible> + Copy {
-    filter_fn_one(|stanza: &mut Stanza| {
-        let from = match stanza {
-            Stanza::Message(msg) => msg.from.clone(),
-            Stanza::Iq(iq) => match iq {
-                xmpp_parsers::iq::Iq::Get { from, .. }
-                | xmpp_parsers::iq::Iq::Set { from, .. }
-                | xmpp_parsers::iq::Iq::Result { from, .. }
-                | xmpp_parsers::iq::Iq::Error { from, .. } => from.clone(),
-            },
-            Stanza::Presence(pres) => pres.from.clone(),
-        };
-        future::ok::<_, Infallible>(from)
-    })
+    filter_fn_one(|stanza: &Stanza| future::ok::<_, Infallible>(from_of(stanza).cloned()))
 }
 
 /// Extract the `to` JID attribute from the incoming stanza.
 pub fn to() -> impl Filter<Extract = One<Option<Jid>>, Error = Infallible> + Copy {
-    filter_fn_one(|stanza: &mut Stanza| {
-        let to = match stanza {
-            Stanza::Message(msg) => msg.to.clone(),
-            Stanza::Iq(iq) => match iq {
-                xmpp_parsers::iq::Iq::Get { to, .. }
-                | xmpp_parsers::iq::Iq::Set { to, .. }
-                | xmpp_parsers::iq::Iq::Result { to, .. }
-                | xmpp_parsers::iq::Iq::Error { to, .. } => to.clone(),
-            },
-            Stanza::Presence(pres) => pres.to.clone(),
-        };
-        future::ok::<_, Infallible>(to)
-    })
+    filter_fn_one(|stanza: &Stanza| future::ok::<_, Infallible>(to_of(stanza).cloned()))
 }
 
 /// Extract the `from` JID attribute, rejecting if absent.
 pub fn require_from() -> impl Filter<Extract = One<Jid>, Error = Rejection> + Copy {
-    filter_fn_one(|stanza: &mut Stanza| {
-        let from = match stanza {
-            Stanza::Message(msg) => msg.from.clone(),
-            Stanza::Iq(iq) => match iq {
-                xmpp_parsers::iq::Iq::Get { from, .. }
-                | xmpp_parsers::iq::Iq::Set { from, .. }
-                | xmpp_parsers::iq::Iq::Result { from, .. }
-                | xmpp_parsers::iq::Iq::Error { from, .. } => from.clone(),
-            },
-            Stanza::Presence(pres) => pres.from.clone(),
-        };
-        match from {
-            Some(jid) => future::ok(jid),
-            None => future::err(crate::reject::item_not_found()),
-        }
+    filter_fn_one(|stanza: &Stanza| match from_of(stanza) {
+        Some(jid) => future::ok(jid.clone()),
+        None => future::err(crate::reject::item_not_found()),
     })
 }
 
 /// Extract the `to` JID attribute, rejecting if absent.
 pub fn require_to() -> impl Filter<Extract = One<Jid>, Error = Rejection> + Copy {
-    filter_fn_one(|stanza: &mut Stanza| {
-        let to = match stanza {
-            Stanza::Message(msg) => msg.to.clone(),
-            Stanza::Iq(iq) => match iq {
-                xmpp_parsers::iq::Iq::Get { to, .. }
-                | xmpp_parsers::iq::Iq::Set { to, .. }
-                | xmpp_parsers::iq::Iq::Result { to, .. }
-                | xmpp_parsers::iq::Iq::Error { to, .. } => to.clone(),
-            },
-            Stanza::Presence(pres) => pres.to.clone(),
-        };
-        match to {
-            Some(jid) => future::ok(jid),
-            None => future::err(crate::reject::item_not_found()),
-        }
+    filter_fn_one(|stanza: &Stanza| match to_of(stanza) {
+        Some(jid) => future::ok(jid.clone()),
+        None => future::err(crate::reject::item_not_found()),
     })
 }
 
+/// The `from` attribute of `stanza`, borrowed.
+pub(crate) fn from_of(stanza: &Stanza) -> Option<&Jid> {
+    match stanza {
+        Stanza::Message(msg) => msg.from.as_ref(),
+        Stanza::Iq(
+            Iq::Get { from, .. }
+            | Iq::Set { from, .. }
+            | Iq::Result { from, .. }
+            | Iq::Error { from, .. },
+        ) => from.as_ref(),
+        Stanza::Presence(pres) => pres.from.as_ref(),
+    }
+}
+
+/// The `to` attribute of `stanza`, borrowed.
+pub(crate) fn to_of(stanza: &Stanza) -> Option<&Jid> {
+    match stanza {
+        Stanza::Message(msg) => msg.to.as_ref(),
+        Stanza::Iq(
+            Iq::Get { to, .. } | Iq::Set { to, .. } | Iq::Result { to, .. } | Iq::Error { to, .. },
+        ) => to.as_ref(),
+        Stanza::Presence(pres) => pres.to.as_ref(),
+    }
+}
+
+/// The payloads of `stanza`: the child of an IQ, or the extension
+/// elements of a message or presence.
+pub(crate) fn payloads_of(stanza: &Stanza) -> &[Element] {
+    match stanza {
+        Stanza::Message(msg) => &msg.payloads,
+        Stanza::Iq(Iq::Get { payload, .. } | Iq::Set { payload, .. }) => {
+            std::slice::from_ref(payload)
+        }
+        Stanza::Iq(Iq::Result { payload, .. } | Iq::Error { payload, .. }) => payload.as_slice(),
+        Stanza::Presence(pres) => &pres.payloads,
+    }
+}
+
 /// Create a message reply with the given body.
 ///
 /// The reply's `to` is the incoming stanza's `from`, and the reply's `from`
-/// is the incoming stanza's `to`.
+/// is the incoming stanza's `to`. See [`wax::reply::message`] to build
+/// richer replies.
+///
+/// [`wax::reply::message`]: crate::reply::message
 pub fn reply(
     body: impl Into<String>,
 ) -> impl Filter<Extract = One<Message>, Error = Infallible> + Clone {
     let body = body.into();
-    from()
-        .and(to())
-        .map(move |sender: Option<Jid>, recipient: Option<Jid>| {
-            let mut msg = Message::new(sender);
-            msg.from = recipient;
-            msg.with_body(Lang::default(), body.clone())
-        })
+    crate::any().map(move || Message::from(crate::reply::message().body(body.clone())))
 }
 
 /// Extract the message body and echo it back as a reply.
 pub fn echo() -> impl Filter<Extract = One<Message>, Error = Rejection> + Copy {
-    message::body::param().and(from()).and(to()).map(
-        |body: String, sender: Option<Jid>, recipient: Option<Jid>| {
-            let mut msg = Message::new(sender);
-            msg.from = recipient;
-            msg.with_body(Lang::default(), body)
-        },
-    )
+    message::body::param().map(|body: String| Message::from(crate::reply::message().body(body)))
 }
 
 pub fn sink() -> impl Reply {
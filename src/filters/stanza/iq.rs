@@ -0,0 +1,43 @@
+//! IQ stanza extraction.
+
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::minidom::Element;
+
+use crate::filter::{filter_fn_one, Filter};
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+
+/// Extract the payload of an IQ get or set as `T`, e.g. a
+/// [`Ping`](xmpp_parsers::ping::Ping) or a
+/// [`DiscoInfoQuery`](xmpp_parsers::disco::DiscoInfoQuery).
+///
+/// Rejects with `item-not-found` for other stanzas, and with
+/// `service-unavailable` for IQs whose payload is not a `T`, as XMPP
+/// requires for requests nobody handles.
+///
+/// # Example
+///
+/// ```ignore
+/// use wax::Filter;
+/// use xmpp_parsers::ping::Ping;
+///
+/// let route = wax::iq::payload::<Ping>().map(|_: Ping| wax::sink());
+/// ```
+pub fn payload<T>() -> impl Filter<Extract = One<T>, Error = Rejection> + Copy
+where
+    T: TryFrom<Element> + Send + 'static,
+{
+    filter_fn_one(|stanza: &Stanza| future::ready(payload_of(stanza)))
+}
+
+/// The payload of `stanza` as `T`, if it is an IQ get or set.
+pub(crate) fn payload_of<T: TryFrom<Element>>(stanza: &Stanza) -> Result<T, Rejection> {
+    match stanza {
+        Stanza::Iq(Iq::Get { payload, .. } | Iq::Set { payload, .. }) => {
+            T::try_from(payload.clone()).map_err(|_| reject::service_unavailable())
+        }
+        _ => Err(reject::item_not_found()),
+    }
+}
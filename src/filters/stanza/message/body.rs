@@ -1,10 +1,19 @@
 //! Message body extraction.
+//!
+//! Language preferences known when building routes are passed to
+//! [`param_with_lang`] or [`param_with_langs`]. Ones only known while
+//! handling a stanza, e.g. looked up per user, are applied to the
+//! [`Bodies`] extracted by [`bodies`].
+
+use std::fmt;
+use std::sync::Arc;
 
 use futures_util::future;
 use tokio_xmpp::Stanza;
 use xmpp_parsers::message::Lang;
 
-use crate::filter::{filter_fn_one, Filter};
+use crate::filter::{filter_fn, filter_fn_one, Filter, FilterBase, Internal};
+use crate::filtered_stanza;
 use crate::generic::One;
 use crate::reject::Rejection;
 
@@ -21,7 +30,7 @@ use crate::reject::Rejection;
 ///
 /// let route = wax::message::body::param()
 ///     .map(|body: String| {
-///         wax::reply(body)
+///         wax::reply::message().body(body)
 ///     });
 /// ```
 pub fn param() -> impl Filter<Extract = One<String>, Error = Rejection> + Copy {
@@ -53,7 +62,7 @@ pub fn param() -> impl Filter<Extract = One<String>, Error = Rejection> + Copy {
 pub fn param_with_lang(
     preferred_langs: &'static [&'static str],
 ) -> impl Filter<Extract = One<(Lang, String)>, Error = Rejection> + Copy {
-    filter_fn_one(move |stanza: &mut Stanza| {
+    filter_fn_one(move |stanza: &Stanza| {
         let result = match stanza {
             Stanza::Message(msg) => msg
                 .get_best_body_cloned(preferred_langs.to_vec())
@@ -63,3 +72,99 @@ pub fn param_with_lang(
         future::ready(result)
     })
 }
+
+/// Extract body with language tag as `(Lang, String)`, preferring
+/// `preferred_langs`.
+///
+/// The same as [`param_with_lang`], for preferences only known at runtime,
+/// e.g. read from configuration.
+pub fn param_with_langs<L: Into<Lang>>(
+    preferred_langs: impl IntoIterator<Item = L>,
+) -> impl Filter<Extract = One<(Lang, String)>, Error = Rejection> + Clone {
+    let preferred_langs: Arc<[Lang]> = preferred_langs.into_iter().map(Into::into).collect();
+    filter_fn(move |stanza: &Stanza| {
+        let result = match stanza {
+            Stanza::Message(msg) => msg
+                .get_best_body_cloned(preferred_langs.iter().map(String::as_str).collect())
+                .map(|body| (body,))
+                .ok_or_else(crate::reject::item_not_found),
+            _ => Err(crate::reject::item_not_found()),
+        };
+        future::ready(result)
+    })
+}
+
+/// Extract the bodies of a message, to pick one later with
+/// [`Bodies::best`].
+///
+/// Rejects with `item-not-found` if the stanza is not a message or has no
+/// body.
+///
+/// # Example
+///
+/// ```ignore
+/// use wax::message::body::Bodies;
+/// use wax::Filter;
+///
+/// let route = wax::require_from()
+///     .and_then(|from: Jid| async move { Ok::<_, wax::Rejection>(users.langs(&from).await?) })
+///     .and(wax::message::body::bodies())
+///     .map(|langs: Vec<String>, bodies: Bodies| {
+///         let (lang, body) = bodies.best(&langs).expect("a message with bodies");
+///         wax::sink()
+///     });
+/// ```
+pub fn bodies() -> impl Filter<Extract = One<Bodies>, Error = Rejection> + Copy {
+    BodiesParam
+}
+
+#[derive(Copy, Clone)]
+#[allow(missing_debug_implementations)]
+struct BodiesParam;
+
+impl FilterBase for BodiesParam {
+    type Extract = One<Bodies>;
+    type Error = Rejection;
+    type Future = future::Ready<Result<Self::Extract, Self::Error>>;
+
+    fn filter(&self, _: Internal) -> Self::Future {
+        let has_body = filtered_stanza::with(|stanza| match stanza {
+            Stanza::Message(msg) => !msg.bodies.is_empty(),
+            _ => false,
+        });
+        if has_body {
+            future::ok((Bodies {
+                stanza: filtered_stanza::shared(),
+            },))
+        } else {
+            future::err(crate::reject::item_not_found())
+        }
+    }
+}
+
+/// The bodies of a message, in every language it has one in.
+///
+/// The message is shared rather than cloned.
+#[derive(Clone)]
+pub struct Bodies {
+    stanza: Arc<Stanza>,
+}
+
+impl Bodies {
+    /// The body in the first of `preferred_langs` the message has one in,
+    /// else the body without a language, else any.
+    pub fn best<L: AsRef<str>>(&self, preferred_langs: &[L]) -> Option<(Lang, String)> {
+        match *self.stanza {
+            Stanza::Message(ref msg) => {
+                msg.get_best_body_cloned(preferred_langs.iter().map(AsRef::as_ref).collect())
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for Bodies {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Bodies").finish_non_exhaustive()
+    }
+}
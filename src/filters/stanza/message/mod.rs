@@ -1,12 +1,16 @@
 //! Message stanza extraction.
+//!
+//! - `wax::message::param()` - Extract the whole [`Message`]
+//! - `wax::message::chat()`, `groupchat()`, `headline()`, `normal()` and
+//!   `error()` - Match messages of one type without extracting
 
 pub mod body;
 
 use futures_util::future;
 use tokio_xmpp::Stanza;
-use xmpp_parsers::message::Message;
+use xmpp_parsers::message::{Message, MessageType};
 
-use crate::filter::{filter_fn_one, Filter};
+use crate::filter::{filter_fn, filter_fn_one, Filter};
 use crate::generic::One;
 use crate::reject::Rejection;
 
@@ -24,8 +28,54 @@ use crate::reject::Rejection;
 ///     });
 /// ```
 pub fn param() -> impl Filter<Extract = One<Message>, Error = Rejection> + Copy {
-    filter_fn_one(|stanza: &mut Stanza| match stanza {
+    filter_fn_one(|stanza: &Stanza| match stanza {
         Stanza::Message(ref msg) => future::ok(msg.clone()),
         _ => future::err(crate::reject::item_not_found()),
     })
 }
+
+/// Match `chat` messages, sent in one-to-one conversations.
+///
+/// # Example
+///
+/// ```ignore
+/// use wax::Filter;
+///
+/// let route = wax::message::chat()
+///     .and(wax::message::body::param())
+///     .map(|body: String| wax::reply::message().body(body));
+/// ```
+pub fn chat() -> impl Filter<Extract = (), Error = Rejection> + Copy {
+    of_type(|type_| *type_ == MessageType::Chat)
+}
+
+/// Match `groupchat` messages, sent in multi-user chat rooms.
+pub fn groupchat() -> impl Filter<Extract = (), Error = Rejection> + Copy {
+    of_type(|type_| *type_ == MessageType::Groupchat)
+}
+
+/// Match `headline` messages, such as alerts, which expect no reply.
+pub fn headline() -> impl Filter<Extract = (), Error = Rejection> + Copy {
+    of_type(|type_| *type_ == MessageType::Headline)
+}
+
+/// Match `normal` messages, including those without a type.
+pub fn normal() -> impl Filter<Extract = (), Error = Rejection> + Copy {
+    of_type(|type_| *type_ == MessageType::Normal)
+}
+
+/// Match `error` messages, bounced back for a message that could not be
+/// delivered.
+pub fn error() -> impl Filter<Extract = (), Error = Rejection> + Copy {
+    of_type(|type_| *type_ == MessageType::Error)
+}
+
+// Rejects with `item-not-found` for other messages and stanzas.
+fn of_type(
+    matches: fn(&MessageType) -> bool,
+) -> impl Filter<Extract = (), Error = Rejection> + Copy {
+    filter_fn(move |stanza: &Stanza| match stanza {
+        Stanza::Message(msg) if matches(&msg.type_) => future::ok(()),
+        _ => future::err(crate::reject::item_not_found()),
+    })
+}
@@ -1,8 +1,14 @@
 //! Presence stanza extraction.
+//!
+//! - `wax::presence::param()` - Extract the whole [`Presence`]
+//! - `wax::presence::show()` - Extract the availability, if any
+//! - `wax::presence::priority()` - Extract the priority
+//! - `wax::presence::status()` - Extract the best matching status text
 
 use futures_util::future;
 use tokio_xmpp::Stanza;
-use xmpp_parsers::presence::Presence;
+use xmpp_parsers::message::Lang;
+use xmpp_parsers::presence::{Presence, Show};
 
 use crate::filter::{filter_fn_one, Filter};
 use crate::generic::One;
@@ -22,8 +28,74 @@ use crate::reject::Rejection;
 ///     });
 /// ```
 pub fn param() -> impl Filter<Extract = One<Presence>, Error = Rejection> + Copy {
-    filter_fn_one(|stanza: &mut Stanza| match stanza {
+    filter_fn_one(|stanza: &Stanza| match stanza {
         Stanza::Presence(pres) => future::ok(pres.clone()),
         _ => future::err(crate::reject::item_not_found()),
     })
 }
+
+/// Extract the availability of a presence, `None` meaning plainly
+/// available.
+///
+/// Rejects with `item-not-found` for other stanzas.
+///
+/// # Example
+///
+/// ```ignore
+/// use wax::Filter;
+/// use xmpp_parsers::presence::Show;
+///
+/// let route = wax::presence::show()
+///     .map(|show: Option<Show>| {
+///         // mirror the status on the legacy network...
+///         wax::sink()
+///     });
+/// ```
+pub fn show() -> impl Filter<Extract = One<Option<Show>>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| match stanza {
+        Stanza::Presence(pres) => future::ok(pres.show.clone()),
+        _ => future::err(crate::reject::item_not_found()),
+    })
+}
+
+/// Extract the priority of a presence, `0` if it has none.
+///
+/// Rejects with `item-not-found` for other stanzas.
+pub fn priority() -> impl Filter<Extract = One<i8>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| match stanza {
+        Stanza::Presence(pres) => future::ok(pres.priority),
+        _ => future::err(crate::reject::item_not_found()),
+    })
+}
+
+/// Extract the best matching status text of a presence.
+///
+/// Uses the default language preference. Rejects with `item-not-found` if
+/// the stanza is not a presence or has no status.
+pub fn status() -> impl Filter<Extract = One<String>, Error = Rejection> + Copy {
+    status_with_lang(&[]).map(|(_lang, status)| status)
+}
+
+/// Extract the status with its language tag as `(Lang, String)`.
+///
+/// The first of `preferred_langs` the presence has a status in is chosen,
+/// else the status without a language, else any. Rejects with
+/// `item-not-found` if the stanza is not a presence or has no status.
+pub fn status_with_lang(
+    preferred_langs: &'static [&'static str],
+) -> impl Filter<Extract = One<(Lang, String)>, Error = Rejection> + Copy {
+    filter_fn_one(move |stanza: &Stanza| {
+        let result = match stanza {
+            Stanza::Presence(pres) => preferred_langs
+                .iter()
+                .copied()
+                .chain([""])
+                .find_map(|lang| pres.statuses.get_key_value(lang))
+                .or_else(|| pres.statuses.iter().next())
+                .map(|(lang, status)| (lang.clone(), status.clone()))
+                .ok_or_else(crate::reject::item_not_found),
+            _ => Err(crate::reject::item_not_found()),
+        };
+        future::ready(result)
+    })
+}
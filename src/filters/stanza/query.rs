@@ -4,7 +4,9 @@ use std::marker::PhantomData;
 use futures_util::future;
 use tokio_xmpp::Stanza;
 use xmpp_parsers::jid::Jid;
+use xmpp_parsers::minidom::Element;
 
+use crate::disco::Features;
 use crate::filter::{filter_fn, Filter, FilterBase, Internal};
 use crate::generic::{self, Combine, CombinedTuples, HListProduct, One, Tuple};
 use crate::reject::{CombineRejection, Rejection};
@@ -22,6 +24,12 @@ pub mod state {
     impl Iq for IqAny {}
     impl Iq for Get {}
     impl Iq for Set {}
+
+    /// A state narrowed to requests, which carry exactly one payload.
+    pub trait Request: Iq {}
+
+    impl Request for Get {}
+    impl Request for Set {}
 }
 
 #[derive(Clone, Copy)]
@@ -38,6 +46,10 @@ impl<S, F: FilterBase> FilterBase for Query<S, F> {
     fn filter(&self, internal: Internal) -> Self::Future {
         self.filter.filter(internal)
     }
+
+    fn advertise(&self, features: &mut Features) {
+        self.filter.advertise(features);
+    }
 }
 
 // === IQ type narrowing (only before narrowing to get/set) ===
@@ -48,24 +60,64 @@ where
 {
     pub fn get(self) -> Query<state::Get, impl Filter<Extract = (), Error = Rejection> + Copy> {
         Query {
-            filter: self
-                .filter
-                .and(filter_fn(|stanza: &mut Stanza| match stanza {
-                    Stanza::Iq(xmpp_parsers::iq::Iq::Get { .. }) => future::ok(()),
-                    _ => future::err(crate::reject::item_not_found()),
-                })),
+            filter: self.filter.and(filter_fn(|stanza: &Stanza| match stanza {
+                Stanza::Iq(xmpp_parsers::iq::Iq::Get { .. }) => future::ok(()),
+                _ => future::err(crate::reject::item_not_found()),
+            })),
             _state: PhantomData,
         }
     }
 
     pub fn set(self) -> Query<state::Set, impl Filter<Extract = (), Error = Rejection> + Copy> {
         Query {
-            filter: self
-                .filter
-                .and(filter_fn(|stanza: &mut Stanza| match stanza {
-                    Stanza::Iq(xmpp_parsers::iq::Iq::Set { .. }) => future::ok(()),
-                    _ => future::err(crate::reject::item_not_found()),
-                })),
+            filter: self.filter.and(filter_fn(|stanza: &Stanza| match stanza {
+                Stanza::Iq(xmpp_parsers::iq::Iq::Set { .. }) => future::ok(()),
+                _ => future::err(crate::reject::item_not_found()),
+            })),
+            _state: PhantomData,
+        }
+    }
+}
+
+// === Payload extraction (only after narrowing to get/set) ===
+
+impl<S: state::Request, F> Query<S, F> {
+    /// Extract the payload of the request as `T`, after what is already
+    /// extracted.
+    ///
+    /// Rejects with `service-unavailable` if the payload is not a `T`.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use wax::Filter;
+    ///
+    /// let route = wax::iq()
+    ///     .set()
+    ///     .payload::<Register>()
+    ///     .id()
+    ///     .require_from()
+    ///     .map(|register: Register, id: String, from: Jid| wax::sink());
+    /// ```
+    pub fn payload<T>(
+        self,
+    ) -> Query<
+        S,
+        impl Filter<
+                Extract = CombinedTuples<F::Extract, One<T>>,
+                Error = <Rejection as CombineRejection<F::Error>>::One,
+            > + Copy,
+    >
+    where
+        T: TryFrom<Element> + Send + 'static,
+        F: Filter + Copy,
+        F::Extract: Send,
+        <F::Extract as Tuple>::HList: Combine<HListProduct!(T)> + Send,
+        CombinedTuples<F::Extract, One<T>>: Send,
+        Rejection: CombineRejection<F::Error>,
+    {
+        Query {
+            filter: self.filter.and(super::iq::payload::<T>()),
             _state: PhantomData,
         }
     }
@@ -161,4 +213,27 @@ impl<S, F> Query<S, F> {
             _state: PhantomData,
         }
     }
+
+    /// Extract the id of the IQ, after what is already extracted.
+    pub fn id(
+        self,
+    ) -> Query<
+        S,
+        impl Filter<
+                Extract = CombinedTuples<F::Extract, One<String>>,
+                Error = <Rejection as CombineRejection<F::Error>>::One,
+            > + Copy,
+    >
+    where
+        F: Filter + Copy,
+        F::Extract: Send,
+        <F::Extract as Tuple>::HList: Combine<HListProduct!(String)> + Send,
+        CombinedTuples<F::Extract, One<String>>: Send,
+        Rejection: CombineRejection<F::Error>,
+    {
+        Query {
+            filter: self.filter.and(crate::filters::id::param()),
+            _state: PhantomData,
+        }
+    }
 }
@@ -0,0 +1,135 @@
+//! Unique and Stable Stanza IDs (XEP-0359).
+//!
+//! - `wax::stanza_id::param()` - Extract every [`StanzaId`] of a message
+//! - `wax::stanza_id::by(jid)` - Extract the id assigned by one entity
+//! - `wax::stanza_id::origin()` - Extract the origin-id set by the sender
+//!
+//! Any entity on the path of a message can claim to have assigned a
+//! stanza-id, so only trust those assigned by an entity you know strips
+//! forged ones, usually your server, through [`by`].
+//!
+//! Stamp replies with `wax::reply::with::stanza_id(by)` and
+//! `wax::reply::with::origin_id()`.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::Filter;
+//!
+//! let archived = wax::stanza_id::by(server_jid)
+//!     .map(|id: String| {
+//!         // deduplicate on id...
+//!         wax::sink()
+//!     });
+//! ```
+
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::jid::Jid;
+use xmpp_parsers::message::Message;
+use xmpp_parsers::minidom::Element;
+
+use crate::filter::{filter_fn_one, Filter};
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+
+/// The `urn:xmpp:sid:0` namespace.
+pub const NS: &str = "urn:xmpp:sid:0";
+
+/// An id assigned to a message by an entity on its path.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StanzaId {
+    /// The id.
+    pub id: String,
+    /// The entity that assigned it.
+    pub by: Jid,
+}
+
+impl StanzaId {
+    /// The id `id` assigned by `by`.
+    pub fn new(id: impl Into<String>, by: Jid) -> Self {
+        StanzaId { id: id.into(), by }
+    }
+}
+
+impl From<StanzaId> for Element {
+    fn from(stanza_id: StanzaId) -> Element {
+        Element::builder("stanza-id", NS)
+            .attr("id", stanza_id.id)
+            .attr("by", stanza_id.by.to_string())
+            .build()
+    }
+}
+
+/// Extract the stanza-ids of a message.
+///
+/// Rejects with `item-not-found` if it has none.
+pub fn param() -> impl Filter<Extract = One<Vec<StanzaId>>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        let ids = match stanza {
+            Stanza::Message(msg) => stanza_ids(msg),
+            _ => Vec::new(),
+        };
+        future::ready(if ids.is_empty() {
+            Err(reject::item_not_found())
+        } else {
+            Ok(ids)
+        })
+    })
+    .advertises(NS)
+}
+
+/// Extract the stanza-id assigned to a message by `by`.
+///
+/// Rejects with `item-not-found` if there is none.
+pub fn by(by: Jid) -> impl Filter<Extract = One<String>, Error = Rejection> + Clone {
+    filter_fn_one(|stanza: &Stanza| {
+        future::ok::<_, Rejection>(match stanza {
+            Stanza::Message(msg) => stanza_ids(msg),
+            _ => Vec::new(),
+        })
+    })
+    .and_then(move |ids: Vec<StanzaId>| {
+        let id = ids
+            .into_iter()
+            .find(|stanza_id| stanza_id.by == by)
+            .map(|stanza_id| stanza_id.id);
+        future::ready(id.ok_or_else(reject::item_not_found))
+    })
+    .advertises(NS)
+}
+
+/// Extract the origin-id the sender gave a message.
+///
+/// Rejects with `item-not-found` if there is none.
+pub fn origin() -> impl Filter<Extract = One<String>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        let id = match stanza {
+            Stanza::Message(msg) => origin_id(msg).map(str::to_owned),
+            _ => None,
+        };
+        future::ready(id.ok_or_else(reject::item_not_found))
+    })
+    .advertises(NS)
+}
+
+fn stanza_ids(msg: &Message) -> Vec<StanzaId> {
+    msg.payloads
+        .iter()
+        .filter(|payload| payload.is("stanza-id", NS))
+        .filter_map(|payload| {
+            Some(StanzaId {
+                id: payload.attr("id")?.to_owned(),
+                by: payload.attr("by")?.parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+/// The origin-id of `msg`, if any.
+pub(crate) fn origin_id(msg: &Message) -> Option<&str> {
+    msg.payloads
+        .iter()
+        .find(|payload| payload.is("origin-id", NS))
+        .and_then(|payload| payload.attr("id"))
+}
@@ -0,0 +1,122 @@
+//! Telephone numbers.
+//!
+//! - `wax::tel::param()` - Extract the [`Tel`] addressed by the recipient localpart
+//!
+//! Telephony gateways address each number as `+15551234567@gateway`, in
+//! E.164 format: a `+`, then up to 15 digits, the first of which is not
+//! zero.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::tel::Tel;
+//! use wax::Filter;
+//!
+//! let sms = wax::tel::param()
+//!     .and(wax::message::body::param())
+//!     .map(|to: Tel, body: String| {
+//!         // send `body` as an SMS to `to`...
+//!         wax::sink()
+//!     });
+//! ```
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::filter::Filter;
+use crate::filters::node;
+use crate::generic::One;
+use crate::reject::Rejection;
+
+/// A telephone number in E.164 format, e.g. `+15551234567`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Tel(String);
+
+impl Tel {
+    /// The number, including the leading `+`.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The digits of the number, without the leading `+`.
+    pub fn digits(&self) -> &str {
+        &self.0[1..]
+    }
+}
+
+impl FromStr for Tel {
+    type Err = ParseTelError;
+
+    fn from_str(s: &str) -> Result<Self, ParseTelError> {
+        let digits = s.strip_prefix('+').ok_or(ParseTelError { _p: () })?;
+        let valid = (1..=15).contains(&digits.len())
+            && digits.bytes().all(|digit| digit.is_ascii_digit())
+            && !digits.starts_with('0');
+        if valid {
+            Ok(Tel(s.to_owned()))
+        } else {
+            Err(ParseTelError { _p: () })
+        }
+    }
+}
+
+impl TryFrom<String> for Tel {
+    type Error = ParseTelError;
+
+    fn try_from(s: String) -> Result<Self, ParseTelError> {
+        s.parse()
+    }
+}
+
+impl AsRef<str> for Tel {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Tel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Why a string is not an E.164 telephone number.
+#[derive(Debug)]
+pub struct ParseTelError {
+    _p: (),
+}
+
+impl fmt::Display for ParseTelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("not an E.164 telephone number")
+    }
+}
+
+impl StdError for ParseTelError {}
+
+/// Extract the localpart of the `to` JID as a [`Tel`].
+///
+/// Rejects with `item-not-found` if the recipient has no localpart, and
+/// with `jid-malformed` if it is not an E.164 number.
+pub fn param() -> impl Filter<Extract = One<Tel>, Error = Rejection> + Copy {
+    node::param::<Tel>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_e164_numbers() {
+        assert_eq!(
+            "+15551234567".parse::<Tel>().unwrap().digits(),
+            "15551234567"
+        );
+        assert!("+442071838750".parse::<Tel>().is_ok());
+        assert!("15551234567".parse::<Tel>().is_err());
+        assert!("+05551234567".parse::<Tel>().is_err());
+        assert!("+1555123456789012".parse::<Tel>().is_err());
+        assert!("+1 555 123 4567".parse::<Tel>().is_err());
+    }
+}
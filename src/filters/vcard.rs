@@ -0,0 +1,555 @@
+//! vCards (XEP-0054 and XEP-0292).
+//!
+//! - `wax::vcard::request()` - Extract a [`VcardRequest`]
+//! - `wax::vcard::serve(source)` - Answer requests from a [`VcardSource`]
+//! - `wax::vcard::update()` - Extract a [`VcardUpdate`] setting a vCard
+//!
+//! Both `vcard-temp` and vCard4 requests are understood, and answered in the
+//! format they were asked in. A gateway can answer for its puppet JIDs as
+//! well as for itself: the source gets the JID the request was sent to.
+//! A [`Namespace`] of the shared [store](crate::store) is a source, filled
+//! with [`save`].
+//!
+//! # Example
+//!
+//! ```ignore
+//! use futures_util::future::BoxFuture;
+//! use wax::vcard::{self, Vcard, VcardSource};
+//! use wax::Rejection;
+//!
+//! struct Contacts(Db);
+//!
+//! impl VcardSource for Contacts {
+//!     fn vcard<'a>(&'a self, jid: &'a Jid) -> BoxFuture<'a, Result<Option<Vcard>, Rejection>> {
+//!         Box::pin(async move {
+//!             let contact = self.0.contact(jid).await?;
+//!             Ok(contact.map(|contact| Vcard::new().full_name(contact.name)))
+//!         })
+//!     }
+//! }
+//!
+//! let route = vcard::serve(Contacts(db));
+//! ```
+
+use std::sync::Arc;
+
+use base64::Engine;
+use futures_util::future::{self, BoxFuture};
+use tokio_xmpp::Stanza;
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::jid::Jid;
+use xmpp_parsers::minidom::Element;
+
+use crate::filter::{filter_fn_one, Filter};
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+use crate::store::Namespace;
+
+/// The `vcard-temp` namespace.
+pub const NS: &str = "vcard-temp";
+
+/// The `urn:ietf:params:xml:ns:vcard-4.0` namespace.
+pub const NS_VCARD4: &str = "urn:ietf:params:xml:ns:vcard-4.0";
+
+/// The format of a vCard.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Format {
+    /// `vcard-temp` (XEP-0054).
+    Temp,
+    /// vCard4 (XEP-0292).
+    Vcard4,
+}
+
+/// A vCard.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Vcard {
+    /// The formatted name.
+    pub full_name: Option<String>,
+    /// The nickname.
+    pub nickname: Option<String>,
+    /// The given name.
+    pub given: Option<String>,
+    /// The family name.
+    pub family: Option<String>,
+    /// Email addresses.
+    pub emails: Vec<String>,
+    /// A web page.
+    pub url: Option<String>,
+    /// A free-form description.
+    pub note: Option<String>,
+    /// A picture.
+    pub photo: Option<Photo>,
+}
+
+/// The picture of a vCard.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Photo {
+    /// An inline image.
+    Data {
+        /// The MIME type of the image.
+        content_type: String,
+        /// The image.
+        bytes: Vec<u8>,
+    },
+    /// An image to download.
+    Url(String),
+}
+
+impl Vcard {
+    /// An empty vCard.
+    pub fn new() -> Self {
+        Vcard::default()
+    }
+
+    /// Set the formatted name.
+    pub fn full_name(mut self, full_name: impl Into<String>) -> Self {
+        self.full_name = Some(full_name.into());
+        self
+    }
+
+    /// Set the nickname.
+    pub fn nickname(mut self, nickname: impl Into<String>) -> Self {
+        self.nickname = Some(nickname.into());
+        self
+    }
+
+    /// Add an email address.
+    pub fn email(mut self, email: impl Into<String>) -> Self {
+        self.emails.push(email.into());
+        self
+    }
+
+    /// Set the picture.
+    pub fn photo(mut self, photo: Photo) -> Self {
+        self.photo = Some(photo);
+        self
+    }
+
+    /// Render the vCard in `format`.
+    pub fn to_element(&self, format: Format) -> Element {
+        match format {
+            Format::Temp => self.to_temp(),
+            Format::Vcard4 => self.to_vcard4(),
+        }
+    }
+
+    fn to_temp(&self) -> Element {
+        let text = |name: &str, value: &str| Element::builder(name, NS).append(value).build();
+        let mut vcard = Element::builder("vCard", NS);
+        if let Some(ref full_name) = self.full_name {
+            vcard = vcard.append(text("FN", full_name));
+        }
+        if self.given.is_some() || self.family.is_some() {
+            let mut n = Element::builder("N", NS);
+            if let Some(ref family) = self.family {
+                n = n.append(text("FAMILY", family));
+            }
+            if let Some(ref given) = self.given {
+                n = n.append(text("GIVEN", given));
+            }
+            vcard = vcard.append(n.build());
+        }
+        if let Some(ref nickname) = self.nickname {
+            vcard = vcard.append(text("NICKNAME", nickname));
+        }
+        for email in &self.emails {
+            vcard = vcard.append(
+                Element::builder("EMAIL", NS)
+                    .append(Element::builder("INTERNET", NS).build())
+                    .append(text("USERID", email))
+                    .build(),
+            );
+        }
+        if let Some(ref url) = self.url {
+            vcard = vcard.append(text("URL", url));
+        }
+        if let Some(ref note) = self.note {
+            vcard = vcard.append(text("DESC", note));
+        }
+        if let Some(ref photo) = self.photo {
+            let photo = match photo {
+                Photo::Data {
+                    content_type,
+                    bytes,
+                } => Element::builder("PHOTO", NS)
+                    .append(text("TYPE", content_type))
+                    .append(text(
+                        "BINVAL",
+                        &base64::engine::general_purpose::STANDARD.encode(bytes),
+                    )),
+                Photo::Url(url) => Element::builder("PHOTO", NS).append(text("EXTVAL", url)),
+            };
+            vcard = vcard.append(photo.build());
+        }
+        vcard.build()
+    }
+
+    fn to_vcard4(&self) -> Element {
+        let property = |name: &str, kind: &str, value: &str| {
+            Element::builder(name, NS_VCARD4)
+                .append(Element::builder(kind, NS_VCARD4).append(value).build())
+                .build()
+        };
+        let mut vcard = Element::builder("vcard", NS_VCARD4);
+        if let Some(ref full_name) = self.full_name {
+            vcard = vcard.append(property("fn", "text", full_name));
+        }
+        if self.given.is_some() || self.family.is_some() {
+            let part = |name: &str, value: &Option<String>| {
+                Element::builder(name, NS_VCARD4)
+                    .append(value.clone().unwrap_or_default())
+                    .build()
+            };
+            vcard = vcard.append(
+                Element::builder("n", NS_VCARD4)
+                    .append(part("surname", &self.family))
+                    .append(part("given", &self.given))
+                    .build(),
+            );
+        }
+        if let Some(ref nickname) = self.nickname {
+            vcard = vcard.append(property("nickname", "text", nickname));
+        }
+        for email in &self.emails {
+            vcard = vcard.append(property("email", "text", email));
+        }
+        if let Some(ref url) = self.url {
+            vcard = vcard.append(property("url", "uri", url));
+        }
+        if let Some(ref note) = self.note {
+            vcard = vcard.append(property("note", "text", note));
+        }
+        if let Some(ref photo) = self.photo {
+            let uri = match photo {
+                Photo::Data {
+                    content_type,
+                    bytes,
+                } => format!(
+                    "data:{};base64,{}",
+                    content_type,
+                    base64::engine::general_purpose::STANDARD.encode(bytes)
+                ),
+                Photo::Url(url) => url.clone(),
+            };
+            vcard = vcard.append(property("photo", "uri", &uri));
+        }
+        vcard.build()
+    }
+
+    /// Parse a `vcard-temp` or vCard4 element.
+    ///
+    /// Unknown properties are ignored.
+    pub fn from_element(elem: &Element) -> Option<Self> {
+        if elem.is("vCard", NS) {
+            Some(Vcard::from_temp(elem))
+        } else if elem.is("vcard", NS_VCARD4) {
+            Some(Vcard::from_vcard4(elem))
+        } else {
+            None
+        }
+    }
+
+    fn from_temp(elem: &Element) -> Self {
+        let text = |parent: &Element, name: &str| parent.get_child(name, NS).map(Element::text);
+        let n = elem.get_child("N", NS);
+        let photo = elem.get_child("PHOTO", NS).and_then(|photo| {
+            if let Some(url) = text(photo, "EXTVAL") {
+                return Some(Photo::Url(url));
+            }
+            let binval: String = text(photo, "BINVAL")?
+                .chars()
+                .filter(|c| !c.is_whitespace())
+                .collect();
+            Some(Photo::Data {
+                content_type: text(photo, "TYPE").unwrap_or_default(),
+                bytes: base64::engine::general_purpose::STANDARD
+                    .decode(binval)
+                    .ok()?,
+            })
+        });
+        Vcard {
+            full_name: text(elem, "FN"),
+            nickname: text(elem, "NICKNAME"),
+            given: n.and_then(|n| text(n, "GIVEN")),
+            family: n.and_then(|n| text(n, "FAMILY")),
+            emails: elem
+                .children()
+                .filter(|child| child.is("EMAIL", NS))
+                .filter_map(|email| text(email, "USERID"))
+                .collect(),
+            url: text(elem, "URL"),
+            note: text(elem, "DESC"),
+            photo,
+        }
+    }
+
+    fn from_vcard4(elem: &Element) -> Self {
+        let value = |property: &Element| {
+            property
+                .children()
+                .find(|child| child.name() == "text" || child.name() == "uri")
+                .map(Element::text)
+        };
+        let property = |name: &str| elem.get_child(name, NS_VCARD4).and_then(value);
+        let n = elem.get_child("n", NS_VCARD4);
+        let part = |name: &str| {
+            n.and_then(|n| n.get_child(name, NS_VCARD4))
+                .map(Element::text)
+                .filter(|part| !part.is_empty())
+        };
+        let photo = property("photo").map(|uri| {
+            let data = uri
+                .strip_prefix("data:")
+                .and_then(|data| data.split_once(";base64,"));
+            match data {
+                Some((content_type, data)) => {
+                    match base64::engine::general_purpose::STANDARD.decode(data) {
+                        Ok(bytes) => Photo::Data {
+                            content_type: content_type.to_owned(),
+                            bytes,
+                        },
+                        Err(_) => Photo::Url(uri),
+                    }
+                }
+                None => Photo::Url(uri),
+            }
+        });
+        Vcard {
+            full_name: property("fn"),
+            nickname: property("nickname"),
+            given: part("given"),
+            family: part("surname"),
+            emails: elem
+                .children()
+                .filter(|child| child.is("email", NS_VCARD4))
+                .filter_map(value)
+                .collect(),
+            url: property("url"),
+            note: property("note"),
+            photo,
+        }
+    }
+}
+
+/// A request for the vCard of an entity.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VcardRequest {
+    /// The requesting entity.
+    pub from: Option<Jid>,
+    /// The entity whose vCard is requested.
+    pub to: Option<Jid>,
+    /// The format asked for.
+    pub format: Format,
+    id: String,
+}
+
+impl VcardRequest {
+    /// Answer with `vcard`, in the format asked for.
+    pub fn result(self, vcard: &Vcard) -> Iq {
+        Iq::Result {
+            from: self.to,
+            to: self.from,
+            id: self.id,
+            payload: Some(vcard.to_element(self.format)),
+        }
+    }
+}
+
+/// A request setting the vCard of the sender.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VcardUpdate {
+    /// The entity setting its vCard.
+    pub from: Option<Jid>,
+    /// The entity asked to store it.
+    pub to: Option<Jid>,
+    /// The new vCard.
+    pub vcard: Vcard,
+    /// The format it was sent in.
+    pub format: Format,
+    id: String,
+}
+
+impl VcardUpdate {
+    /// Acknowledge the update.
+    pub fn ok(self) -> Iq {
+        Iq::Result {
+            from: self.to,
+            to: self.from,
+            id: self.id,
+            payload: None,
+        }
+    }
+}
+
+/// Looks up the vCards of the component and the entities it hosts.
+pub trait VcardSource: Send + Sync + 'static {
+    /// The vCard of `jid`, or `None` if it has none.
+    fn vcard<'a>(&'a self, jid: &'a Jid) -> BoxFuture<'a, Result<Option<Vcard>, Rejection>>;
+}
+
+impl<S: VcardSource + ?Sized> VcardSource for Arc<S> {
+    fn vcard<'a>(&'a self, jid: &'a Jid) -> BoxFuture<'a, Result<Option<Vcard>, Rejection>> {
+        (**self).vcard(jid)
+    }
+}
+
+/// vCards kept in a [`Namespace`] of the shared store, as vCard4 XML under
+/// each JID. Store them with [`save`].
+impl VcardSource for Namespace {
+    fn vcard<'a>(&'a self, jid: &'a Jid) -> BoxFuture<'a, Result<Option<Vcard>, Rejection>> {
+        Box::pin(async move {
+            let Some(xml) = self.get::<String>(jid.as_str()).await? else {
+                return Ok(None);
+            };
+            let elem = xml.parse::<Element>().map_err(|err| {
+                tracing::error!("invalid vCard stored for {}: {}", jid, err);
+                reject::internal_server_error()
+            })?;
+            Ok(Vcard::from_element(&elem))
+        })
+    }
+}
+
+/// Store `vcard` as the vCard of `jid` in `store`, or remove the vCard of
+/// `jid` if `None`.
+pub async fn save(store: &Namespace, jid: &Jid, vcard: Option<&Vcard>) -> Result<(), Rejection> {
+    let Some(vcard) = vcard else {
+        return store.delete(jid.as_str()).await;
+    };
+    let mut xml = Vec::new();
+    vcard
+        .to_element(Format::Vcard4)
+        .write_to(&mut xml)
+        .map_err(|err| {
+            tracing::error!("failed to encode the vCard of {}: {}", jid, err);
+            reject::internal_server_error()
+        })?;
+    store
+        .put(jid.as_str(), &String::from_utf8_lossy(&xml))
+        .await
+}
+
+/// Extract a request for a vCard.
+///
+/// Rejects with `item-not-found` for other stanzas.
+pub fn request() -> impl Filter<Extract = One<VcardRequest>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| match stanza {
+        Stanza::Iq(Iq::Get {
+            from,
+            to,
+            id,
+            payload,
+        }) => match format_of(payload) {
+            Some(format) => future::ok(VcardRequest {
+                from: from.clone(),
+                to: to.clone(),
+                format,
+                id: id.clone(),
+            }),
+            None => future::err(reject::item_not_found()),
+        },
+        _ => future::err(reject::item_not_found()),
+    })
+    .advertises(NS)
+    .advertises(NS_VCARD4)
+}
+
+/// Extract a request setting a vCard.
+///
+/// Rejects with `item-not-found` for other stanzas.
+pub fn update() -> impl Filter<Extract = One<VcardUpdate>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(match stanza {
+            Stanza::Iq(Iq::Set {
+                from,
+                to,
+                id,
+                payload,
+            }) => match (format_of(payload), Vcard::from_element(payload)) {
+                (Some(format), Some(vcard)) => Ok(VcardUpdate {
+                    from: from.clone(),
+                    to: to.clone(),
+                    vcard,
+                    format,
+                    id: id.clone(),
+                }),
+                _ => Err(reject::item_not_found()),
+            },
+            _ => Err(reject::item_not_found()),
+        })
+    })
+    .advertises(NS)
+    .advertises(NS_VCARD4)
+}
+
+/// Answer vCard requests from `source`.
+///
+/// Rejects with `item-not-found` for entities without a vCard.
+pub fn serve(
+    source: impl VcardSource,
+) -> impl Filter<Extract = One<Iq>, Error = Rejection> + Clone {
+    let source = Arc::new(source);
+    request().and_then(move |request: VcardRequest| {
+        let source = source.clone();
+        async move {
+            let jid = request.to.clone().ok_or_else(reject::bad_request)?;
+            match source.vcard(&jid).await? {
+                Some(vcard) => Ok(request.result(&vcard)),
+                None => Err(reject::item_not_found()),
+            }
+        }
+    })
+}
+
+fn format_of(payload: &Element) -> Option<Format> {
+    if payload.is("vCard", NS) {
+        Some(Format::Temp)
+    } else if payload.is("vcard", NS_VCARD4) {
+        Some(Format::Vcard4)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{KvStore, MemoryStore};
+
+    #[test]
+    fn round_trips_both_formats() {
+        let vcard = Vcard::new()
+            .full_name("Juliet Capulet")
+            .nickname("JC")
+            .email("juliet@capulet.lit")
+            .photo(Photo::Data {
+                content_type: "image/png".to_owned(),
+                bytes: vec![0x89, b'P', b'N', b'G'],
+            });
+        for format in [Format::Temp, Format::Vcard4] {
+            let elem = vcard.to_element(format);
+            assert_eq!(format_of(&elem), Some(format));
+            assert_eq!(Vcard::from_element(&elem), Some(vcard.clone()));
+        }
+    }
+
+    #[tokio::test]
+    async fn namespace_serves_saved_vcards() {
+        let store = MemoryStore::new().namespace("vcards");
+        let juliet = Jid::new("juliet@capulet.lit").unwrap();
+        let vcard = Vcard::new()
+            .full_name("Juliet Capulet")
+            .email("juliet@capulet.lit")
+            .photo(Photo::Url("https://capulet.lit/juliet.png".to_owned()));
+
+        assert_eq!(store.vcard(&juliet).await.unwrap(), None);
+
+        save(&store, &juliet, Some(&vcard)).await.unwrap();
+        assert_eq!(store.vcard(&juliet).await.unwrap(), Some(vcard));
+        let romeo = Jid::new("romeo@montague.lit").unwrap();
+        assert_eq!(store.vcard(&romeo).await.unwrap(), None);
+
+        save(&store, &juliet, None).await.unwrap();
+        assert_eq!(store.vcard(&juliet).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,448 @@
+//! Webhook bridge.
+//!
+//! Available with the `webhook` feature.
+//!
+//! - `wax::webhook::post(url)` - Forward the current stanza to an HTTP endpoint
+//!
+//! Many components are thin shims in front of an existing web backend.
+//! [`post`] builds a terminal filter that serializes the stanza being
+//! filtered, POSTs it to the backend, and replies with whatever stanza the
+//! backend answers with.
+//!
+//! Failed requests are retried with exponential backoff. If the backend is
+//! still unreachable or keeps answering with a server error, the filter
+//! rejects with a `wait`-type `service-unavailable`, so the sender knows to
+//! retry later. A client error status (4xx, besides timeouts and rate
+//! limiting) is not retried: the backend refused the stanza itself, so the
+//! filter rejects with a `modify`-type `not-acceptable`.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::Filter;
+//!
+//! let route = wax::message::body::param()
+//!     .and(wax::webhook::post("https://backend.internal/xmpp/inbound").xml())
+//!     .map(|_body: String, reply: Option<wax::Stanza>| reply);
+//! ```
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::future::BoxFuture;
+use serde::Serialize;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::message::MessageType;
+
+use crate::filter::{FilterBase, Internal};
+use crate::filtered_stanza;
+use crate::reject::{self, Rejection};
+use crate::serialize;
+
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Create a filter that POSTs the current stanza to `url`.
+///
+/// By default the stanza is sent as JSON (see [`Format::Json`]), up to 3
+/// attempts are made, and each attempt times out after 10 seconds.
+///
+/// The filter extracts an `Option<Stanza>`: if the backend answers with an
+/// XML body, it is parsed as the reply stanza; any other successful answer
+/// produces no reply.
+pub fn post(url: impl Into<String>) -> Webhook {
+    Webhook {
+        client: reqwest::Client::new(),
+        url: url.into(),
+        format: Format::Json,
+        headers: Vec::new(),
+        attempts: 3,
+        backoff: Duration::from_millis(200),
+        timeout: Duration::from_secs(10),
+    }
+}
+
+/// How a stanza is encoded in the request body.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// A JSON envelope with the routing attributes, the message body if any,
+    /// and the full stanza as XML text under `xml`.
+    Json,
+    /// The stanza as XML text.
+    Xml,
+}
+
+/// A filter forwarding stanzas to an HTTP endpoint.
+///
+/// Created with [`post`].
+#[derive(Clone, Debug)]
+pub struct Webhook {
+    client: reqwest::Client,
+    url: String,
+    format: Format,
+    headers: Vec<(String, String)>,
+    attempts: u32,
+    backoff: Duration,
+    timeout: Duration,
+}
+
+impl Webhook {
+    /// Send stanzas as XML text instead of JSON.
+    pub fn xml(mut self) -> Self {
+        self.format = Format::Xml;
+        self
+    }
+
+    /// Set how stanzas are encoded.
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Add a header to every request, e.g. for authentication.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Set how many attempts are made before giving up. At least one
+    /// attempt is always made.
+    pub fn attempts(mut self, attempts: u32) -> Self {
+        self.attempts = attempts.max(1);
+        self
+    }
+
+    /// Set the delay before the first retry. It doubles on every further
+    /// retry, up to 10 seconds.
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Set the timeout of each attempt.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Use the given client, e.g. to share its connection pool.
+    pub fn client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    async fn send(self, stanza: Arc<Stanza>) -> Result<Option<Stanza>, Rejection> {
+        let (content_type, body) = encode(&stanza, self.format)?;
+
+        let mut delay = self.backoff;
+        for attempt in 1..=self.attempts {
+            let mut request = self
+                .client
+                .post(&self.url)
+                .timeout(self.timeout)
+                .header(reqwest::header::CONTENT_TYPE, content_type)
+                .body(body.clone());
+            for (name, value) in &self.headers {
+                request = request.header(name.as_str(), value.as_str());
+            }
+
+            match request.send().await {
+                Ok(resp) if resp.status().is_success() => return decode(resp).await,
+                Ok(resp) if !is_retryable(resp.status()) => {
+                    tracing::warn!("webhook {} answered {}", self.url, resp.status());
+                    if resp.status().is_client_error() {
+                        return Err(reject::not_acceptable());
+                    }
+                    break;
+                }
+                Ok(resp) => {
+                    tracing::warn!(
+                        "webhook {} answered {} (attempt {}/{})",
+                        self.url,
+                        resp.status(),
+                        attempt,
+                        self.attempts
+                    );
+                }
+                Err(err) => {
+                    tracing::warn!(
+                        "webhook {} failed: {} (attempt {}/{})",
+                        self.url,
+                        err,
+                        attempt,
+                        self.attempts
+                    );
+                }
+            }
+
+            if attempt < self.attempts {
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_BACKOFF);
+            }
+        }
+
+        Err(reject::known(WebhookUnavailable { _p: () }))
+    }
+}
+
+impl FilterBase for Webhook {
+    type Extract = (Option<Stanza>,);
+    type Error = Rejection;
+    type Future = BoxFuture<'static, Result<Self::Extract, Self::Error>>;
+
+    fn filter(&self, _: Internal) -> Self::Future {
+        let stanza = filtered_stanza::shared();
+        let webhook = self.clone();
+        Box::pin(async move { webhook.send(stanza).await.map(|reply| (reply,)) })
+    }
+}
+
+fn is_retryable(status: reqwest::StatusCode) -> bool {
+    status.is_server_error()
+        || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+        || status == reqwest::StatusCode::REQUEST_TIMEOUT
+}
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    kind: &'static str,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    type_: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    from: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    to: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body: Option<String>,
+    xml: &'a str,
+}
+
+fn encode(stanza: &Stanza, format: Format) -> Result<(&'static str, Vec<u8>), Rejection> {
+    let xml = serialize::to_xml(stanza).map_err(|err| {
+        tracing::error!("failed to serialize stanza for webhook: {}", err);
+        reject::internal_server_error()
+    })?;
+
+    if format == Format::Xml {
+        return Ok(("application/xml", xml.into_bytes()));
+    }
+
+    let envelope = match stanza {
+        Stanza::Iq(iq) => {
+            let (kind, from, to, id) = match iq {
+                xmpp_parsers::iq::Iq::Get { from, to, id, .. } => ("get", from, to, id),
+                xmpp_parsers::iq::Iq::Set { from, to, id, .. } => ("set", from, to, id),
+                xmpp_parsers::iq::Iq::Result { from, to, id, .. } => ("result", from, to, id),
+                xmpp_parsers::iq::Iq::Error { from, to, id, .. } => ("error", from, to, id),
+            };
+            Envelope {
+                kind: "iq",
+                type_: Some(kind),
+                from: from.as_ref().map(ToString::to_string),
+                to: to.as_ref().map(ToString::to_string),
+                id: Some(id.clone()),
+                body: None,
+                xml: &xml,
+            }
+        }
+        Stanza::Message(msg) => Envelope {
+            kind: "message",
+            type_: Some(match msg.type_ {
+                MessageType::Chat => "chat",
+                MessageType::Error => "error",
+                MessageType::Groupchat => "groupchat",
+                MessageType::Headline => "headline",
+                MessageType::Normal => "normal",
+            }),
+            from: msg.from.as_ref().map(ToString::to_string),
+            to: msg.to.as_ref().map(ToString::to_string),
+            id: msg.id.as_ref().map(|id| id.0.clone()),
+            body: msg.get_best_body_cloned(vec![]).map(|(_, body)| body),
+            xml: &xml,
+        },
+        Stanza::Presence(presence) => Envelope {
+            kind: "presence",
+            type_: None,
+            from: presence.from.as_ref().map(ToString::to_string),
+            to: presence.to.as_ref().map(ToString::to_string),
+            id: presence.id.clone(),
+            body: None,
+            xml: &xml,
+        },
+    };
+
+    let json = serde_json::to_vec(&envelope).map_err(|err| {
+        tracing::error!("failed to encode webhook envelope: {}", err);
+        reject::internal_server_error()
+    })?;
+    Ok(("application/json", json))
+}
+
+async fn decode(resp: reqwest::Response) -> Result<Option<Stanza>, Rejection> {
+    let is_xml = resp
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("xml"));
+    if !is_xml {
+        return Ok(None);
+    }
+
+    let body = resp.text().await.map_err(|err| {
+        tracing::warn!("failed to read webhook response: {}", err);
+        reject::known(WebhookUnavailable { _p: () })
+    })?;
+    if body.trim().is_empty() {
+        return Ok(None);
+    }
+    serialize::from_xml(&body).map(Some).map_err(|err| {
+        tracing::error!("webhook answered with an invalid stanza: {}", err);
+        reject::internal_server_error()
+    })
+}
+
+crate::unit_error! {
+    /// The webhook endpoint could not be reached, or kept failing.
+    pub WebhookUnavailable: "webhook unavailable"
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use xmpp_parsers::iq::Iq;
+    use xmpp_parsers::minidom::Element;
+    use xmpp_parsers::stanza_error::{DefinedCondition, ErrorType};
+
+    use super::*;
+    use crate::Filter;
+
+    fn ping() -> Stanza {
+        Stanza::Iq(Iq::Get {
+            from: Some("juliet@capulet.lit/balcony".parse().unwrap()),
+            to: Some("hook.capulet.lit".parse().unwrap()),
+            id: "ping".to_owned(),
+            payload: Element::builder("ping", "urn:xmpp:ping").build(),
+        })
+    }
+
+    fn pong() -> String {
+        serialize::to_xml(&Stanza::Iq(Iq::Result {
+            from: Some("hook.capulet.lit".parse().unwrap()),
+            to: Some("juliet@capulet.lit/balcony".parse().unwrap()),
+            id: "ping".to_owned(),
+            payload: None,
+        }))
+        .unwrap()
+    }
+
+    // Answers the requests to the returned URL with `responses` in turn,
+    // then with 500, counting them.
+    async fn backend(responses: Vec<(u16, Option<String>)>) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counted = requests.clone();
+        tokio::spawn(async move {
+            let mut responses = responses.into_iter();
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                read_request(&mut socket).await;
+                counted.fetch_add(1, Ordering::SeqCst);
+                let response = match responses.next().unwrap_or((500, None)) {
+                    (status, Some(xml)) => format!(
+                        "HTTP/1.1 {status} Hook\r\ncontent-type: application/xml\r\n\
+                         content-length: {}\r\nconnection: close\r\n\r\n{xml}",
+                        xml.len()
+                    ),
+                    (status, None) => format!(
+                        "HTTP/1.1 {status} Hook\r\ncontent-length: 0\r\n\
+                         connection: close\r\n\r\n"
+                    ),
+                };
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    async fn read_request(socket: &mut TcpStream) {
+        let mut request = Vec::new();
+        let mut buf = [0; 1024];
+        loop {
+            let read = socket.read(&mut buf).await.unwrap();
+            if read == 0 {
+                return;
+            }
+            request.extend_from_slice(&buf[..read]);
+            let text = String::from_utf8_lossy(&request);
+            let Some(end) = text.find("\r\n\r\n") else {
+                continue;
+            };
+            let length = text[..end]
+                .lines()
+                .filter_map(|line| line.split_once(':'))
+                .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+                .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+                .unwrap_or(0);
+            if request.len() >= end + 4 + length {
+                return;
+            }
+        }
+    }
+
+    async fn call(webhook: Webhook) -> Vec<Stanza> {
+        let route = webhook
+            .backoff(Duration::ZERO)
+            .map(|reply: Option<Stanza>| reply);
+        let response = crate::service(route).call_stanza(ping()).await.unwrap();
+        response.stanzas().to_vec()
+    }
+
+    fn error(stanzas: &[Stanza]) -> (ErrorType, DefinedCondition) {
+        match stanzas {
+            [Stanza::Iq(Iq::Error { error, .. })] => {
+                (error.type_.clone(), error.defined_condition.clone())
+            }
+            other => panic!("expected an error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn replies_with_the_backend_answer() {
+        let (url, requests) = backend(vec![(200, Some(pong()))]).await;
+        let stanzas = call(post(url).xml()).await;
+        assert!(matches!(stanzas[..], [Stanza::Iq(Iq::Result { ref id, .. })] if id == "ping"));
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors() {
+        let (url, requests) = backend(vec![(503, None), (200, Some(pong()))]).await;
+        let stanzas = call(post(url)).await;
+        assert!(matches!(stanzas[..], [Stanza::Iq(Iq::Result { .. })]));
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        let (url, requests) = backend(Vec::new()).await;
+        let stanzas = call(post(url).attempts(2)).await;
+        assert_eq!(
+            error(&stanzas),
+            (ErrorType::Wait, DefinedCondition::ServiceUnavailable)
+        );
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn refuses_on_client_errors_without_retrying() {
+        let (url, requests) = backend(vec![(422, None)]).await;
+        let stanzas = call(post(url)).await;
+        assert_eq!(
+            error(&stanzas),
+            (ErrorType::Modify, DefinedCondition::NotAcceptable)
+        );
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,240 @@
+//! Gateway Interaction (XEP-0100).
+//!
+//! A gateway (or transport) lets XMPP users talk to contacts on a legacy
+//! network, each contact appearing as a JID of the gateway. This module
+//! covers the parts of the protocol every gateway repeats:
+//!
+//! - `wax::gateway::prompt()` / `translate()` - Turn legacy addresses into
+//!   JIDs of the gateway (`jabber:iq:gateway`)
+//! - [`registered`] / [`unregistered`] - The presence exchanged with a user
+//!   once they register or unregister, registration itself being served
+//!   with [`wax::ibr`](crate::ibr)
+//! - `wax::gateway::subscription()` - Extract a [`Subscription`] change sent
+//!   to the gateway or a contact, to approve or deny
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::gateway::{self, Prompt, Subscription, Translate};
+//! use wax::Filter;
+//!
+//! let prompt = gateway::prompt().map(|request: Prompt| {
+//!     request.prompt(Some("Enter the phone number of the contact.".to_owned()), "Phone number")
+//! });
+//! let translate = gateway::translate().and_then(|request: Translate| async move {
+//!     let jid = phone_to_jid(&request.prompt).ok_or_else(wax::reject::not_acceptable)?;
+//!     Ok::<_, wax::Rejection>(request.jid(jid))
+//! });
+//! let subscriptions = gateway::subscription().map(|change: Subscription| change.approve());
+//!
+//! let routes = prompt.or(translate).or(subscriptions);
+//! ```
+
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::jid::Jid;
+use xmpp_parsers::minidom::Element;
+use xmpp_parsers::presence::{Presence, Type as PresenceType};
+
+use crate::filter::{filter_fn_one, Filter};
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+
+/// The `jabber:iq:gateway` namespace.
+pub const NS: &str = "jabber:iq:gateway";
+
+/// A request for the prompt of the gateway.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Prompt {
+    /// The user asking.
+    pub from: Option<Jid>,
+    /// The gateway.
+    pub to: Option<Jid>,
+    id: String,
+}
+
+impl Prompt {
+    /// Answer with the label of the legacy address to enter, and a
+    /// description of what to enter.
+    pub fn prompt(self, desc: Option<String>, prompt: impl Into<String>) -> Iq {
+        let query = Element::builder("query", NS)
+            .append_all(desc.map(|desc| Element::builder("desc", NS).append(desc).build()))
+            .append(Element::builder("prompt", NS).append(prompt.into()).build())
+            .build();
+        Iq::Result {
+            from: self.to,
+            to: self.from,
+            id: self.id,
+            payload: Some(query),
+        }
+    }
+}
+
+/// A request to translate a legacy address into a JID.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Translate {
+    /// The user asking.
+    pub from: Option<Jid>,
+    /// The gateway.
+    pub to: Option<Jid>,
+    /// The legacy address entered.
+    pub prompt: String,
+    id: String,
+}
+
+impl Translate {
+    /// Answer with the JID of the contact.
+    pub fn jid(self, jid: Jid) -> Iq {
+        let query = Element::builder("query", NS)
+            .append(Element::builder("jid", NS).append(jid.to_string()).build())
+            .build();
+        Iq::Result {
+            from: self.to,
+            to: self.from,
+            id: self.id,
+            payload: Some(query),
+        }
+    }
+}
+
+/// Match requests for the prompt of the gateway.
+pub fn prompt() -> impl Filter<Extract = One<Prompt>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| match stanza {
+        Stanza::Iq(Iq::Get {
+            from,
+            to,
+            id,
+            payload,
+        }) if payload.is("query", NS) => future::ok(Prompt {
+            from: from.clone(),
+            to: to.clone(),
+            id: id.clone(),
+        }),
+        _ => future::err(reject::item_not_found()),
+    })
+    .advertises(NS)
+}
+
+/// Extract a request to translate a legacy address.
+///
+/// Rejects with `item-not-found` for other stanzas, and with `bad-request`
+/// if the address is missing.
+pub fn translate() -> impl Filter<Extract = One<Translate>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(match stanza {
+            Stanza::Iq(Iq::Set {
+                from,
+                to,
+                id,
+                payload,
+            }) if payload.is("query", NS) => {
+                match payload
+                    .get_child("prompt", NS)
+                    .map(|prompt| prompt.text().trim().to_owned())
+                {
+                    Some(prompt) if !prompt.is_empty() => Ok(Translate {
+                        from: from.clone(),
+                        to: to.clone(),
+                        prompt,
+                        id: id.clone(),
+                    }),
+                    _ => Err(reject::bad_request()),
+                }
+            }
+            _ => Err(reject::item_not_found()),
+        })
+    })
+    .advertises(NS)
+}
+
+/// The presence to send `user` once they registered with `gateway`: a
+/// subscription request, so that the gateway appears in their roster.
+pub fn registered(gateway: Jid, user: &Jid) -> Presence {
+    presence(PresenceType::Subscribe, gateway, user)
+}
+
+/// The presences to send `user` once they unregistered from `gateway`:
+/// cancelling both directions of the subscription, then going offline.
+pub fn unregistered(gateway: Jid, user: &Jid) -> Vec<Presence> {
+    [
+        PresenceType::Unsubscribe,
+        PresenceType::Unsubscribed,
+        PresenceType::Unavailable,
+    ]
+    .into_iter()
+    .map(|type_| presence(type_, gateway.clone(), user))
+    .collect()
+}
+
+/// A change of subscription sent by a user to the gateway or a contact.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Subscription {
+    /// The user.
+    pub from: Jid,
+    /// The gateway or contact.
+    pub to: Jid,
+    /// `Subscribe`, `Subscribed`, `Unsubscribe` or `Unsubscribed`.
+    pub type_: PresenceType,
+}
+
+impl Subscription {
+    /// Whether the user asks for the presence of the gateway or contact.
+    pub fn is_request(&self) -> bool {
+        self.type_ == PresenceType::Subscribe
+    }
+
+    /// Answer a subscription request with `subscribed`, or acknowledge any
+    /// other change with the same type, as XEP-0100 expects.
+    pub fn approve(self) -> Presence {
+        let type_ = match self.type_ {
+            PresenceType::Subscribe => PresenceType::Subscribed,
+            PresenceType::Unsubscribe => PresenceType::Unsubscribed,
+            other => other,
+        };
+        presence(type_, self.to, &self.from)
+    }
+
+    /// Deny a subscription request, or cancel the subscription.
+    pub fn deny(self) -> Presence {
+        presence(PresenceType::Unsubscribed, self.to, &self.from)
+    }
+}
+
+/// Extract a change of subscription.
+///
+/// Rejects with `item-not-found` for other stanzas, and with `bad-request`
+/// if the sender or recipient is missing.
+pub fn subscription() -> impl Filter<Extract = One<Subscription>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(match stanza {
+            Stanza::Presence(presence)
+                if matches!(
+                    presence.type_,
+                    PresenceType::Subscribe
+                        | PresenceType::Subscribed
+                        | PresenceType::Unsubscribe
+                        | PresenceType::Unsubscribed
+                ) =>
+            {
+                match (&presence.from, &presence.to) {
+                    (Some(from), Some(to)) => Ok(Subscription {
+                        from: from.clone(),
+                        to: to.clone(),
+                        type_: presence.type_.clone(),
+                    }),
+                    _ => Err(reject::bad_request()),
+                }
+            }
+            _ => Err(reject::item_not_found()),
+        })
+    })
+}
+
+// Subscription presences go to bare JIDs.
+fn presence(type_: PresenceType, from: Jid, to: &Jid) -> Presence {
+    let mut presence = Presence::new(type_);
+    presence.from = Some(from);
+    presence.to = Some(Jid::from(to.to_bare()));
+    presence
+}
@@ -0,0 +1,379 @@
+//! HTTP-to-XMPP ingress.
+//!
+//! Available with the `http-ingress` feature.
+//!
+//! An [`Ingress`] is a small HTTP listener that lets a web backend push
+//! stanzas out through the component. Paired with the `wax::webhook` egress
+//! (the `webhook` feature), it turns a component into a complete bridge
+//! between a web application and XMPP.
+//!
+//! Every request must be a `POST` carrying `Authorization: Bearer <token>`.
+//! The body is either:
+//!
+//! - a full stanza as XML text, with an XML `Content-Type`, or
+//! - a simplified message as JSON:
+//!
+//! ```json
+//! { "to": "juliet@capulet.lit", "body": "Wherefore art thou?", "type": "chat" }
+//! ```
+//!
+//! `from` may be given too, and defaults to the component's JID; `type`
+//! defaults to `chat`. Accepted stanzas are queued on the server's outbound
+//! channel and answered with `202 Accepted`.
+//!
+//! # Example
+//!
+//! ```ignore
+//! let ingress = wax::ingress::Ingress::bind(([127, 0, 0, 1], 8080), token);
+//!
+//! component.serve(routes).ingress(ingress).run().await?;
+//! ```
+
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use http::{header, Method, Request, Response, StatusCode};
+use http_body::Body;
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::service::service_fn;
+use hyper_util::rt::TokioIo;
+use serde::Deserialize;
+use tokio::net::TcpListener;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::jid::Jid;
+use xmpp_parsers::message::{Lang, Message, MessageType};
+
+use crate::correlation::Outbound;
+use crate::serialize;
+
+const DEFAULT_BODY_LIMIT: usize = 64 * 1024;
+
+/// An HTTP listener injecting stanzas into a component's outbound stream.
+///
+/// Attach it to a server with `Server::ingress`; it starts listening when
+/// the server runs, and stops accepting connections once the server shuts
+/// down.
+#[derive(Clone)]
+pub struct Ingress {
+    addr: SocketAddr,
+    token: Arc<str>,
+    body_limit: usize,
+}
+
+impl Ingress {
+    /// Listen on `addr`, accepting requests bearing `token`.
+    pub fn bind(addr: impl Into<SocketAddr>, token: impl Into<String>) -> Self {
+        Ingress {
+            addr: addr.into(),
+            token: token.into().into(),
+            body_limit: DEFAULT_BODY_LIMIT,
+        }
+    }
+
+    /// Set the largest accepted request body, in bytes. Defaults to 64 KiB.
+    pub fn body_limit(mut self, limit: usize) -> Self {
+        self.body_limit = limit;
+        self
+    }
+
+    /// Accept connections until `shutdown` resolves, queueing each accepted
+    /// stanza on `outbound`. Stanzas without a `from` are sent from
+    /// `component`.
+    pub(crate) async fn serve(
+        self,
+        component: Jid,
+        outbound: Outbound,
+        shutdown: impl Future<Output = ()>,
+    ) {
+        let listener = match TcpListener::bind(self.addr).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                tracing::error!("http ingress failed to bind {}: {}", self.addr, err);
+                return;
+            }
+        };
+        tracing::info!("http ingress listening on {}", self.addr);
+
+        let state = Arc::new(State {
+            ingress: self,
+            component,
+            outbound,
+        });
+        let mut shutdown = std::pin::pin!(shutdown);
+        loop {
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                () = &mut shutdown => {
+                    tracing::info!("http ingress stopped");
+                    return;
+                }
+            };
+            let (stream, remote) = match accepted {
+                Ok(conn) => conn,
+                Err(err) => {
+                    crate::server::run::handle_accept_error(err).await;
+                    continue;
+                }
+            };
+            let state = state.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |req| {
+                    let state = state.clone();
+                    async move { Ok::<_, Infallible>(state.handle(req).await) }
+                });
+                if let Err(err) = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await
+                {
+                    tracing::debug!("http ingress connection from {} failed: {}", remote, err);
+                }
+            });
+        }
+    }
+}
+
+impl std::fmt::Debug for Ingress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Ingress")
+            .field("addr", &self.addr)
+            .field("body_limit", &self.body_limit)
+            .finish()
+    }
+}
+
+struct State {
+    ingress: Ingress,
+    component: Jid,
+    outbound: Outbound,
+}
+
+/// The simplified JSON form of a message.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SimpleMessage {
+    to: String,
+    from: Option<String>,
+    body: String,
+    #[serde(rename = "type")]
+    type_: Option<String>,
+}
+
+impl State {
+    async fn handle<B>(&self, req: Request<B>) -> Response<Full<Bytes>>
+    where
+        B: Body<Data = Bytes>,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        if req.method() != Method::POST {
+            return respond(StatusCode::METHOD_NOT_ALLOWED, "only POST is accepted");
+        }
+        if !self.authorized(&req) {
+            return respond(StatusCode::UNAUTHORIZED, "missing or invalid token");
+        }
+
+        let is_xml = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.contains("xml"));
+        let body = match Limited::new(req.into_body(), self.ingress.body_limit)
+            .collect()
+            .await
+        {
+            Ok(body) => body.to_bytes(),
+            Err(_) => return respond(StatusCode::PAYLOAD_TOO_LARGE, "body too large"),
+        };
+
+        let stanza = if is_xml {
+            std::str::from_utf8(&body)
+                .map_err(|err| err.to_string())
+                .and_then(|xml| serialize::from_xml(xml).map_err(|err| err.to_string()))
+        } else {
+            self.message_from_json(&body)
+        };
+        let stanza = match stanza {
+            Ok(stanza) => stanza,
+            Err(err) => return respond(StatusCode::BAD_REQUEST, &err),
+        };
+
+        match self.outbound.send(stanza) {
+            Ok(()) => respond(StatusCode::ACCEPTED, ""),
+            Err(_) => respond(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "outbound queue unavailable",
+            ),
+        }
+    }
+
+    fn authorized<B>(&self, req: &Request<B>) -> bool {
+        let Some(provided) = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+        else {
+            return false;
+        };
+        // Compare without short-circuiting, so timing leaks nothing but the length.
+        let expected = self.ingress.token.as_bytes();
+        provided.len() == expected.len()
+            && provided
+                .bytes()
+                .zip(expected)
+                .fold(0, |acc, (a, b)| acc | (a ^ b))
+                == 0
+    }
+
+    fn message_from_json(&self, body: &[u8]) -> Result<Stanza, String> {
+        let simple: SimpleMessage = serde_json::from_slice(body).map_err(|err| err.to_string())?;
+        let to = Jid::new(&simple.to).map_err(|err| format!("invalid to: {}", err))?;
+        let from = match simple.from {
+            Some(from) => Jid::new(&from).map_err(|err| format!("invalid from: {}", err))?,
+            None => self.component.clone(),
+        };
+        let type_ = match simple.type_.as_deref() {
+            None | Some("chat") => MessageType::Chat,
+            Some("normal") => MessageType::Normal,
+            Some("headline") => MessageType::Headline,
+            Some("groupchat") => MessageType::Groupchat,
+            Some(other) => return Err(format!("unsupported message type: {}", other)),
+        };
+
+        let mut msg = Message::new(Some(to)).with_body(Lang::default(), simple.body);
+        msg.from = Some(from);
+        msg.type_ = type_;
+        Ok(Stanza::Message(msg))
+    }
+}
+
+fn respond(status: StatusCode, body: &str) -> Response<Full<Bytes>> {
+    let mut resp = Response::new(Full::new(Bytes::copy_from_slice(body.as_bytes())));
+    *resp.status_mut() = status;
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::correlation::{self, OutboundReceiver};
+
+    const TOKEN: &str = "s3cret";
+
+    fn state(body_limit: usize) -> (State, OutboundReceiver) {
+        let (outbound, outbound_rx) = correlation::outbound_channel(None);
+        let state = State {
+            ingress: Ingress::bind(([127, 0, 0, 1], 0), TOKEN).body_limit(body_limit),
+            component: Jid::new("sms.example.org").unwrap(),
+            outbound,
+        };
+        (state, outbound_rx)
+    }
+
+    fn post(token: Option<&str>, content_type: &str, body: &str) -> Request<Full<Bytes>> {
+        let mut req = Request::post("/").header(header::CONTENT_TYPE, content_type);
+        if let Some(token) = token {
+            req = req.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        req.body(Full::new(Bytes::copy_from_slice(body.as_bytes())))
+            .unwrap()
+    }
+
+    const JSON: &str = r#"{ "to": "juliet@capulet.lit", "body": "Wherefore art thou?" }"#;
+
+    #[tokio::test]
+    async fn requires_the_token() {
+        let (state, mut outbound_rx) = state(DEFAULT_BODY_LIMIT);
+
+        let missing = state.handle(post(None, "application/json", JSON)).await;
+        assert_eq!(missing.status(), StatusCode::UNAUTHORIZED);
+        let wrong = state
+            .handle(post(Some("s3cres"), "application/json", JSON))
+            .await;
+        assert_eq!(wrong.status(), StatusCode::UNAUTHORIZED);
+        let get = Request::get("/")
+            .header(header::AUTHORIZATION, format!("Bearer {}", TOKEN))
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        assert_eq!(
+            state.handle(get).await.status(),
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+
+        assert!(outbound_rx.try_recv().is_none());
+    }
+
+    #[tokio::test]
+    async fn limits_the_body() {
+        let (state, mut outbound_rx) = state(16);
+
+        let resp = state
+            .handle(post(Some(TOKEN), "application/json", JSON))
+            .await;
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(outbound_rx.try_recv().is_none());
+    }
+
+    #[tokio::test]
+    async fn queues_json_messages() {
+        let (state, mut outbound_rx) = state(DEFAULT_BODY_LIMIT);
+
+        let resp = state
+            .handle(post(Some(TOKEN), "application/json", JSON))
+            .await;
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+
+        let Some(Stanza::Message(msg)) = outbound_rx.try_recv() else {
+            panic!("expected a queued message");
+        };
+        assert_eq!(msg.to, Some(Jid::new("juliet@capulet.lit").unwrap()));
+        assert_eq!(msg.from, Some(Jid::new("sms.example.org").unwrap()));
+        assert_eq!(msg.type_, MessageType::Chat);
+        assert_eq!(
+            msg.get_best_body_cloned(vec![]).map(|(_, body)| body),
+            Some("Wherefore art thou?".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_bodies() {
+        let (state, mut outbound_rx) = state(DEFAULT_BODY_LIMIT);
+
+        for (content_type, body) in [
+            ("application/json", r#"{ "to": "juliet@capulet.lit" }"#),
+            ("application/json", r#"{ "to": "@", "body": "hi" }"#),
+            (
+                "application/json",
+                r#"{ "to": "juliet@capulet.lit", "body": "hi", "type": "error" }"#,
+            ),
+            ("application/xml", "<message"),
+            ("application/xml", "<stream xmlns='jabber:client'/>"),
+        ] {
+            let resp = state.handle(post(Some(TOKEN), content_type, body)).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", body);
+        }
+        assert!(outbound_rx.try_recv().is_none());
+    }
+
+    #[tokio::test]
+    async fn queues_xml_stanzas() {
+        let (state, mut outbound_rx) = state(DEFAULT_BODY_LIMIT);
+        let mut msg = Message::new(Some(Jid::new("juliet@capulet.lit").unwrap()))
+            .with_body(Lang::default(), "hi".to_owned());
+        msg.from = Some(Jid::new("romeo.sms.example.org").unwrap());
+        let xml = serialize::to_xml(&Stanza::Message(msg)).unwrap();
+
+        let resp = state.handle(post(Some(TOKEN), "text/xml", &xml)).await;
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+
+        let Some(Stanza::Message(queued)) = outbound_rx.try_recv() else {
+            panic!("expected a queued message");
+        };
+        assert_eq!(
+            queued.from,
+            Some(Jid::new("romeo.sms.example.org").unwrap())
+        );
+    }
+}
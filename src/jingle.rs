@@ -0,0 +1,408 @@
+//! Jingle (XEP-0166).
+//!
+//! - `wax::jingle::request()` - Extract a [`JingleRequest`], whichever its action
+//!
+//! [`Sessions`] tracks the sessions a component takes part in, keyed by
+//! initiator and session id, and its filters only let through the actions
+//! that make sense in the state of their session: an action on an unknown
+//! session rejects with `item-not-found`, and one out of order with
+//! `unexpected-request`.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::jingle::{JingleRequest, Sessions};
+//! use wax::Filter;
+//!
+//! let sessions = Sessions::new();
+//!
+//! let incoming = sessions.initiate().map(|request: JingleRequest| {
+//!     // negotiate the contents of request.jingle...
+//!     request.ok()
+//! });
+//! let hangup = sessions.terminate().map(|request: JingleRequest| {
+//!     // tear the call down...
+//!     request.ok()
+//! });
+//!
+//! let routes = incoming.or(hangup).or(other_routes);
+//! ```
+
+use std::fmt;
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::jid::Jid;
+use xmpp_parsers::minidom::Element;
+
+use crate::filter::{filter_fn_one, Filter};
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+
+/// The `urn:xmpp:jingle:1` namespace.
+pub const NS: &str = "urn:xmpp:jingle:1";
+
+/// The action of a Jingle request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    /// Offer a session.
+    SessionInitiate,
+    /// Accept an offered session.
+    SessionAccept,
+    /// Send information about an ongoing session, e.g. ringing.
+    SessionInfo,
+    /// End a session, whatever its state.
+    SessionTerminate,
+    /// Exchange transport candidates.
+    TransportInfo,
+}
+
+impl Action {
+    const ALL: [Action; 5] = [
+        Action::SessionInitiate,
+        Action::SessionAccept,
+        Action::SessionInfo,
+        Action::SessionTerminate,
+        Action::TransportInfo,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Action::SessionInitiate => "session-initiate",
+            Action::SessionAccept => "session-accept",
+            Action::SessionInfo => "session-info",
+            Action::SessionTerminate => "session-terminate",
+            Action::TransportInfo => "transport-info",
+        }
+    }
+}
+
+/// A Jingle request.
+#[derive(Clone, Debug, PartialEq)]
+pub struct JingleRequest {
+    /// The party sending the request.
+    pub from: Option<Jid>,
+    /// The party receiving it.
+    pub to: Option<Jid>,
+    /// The action.
+    pub action: Action,
+    /// The id of the session.
+    pub sid: String,
+    /// The initiator of the session, if named by the request.
+    pub initiator: Option<Jid>,
+    /// The `<jingle/>` element, with its contents and reason.
+    pub jingle: Element,
+    id: String,
+}
+
+impl JingleRequest {
+    /// Acknowledge the request.
+    pub fn ok(self) -> Iq {
+        Iq::Result {
+            from: self.to,
+            to: self.from,
+            id: self.id,
+            payload: None,
+        }
+    }
+}
+
+/// The state of a session.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum State {
+    /// Offered, but not accepted yet.
+    Pending,
+    /// Accepted.
+    Active,
+}
+
+/// A session tracked by [`Sessions`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Session {
+    /// The party that offered the session.
+    pub initiator: Jid,
+    /// The party it was offered to.
+    pub responder: Jid,
+    /// The id of the session.
+    pub sid: String,
+    /// The state of the session.
+    pub state: State,
+}
+
+/// Extract a Jingle request.
+///
+/// Rejects with `item-not-found` for other stanzas, and with `bad-request`
+/// if the action or session id is missing or unknown.
+pub fn request() -> impl Filter<Extract = One<JingleRequest>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(parse(stanza).unwrap_or_else(|| Err(reject::item_not_found())))
+    })
+    .advertises(NS)
+}
+
+/// The Jingle sessions of a component, shared by clones.
+#[derive(Clone, Default)]
+pub struct Sessions {
+    sessions: Arc<DashMap<(Jid, String), Session>>,
+}
+
+impl Sessions {
+    /// No sessions.
+    pub fn new() -> Self {
+        Sessions::default()
+    }
+
+    /// The session offered by `initiator` with `sid`, if any.
+    pub fn get(&self, initiator: &Jid, sid: &str) -> Option<Session> {
+        self.sessions
+            .get(&(initiator.clone(), sid.to_owned()))
+            .map(|session| session.clone())
+    }
+
+    /// Track a session the component offers itself, before sending its
+    /// `session-initiate`.
+    pub fn open(&self, initiator: Jid, responder: Jid, sid: impl Into<String>) {
+        let sid = sid.into();
+        self.sessions.insert(
+            (initiator.clone(), sid.clone()),
+            Session {
+                initiator,
+                responder,
+                sid,
+                state: State::Pending,
+            },
+        );
+    }
+
+    /// Forget a session without waiting for its `session-terminate`.
+    pub fn close(&self, initiator: &Jid, sid: &str) -> Option<Session> {
+        self.sessions
+            .remove(&(initiator.clone(), sid.to_owned()))
+            .map(|(_, session)| session)
+    }
+
+    /// Extract a `session-initiate`, and track the session it offers.
+    ///
+    /// Rejects with `unexpected-request` if the session already exists.
+    pub fn initiate(&self) -> impl Filter<Extract = One<JingleRequest>, Error = Rejection> + Clone {
+        self.action(Action::SessionInitiate)
+    }
+
+    /// Extract a `session-accept`, and mark its session active.
+    ///
+    /// Rejects with `item-not-found` if the session is unknown, and with
+    /// `unexpected-request` if it was already accepted.
+    pub fn accept(&self) -> impl Filter<Extract = One<JingleRequest>, Error = Rejection> + Clone {
+        self.action(Action::SessionAccept)
+    }
+
+    /// Extract a `session-terminate`, and forget its session.
+    ///
+    /// Rejects with `item-not-found` if the session is unknown.
+    pub fn terminate(
+        &self,
+    ) -> impl Filter<Extract = One<JingleRequest>, Error = Rejection> + Clone {
+        self.action(Action::SessionTerminate)
+    }
+
+    /// Extract a `transport-info`.
+    ///
+    /// Rejects with `item-not-found` if the session is unknown.
+    pub fn transport_info(
+        &self,
+    ) -> impl Filter<Extract = One<JingleRequest>, Error = Rejection> + Clone {
+        self.action(Action::TransportInfo)
+    }
+
+    /// Extract any Jingle request, and apply it to its session.
+    ///
+    /// Rejects like the filters of each action.
+    pub fn filter(&self) -> impl Filter<Extract = One<JingleRequest>, Error = Rejection> + Clone {
+        let sessions = self.clone();
+        request().and_then(move |request: JingleRequest| {
+            future::ready(sessions.apply(&request).map(|_| request))
+        })
+    }
+
+    fn action(
+        &self,
+        action: Action,
+    ) -> impl Filter<Extract = One<JingleRequest>, Error = Rejection> + Clone {
+        let sessions = self.clone();
+        filter_fn_one(move |stanza: &Stanza| {
+            future::ready(match parse(stanza) {
+                Some(Ok(request)) if request.action == action => Ok(request),
+                Some(Err(rejection)) => Err(rejection),
+                _ => Err(reject::item_not_found()),
+            })
+        })
+        .and_then(move |request: JingleRequest| {
+            future::ready(sessions.apply(&request).map(|_| request))
+        })
+        .advertises(NS)
+    }
+
+    // Move the session of `request` to its next state.
+    fn apply(&self, request: &JingleRequest) -> Result<(), Rejection> {
+        let from = request.from.clone().ok_or_else(reject::bad_request)?;
+        if request.action == Action::SessionInitiate {
+            let initiator = request.initiator.clone().unwrap_or_else(|| from.clone());
+            let responder = request.to.clone().ok_or_else(reject::bad_request)?;
+            let key = (initiator.clone(), request.sid.clone());
+            if self.sessions.contains_key(&key) {
+                tracing::debug!("session {} initiated twice", request.sid);
+                return Err(reject::unexpected_request());
+            }
+            self.sessions.insert(
+                key,
+                Session {
+                    initiator,
+                    responder,
+                    sid: request.sid.clone(),
+                    state: State::Pending,
+                },
+            );
+            return Ok(());
+        }
+
+        let key = self.key_of(request).ok_or_else(|| {
+            tracing::debug!(
+                "{} for unknown session {}",
+                request.action.as_str(),
+                request.sid
+            );
+            reject::item_not_found()
+        })?;
+        let Some(mut session) = self.sessions.get_mut(&key) else {
+            return Err(reject::item_not_found());
+        };
+        if from != session.initiator && from != session.responder {
+            return Err(reject::item_not_found());
+        }
+        match request.action {
+            Action::SessionAccept if session.state == State::Pending => {
+                session.state = State::Active;
+            }
+            Action::SessionAccept => {
+                tracing::debug!("session {} accepted twice", request.sid);
+                return Err(reject::unexpected_request());
+            }
+            Action::SessionTerminate => {
+                drop(session);
+                self.sessions.remove(&key);
+            }
+            Action::SessionInfo | Action::TransportInfo => {}
+            Action::SessionInitiate => unreachable!("handled above"),
+        }
+        Ok(())
+    }
+
+    // Later requests need not name the initiator: it is then either party.
+    fn key_of(&self, request: &JingleRequest) -> Option<(Jid, String)> {
+        [&request.initiator, &request.from, &request.to]
+            .into_iter()
+            .flatten()
+            .map(|initiator| (initiator.clone(), request.sid.clone()))
+            .find(|key| self.sessions.contains_key(key))
+    }
+}
+
+impl fmt::Debug for Sessions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sessions")
+            .field("sessions", &self.sessions.len())
+            .finish()
+    }
+}
+
+fn parse(stanza: &Stanza) -> Option<Result<JingleRequest, Rejection>> {
+    let Stanza::Iq(Iq::Set {
+        from,
+        to,
+        id,
+        payload,
+    }) = stanza
+    else {
+        return None;
+    };
+    if !payload.is("jingle", NS) {
+        return None;
+    }
+    let action = payload.attr("action").and_then(|name| {
+        Action::ALL
+            .into_iter()
+            .find(|action| action.as_str() == name)
+    });
+    let (Some(action), Some(sid)) = (action, payload.attr("sid")) else {
+        tracing::debug!("jingle request without known action or sid");
+        return Some(Err(reject::bad_request()));
+    };
+    let initiator = match payload.attr("initiator") {
+        Some(initiator) => match initiator.parse() {
+            Ok(initiator) => Some(initiator),
+            Err(_) => return Some(Err(reject::bad_request())),
+        },
+        None => None,
+    };
+    Some(Ok(JingleRequest {
+        from: from.clone(),
+        to: to.clone(),
+        action,
+        sid: sid.to_owned(),
+        initiator,
+        jingle: payload.clone(),
+        id: id.clone(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(action: Action, from: &str, to: &str) -> JingleRequest {
+        JingleRequest {
+            from: Some(Jid::new(from).unwrap()),
+            to: Some(Jid::new(to).unwrap()),
+            action,
+            sid: "a73sjjvkla37jfea".to_owned(),
+            initiator: None,
+            jingle: Element::builder("jingle", NS).build(),
+            id: "jingle1".to_owned(),
+        }
+    }
+
+    #[test]
+    fn rejects_out_of_order_actions() {
+        let sessions = Sessions::new();
+        let romeo = "romeo@montague.lit/orchard";
+        let juliet = "juliet@capulet.lit/balcony";
+
+        let accept = request(Action::SessionAccept, juliet, romeo);
+        assert!(sessions.apply(&accept).unwrap_err().is_item_not_found());
+
+        sessions
+            .apply(&request(Action::SessionInitiate, romeo, juliet))
+            .unwrap();
+        assert!(sessions
+            .apply(&request(Action::SessionInitiate, romeo, juliet))
+            .is_err());
+        sessions
+            .apply(&request(Action::TransportInfo, romeo, juliet))
+            .unwrap();
+        sessions.apply(&accept).unwrap();
+        assert!(sessions.apply(&accept).is_err());
+        let initiator = Jid::new(romeo).unwrap();
+        assert_eq!(
+            sessions.get(&initiator, "a73sjjvkla37jfea").unwrap().state,
+            State::Active
+        );
+
+        sessions
+            .apply(&request(Action::SessionTerminate, juliet, romeo))
+            .unwrap();
+        assert!(sessions.get(&initiator, "a73sjjvkla37jfea").is_none());
+    }
+}
@@ -27,40 +27,118 @@
 //! [Filter]: trait.Filter.html
 //! [reject]: reject/index.html
 
+#[cfg(feature = "client")]
+mod client;
+pub mod commands;
+#[cfg(feature = "config")]
+pub mod config;
+#[cfg(feature = "server")]
+pub mod connect;
 pub(crate) mod correlation;
-mod error;
+pub mod error;
+pub mod ext;
+#[cfg(all(test, feature = "server"))]
+mod fake_server;
 mod filter;
 mod filtered_stanza;
 pub mod filters;
+pub mod gateway;
 mod generic;
+#[cfg(feature = "http-ingress")]
+pub mod ingress;
+pub mod jingle;
+pub mod mam;
+pub mod mapping;
+pub mod privilege;
+pub mod pubsub;
 pub mod reject;
 pub mod reply;
+#[cfg(feature = "wax-serde")]
+pub mod serialize;
 #[cfg(feature = "server")]
 mod server;
 mod service;
+pub mod session;
+pub mod store;
+pub mod styling;
 pub use self::error::Error;
 pub use self::filter::wrap_fn;
 pub use self::filter::Filter;
+pub use self::filters::addressing;
+pub use self::filters::amp;
 pub use self::filters::any::any;
+pub use self::filters::attention;
+pub use self::filters::avatar;
+pub use self::filters::blocking;
+pub use self::filters::cache;
+pub use self::filters::caps;
+pub use self::filters::carbons;
+pub use self::filters::chatstate;
+pub use self::filters::command::{self, command, command_as};
+pub use self::filters::correction;
+pub use self::filters::delay;
+pub use self::filters::disco;
+pub use self::filters::fallback::fallback;
+pub use self::filters::form;
+pub use self::filters::forwarded;
+pub use self::filters::hints;
+pub use self::filters::httpupload;
+pub use self::filters::ibr;
 pub use self::filters::id::id;
 pub mod id {
     //! Stanza ID filters.
     pub use crate::filters::id::param;
 }
+pub use self::filters::jmi;
+pub use self::filters::last;
 pub use self::filters::log::log;
+#[cfg(feature = "jid-regex")]
+pub use self::filters::matching::from_regex;
+pub use self::filters::matching::{from_is, from_matches, to_is};
+pub use self::filters::muc;
+pub use self::filters::nick;
+pub use self::filters::node;
+pub use self::filters::ns::{attr, attr_as, element, ns};
+pub mod ns {
+    //! Namespace filters.
+    pub use crate::filters::ns::named;
+}
+pub use self::filters::oob;
+pub use self::filters::pep;
+pub use self::filters::private;
+pub use self::filters::reactions;
+pub use self::filters::receipts;
+pub use self::filters::relay;
+pub use self::filters::replies;
+pub use self::filters::resource::{self, resource_is};
+pub use self::filters::rosterx;
+pub use self::filters::rsm;
+pub use self::filters::search;
+pub use self::filters::sfs;
+pub use self::filters::shim;
 pub use self::filters::stanza::message;
 pub use self::filters::stanza::presence;
 pub use self::filters::stanza::query;
-pub use self::filters::stanza::{echo, from, iq, reply, require_from, require_to, sink, to};
+pub use self::filters::stanza::{
+    echo, from, iq, reply, require_from, require_to, sink, stanza, to,
+};
+pub use self::filters::stanza_id;
+pub use self::filters::tel;
+pub use self::filters::vcard;
+#[cfg(feature = "webhook")]
+pub use self::filters::webhook;
 pub mod log {
     //! Stanza logging.
     pub use crate::filters::log::{custom, Info, Log};
 }
+#[cfg(feature = "client")]
+pub use self::client::ServeClient;
 pub use self::reject::{reject, Rejection};
 pub use self::reply::Reply;
 #[cfg(feature = "server")]
-pub use self::server::ServeComponent;
+pub use self::server::{Overflow, ReconnectPolicy, ServeComponent, Servers, StanzaService};
 pub use self::service::service;
+pub use self::session::session;
 
 // Re-export XMPP types for convenience
 #[doc(hidden)]
@@ -0,0 +1,506 @@
+//! Message Archive Management (XEP-0313).
+//!
+//! - `wax::mam::query()` - Extract an archive [`Query`]
+//! - `wax::mam::archive(backend)` - Answer queries from a [`MessageArchive`]
+//!
+//! A query is answered with one message per archived message, followed by
+//! a `<fin/>` IQ closing the page. [`Query::results`] builds them;
+//! [`archive`] sends them in order through the outbound queue of the
+//! server.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use futures_util::future::BoxFuture;
+//! use wax::mam::{self, MessageArchive, Query, ResultPage};
+//! use wax::Rejection;
+//!
+//! struct Archive(Db);
+//!
+//! impl MessageArchive for Archive {
+//!     fn query<'a>(&'a self, query: &'a Query) -> BoxFuture<'a, Result<ResultPage, Rejection>> {
+//!         Box::pin(async move {
+//!             let owner = query.from.as_ref().ok_or_else(wax::reject::bad_request)?;
+//!             self.0.messages(owner, &query.filter, &query.page).await
+//!         })
+//!     }
+//! }
+//!
+//! let route = mam::archive(Archive(db));
+//! ```
+
+use std::sync::Arc;
+
+use futures_util::future::{self, BoxFuture};
+use tokio_xmpp::Stanza;
+use xmpp_parsers::date::DateTime;
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::jid::Jid;
+use xmpp_parsers::message::{Id, Message};
+use xmpp_parsers::minidom::Element;
+
+use crate::correlation::{self, Outbound};
+use crate::delay::Delay;
+use crate::filter::{filter_fn_one, Filter};
+use crate::form::{self, DataForm};
+use crate::forwarded::Forwarded;
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+use crate::rsm::{self, Page};
+
+/// The `urn:xmpp:mam:2` namespace.
+pub const NS: &str = "urn:xmpp:mam:2";
+
+/// An archive query.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Query {
+    /// The requesting entity.
+    pub from: Option<Jid>,
+    /// The archive, if not the requester's own.
+    pub to: Option<Jid>,
+    /// The id echoed in every result message.
+    pub query_id: Option<String>,
+    /// The pubsub node archived, if any.
+    pub node: Option<String>,
+    /// Which messages are wanted.
+    pub filter: ArchiveFilter,
+    /// Which page of them.
+    pub page: rsm::Request,
+    /// Whether the page should be returned in reverse order.
+    pub flip_page: bool,
+    id: String,
+}
+
+/// The messages wanted by a [`Query`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ArchiveFilter {
+    /// Only messages exchanged with this JID.
+    pub with: Option<Jid>,
+    /// Only messages sent at or after this time.
+    pub start: Option<DateTime>,
+    /// Only messages sent at or before this time.
+    pub end: Option<DateTime>,
+    /// Only messages after the one with this archive id.
+    pub after_id: Option<String>,
+    /// Only messages before the one with this archive id.
+    pub before_id: Option<String>,
+    /// Only the messages with these archive ids.
+    pub ids: Vec<String>,
+}
+
+/// An archived message.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArchivedMessage {
+    /// The archive id of the message.
+    pub id: String,
+    /// When the message was archived.
+    pub stamp: DateTime,
+    /// The message.
+    pub message: Message,
+}
+
+/// A page of archived messages, oldest first.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ResultPage {
+    /// The messages.
+    pub messages: Vec<ArchivedMessage>,
+    /// Whether this is the last page in the direction of the query.
+    pub complete: bool,
+    /// The total number of messages matching the query, if known.
+    pub count: Option<usize>,
+}
+
+impl Query {
+    /// The result messages of `page`, followed by the `<fin/>` IQ.
+    pub fn results(self, page: ResultPage) -> Vec<Stanza> {
+        let mut stanzas = Vec::with_capacity(page.messages.len() + 1);
+        let mut set = Page::of(&page.messages, |archived| archived.id.clone());
+        set.count = page.count;
+        let mut messages = page.messages;
+        if self.flip_page {
+            messages.reverse();
+        }
+        for archived in messages {
+            let forwarded =
+                Forwarded::new(Stanza::Message(archived.message)).delay(Delay::new(archived.stamp));
+            let result = Element::builder("result", NS)
+                .attr("queryid", self.query_id.clone())
+                .attr("id", archived.id)
+                .append(Element::from(forwarded))
+                .build();
+            let mut message = Message::new(self.from.clone());
+            message.from = self.to.clone();
+            message.id = Some(Id(correlation::unique_id()));
+            message.payloads.push(result);
+            stanzas.push(Stanza::Message(message));
+        }
+
+        let fin = Element::builder("fin", NS)
+            .attr("complete", page.complete.then_some("true"))
+            .append(Element::from(set))
+            .build();
+        stanzas.push(Stanza::Iq(Iq::Result {
+            from: self.to,
+            to: self.from,
+            id: self.id,
+            payload: Some(fin),
+        }));
+        stanzas
+    }
+}
+
+/// Storage for archived messages.
+pub trait MessageArchive: Send + Sync + 'static {
+    /// The page of messages matching `query`.
+    ///
+    /// Reject with `item-not-found` if the archive does not exist or an id
+    /// in the query is unknown, and with `forbidden` if the requester may
+    /// not read it.
+    fn query<'a>(&'a self, query: &'a Query) -> BoxFuture<'a, Result<ResultPage, Rejection>>;
+}
+
+impl<A: MessageArchive + ?Sized> MessageArchive for Arc<A> {
+    fn query<'a>(&'a self, query: &'a Query) -> BoxFuture<'a, Result<ResultPage, Rejection>> {
+        (**self).query(query)
+    }
+}
+
+/// Extract an archive query.
+///
+/// Rejects with `item-not-found` for other stanzas, and with `bad-request`
+/// if the query is malformed.
+pub fn query() -> impl Filter<Extract = One<Query>, Error = Rejection> + Copy {
+    filter_fn_one(|stanza: &Stanza| {
+        future::ready(match stanza {
+            Stanza::Iq(Iq::Set {
+                from,
+                to,
+                id,
+                payload,
+            }) if payload.is("query", NS) => parse(payload).map(|(filter, page)| Query {
+                from: from.clone(),
+                to: to.clone(),
+                query_id: payload.attr("queryid").map(str::to_owned),
+                node: payload.attr("node").map(str::to_owned),
+                filter,
+                page,
+                flip_page: payload.has_child("flip-page", NS),
+                id: id.clone(),
+            }),
+            _ => Err(reject::item_not_found()),
+        })
+    })
+    .advertises(NS)
+}
+
+/// Answer archive queries from `archive`.
+///
+/// The result messages and the `<fin/>` IQ are sent in order through the
+/// outbound queue of the server. Outside of a server, only the `<fin/>` is
+/// returned, as the reply.
+pub fn archive(
+    archive: impl MessageArchive,
+) -> impl Filter<Extract = One<Option<Stanza>>, Error = Rejection> + Clone {
+    let archive = Arc::new(archive);
+    query()
+        .and(filter_fn_one(|_: &Stanza| {
+            future::ok::<_, std::convert::Infallible>(correlation::outbound())
+        }))
+        .and_then(move |query: Query, outbound: Option<Outbound>| {
+            let archive = archive.clone();
+            async move {
+                let page = archive.query(&query).await?;
+                let mut stanzas = query.results(page);
+                let Some(outbound) = outbound else {
+                    tracing::debug!("no outbound queue, only replying with the fin");
+                    return Ok(stanzas.pop());
+                };
+                for stanza in stanzas {
+                    if outbound.send(stanza).is_err() {
+                        tracing::warn!("dropped archive result");
+                    }
+                }
+                Ok::<_, Rejection>(None)
+            }
+        })
+}
+
+fn parse(query: &Element) -> Result<(ArchiveFilter, rsm::Request), Rejection> {
+    let mut filter = ArchiveFilter::default();
+    if let Some(x) = query.get_child("x", form::NS) {
+        let form = DataForm::try_from(x).map_err(|err| {
+            tracing::debug!("invalid archive query form: {}", err);
+            reject::bad_request()
+        })?;
+        let single = |var| {
+            form.values(var)
+                .and_then(|values| values.first())
+                .filter(|value| !value.is_empty())
+        };
+        filter.with = single("with")
+            .map(|with| with.parse())
+            .transpose()
+            .map_err(|_| reject::bad_request())?;
+        filter.start = single("start")
+            .map(|start| start.parse())
+            .transpose()
+            .map_err(|_| reject::bad_request())?;
+        filter.end = single("end")
+            .map(|end| end.parse())
+            .transpose()
+            .map_err(|_| reject::bad_request())?;
+        filter.after_id = single("after-id").cloned();
+        filter.before_id = single("before-id").cloned();
+        filter.ids = form
+            .values("ids")
+            .map(<[String]>::to_vec)
+            .unwrap_or_default();
+    }
+
+    let page = rsm::parse(query).transpose()?.unwrap_or_default();
+    Ok((filter, page))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use xmpp_parsers::message::Lang;
+    use xmpp_parsers::stanza_error::DefinedCondition;
+
+    use super::*;
+
+    const JULIET: &str = "juliet@capulet.lit/balcony";
+    const ARCHIVE: &str = "juliet@capulet.lit";
+
+    // Answers every query with `page`, keeping the last query.
+    struct Recording {
+        query: Mutex<Option<Query>>,
+        page: ResultPage,
+    }
+
+    impl MessageArchive for Recording {
+        fn query<'a>(&'a self, query: &'a Query) -> BoxFuture<'a, Result<ResultPage, Rejection>> {
+            *self.query.lock().unwrap() = Some(query.clone());
+            Box::pin(future::ok(self.page.clone()))
+        }
+    }
+
+    fn recording(page: ResultPage) -> Arc<Recording> {
+        Arc::new(Recording {
+            query: Mutex::new(None),
+            page,
+        })
+    }
+
+    fn request(query: &str) -> Stanza {
+        Stanza::Iq(Iq::Set {
+            from: Some(JULIET.parse().unwrap()),
+            to: Some(ARCHIVE.parse().unwrap()),
+            id: "mam".to_owned(),
+            payload: query.parse().unwrap(),
+        })
+    }
+
+    fn archived(id: &str, body: &str) -> ArchivedMessage {
+        let mut message =
+            Message::new(Some(JULIET.parse().unwrap())).with_body(Lang::default(), body.to_owned());
+        message.from = Some("romeo@montague.lit/orchard".parse().unwrap());
+        ArchivedMessage {
+            id: id.to_owned(),
+            stamp: "2010-07-10T23:08:25Z".parse().unwrap(),
+            message,
+        }
+    }
+
+    fn page() -> ResultPage {
+        ResultPage {
+            messages: vec![
+                archived("28482-98726-73623", "Call me"),
+                archived("09af3-cc343-b409f", "Soon"),
+            ],
+            complete: true,
+            count: Some(2),
+        }
+    }
+
+    fn query_of(query_id: Option<&str>, flip_page: bool) -> Query {
+        Query {
+            from: Some(JULIET.parse().unwrap()),
+            to: Some(ARCHIVE.parse().unwrap()),
+            query_id: query_id.map(str::to_owned),
+            node: None,
+            filter: ArchiveFilter::default(),
+            page: rsm::Request::default(),
+            flip_page,
+            id: "mam".to_owned(),
+        }
+    }
+
+    // The archive ids of the result messages.
+    fn result_ids(stanzas: &[Stanza]) -> Vec<&str> {
+        stanzas
+            .iter()
+            .filter_map(|stanza| match stanza {
+                Stanza::Message(message) => message.payloads.iter().find(|p| p.is("result", NS)),
+                _ => None,
+            })
+            .map(|result| result.attr("id").unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn parses_filters_and_paging() {
+        let archive = recording(ResultPage::default());
+        let stanza = request(&format!(
+            "<query xmlns='{NS}' queryid='f27'>\
+               <x xmlns='{form}' type='submit'>\
+                 <field var='FORM_TYPE' type='hidden'><value>{NS}</value></field>\
+                 <field var='with'><value>romeo@montague.lit</value></field>\
+                 <field var='start'><value>2010-06-07T00:00:00Z</value></field>\
+                 <field var='end'><value>2010-07-07T13:23:54Z</value></field>\
+                 <field var='after-id'><value>09af3-cc343-b409f</value></field>\
+                 <field var='ids'><value>a</value><value>b</value></field>\
+               </x>\
+               <set xmlns='{rsm}'><max>10</max><before/></set>\
+               <flip-page/>\
+             </query>",
+            form = form::NS,
+            rsm = rsm::NS,
+        ));
+        crate::service(super::archive(archive.clone()))
+            .call_stanza(stanza)
+            .await
+            .unwrap();
+
+        let query = archive.query.lock().unwrap().take().unwrap();
+        assert_eq!(query.query_id.as_deref(), Some("f27"));
+        assert!(query.flip_page);
+        assert_eq!(
+            query.filter,
+            ArchiveFilter {
+                with: Some("romeo@montague.lit".parse().unwrap()),
+                start: Some("2010-06-07T00:00:00Z".parse().unwrap()),
+                end: Some("2010-07-07T13:23:54Z".parse().unwrap()),
+                after_id: Some("09af3-cc343-b409f".to_owned()),
+                before_id: None,
+                ids: vec!["a".to_owned(), "b".to_owned()],
+            }
+        );
+        assert_eq!(
+            query.page,
+            rsm::Request {
+                max: Some(10),
+                before: Some(String::new()),
+                ..rsm::Request::default()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_queries() {
+        let archive = recording(ResultPage::default());
+        let stanza = request(&format!(
+            "<query xmlns='{NS}'>\
+               <x xmlns='{form}' type='submit'>\
+                 <field var='start'><value>last tuesday</value></field>\
+               </x>\
+             </query>",
+            form = form::NS,
+        ));
+        let response = crate::service(super::archive(archive.clone()))
+            .call_stanza(stanza)
+            .await
+            .unwrap();
+        let [Stanza::Iq(Iq::Error { error, .. })] = response.stanzas() else {
+            panic!("expected an error");
+        };
+        assert_eq!(error.defined_condition, DefinedCondition::BadRequest);
+        assert!(archive.query.lock().unwrap().is_none());
+    }
+
+    #[test]
+    fn sends_results_then_fin() {
+        let stanzas = query_of(Some("f27"), false).results(page());
+        assert_eq!(stanzas.len(), 3);
+        assert_eq!(
+            result_ids(&stanzas),
+            ["28482-98726-73623", "09af3-cc343-b409f"]
+        );
+
+        let Stanza::Message(first) = &stanzas[0] else {
+            panic!("expected a result message");
+        };
+        assert_eq!(first.to, Some(JULIET.parse().unwrap()));
+        assert_eq!(first.from, Some(ARCHIVE.parse().unwrap()));
+        let result = first.payloads.iter().find(|p| p.is("result", NS)).unwrap();
+        assert_eq!(result.attr("queryid"), Some("f27"));
+        let forwarded = result.get_child("forwarded", crate::forwarded::NS).unwrap();
+        let delay = forwarded.get_child("delay", crate::delay::NS).unwrap();
+        let stamp: DateTime = delay.attr("stamp").unwrap().parse().unwrap();
+        assert_eq!(stamp, "2010-07-10T23:08:25Z".parse().unwrap());
+        assert!(forwarded.children().any(|child| child.name() == "message"));
+
+        let Stanza::Iq(Iq::Result {
+            id,
+            payload: Some(fin),
+            ..
+        }) = &stanzas[2]
+        else {
+            panic!("expected the fin");
+        };
+        assert_eq!(id, "mam");
+        assert!(fin.is("fin", NS));
+        assert_eq!(fin.attr("complete"), Some("true"));
+        let set = fin.get_child("set", rsm::NS).unwrap();
+        assert_eq!(
+            set.get_child("first", rsm::NS).unwrap().text(),
+            "28482-98726-73623"
+        );
+        assert_eq!(
+            set.get_child("last", rsm::NS).unwrap().text(),
+            "09af3-cc343-b409f"
+        );
+        assert_eq!(set.get_child("count", rsm::NS).unwrap().text(), "2");
+    }
+
+    #[test]
+    fn flips_pages_but_not_their_set() {
+        let stanzas = query_of(None, true).results(ResultPage {
+            complete: false,
+            ..page()
+        });
+        assert_eq!(
+            result_ids(&stanzas),
+            ["09af3-cc343-b409f", "28482-98726-73623"]
+        );
+
+        let Some(Stanza::Iq(Iq::Result {
+            payload: Some(fin), ..
+        })) = stanzas.last()
+        else {
+            panic!("expected the fin");
+        };
+        assert_eq!(fin.attr("complete"), None);
+        let set = fin.get_child("set", rsm::NS).unwrap();
+        assert_eq!(
+            set.get_child("first", rsm::NS).unwrap().text(),
+            "28482-98726-73623"
+        );
+    }
+
+    #[tokio::test]
+    async fn replies_with_the_fin_outside_of_a_server() {
+        let response = crate::service(super::archive(recording(page())))
+            .call_stanza(request(&format!("<query xmlns='{NS}'/>")))
+            .await
+            .unwrap();
+        let [Stanza::Iq(Iq::Result {
+            payload: Some(fin), ..
+        })] = response.stanzas()
+        else {
+            panic!("expected only the fin");
+        };
+        assert!(fin.is("fin", NS));
+    }
+}
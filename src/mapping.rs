@@ -0,0 +1,493 @@
+//! JID ↔ external identity mapping.
+//!
+//! Gateways need to know which external account (a phone number, a Matrix
+//! ID, a username) belongs to which XMPP user, and back. A [`Mapping`] puts
+//! a cache in front of a [`Resolver`] that knows the answer, and provides:
+//!
+//! - `wax::mapping::mapped_from(mapping)` - Extract the external identity of the sender
+//! - `wax::mapping::mapped_to(mapping)` - Extract the external identity of the recipient
+//! - [`Mapping::address`] - Address an outbound stanza to the user behind an external identity
+//!
+//! [`StoreResolver`] keeps the mapping in a [`Namespace`] of the shared
+//! [`KvStore`](crate::store::KvStore).
+//!
+//! # Example
+//!
+//! ```ignore
+//! use std::time::Duration;
+//! use wax::mapping::{self, Mapping, StoreResolver};
+//! use wax::store::{KvStore, MemoryStore};
+//! use wax::Filter;
+//!
+//! let store = MemoryStore::new();
+//! let phones: Mapping<String> =
+//!     Mapping::new(StoreResolver::new(store.namespace("phones")), Duration::from_secs(300));
+//!
+//! let route = wax::message::body::param()
+//!     .and(mapping::mapped_from(phones.clone()))
+//!     .map(|body: String, phone: String| {
+//!         // forward `body` as an SMS from `phone`...
+//!         wax::sink()
+//!     });
+//! ```
+
+use std::fmt;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use futures_util::future::BoxFuture;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::jid::{BareJid, Jid};
+
+use crate::filter::Filter;
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+use crate::store::Namespace;
+
+/// Knows which external identity belongs to which bare JID.
+pub trait Resolver<T>: Send + Sync + 'static {
+    /// The external identity of `jid`, if it has one.
+    fn external<'a>(&'a self, jid: &'a BareJid) -> BoxFuture<'a, Result<Option<T>, Rejection>>;
+
+    /// The bare JID behind `external`, if any.
+    fn jid<'a>(&'a self, external: &'a T) -> BoxFuture<'a, Result<Option<BareJid>, Rejection>>;
+}
+
+/// A cached, bidirectional JID ↔ external identity mapping.
+///
+/// Clones share the same resolver and cache.
+pub struct Mapping<T> {
+    resolver: Arc<dyn Resolver<T>>,
+    ttl: Duration,
+    by_jid: Arc<DashMap<BareJid, (Option<T>, Instant)>>,
+    by_external: Arc<DashMap<T, (Option<BareJid>, Instant)>>,
+}
+
+impl<T> Mapping<T>
+where
+    T: Clone + Eq + Hash + Send + Sync + 'static,
+{
+    /// Create a mapping answering from `resolver`, caching answers (including
+    /// negative ones) for `ttl`.
+    pub fn new(resolver: impl Resolver<T>, ttl: Duration) -> Self {
+        Mapping {
+            resolver: Arc::new(resolver),
+            ttl,
+            by_jid: Arc::new(DashMap::new()),
+            by_external: Arc::new(DashMap::new()),
+        }
+    }
+
+    /// The external identity of `jid`, if it has one.
+    pub async fn external(&self, jid: &BareJid) -> Result<Option<T>, Rejection> {
+        if let Some(hit) = cached(&self.by_jid, jid) {
+            return Ok(hit);
+        }
+        let external = self.resolver.external(jid).await?;
+        self.by_jid
+            .insert(jid.clone(), (external.clone(), Instant::now() + self.ttl));
+        Ok(external)
+    }
+
+    /// The bare JID behind `external`, if any.
+    pub async fn jid(&self, external: &T) -> Result<Option<BareJid>, Rejection> {
+        if let Some(hit) = cached(&self.by_external, external) {
+            return Ok(hit);
+        }
+        let jid = self.resolver.jid(external).await?;
+        self.by_external
+            .insert(external.clone(), (jid.clone(), Instant::now() + self.ttl));
+        Ok(jid)
+    }
+
+    /// Address `stanza` to the user behind `external`.
+    ///
+    /// Rejects with `item-not-found` if no user is mapped to `external`.
+    pub async fn address(&self, external: &T, mut stanza: Stanza) -> Result<Stanza, Rejection> {
+        let jid = Jid::from(
+            self.jid(external)
+                .await?
+                .ok_or_else(reject::item_not_found)?,
+        );
+        match stanza {
+            Stanza::Message(ref mut msg) => msg.to = Some(jid),
+            Stanza::Presence(ref mut presence) => presence.to = Some(jid),
+            Stanza::Iq(
+                Iq::Get { ref mut to, .. }
+                | Iq::Set { ref mut to, .. }
+                | Iq::Result { ref mut to, .. }
+                | Iq::Error { ref mut to, .. },
+            ) => *to = Some(jid),
+        }
+        Ok(stanza)
+    }
+
+    /// Forget everything cached about `jid` and `external`, e.g. after
+    /// changing the mapping in the resolver's backing store.
+    pub fn invalidate(&self, jid: &BareJid, external: &T) {
+        self.by_jid.remove(jid);
+        self.by_external.remove(external);
+    }
+
+    /// Forget everything cached.
+    pub fn clear(&self) {
+        self.by_jid.clear();
+        self.by_external.clear();
+    }
+}
+
+fn cached<K, V>(cache: &DashMap<K, (Option<V>, Instant)>, key: &K) -> Option<Option<V>>
+where
+    K: Eq + Hash,
+    V: Clone,
+{
+    let now = Instant::now();
+    cache.remove_if(key, |_, (_, expires)| *expires <= now);
+    cache.get(key).map(|entry| entry.0.clone())
+}
+
+impl<T> Clone for Mapping<T> {
+    fn clone(&self) -> Self {
+        Mapping {
+            resolver: self.resolver.clone(),
+            ttl: self.ttl,
+            by_jid: self.by_jid.clone(),
+            by_external: self.by_external.clone(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Mapping<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mapping")
+            .field("ttl", &self.ttl)
+            .field("cached", &(self.by_jid.len() + self.by_external.len()))
+            .finish()
+    }
+}
+
+/// Extract the external identity of the stanza's sender.
+///
+/// Rejects with `item-not-found` if the stanza has no `from`, and with
+/// `registration-required` if the sender has no external identity.
+pub fn mapped_from<T>(
+    mapping: Mapping<T>,
+) -> impl Filter<Extract = One<T>, Error = Rejection> + Clone
+where
+    T: Clone + Eq + Hash + Send + Sync + 'static,
+{
+    crate::require_from().and_then(move |from: Jid| {
+        let mapping = mapping.clone();
+        async move { require_mapped(&mapping, &from.to_bare()).await }
+    })
+}
+
+/// Extract the external identity of the stanza's recipient.
+///
+/// Rejects with `item-not-found` if the stanza has no `to`, and with
+/// `registration-required` if the recipient has no external identity.
+pub fn mapped_to<T>(mapping: Mapping<T>) -> impl Filter<Extract = One<T>, Error = Rejection> + Clone
+where
+    T: Clone + Eq + Hash + Send + Sync + 'static,
+{
+    crate::require_to().and_then(move |to: Jid| {
+        let mapping = mapping.clone();
+        async move { require_mapped(&mapping, &to.to_bare()).await }
+    })
+}
+
+async fn require_mapped<T>(mapping: &Mapping<T>, jid: &BareJid) -> Result<T, Rejection>
+where
+    T: Clone + Eq + Hash + Send + Sync + 'static,
+{
+    mapping
+        .external(jid)
+        .await?
+        .ok_or_else(reject::registration_required)
+}
+
+/// A [`Resolver`] keeping the mapping in a [`Namespace`].
+///
+/// Both directions are stored, under `jid:{bare jid}` and
+/// `external:{external}` keys, so lookups are a single read either way.
+pub struct StoreResolver<T> {
+    store: Namespace,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> StoreResolver<T>
+where
+    T: fmt::Display + Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    /// Create a resolver reading and writing `store`.
+    pub fn new(store: Namespace) -> Self {
+        StoreResolver {
+            store,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Map `jid` to `external`, replacing any previous mapping of either.
+    ///
+    /// Remember to [`invalidate`](Mapping::invalidate) any [`Mapping`]
+    /// caching this resolver.
+    pub async fn link(&self, jid: &BareJid, external: &T) -> Result<(), Rejection> {
+        self.unlink(jid).await?;
+        if let Some(previous) = self.store.get::<String>(&external_key(external)).await? {
+            self.store.delete(&jid_key(&previous)).await?;
+        }
+        self.store.put(&jid_key(jid.as_str()), external).await?;
+        self.store.put(&external_key(external), jid.as_str()).await
+    }
+
+    /// Remove the mapping of `jid`, if any.
+    pub async fn unlink(&self, jid: &BareJid) -> Result<(), Rejection> {
+        if let Some(external) = self.store.get::<T>(&jid_key(jid.as_str())).await? {
+            self.store.delete(&external_key(&external)).await?;
+        }
+        self.store.delete(&jid_key(jid.as_str())).await
+    }
+}
+
+fn jid_key(jid: &str) -> String {
+    format!("jid:{}", jid)
+}
+
+fn external_key(external: &impl fmt::Display) -> String {
+    format!("external:{}", external)
+}
+
+impl<T> Resolver<T> for StoreResolver<T>
+where
+    T: fmt::Display + Serialize + DeserializeOwned + Send + Sync + 'static,
+{
+    fn external<'a>(&'a self, jid: &'a BareJid) -> BoxFuture<'a, Result<Option<T>, Rejection>> {
+        Box::pin(async move { self.store.get(&jid_key(jid.as_str())).await })
+    }
+
+    fn jid<'a>(&'a self, external: &'a T) -> BoxFuture<'a, Result<Option<BareJid>, Rejection>> {
+        Box::pin(async move {
+            match self.store.get::<String>(&external_key(external)).await? {
+                Some(jid) => BareJid::new(&jid).map(Some).map_err(|err| {
+                    tracing::error!("invalid JID stored for {}: {}", external, err);
+                    reject::internal_server_error()
+                }),
+                None => Ok(None),
+            }
+        })
+    }
+}
+
+impl<T> fmt::Debug for StoreResolver<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("StoreResolver").field(&self.store).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use futures_util::{future, TryFuture};
+    use xmpp_parsers::message::Message;
+    use xmpp_parsers::presence::{Presence, Type as PresenceType};
+    use xmpp_parsers::stanza_error::DefinedCondition;
+
+    use super::*;
+    use crate::filter::{FilterBase, Internal};
+    use crate::filtered_stanza;
+    use crate::reject::IsReject;
+    use crate::store::{KvStore, MemoryStore};
+
+    const PHONE: &str = "+15555550100";
+
+    fn juliet() -> BareJid {
+        BareJid::new("juliet@capulet.lit").unwrap()
+    }
+
+    fn romeo() -> BareJid {
+        BareJid::new("romeo@montague.lit").unwrap()
+    }
+
+    // A store resolver counting how often the mapping falls back to it.
+    struct Counting {
+        inner: StoreResolver<String>,
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl Resolver<String> for Counting {
+        fn external<'a>(
+            &'a self,
+            jid: &'a BareJid,
+        ) -> BoxFuture<'a, Result<Option<String>, Rejection>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            self.inner.external(jid)
+        }
+
+        fn jid<'a>(
+            &'a self,
+            external: &'a String,
+        ) -> BoxFuture<'a, Result<Option<BareJid>, Rejection>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            self.inner.jid(external)
+        }
+    }
+
+    // A mapping with juliet linked to `PHONE`, the resolver writing its
+    // store, and the number of lookups that reached the store.
+    async fn linked(ttl: Duration) -> (Mapping<String>, StoreResolver<String>, Arc<AtomicUsize>) {
+        let store = MemoryStore::new().namespace("phones");
+        let writer = StoreResolver::new(store.clone());
+        writer.link(&juliet(), &PHONE.to_owned()).await.unwrap();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let resolver = Counting {
+            inner: StoreResolver::new(store),
+            calls: calls.clone(),
+        };
+        (Mapping::new(resolver, ttl), writer, calls)
+    }
+
+    // Run `filter` on `stanza`, as a server would.
+    async fn extract<F: Filter>(
+        filter: F,
+        stanza: Stanza,
+    ) -> Result<<F::Future as TryFuture>::Ok, <F::Future as TryFuture>::Error> {
+        let stanza = RefCell::new(Arc::new(stanza));
+        let mut fut = Box::pin(filtered_stanza::set(&stanza, || filter.filter(Internal)));
+        future::poll_fn(|cx| filtered_stanza::set(&stanza, || fut.as_mut().try_poll(cx))).await
+    }
+
+    fn condition<T>(result: Result<T, Rejection>) -> DefinedCondition {
+        match result {
+            Ok(_) => panic!("expected a rejection"),
+            Err(rejection) => rejection.error_condition(),
+        }
+    }
+
+    #[tokio::test]
+    async fn link_both_ways() {
+        let resolver = StoreResolver::<String>::new(MemoryStore::new().namespace("phones"));
+        let juliet = BareJid::new("juliet@capulet.lit").unwrap();
+        let romeo = BareJid::new("romeo@montague.lit").unwrap();
+        let phone = "+15555550100".to_owned();
+
+        resolver.link(&juliet, &phone).await.unwrap();
+        resolver.link(&romeo, &phone).await.unwrap();
+
+        assert_eq!(resolver.jid(&phone).await.unwrap(), Some(romeo.clone()));
+        assert_eq!(resolver.external(&juliet).await.unwrap(), None);
+        assert_eq!(resolver.external(&romeo).await.unwrap(), Some(phone));
+    }
+
+    #[tokio::test]
+    async fn caches_answers_both_ways() {
+        let (mapping, _, calls) = linked(Duration::from_secs(300)).await;
+
+        assert_eq!(
+            mapping.external(&juliet()).await.unwrap(),
+            Some(PHONE.to_owned())
+        );
+        assert_eq!(
+            mapping.external(&juliet()).await.unwrap(),
+            Some(PHONE.to_owned())
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        assert_eq!(
+            mapping.jid(&PHONE.to_owned()).await.unwrap(),
+            Some(juliet())
+        );
+        assert_eq!(
+            mapping.jid(&PHONE.to_owned()).await.unwrap(),
+            Some(juliet())
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn caches_misses_until_invalidated() {
+        let (mapping, writer, calls) = linked(Duration::from_secs(300)).await;
+        let other = "+15555550199".to_owned();
+
+        assert_eq!(mapping.external(&romeo()).await.unwrap(), None);
+        writer.link(&romeo(), &other).await.unwrap();
+        assert_eq!(mapping.external(&romeo()).await.unwrap(), None);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        mapping.invalidate(&romeo(), &other);
+        assert_eq!(mapping.external(&romeo()).await.unwrap(), Some(other));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_the_store_once_expired() {
+        let (mapping, writer, calls) = linked(Duration::ZERO).await;
+
+        assert_eq!(
+            mapping.external(&juliet()).await.unwrap(),
+            Some(PHONE.to_owned())
+        );
+        writer.unlink(&juliet()).await.unwrap();
+        assert_eq!(mapping.external(&juliet()).await.unwrap(), None);
+        assert_eq!(mapping.jid(&PHONE.to_owned()).await.unwrap(), None);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn addresses_stanzas_to_the_mapped_user() {
+        let (mapping, _, _) = linked(Duration::from_secs(300)).await;
+        let to = Some(Jid::from(juliet()));
+
+        let addressed = mapping
+            .address(&PHONE.to_owned(), Stanza::Message(Message::new(None)))
+            .await
+            .unwrap();
+        assert!(matches!(addressed, Stanza::Message(msg) if msg.to == to));
+
+        let addressed = mapping
+            .address(
+                &PHONE.to_owned(),
+                Stanza::Presence(Presence::new(PresenceType::None)),
+            )
+            .await
+            .unwrap();
+        assert!(matches!(addressed, Stanza::Presence(presence) if presence.to == to));
+
+        let unknown = mapping
+            .address(
+                &"+15555550199".to_owned(),
+                Stanza::Message(Message::new(None)),
+            )
+            .await;
+        assert_eq!(condition(unknown), DefinedCondition::ItemNotFound);
+    }
+
+    #[tokio::test]
+    async fn extracts_mapped_senders_and_recipients() {
+        let (mapping, _, _) = linked(Duration::from_secs(300)).await;
+        let mut msg = Message::new(Some("romeo@montague.lit/orchard".parse().unwrap()));
+        msg.from = Some("juliet@capulet.lit/balcony".parse().unwrap());
+
+        let (phone,) = extract(mapped_from(mapping.clone()), Stanza::Message(msg.clone()))
+            .await
+            .unwrap();
+        assert_eq!(phone, PHONE);
+        assert_eq!(
+            condition(extract(mapped_to(mapping.clone()), Stanza::Message(msg.clone())).await),
+            DefinedCondition::RegistrationRequired
+        );
+
+        msg.from = None;
+        assert_eq!(
+            condition(extract(mapped_from(mapping), Stanza::Message(msg)).await),
+            DefinedCondition::ItemNotFound
+        );
+    }
+}
@@ -0,0 +1,429 @@
+//! Privileged Entity (XEP-0356).
+//!
+//! A server can grant a component privileges over its users: reading and
+//! editing their rosters, sending messages and IQs on their behalf, and
+//! receiving their presence. The grant is advertised in a `<privilege/>`
+//! message when the component connects.
+//!
+//! [`Privileges`] records the advertisements through its filter, and builds
+//! the stanzas acting on behalf of users, rejecting with `forbidden` before
+//! anything is sent when the needed privilege was not granted.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::privilege::Privileges;
+//! use wax::Filter;
+//!
+//! let privileges = Privileges::new();
+//! let routes = privileges.filter().or(other_routes);
+//!
+//! // later, from a handler:
+//! let notice = privileges.message(component_jid.clone(), msg)?;
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use futures_util::future;
+use tokio_xmpp::Stanza;
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::jid::Jid;
+use xmpp_parsers::message::{Id, Message};
+use xmpp_parsers::minidom::Element;
+
+use crate::correlation;
+use crate::filter::{filter_fn_one, Filter};
+use crate::forwarded::{self, Forwarded};
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+
+/// The `urn:xmpp:privilege:2` namespace.
+pub const NS: &str = "urn:xmpp:privilege:2";
+
+const NS_ROSTER: &str = "jabber:iq:roster";
+
+/// Access granted to rosters, or to an IQ namespace.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Access {
+    /// No access.
+    #[default]
+    None,
+    /// Only `get` requests.
+    Get,
+    /// Only `set` requests.
+    Set,
+    /// Both `get` and `set` requests.
+    Both,
+}
+
+impl Access {
+    /// Whether `get` requests are allowed.
+    pub fn allows_get(self) -> bool {
+        matches!(self, Access::Get | Access::Both)
+    }
+
+    /// Whether `set` requests are allowed.
+    pub fn allows_set(self) -> bool {
+        matches!(self, Access::Set | Access::Both)
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(Access::None),
+            "get" => Some(Access::Get),
+            "set" => Some(Access::Set),
+            "both" => Some(Access::Both),
+            _ => None,
+        }
+    }
+}
+
+/// Access granted to the presence of users.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum PresenceAccess {
+    /// No access.
+    #[default]
+    None,
+    /// The presence of every user of the server.
+    ManagedEntity,
+    /// Same, plus the presence of the contacts in their rosters.
+    Roster,
+}
+
+/// The privileges a server granted the component.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Permissions {
+    /// Access to rosters.
+    pub roster: Access,
+    /// Whether messages may be sent on behalf of users.
+    pub message: bool,
+    /// Access to presence.
+    pub presence: PresenceAccess,
+    /// Access to IQs on behalf of users, by payload namespace.
+    pub iq: HashMap<String, Access>,
+}
+
+impl Permissions {
+    fn parse(privilege: &Element) -> Self {
+        let mut permissions = Permissions::default();
+        for perm in privilege.children().filter(|child| child.is("perm", NS)) {
+            let type_ = perm.attr("type").unwrap_or("none");
+            match perm.attr("access") {
+                Some("roster") => permissions.roster = Access::from_name(type_).unwrap_or_default(),
+                Some("message") => permissions.message = type_ == "outgoing",
+                Some("presence") => {
+                    permissions.presence = match type_ {
+                        "managed_entity" => PresenceAccess::ManagedEntity,
+                        "roster" => PresenceAccess::Roster,
+                        _ => PresenceAccess::None,
+                    }
+                }
+                Some("iq") => {
+                    for namespace in perm.children().filter(|child| child.is("namespace", NS)) {
+                        let access = namespace.attr("type").and_then(Access::from_name);
+                        if let (Some(ns), Some(access)) = (namespace.attr("ns"), access) {
+                            permissions.iq.insert(ns.to_owned(), access);
+                        }
+                    }
+                }
+                other => tracing::debug!("ignoring unknown privilege {:?}", other),
+            }
+        }
+        permissions
+    }
+}
+
+/// The privileges granted by each server, shared by clones.
+#[derive(Clone, Default)]
+pub struct Privileges {
+    granted: Arc<DashMap<String, Permissions>>,
+}
+
+impl Privileges {
+    /// No privileges, until a server advertises some.
+    pub fn new() -> Self {
+        Privileges::default()
+    }
+
+    /// The privileges granted over the users of `domain`.
+    pub fn granted(&self, domain: &str) -> Option<Permissions> {
+        self.granted
+            .get(domain)
+            .map(|permissions| permissions.clone())
+    }
+
+    /// Record privilege advertisements.
+    ///
+    /// Rejects with `forbidden` for advertisements not sent by a server, and
+    /// with `item-not-found` for other stanzas.
+    pub fn filter(&self) -> impl Filter<Extract = One<Option<Stanza>>, Error = Rejection> + Clone {
+        let privileges = self.clone();
+        filter_fn_one(|stanza: &Stanza| {
+            future::ready(match stanza {
+                Stanza::Message(msg) => match msg.payloads.iter().find(|p| p.is("privilege", NS)) {
+                    Some(privilege) if !privilege.has_child("forwarded", forwarded::NS) => {
+                        match msg.from {
+                            Some(ref from)
+                                if from.node().is_none() && from.resource().is_none() =>
+                            {
+                                Ok((
+                                    from.domain().as_str().to_owned(),
+                                    Permissions::parse(privilege),
+                                ))
+                            }
+                            _ => Err(reject::forbidden()),
+                        }
+                    }
+                    _ => Err(reject::item_not_found()),
+                },
+                _ => Err(reject::item_not_found()),
+            })
+        })
+        .map(move |(domain, permissions): (String, Permissions)| {
+            tracing::debug!("privileges granted by {}: {:?}", domain, permissions);
+            privileges.granted.insert(domain, permissions);
+            None::<Stanza>
+        })
+    }
+
+    /// Send `msg` from `component` on behalf of its sender, a user of the
+    /// server.
+    ///
+    /// Rejects with `forbidden` if the message privilege was not granted by
+    /// the server of the sender.
+    pub fn message(&self, component: Jid, mut msg: Message) -> Result<Message, Rejection> {
+        let user = msg.from.clone().ok_or_else(reject::bad_request)?;
+        let server = self.check(&user, |permissions| permissions.message)?;
+        msg.from = Some(Jid::from(user.to_bare()));
+        let privilege = Element::builder("privilege", NS)
+            .append(Element::from(Forwarded::new(Stanza::Message(msg))))
+            .build();
+        let mut wrapper = Message::new(Some(server));
+        wrapper.from = Some(component);
+        wrapper.id = Some(Id(correlation::unique_id()));
+        wrapper.payloads.push(privilege);
+        Ok(wrapper)
+    }
+
+    /// Send `iq` from `component` on behalf of `user`.
+    ///
+    /// Rejects with `forbidden` if the server of `user` did not grant access
+    /// to the namespace of the payload, and with `bad-request` for results
+    /// and errors, which cannot be sent on behalf of users.
+    pub fn iq(&self, component: Jid, user: &Jid, iq: Iq) -> Result<Iq, Rejection> {
+        let (ns, set) = match iq {
+            Iq::Get { ref payload, .. } => (payload.ns(), false),
+            Iq::Set { ref payload, .. } => (payload.ns(), true),
+            _ => return Err(reject::bad_request()),
+        };
+        let server = self.check(user, |permissions| {
+            let access = permissions.iq.get(&ns).copied().unwrap_or_default();
+            if set {
+                access.allows_set()
+            } else {
+                access.allows_get()
+            }
+        })?;
+        Ok(wrap_iq(component, server, &Jid::from(user.to_bare()), iq))
+    }
+
+    /// Ask for the roster of `user`.
+    ///
+    /// Rejects with `forbidden` if roster access was not granted by the
+    /// server of `user`.
+    pub fn roster(&self, component: Jid, user: &Jid) -> Result<Iq, Rejection> {
+        self.check(user, |permissions| permissions.roster.allows_get())?;
+        Ok(Iq::Get {
+            from: Some(component),
+            to: Some(Jid::from(user.to_bare())),
+            id: correlation::unique_id(),
+            payload: Element::builder("query", NS_ROSTER).build(),
+        })
+    }
+
+    /// Add, update or, with `subscription='remove'`, remove `item` in the
+    /// roster of `user`.
+    ///
+    /// Rejects with `forbidden` if roster edits were not granted by the
+    /// server of `user`.
+    pub fn roster_push(&self, component: Jid, user: &Jid, item: Element) -> Result<Iq, Rejection> {
+        self.check(user, |permissions| permissions.roster.allows_set())?;
+        Ok(Iq::Set {
+            from: Some(component),
+            to: Some(Jid::from(user.to_bare())),
+            id: correlation::unique_id(),
+            payload: Element::builder("query", NS_ROSTER).append(item).build(),
+        })
+    }
+
+    // The server of `user`, if it granted what `allowed` checks.
+    fn check(
+        &self,
+        user: &Jid,
+        allowed: impl FnOnce(&Permissions) -> bool,
+    ) -> Result<Jid, Rejection> {
+        let domain = user.domain().as_str();
+        match self.granted.get(domain) {
+            Some(permissions) if allowed(&permissions) => {
+                Jid::new(domain).map_err(|_| reject::bad_request())
+            }
+            _ => {
+                tracing::debug!("privilege not granted by {}", domain);
+                Err(reject::forbidden())
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Privileges {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Privileges")
+            .field("servers", &self.granted.len())
+            .finish()
+    }
+}
+
+/// Wrap `iq` into a `<privileged_iq/>` sent from `component` to `server`, to
+/// be performed on behalf of `user`.
+pub(crate) fn wrap_iq(component: Jid, server: Jid, user: &Jid, iq: Iq) -> Iq {
+    let inner = match iq {
+        Iq::Get { id, payload, .. } => Iq::Get {
+            from: Some(user.clone()),
+            to: Some(user.clone()),
+            id,
+            payload,
+        },
+        Iq::Set { id, payload, .. } => Iq::Set {
+            from: Some(user.clone()),
+            to: Some(user.clone()),
+            id,
+            payload,
+        },
+        other => other,
+    };
+    Iq::Set {
+        from: Some(component),
+        to: Some(server),
+        id: correlation::unique_id(),
+        payload: Element::builder("privileged_iq", NS)
+            .append(Element::from(inner))
+            .build(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use futures_util::TryFuture;
+    use xmpp_parsers::stanza_error::DefinedCondition;
+
+    use super::*;
+    use crate::filter::{FilterBase, Internal};
+    use crate::filtered_stanza;
+    use crate::reject::IsReject;
+
+    const ADVERTISEMENT: &str = "<privilege xmlns='urn:xmpp:privilege:2'>\
+        <perm access='roster' type='both'/>\
+        <perm access='message' type='outgoing'/>\
+        </privilege>";
+
+    fn message(from: &str, payload: Element) -> Stanza {
+        let mut msg = Message::new(Some(Jid::new("pubsub.capulet.lit").unwrap()));
+        msg.from = Some(Jid::new(from).unwrap());
+        msg.payloads.push(payload);
+        Stanza::Message(msg)
+    }
+
+    // Run `filter` on `stanza`, as a server would.
+    async fn extract<F: Filter>(
+        filter: F,
+        stanza: Stanza,
+    ) -> Result<<F::Future as TryFuture>::Ok, <F::Future as TryFuture>::Error> {
+        let stanza = RefCell::new(Arc::new(stanza));
+        let mut fut = Box::pin(filtered_stanza::set(&stanza, || filter.filter(Internal)));
+        future::poll_fn(|cx| filtered_stanza::set(&stanza, || fut.as_mut().try_poll(cx))).await
+    }
+
+    fn condition<T>(result: Result<T, Rejection>) -> DefinedCondition {
+        match result {
+            Ok(_) => panic!("expected a rejection"),
+            Err(rejection) => rejection.error_condition(),
+        }
+    }
+
+    #[test]
+    fn checks_granted_privileges() {
+        let privileges = Privileges::new();
+        let advertisement: Element = "<privilege xmlns='urn:xmpp:privilege:2'>\
+            <perm access='roster' type='get'/>\
+            <perm access='message' type='outgoing'/>\
+            <perm access='iq'><namespace ns='http://jabber.org/protocol/pubsub' type='set'/></perm>\
+            </privilege>"
+            .parse()
+            .unwrap();
+        let permissions = Permissions::parse(&advertisement);
+        assert_eq!(permissions.roster, Access::Get);
+        assert!(permissions.message);
+        privileges
+            .granted
+            .insert("capulet.lit".to_owned(), permissions);
+
+        let component = Jid::new("pubsub.capulet.lit").unwrap();
+        let juliet = Jid::new("juliet@capulet.lit/balcony").unwrap();
+        assert!(privileges.roster(component.clone(), &juliet).is_ok());
+        assert!(privileges
+            .roster_push(
+                component.clone(),
+                &juliet,
+                Element::builder("item", NS_ROSTER).build()
+            )
+            .is_err());
+
+        let romeo = Jid::new("romeo@montague.lit").unwrap();
+        assert!(privileges.roster(component, &romeo).is_err());
+    }
+
+    #[tokio::test]
+    async fn records_advertisements_from_servers() {
+        let privileges = Privileges::new();
+        let advertisement = message("capulet.lit", ADVERTISEMENT.parse().unwrap());
+
+        let (reply,) = extract(privileges.filter(), advertisement).await.unwrap();
+        assert!(reply.is_none());
+        let permissions = privileges.granted("capulet.lit").unwrap();
+        assert_eq!(permissions.roster, Access::Both);
+        assert!(permissions.message);
+        assert_eq!(privileges.granted("montague.lit"), None);
+    }
+
+    #[tokio::test]
+    async fn ignores_forwarded_stanzas_and_forbids_users() {
+        let privileges = Privileges::new();
+        let presence: Element =
+            "<presence xmlns='jabber:client' from='juliet@capulet.lit/balcony'/>"
+                .parse()
+                .unwrap();
+        let forwarded = Element::builder("privilege", NS)
+            .append(
+                Element::builder("forwarded", forwarded::NS)
+                    .append(presence)
+                    .build(),
+            )
+            .build();
+        assert_eq!(
+            condition(extract(privileges.filter(), message("capulet.lit", forwarded)).await),
+            DefinedCondition::ItemNotFound
+        );
+
+        let spoofed = message("juliet@capulet.lit/balcony", ADVERTISEMENT.parse().unwrap());
+        assert_eq!(
+            condition(extract(privileges.filter(), spoofed).await),
+            DefinedCondition::Forbidden
+        );
+        assert_eq!(privileges.granted("capulet.lit"), None);
+    }
+}
@@ -0,0 +1,1040 @@
+//! Publish-Subscribe (XEP-0060).
+//!
+//! - `wax::pubsub::create()` / `delete()` - Extract node management requests
+//! - `wax::pubsub::publish()` / `retract()` - Extract item publications and retractions
+//! - `wax::pubsub::subscribe()` / `unsubscribe()` - Extract subscription requests
+//! - `wax::pubsub::items()` - Extract requests for the items of a node
+//!
+//! The filters only parse requests, and each request has builders for its
+//! answer. To serve the whole protocol, give a [`NodeStore`] to [`PubSub`]:
+//! it answers every request from the store and notifies the subscribers of
+//! a node when items are published to it.
+//!
+//! Requests without a `node`, such as instant node creation, are not
+//! supported: the filters leave them to other routes.
+//!
+//! [`MemoryNodeStore`] keeps nodes in memory; implement [`NodeStore`] to
+//! keep them elsewhere.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::pubsub::{MemoryNodeStore, PubSub};
+//! use wax::Filter;
+//!
+//! let pubsub = PubSub::new(MemoryNodeStore::new());
+//!
+//! let routes = pubsub.filter().or(other_routes);
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use futures_util::future::{self, BoxFuture};
+use tokio_xmpp::Stanza;
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::jid::{BareJid, Jid};
+use xmpp_parsers::message::{Message, MessageType};
+use xmpp_parsers::minidom::Element;
+
+use crate::correlation::{self, Outbound};
+use crate::filter::{filter_fn_one, Filter};
+use crate::generic::One;
+use crate::reject::{self, Rejection};
+
+/// The `http://jabber.org/protocol/pubsub` namespace.
+pub const NS: &str = "http://jabber.org/protocol/pubsub";
+
+/// The `http://jabber.org/protocol/pubsub#owner` namespace.
+pub const NS_OWNER: &str = "http://jabber.org/protocol/pubsub#owner";
+
+/// The `http://jabber.org/protocol/pubsub#event` namespace.
+pub const NS_EVENT: &str = "http://jabber.org/protocol/pubsub#event";
+
+/// A published item.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Item {
+    /// The item id.
+    pub id: String,
+    /// The payload, if any.
+    pub payload: Option<Element>,
+}
+
+impl Item {
+    /// An item `id` without payload.
+    pub fn new(id: impl Into<String>) -> Self {
+        Item {
+            id: id.into(),
+            payload: None,
+        }
+    }
+
+    /// Set the payload.
+    pub fn payload(mut self, payload: Element) -> Self {
+        self.payload = Some(payload);
+        self
+    }
+
+    pub(crate) fn to_element(&self, ns: &str) -> Element {
+        let item = Element::builder("item", ns).attr("id", self.id.as_str());
+        match self.payload {
+            Some(ref payload) => item.append(payload.clone()).build(),
+            None => item.build(),
+        }
+    }
+}
+
+/// The rights of an entity over a node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Affiliation {
+    /// May publish and retract items, and delete the node.
+    Owner,
+    /// May publish and retract items.
+    Publisher,
+    /// May only subscribe and retrieve items.
+    None,
+}
+
+/// Storage for pubsub nodes, their items, subscribers and affiliations.
+///
+/// Reject with `item-not-found` for nodes that do not exist, and with
+/// `conflict` when creating a node that does.
+pub trait NodeStore: Send + Sync + 'static {
+    /// Create `node`, owned by `owner`.
+    fn create<'a>(
+        &'a self,
+        node: &'a str,
+        owner: &'a BareJid,
+    ) -> BoxFuture<'a, Result<(), Rejection>>;
+
+    /// Delete `node`, returning its subscribers.
+    fn delete<'a>(&'a self, node: &'a str) -> BoxFuture<'a, Result<Vec<Jid>, Rejection>>;
+
+    /// Store `item` in `node`, replacing any item with the same id.
+    fn publish<'a>(&'a self, node: &'a str, item: Item) -> BoxFuture<'a, Result<(), Rejection>>;
+
+    /// Remove the item `id` from `node`.
+    fn retract<'a>(&'a self, node: &'a str, id: &'a str) -> BoxFuture<'a, Result<(), Rejection>>;
+
+    /// The items of `node`, oldest first, limited to the `max` most recent.
+    fn items<'a>(
+        &'a self,
+        node: &'a str,
+        max: Option<usize>,
+    ) -> BoxFuture<'a, Result<Vec<Item>, Rejection>>;
+
+    /// Subscribe `jid` to `node`.
+    fn subscribe<'a>(&'a self, node: &'a str, jid: &'a Jid)
+        -> BoxFuture<'a, Result<(), Rejection>>;
+
+    /// Unsubscribe `jid` from `node`.
+    fn unsubscribe<'a>(
+        &'a self,
+        node: &'a str,
+        jid: &'a Jid,
+    ) -> BoxFuture<'a, Result<(), Rejection>>;
+
+    /// The subscribers of `node`.
+    fn subscribers<'a>(&'a self, node: &'a str) -> BoxFuture<'a, Result<Vec<Jid>, Rejection>>;
+
+    /// The affiliation of `jid` with `node`.
+    fn affiliation<'a>(
+        &'a self,
+        node: &'a str,
+        jid: &'a BareJid,
+    ) -> BoxFuture<'a, Result<Affiliation, Rejection>>;
+
+    /// Change the affiliation of `jid` with `node`, e.g. to let it publish.
+    fn set_affiliation<'a>(
+        &'a self,
+        node: &'a str,
+        jid: &'a BareJid,
+        affiliation: Affiliation,
+    ) -> BoxFuture<'a, Result<(), Rejection>>;
+}
+
+impl<S: NodeStore + ?Sized> NodeStore for Arc<S> {
+    fn create<'a>(
+        &'a self,
+        node: &'a str,
+        owner: &'a BareJid,
+    ) -> BoxFuture<'a, Result<(), Rejection>> {
+        (**self).create(node, owner)
+    }
+
+    fn delete<'a>(&'a self, node: &'a str) -> BoxFuture<'a, Result<Vec<Jid>, Rejection>> {
+        (**self).delete(node)
+    }
+
+    fn publish<'a>(&'a self, node: &'a str, item: Item) -> BoxFuture<'a, Result<(), Rejection>> {
+        (**self).publish(node, item)
+    }
+
+    fn retract<'a>(&'a self, node: &'a str, id: &'a str) -> BoxFuture<'a, Result<(), Rejection>> {
+        (**self).retract(node, id)
+    }
+
+    fn items<'a>(
+        &'a self,
+        node: &'a str,
+        max: Option<usize>,
+    ) -> BoxFuture<'a, Result<Vec<Item>, Rejection>> {
+        (**self).items(node, max)
+    }
+
+    fn subscribe<'a>(
+        &'a self,
+        node: &'a str,
+        jid: &'a Jid,
+    ) -> BoxFuture<'a, Result<(), Rejection>> {
+        (**self).subscribe(node, jid)
+    }
+
+    fn unsubscribe<'a>(
+        &'a self,
+        node: &'a str,
+        jid: &'a Jid,
+    ) -> BoxFuture<'a, Result<(), Rejection>> {
+        (**self).unsubscribe(node, jid)
+    }
+
+    fn subscribers<'a>(&'a self, node: &'a str) -> BoxFuture<'a, Result<Vec<Jid>, Rejection>> {
+        (**self).subscribers(node)
+    }
+
+    fn affiliation<'a>(
+        &'a self,
+        node: &'a str,
+        jid: &'a BareJid,
+    ) -> BoxFuture<'a, Result<Affiliation, Rejection>> {
+        (**self).affiliation(node, jid)
+    }
+
+    fn set_affiliation<'a>(
+        &'a self,
+        node: &'a str,
+        jid: &'a BareJid,
+        affiliation: Affiliation,
+    ) -> BoxFuture<'a, Result<(), Rejection>> {
+        (**self).set_affiliation(node, jid, affiliation)
+    }
+}
+
+/// An in-memory [`NodeStore`].
+///
+/// Clones share the same nodes. Nothing survives a restart.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryNodeStore {
+    nodes: Arc<DashMap<String, Node>>,
+}
+
+#[derive(Debug, Default)]
+struct Node {
+    items: Vec<Item>,
+    subscribers: Vec<Jid>,
+    affiliations: HashMap<BareJid, Affiliation>,
+}
+
+impl MemoryNodeStore {
+    /// An empty store.
+    pub fn new() -> Self {
+        MemoryNodeStore::default()
+    }
+
+    fn with_node<T>(&self, node: &str, func: impl FnOnce(&mut Node) -> T) -> Result<T, Rejection> {
+        self.nodes
+            .get_mut(node)
+            .map(|mut node| func(&mut node))
+            .ok_or_else(reject::item_not_found)
+    }
+}
+
+impl NodeStore for MemoryNodeStore {
+    fn create<'a>(
+        &'a self,
+        node: &'a str,
+        owner: &'a BareJid,
+    ) -> BoxFuture<'a, Result<(), Rejection>> {
+        let created = match self.nodes.entry(node.to_owned()) {
+            dashmap::mapref::entry::Entry::Occupied(_) => Err(reject::conflict()),
+            dashmap::mapref::entry::Entry::Vacant(entry) => {
+                let mut node = Node::default();
+                node.affiliations.insert(owner.clone(), Affiliation::Owner);
+                entry.insert(node);
+                Ok(())
+            }
+        };
+        Box::pin(future::ready(created))
+    }
+
+    fn delete<'a>(&'a self, node: &'a str) -> BoxFuture<'a, Result<Vec<Jid>, Rejection>> {
+        let deleted = self
+            .nodes
+            .remove(node)
+            .map(|(_, node)| node.subscribers)
+            .ok_or_else(reject::item_not_found);
+        Box::pin(future::ready(deleted))
+    }
+
+    fn publish<'a>(&'a self, node: &'a str, item: Item) -> BoxFuture<'a, Result<(), Rejection>> {
+        Box::pin(future::ready(self.with_node(node, |node| {
+            node.items.retain(|stored| stored.id != item.id);
+            node.items.push(item);
+        })))
+    }
+
+    fn retract<'a>(&'a self, node: &'a str, id: &'a str) -> BoxFuture<'a, Result<(), Rejection>> {
+        let retracted = self.with_node(node, |node| {
+            let before = node.items.len();
+            node.items.retain(|stored| stored.id != id);
+            node.items.len() != before
+        });
+        Box::pin(future::ready(match retracted {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(reject::item_not_found()),
+            Err(rejection) => Err(rejection),
+        }))
+    }
+
+    fn items<'a>(
+        &'a self,
+        node: &'a str,
+        max: Option<usize>,
+    ) -> BoxFuture<'a, Result<Vec<Item>, Rejection>> {
+        Box::pin(future::ready(self.with_node(node, |node| {
+            let skip = max.map_or(0, |max| node.items.len().saturating_sub(max));
+            node.items[skip..].to_vec()
+        })))
+    }
+
+    fn subscribe<'a>(
+        &'a self,
+        node: &'a str,
+        jid: &'a Jid,
+    ) -> BoxFuture<'a, Result<(), Rejection>> {
+        Box::pin(future::ready(self.with_node(node, |node| {
+            if !node.subscribers.contains(jid) {
+                node.subscribers.push(jid.clone());
+            }
+        })))
+    }
+
+    fn unsubscribe<'a>(
+        &'a self,
+        node: &'a str,
+        jid: &'a Jid,
+    ) -> BoxFuture<'a, Result<(), Rejection>> {
+        Box::pin(future::ready(self.with_node(node, |node| {
+            node.subscribers.retain(|sub| sub != jid)
+        })))
+    }
+
+    fn subscribers<'a>(&'a self, node: &'a str) -> BoxFuture<'a, Result<Vec<Jid>, Rejection>> {
+        Box::pin(future::ready(
+            self.with_node(node, |node| node.subscribers.clone()),
+        ))
+    }
+
+    fn affiliation<'a>(
+        &'a self,
+        node: &'a str,
+        jid: &'a BareJid,
+    ) -> BoxFuture<'a, Result<Affiliation, Rejection>> {
+        Box::pin(future::ready(self.with_node(node, |node| {
+            node.affiliations
+                .get(jid)
+                .copied()
+                .unwrap_or(Affiliation::None)
+        })))
+    }
+
+    fn set_affiliation<'a>(
+        &'a self,
+        node: &'a str,
+        jid: &'a BareJid,
+        affiliation: Affiliation,
+    ) -> BoxFuture<'a, Result<(), Rejection>> {
+        Box::pin(future::ready(self.with_node(node, |node| {
+            match affiliation {
+                Affiliation::None => node.affiliations.remove(jid),
+                affiliation => node.affiliations.insert(jid.clone(), affiliation),
+            };
+        })))
+    }
+}
+
+/// A request to create a node.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Create {
+    /// The requesting entity.
+    pub from: Option<Jid>,
+    /// The pubsub service.
+    pub to: Option<Jid>,
+    /// The node to create.
+    pub node: String,
+    id: String,
+}
+
+impl Create {
+    /// Acknowledge the creation.
+    pub fn success(self) -> Iq {
+        result(self.from, self.to, self.id, None)
+    }
+}
+
+/// A request to delete a node.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Delete {
+    /// The requesting entity.
+    pub from: Option<Jid>,
+    /// The pubsub service.
+    pub to: Option<Jid>,
+    /// The node to delete.
+    pub node: String,
+    id: String,
+}
+
+impl Delete {
+    /// Acknowledge the deletion.
+    pub fn success(self) -> Iq {
+        result(self.from, self.to, self.id, None)
+    }
+}
+
+/// A request to publish an item.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Publish {
+    /// The publisher.
+    pub from: Option<Jid>,
+    /// The pubsub service.
+    pub to: Option<Jid>,
+    /// The node published to.
+    pub node: String,
+    /// The published item.
+    ///
+    /// Items published without an id are given one when extracted.
+    pub item: Item,
+    id: String,
+}
+
+impl Publish {
+    /// Acknowledge the publication.
+    pub fn success(self) -> Iq {
+        let publish = Element::builder("publish", NS)
+            .attr("node", self.node)
+            .append(
+                Element::builder("item", NS)
+                    .attr("id", self.item.id)
+                    .build(),
+            )
+            .build();
+        result(
+            self.from,
+            self.to,
+            self.id,
+            Some(Element::builder("pubsub", NS).append(publish).build()),
+        )
+    }
+
+    /// The event notification of this publication for `subscriber`.
+    pub fn notification(&self, subscriber: Jid) -> Message {
+        let items = Element::builder("items", NS_EVENT)
+            .attr("node", self.node.as_str())
+            .append(self.item.to_element(NS_EVENT))
+            .build();
+        let mut message = Message::new(Some(subscriber));
+        message.from = self.to.clone();
+        message.type_ = MessageType::Headline;
+        message
+            .payloads
+            .push(Element::builder("event", NS_EVENT).append(items).build());
+        message
+    }
+}
+
+/// A request to retract an item.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Retract {
+    /// The publisher.
+    pub from: Option<Jid>,
+    /// The pubsub service.
+    pub to: Option<Jid>,
+    /// The node of the item.
+    pub node: String,
+    /// The id of the item.
+    pub item_id: String,
+    id: String,
+}
+
+impl Retract {
+    /// Acknowledge the retraction.
+    pub fn success(self) -> Iq {
+        result(self.from, self.to, self.id, None)
+    }
+}
+
+/// A subscription request.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Subscribe {
+    /// The requesting entity.
+    pub from: Option<Jid>,
+    /// The pubsub service.
+    pub to: Option<Jid>,
+    /// The node to subscribe to.
+    pub node: String,
+    /// The JID to subscribe.
+    pub jid: Jid,
+    id: String,
+}
+
+impl Subscribe {
+    /// Acknowledge the subscription.
+    pub fn success(self) -> Iq {
+        let subscription = Element::builder("subscription", NS)
+            .attr("node", self.node)
+            .attr("jid", self.jid.to_string())
+            .attr("subscription", "subscribed")
+            .build();
+        result(
+            self.from,
+            self.to,
+            self.id,
+            Some(Element::builder("pubsub", NS).append(subscription).build()),
+        )
+    }
+}
+
+/// An unsubscription request.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Unsubscribe {
+    /// The requesting entity.
+    pub from: Option<Jid>,
+    /// The pubsub service.
+    pub to: Option<Jid>,
+    /// The node to unsubscribe from.
+    pub node: String,
+    /// The JID to unsubscribe.
+    pub jid: Jid,
+    id: String,
+}
+
+impl Unsubscribe {
+    /// Acknowledge the unsubscription.
+    pub fn success(self) -> Iq {
+        result(self.from, self.to, self.id, None)
+    }
+}
+
+/// A request for the items of a node.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Retrieve {
+    /// The requesting entity.
+    pub from: Option<Jid>,
+    /// The pubsub service.
+    pub to: Option<Jid>,
+    /// The node asked about.
+    pub node: String,
+    /// The maximum number of items wanted, most recent first.
+    pub max_items: Option<usize>,
+    /// The ids of the items wanted, or empty for any.
+    pub item_ids: Vec<String>,
+    id: String,
+}
+
+impl Retrieve {
+    /// Answer with `items`.
+    pub fn result(self, items: impl IntoIterator<Item = Item>) -> Iq {
+        let items = Element::builder("items", NS)
+            .attr("node", self.node)
+            .append_all(items.into_iter().map(|item| item.to_element(NS)))
+            .build();
+        result(
+            self.from,
+            self.to,
+            self.id,
+            Some(Element::builder("pubsub", NS).append(items).build()),
+        )
+    }
+}
+
+enum Request {
+    Create(Create),
+    Delete(Delete),
+    Publish(Publish),
+    Retract(Retract),
+    Subscribe(Subscribe),
+    Unsubscribe(Unsubscribe),
+    Retrieve(Retrieve),
+}
+
+macro_rules! request_filter {
+    ($(#[$doc:meta])* $name:ident => $variant:ident) => {
+        $(#[$doc])*
+        ///
+        /// Rejects with `item-not-found` for other stanzas, and with
+        /// `bad-request` if the request is malformed.
+        pub fn $name() -> impl Filter<Extract = One<$variant>, Error = Rejection> + Copy {
+            filter_fn_one(|stanza: &Stanza| {
+                future::ready(match parse(stanza) {
+                    Some(Ok(Request::$variant(request))) => Ok(request),
+                    Some(Err(rejection)) => Err(rejection),
+                    _ => Err(reject::item_not_found()),
+                })
+            })
+        }
+    };
+}
+
+request_filter!(
+    /// Extract a request to create a node.
+    create => Create
+);
+request_filter!(
+    /// Extract a request to delete a node.
+    delete => Delete
+);
+request_filter!(
+    /// Extract a request to publish an item.
+    publish => Publish
+);
+request_filter!(
+    /// Extract a request to retract an item.
+    retract => Retract
+);
+request_filter!(
+    /// Extract a subscription request.
+    subscribe => Subscribe
+);
+request_filter!(
+    /// Extract an unsubscription request.
+    unsubscribe => Unsubscribe
+);
+request_filter!(
+    /// Extract a request for the items of a node.
+    items => Retrieve
+);
+
+fn parse(stanza: &Stanza) -> Option<Result<Request, Rejection>> {
+    let (from, to, id, payload, set) = match stanza {
+        Stanza::Iq(Iq::Get {
+            from,
+            to,
+            id,
+            payload,
+        }) => (from, to, id, payload, false),
+        Stanza::Iq(Iq::Set {
+            from,
+            to,
+            id,
+            payload,
+        }) => (from, to, id, payload, true),
+        _ => return None,
+    };
+    if payload.name() != "pubsub" || (payload.ns() != NS && payload.ns() != NS_OWNER) {
+        return None;
+    }
+    let op = payload.children().next()?;
+    let node = op.attr("node")?.to_owned();
+    let (from, to, id) = (from.clone(), to.clone(), id.clone());
+    let jid = || {
+        op.attr("jid")
+            .and_then(|jid| jid.parse::<Jid>().ok())
+            .ok_or_else(reject::bad_request)
+    };
+
+    Some(Ok(match (set, op.ns().as_str(), op.name()) {
+        (true, NS, "create") => Request::Create(Create { from, to, node, id }),
+        (true, NS_OWNER, "delete") => Request::Delete(Delete { from, to, node, id }),
+        (true, NS, "publish") => {
+            let item = op.get_child("item", NS);
+            let item = Item {
+                id: match item.and_then(|item| item.attr("id")) {
+                    Some(item_id) => item_id.to_owned(),
+                    None => correlation::unique_id(),
+                },
+                payload: item.and_then(|item| item.children().next().cloned()),
+            };
+            Request::Publish(Publish {
+                from,
+                to,
+                node,
+                item,
+                id,
+            })
+        }
+        (true, NS, "retract") => match op.get_child("item", NS).and_then(|item| item.attr("id")) {
+            Some(item_id) => Request::Retract(Retract {
+                from,
+                to,
+                node,
+                item_id: item_id.to_owned(),
+                id,
+            }),
+            None => return Some(Err(reject::bad_request())),
+        },
+        (true, NS, "subscribe") => match jid() {
+            Ok(jid) => Request::Subscribe(Subscribe {
+                from,
+                to,
+                node,
+                jid,
+                id,
+            }),
+            Err(rejection) => return Some(Err(rejection)),
+        },
+        (true, NS, "unsubscribe") => match jid() {
+            Ok(jid) => Request::Unsubscribe(Unsubscribe {
+                from,
+                to,
+                node,
+                jid,
+                id,
+            }),
+            Err(rejection) => return Some(Err(rejection)),
+        },
+        (false, NS, "items") => {
+            let max_items = match op.attr("max_items").map(str::parse) {
+                None => None,
+                Some(Ok(max)) => Some(max),
+                Some(Err(_)) => return Some(Err(reject::bad_request())),
+            };
+            let item_ids = op
+                .children()
+                .filter(|child| child.is("item", NS))
+                .filter_map(|item| item.attr("id").map(str::to_owned))
+                .collect();
+            Request::Retrieve(Retrieve {
+                from,
+                to,
+                node,
+                max_items,
+                item_ids,
+                id,
+            })
+        }
+        _ => return None,
+    }))
+}
+
+/// A pubsub service answering requests from a [`NodeStore`].
+///
+/// Anyone may create a node, and owns the nodes they create. Only owners
+/// and publishers may publish to a node or retract from it, and only owners
+/// may delete it; other senders are rejected with `forbidden`. Use
+/// [`NodeStore::set_affiliation`] to add publishers.
+///
+/// Only the owner of a subscription, by bare JID, may subscribe or
+/// unsubscribe it. Publications are sent to the subscribers of the node as
+/// `headline` messages, through the outbound queue of the server.
+#[derive(Clone)]
+pub struct PubSub<S> {
+    store: Arc<S>,
+}
+
+impl<S: NodeStore> PubSub<S> {
+    /// A service backed by `store`.
+    pub fn new(store: S) -> Self {
+        PubSub {
+            store: Arc::new(store),
+        }
+    }
+
+    /// The underlying store.
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Serve pubsub requests.
+    pub fn filter(&self) -> impl Filter<Extract = One<Iq>, Error = Rejection> + Clone {
+        let store = self.store.clone();
+        filter_fn_one(|stanza: &Stanza| {
+            future::ready(match parse(stanza) {
+                Some(Ok(request)) => Ok((request, correlation::outbound())),
+                Some(Err(rejection)) => Err(rejection),
+                None => Err(reject::item_not_found()),
+            })
+        })
+        .and_then(move |(request, outbound): (Request, Option<Outbound>)| {
+            let store = store.clone();
+            async move { handle(&*store, request, outbound).await }
+        })
+        .advertises(NS)
+    }
+}
+
+impl<S> fmt::Debug for PubSub<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PubSub").finish()
+    }
+}
+
+async fn handle<S: NodeStore>(
+    store: &S,
+    request: Request,
+    outbound: Option<Outbound>,
+) -> Result<Iq, Rejection> {
+    match request {
+        Request::Create(create) => {
+            let owner = create.from.as_ref().ok_or_else(reject::forbidden)?;
+            store.create(&create.node, &owner.to_bare()).await?;
+            Ok(create.success())
+        }
+        Request::Delete(delete) => {
+            check_affiliation(
+                store,
+                &delete.node,
+                delete.from.as_ref(),
+                &[Affiliation::Owner],
+            )
+            .await?;
+            store.delete(&delete.node).await?;
+            Ok(delete.success())
+        }
+        Request::Publish(publish) => {
+            check_affiliation(store, &publish.node, publish.from.as_ref(), PUBLISHERS).await?;
+            store.publish(&publish.node, publish.item.clone()).await?;
+            let subscribers = store.subscribers(&publish.node).await?;
+            match outbound {
+                Some(outbound) => {
+                    for subscriber in subscribers {
+                        let notification = publish.notification(subscriber);
+                        if outbound.send(Stanza::Message(notification)).is_err() {
+                            tracing::warn!("dropped pubsub notification for {}", publish.node);
+                        }
+                    }
+                }
+                None if !subscribers.is_empty() => {
+                    tracing::debug!("no outbound queue, not notifying subscribers");
+                }
+                None => {}
+            }
+            Ok(publish.success())
+        }
+        Request::Retract(retract) => {
+            check_affiliation(store, &retract.node, retract.from.as_ref(), PUBLISHERS).await?;
+            store.retract(&retract.node, &retract.item_id).await?;
+            Ok(retract.success())
+        }
+        Request::Subscribe(subscribe) => {
+            check_owner(subscribe.from.as_ref(), &subscribe.jid)?;
+            store.subscribe(&subscribe.node, &subscribe.jid).await?;
+            Ok(subscribe.success())
+        }
+        Request::Unsubscribe(unsubscribe) => {
+            check_owner(unsubscribe.from.as_ref(), &unsubscribe.jid)?;
+            store
+                .unsubscribe(&unsubscribe.node, &unsubscribe.jid)
+                .await?;
+            Ok(unsubscribe.success())
+        }
+        Request::Retrieve(retrieve) => {
+            if retrieve.item_ids.is_empty() {
+                let items = store.items(&retrieve.node, retrieve.max_items).await?;
+                return Ok(retrieve.result(items));
+            }
+            let mut items = store.items(&retrieve.node, None).await?;
+            items.retain(|item| retrieve.item_ids.contains(&item.id));
+            Ok(retrieve.result(items))
+        }
+    }
+}
+
+const PUBLISHERS: &[Affiliation] = &[Affiliation::Owner, Affiliation::Publisher];
+
+async fn check_affiliation<S: NodeStore>(
+    store: &S,
+    node: &str,
+    from: Option<&Jid>,
+    allowed: &[Affiliation],
+) -> Result<(), Rejection> {
+    let from = from.ok_or_else(reject::forbidden)?;
+    let affiliation = store.affiliation(node, &from.to_bare()).await?;
+    if allowed.contains(&affiliation) {
+        Ok(())
+    } else {
+        Err(reject::forbidden())
+    }
+}
+
+fn check_owner(from: Option<&Jid>, jid: &Jid) -> Result<(), Rejection> {
+    match from {
+        Some(from) if from.to_bare() == jid.to_bare() => Ok(()),
+        _ => Err(reject::bad_request()),
+    }
+}
+
+fn result(from: Option<Jid>, to: Option<Jid>, id: String, payload: Option<Element>) -> Iq {
+    Iq::Result {
+        from: to,
+        to: from,
+        id,
+        payload,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use xmpp_parsers::stanza_error::DefinedCondition;
+
+    const HAMLET: &str = "hamlet@denmark.lit/elsinore";
+    const HORATIO: &str = "horatio@denmark.lit/castle";
+
+    fn request(from: &str, pubsub: &str) -> Stanza {
+        Stanza::Iq(Iq::Set {
+            from: Some(from.parse().unwrap()),
+            to: Some("pubsub.denmark.lit".parse().unwrap()),
+            id: "ps".to_owned(),
+            payload: pubsub.parse().unwrap(),
+        })
+    }
+
+    fn create(from: &str) -> Stanza {
+        request(
+            from,
+            &format!("<pubsub xmlns='{NS}'><create node='news'/></pubsub>"),
+        )
+    }
+
+    fn publish(from: &str, item_id: &str) -> Stanza {
+        request(
+            from,
+            &format!(
+                "<pubsub xmlns='{NS}'><publish node='news'><item id='{item_id}'>\
+                 <entry xmlns='http://www.w3.org/2005/Atom'/></item></publish></pubsub>"
+            ),
+        )
+    }
+
+    fn retract(from: &str, item_id: &str) -> Stanza {
+        request(
+            from,
+            &format!(
+                "<pubsub xmlns='{NS}'><retract node='news'><item id='{item_id}'/>\
+                 </retract></pubsub>"
+            ),
+        )
+    }
+
+    fn delete(from: &str) -> Stanza {
+        request(
+            from,
+            &format!("<pubsub xmlns='{NS_OWNER}'><delete node='news'/></pubsub>"),
+        )
+    }
+
+    async fn call(pubsub: &PubSub<MemoryNodeStore>, stanza: Stanza) -> Iq {
+        let response = crate::service(pubsub.filter())
+            .call_stanza(stanza)
+            .await
+            .unwrap();
+        match response.stanzas() {
+            [Stanza::Iq(iq)] => iq.clone(),
+            other => panic!("expected an iq, got {:?}", other),
+        }
+    }
+
+    fn condition(iq: &Iq) -> Option<&DefinedCondition> {
+        match iq {
+            Iq::Error { error, .. } => Some(&error.defined_condition),
+            _ => None,
+        }
+    }
+
+    async fn item_ids(pubsub: &PubSub<MemoryNodeStore>) -> Vec<String> {
+        let items = pubsub.store().items("news", None).await.unwrap();
+        items.into_iter().map(|item| item.id).collect()
+    }
+
+    #[tokio::test]
+    async fn owners_publish_retract_and_delete() {
+        let pubsub = PubSub::new(MemoryNodeStore::new());
+
+        assert_eq!(condition(&call(&pubsub, create(HAMLET)).await), None);
+        let created = call(&pubsub, create(HORATIO)).await;
+        assert_eq!(condition(&created), Some(&DefinedCondition::Conflict));
+
+        let Iq::Result {
+            payload: Some(published),
+            ..
+        } = call(&pubsub, publish(HAMLET, "a")).await
+        else {
+            panic!("expected the published item");
+        };
+        let item = published
+            .get_child("publish", NS)
+            .and_then(|publish| publish.get_child("item", NS));
+        assert_eq!(item.and_then(|item| item.attr("id")), Some("a"));
+        call(&pubsub, publish(HAMLET, "b")).await;
+        assert_eq!(item_ids(&pubsub).await, ["a", "b"]);
+
+        assert_eq!(condition(&call(&pubsub, retract(HAMLET, "a")).await), None);
+        assert_eq!(item_ids(&pubsub).await, ["b"]);
+        let missing = call(&pubsub, retract(HAMLET, "a")).await;
+        assert_eq!(condition(&missing), Some(&DefinedCondition::ItemNotFound));
+
+        assert_eq!(condition(&call(&pubsub, delete(HAMLET)).await), None);
+        assert!(pubsub.store().items("news", None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn forbids_others_from_changing_nodes() {
+        let pubsub = PubSub::new(MemoryNodeStore::new());
+        call(&pubsub, create(HAMLET)).await;
+        call(&pubsub, publish(HAMLET, "a")).await;
+
+        let forbidden = Some(&DefinedCondition::Forbidden);
+        assert_eq!(
+            condition(&call(&pubsub, publish(HORATIO, "b")).await),
+            forbidden
+        );
+        assert_eq!(
+            condition(&call(&pubsub, retract(HORATIO, "a")).await),
+            forbidden
+        );
+        assert_eq!(condition(&call(&pubsub, delete(HORATIO)).await), forbidden);
+        assert_eq!(item_ids(&pubsub).await, ["a"]);
+
+        let horatio = "horatio@denmark.lit".parse().unwrap();
+        pubsub
+            .store()
+            .set_affiliation("news", &horatio, Affiliation::Publisher)
+            .await
+            .unwrap();
+        assert_eq!(condition(&call(&pubsub, publish(HORATIO, "b")).await), None);
+        assert_eq!(condition(&call(&pubsub, retract(HORATIO, "a")).await), None);
+        assert_eq!(condition(&call(&pubsub, delete(HORATIO)).await), forbidden);
+        assert_eq!(item_ids(&pubsub).await, ["b"]);
+    }
+
+    #[test]
+    fn leaves_node_less_requests_to_other_routes() {
+        let instant = request(HAMLET, &format!("<pubsub xmlns='{NS}'><create/></pubsub>"));
+        assert!(parse(&instant).is_none());
+    }
+
+    #[tokio::test]
+    async fn memory_store_keeps_latest_items() {
+        let store = MemoryNodeStore::new();
+        let owner = "hamlet@denmark.lit".parse().unwrap();
+        store.create("news", &owner).await.unwrap();
+        assert!(store.create("news", &owner).await.is_err());
+
+        for id in ["a", "b", "c", "a"] {
+            store.publish("news", Item::new(id)).await.unwrap();
+        }
+        let ids = |items: Vec<Item>| items.into_iter().map(|item| item.id).collect::<Vec<_>>();
+        assert_eq!(
+            ids(store.items("news", None).await.unwrap()),
+            ["b", "c", "a"]
+        );
+        assert_eq!(ids(store.items("news", Some(2)).await.unwrap()), ["c", "a"]);
+
+        store.retract("news", "c").await.unwrap();
+        assert!(store.retract("news", "c").await.is_err());
+        assert!(store.items("weather", None).await.is_err());
+    }
+}
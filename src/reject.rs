@@ -25,6 +25,8 @@ use std::any::Any;
 use std::convert::Infallible;
 use std::fmt;
 
+#[cfg(feature = "wax-serde")]
+use xmpp_parsers::minidom::Element;
 pub use xmpp_parsers::stanza_error::{DefinedCondition, ErrorType, StanzaError};
 
 pub(crate) use self::sealed::{CombineRejection, IsReject};
@@ -43,6 +45,72 @@ pub fn item_not_found() -> Rejection {
     }
 }
 
+/// Rejects a stanza with `bad-request`.
+#[inline]
+pub fn bad_request() -> Rejection {
+    known(BadRequest { _p: () })
+}
+
+/// Rejects a stanza with `internal-server-error`.
+#[inline]
+pub fn internal_server_error() -> Rejection {
+    known(InternalServerError { _p: () })
+}
+
+/// Rejects a stanza with `conflict`.
+#[inline]
+pub fn conflict() -> Rejection {
+    known(Conflict { _p: () })
+}
+
+/// Rejects a stanza with `not-acceptable`.
+#[inline]
+pub fn not_acceptable() -> Rejection {
+    known(NotAcceptable { _p: () })
+}
+
+/// Rejects a stanza with `registration-required`.
+#[inline]
+pub fn registration_required() -> Rejection {
+    known(RegistrationRequired { _p: () })
+}
+
+/// Rejects a stanza with `forbidden`.
+#[inline]
+pub fn forbidden() -> Rejection {
+    known(Forbidden { _p: () })
+}
+
+/// Rejects a stanza with `not-allowed`.
+#[inline]
+pub fn not_allowed() -> Rejection {
+    known(NotAllowed { _p: () })
+}
+
+/// Rejects a stanza with `not-authorized`.
+#[inline]
+pub fn not_authorized() -> Rejection {
+    known(NotAuthorized { _p: () })
+}
+
+/// Rejects a stanza with `jid-malformed`.
+#[inline]
+pub fn jid_malformed() -> Rejection {
+    known(JidMalformed { _p: () })
+}
+
+/// Rejects a stanza with `service-unavailable`.
+#[inline]
+pub fn service_unavailable() -> Rejection {
+    known(ServiceUnavailable { _p: () })
+}
+
+/// Rejects a stanza with `unexpected-request`.
+#[inline]
+pub fn unexpected_request() -> Rejection {
+    known(UnexpectedRequest { _p: () })
+}
+
 /// Rejects a stanza with a custom cause.
 ///
 /// A [`recover`][] filter should convert this `Rejection` into an appropriate
@@ -201,6 +269,15 @@ enum_known! {
     SubscriptionRequired(SubscriptionRequired),
     UndefinedCondition(UndefinedCondition),
     UnexpectedRequest(UnexpectedRequest),
+    Unhandled(crate::filters::fallback::Unhandled),
+    #[cfg(feature = "wax-redis")]
+    RedisUnavailable(crate::ext::redis::RedisUnavailable),
+    #[cfg(feature = "ext-sqlx")]
+    DatabaseUnavailable(crate::ext::sqlx::DatabaseUnavailable),
+    #[cfg(feature = "webhook")]
+    WebhookUnavailable(crate::filters::webhook::WebhookUnavailable),
+    #[cfg(feature = "ext-publish")]
+    BrokerUnavailable(crate::ext::publish::BrokerUnavailable),
 }
 
 impl Rejection {
@@ -256,6 +333,57 @@ impl Rejection {
     }
 }
 
+#[cfg(feature = "wax-serde")]
+impl Rejection {
+    /// A serializable summary of this rejection.
+    ///
+    /// Available with the `wax-serde` feature.
+    pub fn summary(&self) -> Summary {
+        let error = self.into_stanza_error();
+        let condition = Element::from(error.defined_condition.clone())
+            .name()
+            .to_owned();
+        let causes = match self.reason {
+            Reason::ItemNotFound => vec!["ItemNotFound".to_owned()],
+            Reason::Other(ref other) => other.causes(),
+        };
+        Summary {
+            condition,
+            error_type: match error.type_ {
+                ErrorType::Auth => "auth",
+                ErrorType::Cancel => "cancel",
+                ErrorType::Continue => "continue",
+                ErrorType::Modify => "modify",
+                ErrorType::Wait => "wait",
+            }
+            .to_owned(),
+            text: error.texts.values().next().cloned(),
+            causes,
+        }
+    }
+}
+
+/// A serializable summary of a [`Rejection`], for logs and spools.
+///
+/// Available with the `wax-serde` feature. Custom causes are recorded by
+/// their `Debug` representation, so a summary cannot be turned back into a
+/// `Rejection`.
+#[cfg(feature = "wax-serde")]
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Summary {
+    /// The defined condition the rejection maps to, e.g. `bad-request`.
+    pub condition: String,
+    /// The error type, e.g. `modify`.
+    #[serde(rename = "type")]
+    pub error_type: String,
+    /// The human-readable error text, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    /// Every accumulated cause, in order.
+    #[serde(default)]
+    pub causes: Vec<String>,
+}
+
 impl<T: Reject> From<T> for Rejection {
     #[inline]
     fn from(err: T) -> Rejection {
@@ -352,6 +480,15 @@ impl Rejections {
                 Known::SubscriptionRequired(_) => DefinedCondition::SubscriptionRequired,
                 Known::UndefinedCondition(_) => DefinedCondition::UndefinedCondition,
                 Known::UnexpectedRequest(_) => DefinedCondition::UnexpectedRequest,
+                Known::Unhandled(_) => DefinedCondition::ServiceUnavailable,
+                #[cfg(feature = "wax-redis")]
+                Known::RedisUnavailable(_) => DefinedCondition::InternalServerError,
+                #[cfg(feature = "ext-sqlx")]
+                Known::DatabaseUnavailable(_) => DefinedCondition::InternalServerError,
+                #[cfg(feature = "webhook")]
+                Known::WebhookUnavailable(_) => DefinedCondition::ServiceUnavailable,
+                #[cfg(feature = "ext-publish")]
+                Known::BrokerUnavailable(_) => DefinedCondition::ServiceUnavailable,
             },
             Rejections::Custom(..) => DefinedCondition::UndefinedCondition,
             Rejections::Combined(..) => self.preferred().error_condition(),
@@ -386,7 +523,16 @@ impl Rejections {
                 Known::RecipientUnavailable(_)
                 | Known::RemoteServerTimeout(_)
                 | Known::ResourceConstraint(_)
-                | Known::ServiceUnavailable(_) => ErrorType::Wait,
+                | Known::ServiceUnavailable(_)
+                | Known::Unhandled(_) => ErrorType::Wait,
+                #[cfg(feature = "wax-redis")]
+                Known::RedisUnavailable(_) => ErrorType::Wait,
+                #[cfg(feature = "ext-sqlx")]
+                Known::DatabaseUnavailable(_) => ErrorType::Wait,
+                #[cfg(feature = "webhook")]
+                Known::WebhookUnavailable(_) => ErrorType::Wait,
+                #[cfg(feature = "ext-publish")]
+                Known::BrokerUnavailable(_) => ErrorType::Wait,
 
                 // Undefined - default to cancel
                 Known::UndefinedCondition(_) | Known::UnexpectedRequest(_) => ErrorType::Cancel,
@@ -428,6 +574,19 @@ impl Rejections {
         }
     }
 
+    #[cfg(feature = "wax-serde")]
+    fn causes(&self) -> Vec<String> {
+        match *self {
+            Rejections::Known(ref e) => vec![format!("{:?}", e)],
+            Rejections::Custom(ref e) => vec![format!("{:?}", e)],
+            Rejections::Combined(ref a, ref b) => {
+                let mut causes = a.causes();
+                causes.extend(b.causes());
+                causes
+            }
+        }
+    }
+
     fn debug_list(&self, f: &mut fmt::DebugList<'_, '_>) {
         match *self {
             Rejections::Known(ref e) => {
@@ -449,18 +608,27 @@ impl Rejections {
             Rejections::Combined(a, b) => {
                 let a = a.preferred();
                 let b = b.preferred();
-                // Compare error types with this priority:
-                // - ItemNotFound is lowest (default rejection)
-                // - Custom rejections are higher priority
-                // - Otherwise prefer the first one
-                match (a.error_condition(), b.error_condition()) {
-                    (_, DefinedCondition::ItemNotFound) => a,
-                    (DefinedCondition::ItemNotFound, _) => b,
-                    _ => a,
+                // Prefer the first one, unless the second has a higher priority.
+                if b.priority() > a.priority() {
+                    b
+                } else {
+                    a
                 }
             }
         }
     }
+
+    // The priority of a single rejection when combined:
+    // - ItemNotFound is lowest (default rejection)
+    // - Unhandled is next, so a fallback does not hide other rejections
+    // - Every other rejection, including custom ones, is highest
+    fn priority(&self) -> u8 {
+        match self {
+            Rejections::Known(Known::Unhandled(_)) => 1,
+            _ if self.error_condition() == DefinedCondition::ItemNotFound => 0,
+            _ => 2,
+        }
+    }
 }
 
 crate::unit_error! {
@@ -750,6 +918,21 @@ mod tests {
         assert_eq!(err.defined_condition, DefinedCondition::UndefinedCondition);
     }
 
+    #[test]
+    fn unhandled_ranks_below_other_rejections() {
+        let unhandled = crate::filters::fallback::unhandled;
+
+        let err = unhandled().combine(forbidden()).into_stanza_error();
+        assert_eq!(err.defined_condition, DefinedCondition::Forbidden);
+        let err = forbidden().combine(unhandled()).into_stanza_error();
+        assert_eq!(err.defined_condition, DefinedCondition::Forbidden);
+
+        let err = unhandled()
+            .combine(known(ItemNotFound { _p: () }))
+            .into_stanza_error();
+        assert_eq!(err.defined_condition, DefinedCondition::ServiceUnavailable);
+    }
+
     #[test]
     fn find_cause() {
         let rej = custom(Left);
@@ -796,6 +979,16 @@ mod tests {
         assert_eq!(s, "Rejection([X(0), X(1), X(2)])");
     }
 
+    #[cfg(feature = "wax-serde")]
+    #[test]
+    fn summary() {
+        let rej = item_not_found().combine(known(BadRequest { _p: () }));
+        let summary = rej.summary();
+        assert_eq!(summary.condition, "bad-request");
+        assert_eq!(summary.error_type, "modify");
+        assert_eq!(summary.causes, vec!["BadRequest".to_owned()]);
+    }
+
     #[test]
     fn convert_big_rejections_into_stanza_error() {
         let mut rejections = Rejections::Custom(Box::new(std::io::Error::from_raw_os_error(100)));
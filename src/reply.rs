@@ -1,66 +1,340 @@
 //! Reply to stanzas.
 //!
-//! A [`Reply`](./trait.Reply.html) is a type that can be converted into an XMPP
-//! stanza to be sent back to the sender. These are typically the successful
-//! counterpart to a [rejection](../reject).
+//! A [`Reply`](./trait.Reply.html) is a type that can be converted into the
+//! XMPP stanzas to send back to the sender. These are typically the
+//! successful counterpart to a [rejection](../reject).
 
+use std::fmt;
+
+use futures_util::stream::{BoxStream, Stream, StreamExt};
 use tokio_xmpp::Stanza;
+use xmpp_parsers::date::DateTime;
 use xmpp_parsers::iq::Iq;
-use xmpp_parsers::message::Message;
+use xmpp_parsers::jid::Jid;
+use xmpp_parsers::message::{Body, Id, Lang, Message, MessageType, Subject, Thread};
+use xmpp_parsers::minidom::Element;
 use xmpp_parsers::presence::Presence;
+use xmpp_parsers::stanza_error::{DefinedCondition, ErrorType, StanzaError};
 
+use crate::correlation;
+use crate::delay::Delay;
+use crate::filter::service::make_error_stanza;
+use crate::filtered_stanza;
+use crate::filters::stanza::{from_of, to_of};
+use crate::forwarded::Forwarded;
 use crate::generic::{Either, One};
 
-/// A type that can be converted into an optional XMPP stanza response.
+/// A type that can be converted into the stanzas to send in response.
 ///
 /// Types implementing this trait can be returned from filter chains.
 pub trait Reply: ReplySealed + Send {
-    /// Convert this reply into an optional stanza to send.
+    /// Convert this reply into the stanzas to send, in order.
     ///
-    /// Returns `None` if no response stanza should be sent.
-    fn into_response(self) -> Option<Stanza>;
+    /// The response is empty if nothing should be sent.
+    fn into_response(self) -> Response;
+}
+
+/// The stanzas a [`Reply`] sends, in order.
+///
+/// Most replies are a single stanza or none; a handler returning a `Vec`
+/// or an array of replies, e.g. a MAM result set followed by its `<fin/>`
+/// IQ, sends each of them in turn. Long result sets can be [streamed](stream)
+/// instead of buffered.
+#[derive(Default)]
+pub struct Response {
+    stanzas: Vec<Stanza>,
+    // Sent after `stanzas`, as it yields.
+    stream: Option<BoxStream<'static, Stanza>>,
 }
 
+impl Response {
+    /// A response sending nothing.
+    pub fn new() -> Self {
+        Response::default()
+    }
+
+    /// Whether nothing is sent.
+    ///
+    /// A streamed response is never considered empty, even if its stream
+    /// ends without yielding.
+    pub fn is_empty(&self) -> bool {
+        self.stanzas.is_empty() && self.stream.is_none()
+    }
+
+    /// The stanzas to send, in order, up to the streamed ones.
+    pub fn stanzas(&self) -> &[Stanza] {
+        &self.stanzas
+    }
+
+    /// Whether some stanzas are streamed.
+    pub fn is_streamed(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Send `stanza` after the others.
+    pub fn push(&mut self, stanza: Stanza) {
+        self.append(Response::from(stanza));
+    }
+
+    /// Send the stanzas of `other` after these.
+    pub fn append(&mut self, other: Response) {
+        match self.stream.take() {
+            Some(stream) => {
+                self.stream = Some(stream.chain(other.into_stream()).boxed());
+            }
+            None => {
+                self.stanzas.extend(other.stanzas);
+                self.stream = other.stream;
+            }
+        }
+    }
+
+    /// The stanzas to send, in order, as they are available.
+    pub fn into_stream(self) -> BoxStream<'static, Stanza> {
+        match self.stream {
+            Some(stream) => futures_util::stream::iter(self.stanzas)
+                .chain(stream)
+                .boxed(),
+            None => futures_util::stream::iter(self.stanzas).boxed(),
+        }
+    }
+
+    /// Apply `f` to every stanza, including streamed ones.
+    pub(crate) fn map<F>(self, mut f: F) -> Response
+    where
+        F: FnMut(Stanza) -> Stanza + Send + 'static,
+    {
+        let stanzas = self.stanzas.into_iter().map(&mut f).collect();
+        Response {
+            stanzas,
+            stream: self.stream.map(|stream| stream.map(f).boxed()),
+        }
+    }
+}
+
+impl fmt::Debug for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Response")
+            .field("stanzas", &self.stanzas)
+            .field("streamed", &self.stream.is_some())
+            .finish()
+    }
+}
+
+impl From<Stanza> for Response {
+    fn from(stanza: Stanza) -> Self {
+        Response {
+            stanzas: vec![stanza],
+            stream: None,
+        }
+    }
+}
+
+impl From<Option<Stanza>> for Response {
+    fn from(stanza: Option<Stanza>) -> Self {
+        stanza.into_iter().collect()
+    }
+}
+
+impl FromIterator<Stanza> for Response {
+    fn from_iter<I: IntoIterator<Item = Stanza>>(iter: I) -> Self {
+        Response {
+            stanzas: iter.into_iter().collect(),
+            stream: None,
+        }
+    }
+}
+
+impl Extend<Stanza> for Response {
+    fn extend<I: IntoIterator<Item = Stanza>>(&mut self, iter: I) {
+        self.append(iter.into_iter().collect());
+    }
+}
+
+/// Send the stanzas of `stream` as it yields them.
+///
+/// Long result sets, like MAM pages, roster dumps or disco item lists, are
+/// then sent incrementally instead of being buffered. The server waits for
+/// each stanza to be written before polling the stream for the next, and
+/// handles no other stanza until the stream ends.
+///
+/// # Example
+///
+/// ```ignore
+/// use futures_util::StreamExt;
+/// use wax::Filter;
+///
+/// let route = mam_query().map(move |query: Query| {
+///     let results = archive.messages(query).map(result_message);
+///     wax::reply::stream(results.chain(fin(query)))
+/// });
+/// ```
+pub fn stream<S>(stanzas: S) -> Response
+where
+    S: Stream<Item = Stanza> + Send + 'static,
+{
+    Response {
+        stanzas: Vec::new(),
+        stream: Some(stanzas.boxed()),
+    }
+}
+
+impl Reply for Response {
+    #[inline]
+    fn into_response(self) -> Response {
+        self
+    }
+}
+
+impl ReplySealed for Response {}
+
+/// Box `reply`, erasing its type.
+///
+/// Handlers with branches replying with different types can box each of
+/// them instead of nesting `Either`s.
+///
+/// # Example
+///
+/// ```ignore
+/// use wax::reply::{self, BoxedReply};
+/// use wax::Filter;
+///
+/// let route = wax::command("!status").map(|args: Vec<String>| -> BoxedReply {
+///     match args.first().map(String::as_str) {
+///         Some("all") => reply::boxed(status_of_every_room()),
+///         Some(_) => reply::boxed(wax::reply::message().body("Unknown room")),
+///         None => reply::boxed(()),
+///     }
+/// });
+/// ```
+pub fn boxed(reply: impl Reply + 'static) -> BoxedReply {
+    BoxedReply {
+        reply: Box::new(reply),
+    }
+}
+
+/// A reply of any type, see [`boxed`].
+pub struct BoxedReply {
+    reply: Box<dyn BoxedInto>,
+}
+
+impl fmt::Debug for BoxedReply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BoxedReply").finish_non_exhaustive()
+    }
+}
+
+impl Reply for BoxedReply {
+    fn into_response(self) -> Response {
+        self.reply.into_response_boxed()
+    }
+}
+
+impl ReplySealed for BoxedReply {}
+
+// `Reply::into_response` takes `self`, so boxed replies are converted
+// through this instead.
+trait BoxedInto: Send {
+    fn into_response_boxed(self: Box<Self>) -> Response;
+}
+
+impl<T: Reply> BoxedInto for T {
+    fn into_response_boxed(self: Box<Self>) -> Response {
+        (*self).into_response()
+    }
+}
+
+impl Reply for () {
+    #[inline]
+    fn into_response(self) -> Response {
+        Response::new()
+    }
+}
+
+impl ReplySealed for () {}
+
 impl<T: Reply + Send> Reply for Option<T> {
-    fn into_response(self) -> Option<Stanza> {
-        self.and_then(Reply::into_response)
+    fn into_response(self) -> Response {
+        self.map(Reply::into_response).unwrap_or_default()
+    }
+}
+
+impl<T: Reply, E: Reply> Reply for Result<T, E> {
+    fn into_response(self) -> Response {
+        match self {
+            Ok(reply) => reply.into_response(),
+            Err(reply) => reply.into_response(),
+        }
+    }
+}
+
+/// Replies with the success, or with `error` addressed back to the sender
+/// of the stanza being handled.
+impl<T: Reply> Reply for Result<T, StanzaError> {
+    fn into_response(self) -> Response {
+        match self {
+            Ok(reply) => reply.into_response(),
+            Err(error) if filtered_stanza::is_set() => {
+                filtered_stanza::with(|stanza| make_error_stanza(stanza, error)).into()
+            }
+            Err(error) => {
+                tracing::warn!("no stanza to address {:?} to, dropping it", error);
+                Response::new()
+            }
+        }
+    }
+}
+
+impl<T: Reply + Send> Reply for Vec<T> {
+    fn into_response(self) -> Response {
+        self.into_iter()
+            .fold(Response::new(), |mut response, reply| {
+                response.append(reply.into_response());
+                response
+            })
+    }
+}
+
+impl<T: Reply + Send, const N: usize> Reply for [T; N] {
+    fn into_response(self) -> Response {
+        Vec::from(self).into_response()
     }
 }
 
 impl Reply for Stanza {
-    fn into_response(self) -> Option<Stanza> {
-        Some(self)
+    fn into_response(self) -> Response {
+        Response::from(self)
     }
 }
 
 impl ReplySealed for Stanza {}
 
 impl Reply for Iq {
-    fn into_response(self) -> Option<Stanza> {
-        Some(Stanza::Iq(self))
+    fn into_response(self) -> Response {
+        Response::from(Stanza::Iq(self))
     }
 }
 
 impl ReplySealed for Iq {}
 
 impl Reply for Message {
-    fn into_response(self) -> Option<Stanza> {
-        Some(Stanza::Message(self))
+    fn into_response(self) -> Response {
+        Response::from(Stanza::Message(self))
     }
 }
 
 impl ReplySealed for Message {}
 
 impl Reply for Presence {
-    fn into_response(self) -> Option<Stanza> {
-        Some(Stanza::Presence(self))
+    fn into_response(self) -> Response {
+        Response::from(Stanza::Presence(self))
     }
 }
 
 impl ReplySealed for Presence {}
 
 impl<T: Reply + Send> Reply for One<T> {
-    fn into_response(self) -> Option<Stanza> {
+    fn into_response(self) -> Response {
         self.0.into_response()
     }
 }
@@ -72,7 +346,7 @@ where
     T: Reply,
     U: Reply,
 {
-    fn into_response(self) -> Option<Stanza> {
+    fn into_response(self) -> Response {
         match self {
             Either::A(a) => a.into_response(),
             Either::B(b) => b.into_response(),
@@ -87,11 +361,960 @@ where
 {
 }
 
+/// The result of the IQ request being handled, carrying `payload`.
+///
+/// The result is addressed back to the sender of the request, from its
+/// recipient, with its id, so handlers do not need to extract them. Call it
+/// while the request is handled, e.g. from `map` or `and_then`.
+///
+/// Returns `None`, sending nothing, if the stanza being handled is not an
+/// IQ request, or outside of a filter.
+///
+/// # Example
+///
+/// ```ignore
+/// use wax::Filter;
+///
+/// let route = wax::iq::payload::<VersionQuery>()
+///     .map(|_| wax::reply::iq_result(Version::current()));
+/// ```
+pub fn iq_result(payload: impl Into<Element>) -> Option<Iq> {
+    result_of_request(Some(payload.into()))
+}
+
+/// The empty result of the IQ request being handled, acknowledging it.
+///
+/// Addressed like [`iq_result`].
+pub fn iq_empty_result() -> Option<Iq> {
+    result_of_request(None)
+}
+
+fn result_of_request(payload: Option<Element>) -> Option<Iq> {
+    if !filtered_stanza::is_set() {
+        return None;
+    }
+    filtered_stanza::with(|stanza| match stanza {
+        Stanza::Iq(Iq::Get { from, to, id, .. } | Iq::Set { from, to, id, .. }) => {
+            Some(Iq::Result {
+                from: to.clone(),
+                to: from.clone(),
+                id: id.clone(),
+                payload,
+            })
+        }
+        _ => None,
+    })
+}
+
+/// An error answering the IQ request being handled, with `condition` and
+/// a human-readable `text`.
+///
+/// Unlike a [rejection](crate::reject), which lets other routes try the
+/// request, this is the reply of the route. The error is addressed like
+/// [`iq_result`], echoes the payload of the request, and has the type RFC
+/// 6120 recommends for `condition`.
+///
+/// Returns `None` if the stanza being handled is not an IQ request, or
+/// outside of a filter.
+///
+/// # Example
+///
+/// ```ignore
+/// use wax::reject::DefinedCondition;
+/// use wax::Filter;
+///
+/// let route = wax::iq::payload::<Register>().map(|_| {
+///     wax::reply::iq_error(DefinedCondition::NotAllowed, "Registration is closed")
+/// });
+/// ```
+pub fn iq_error(condition: DefinedCondition, text: impl Into<String>) -> Option<Iq> {
+    if !filtered_stanza::is_set() {
+        return None;
+    }
+    let error = StanzaError::new(error_type_of(&condition), condition, "en", text);
+    filtered_stanza::with(|stanza| match stanza {
+        Stanza::Iq(
+            Iq::Get {
+                from,
+                to,
+                id,
+                payload,
+            }
+            | Iq::Set {
+                from,
+                to,
+                id,
+                payload,
+            },
+        ) => Some(Iq::Error {
+            from: to.clone(),
+            to: from.clone(),
+            id: id.clone(),
+            error,
+            payload: Some(payload.clone()),
+        }),
+        _ => None,
+    })
+}
+
+fn error_type_of(condition: &DefinedCondition) -> ErrorType {
+    match condition {
+        DefinedCondition::Forbidden
+        | DefinedCondition::NotAuthorized
+        | DefinedCondition::RegistrationRequired
+        | DefinedCondition::SubscriptionRequired => ErrorType::Auth,
+        DefinedCondition::BadRequest
+        | DefinedCondition::JidMalformed
+        | DefinedCondition::NotAcceptable
+        | DefinedCondition::PolicyViolation
+        | DefinedCondition::Redirect { .. } => ErrorType::Modify,
+        DefinedCondition::RecipientUnavailable
+        | DefinedCondition::RemoteServerTimeout
+        | DefinedCondition::ResourceConstraint
+        | DefinedCondition::ServiceUnavailable => ErrorType::Wait,
+        _ => ErrorType::Cancel,
+    }
+}
+
+/// A message replying to the stanza being handled.
+///
+/// The message is addressed back to the sender, from the recipient, so
+/// handlers only fill in its content. Call it while the stanza is handled,
+/// e.g. from `map` or `and_then`; outside of a filter the message is left
+/// unaddressed.
+///
+/// # Example
+///
+/// ```ignore
+/// use wax::Filter;
+///
+/// let route = wax::message::body::param().map(|body: String| {
+///     wax::reply::message()
+///         .body(body.to_uppercase())
+///         .thread("shouting")
+/// });
+/// ```
+pub fn message() -> MessageReply {
+    let (to, from) = if filtered_stanza::is_set() {
+        filtered_stanza::with(|stanza| (from_of(stanza).cloned(), to_of(stanza).cloned()))
+    } else {
+        (None, None)
+    };
+    let mut msg = Message::new(to);
+    msg.from = from;
+    MessageReply { msg }
+}
+
+/// A message reply being built, see [`message`].
+#[derive(Clone, Debug)]
+pub struct MessageReply {
+    msg: Message,
+}
+
+impl MessageReply {
+    /// Set the body, without a language.
+    pub fn body(mut self, body: impl Into<String>) -> Self {
+        self.msg.bodies.insert(Lang::default(), Body(body.into()));
+        self
+    }
+
+    /// Set the subject, without a language.
+    pub fn subject(mut self, subject: impl Into<String>) -> Self {
+        self.msg
+            .subjects
+            .insert(Lang::default(), Subject(subject.into()));
+        self
+    }
+
+    /// Set the thread.
+    pub fn thread(mut self, thread: impl Into<String>) -> Self {
+        self.msg.thread = Some(Thread(thread.into()));
+        self
+    }
+
+    /// Set the type.
+    pub fn type_(mut self, type_: MessageType) -> Self {
+        self.msg.type_ = type_;
+        self
+    }
+
+    /// Add a payload after the others.
+    pub fn payload(mut self, payload: impl Into<Element>) -> Self {
+        self.msg.payloads.push(payload.into());
+        self
+    }
+}
+
+impl From<MessageReply> for Message {
+    fn from(reply: MessageReply) -> Message {
+        reply.msg
+    }
+}
+
+impl Reply for MessageReply {
+    fn into_response(self) -> Response {
+        Message::from(self).into_response()
+    }
+}
+
+impl ReplySealed for MessageReply {}
+
+/// A message to `to` forwarding `stanza` (XEP-0297), stamped as sent at
+/// `sent`.
+///
+/// The message is from the recipient of the stanza being handled, if any.
+/// Carbons and MAM results wrap the `<forwarded/>` element in one of their
+/// own; build those from a [`Forwarded`](crate::forwarded::Forwarded)
+/// instead.
+///
+/// # Example
+///
+/// ```ignore
+/// use wax::{Filter, Stanza};
+///
+/// let route = flagged().and(wax::stanza()).map(move |stanza: Arc<Stanza>| {
+///     wax::reply::forwarded((*stanza).clone(), moderator.clone(), received_at())
+/// });
+/// ```
+pub fn forwarded(stanza: Stanza, to: Jid, sent: DateTime) -> Message {
+    let forwarded = Forwarded::new(stanza).delay(Delay::new(sent));
+    let mut msg = Message::new(Some(to));
+    msg.from = if filtered_stanza::is_set() {
+        filtered_stanza::with(|stanza| to_of(stanza).cloned())
+    } else {
+        None
+    };
+    msg.id = Some(Id(correlation::unique_id()));
+    msg.payloads.push(Element::from(forwarded));
+    msg
+}
+
+pub mod with {
+    //! Wrappers adjusting the replies of a filter, applied with
+    //! [`Filter::with`](crate::Filter::with).
+
+    use std::fmt;
+
+    use tokio_xmpp::Stanza;
+    use xmpp_parsers::date::DateTime;
+    use xmpp_parsers::jid::Jid;
+    use xmpp_parsers::minidom::Element;
+
+    use super::internal::{Transform, WithTransform};
+    use super::Reply;
+    use crate::correlation;
+    use crate::delay::Delay;
+    use crate::filter::{Filter, WrapSealed};
+    use crate::hints::{self, Hint};
+    use crate::nick;
+    use crate::reject::IsReject;
+    use crate::shim;
+    use crate::stanza_id::{self, StanzaId};
+
+    /// Stamp message and presence replies with a `<delay/>` since `stamp`
+    /// (XEP-0203).
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use wax::Filter;
+    ///
+    /// let route = history_message()
+    ///     .with(wax::reply::with::delay(sent_at));
+    /// ```
+    pub fn delay(stamp: DateTime) -> WithDelay {
+        WithDelay {
+            delay: Delay::new(stamp),
+        }
+    }
+
+    /// Stamps replies with a `<delay/>`.
+    #[derive(Clone, Debug)]
+    pub struct WithDelay {
+        delay: Delay,
+    }
+
+    impl<F> WrapSealed<F> for WithDelay
+    where
+        F: Filter + Clone + Send,
+        F::Extract: Reply,
+        F::Error: IsReject,
+    {
+        type Wrapped = WithTransform<WithDelay, F>;
+
+        fn wrap(&self, filter: F) -> Self::Wrapped {
+            WithTransform::new(self.clone(), filter)
+        }
+    }
+
+    impl Transform for WithDelay {
+        fn apply(&self, mut stanza: Stanza) -> Stanza {
+            let delay = Element::from(self.delay.clone());
+            match stanza {
+                Stanza::Message(ref mut msg) => msg.payloads.push(delay),
+                Stanza::Presence(ref mut pres) => pres.payloads.push(delay),
+                Stanza::Iq(_) => {}
+            }
+            stanza
+        }
+    }
+
+    /// Stamp message replies with a `<stanza-id/>` assigned by `by`
+    /// (XEP-0359), replacing any other claiming to be assigned by it.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use wax::Filter;
+    ///
+    /// let route = relay.with(wax::reply::with::stanza_id(component_jid));
+    /// ```
+    pub fn stanza_id(by: Jid) -> WithStanzaId {
+        WithStanzaId { by }
+    }
+
+    /// Stamps message replies with a `<stanza-id/>`.
+    #[derive(Clone, Debug)]
+    pub struct WithStanzaId {
+        by: Jid,
+    }
+
+    impl<F> WrapSealed<F> for WithStanzaId
+    where
+        F: Filter + Clone + Send,
+        F::Extract: Reply,
+        F::Error: IsReject,
+    {
+        type Wrapped = WithTransform<WithStanzaId, F>;
+
+        fn wrap(&self, filter: F) -> Self::Wrapped {
+            WithTransform::new(self.clone(), filter)
+        }
+    }
+
+    impl Transform for WithStanzaId {
+        fn apply(&self, mut stanza: Stanza) -> Stanza {
+            if let Stanza::Message(ref mut msg) = stanza {
+                let by = self.by.to_string();
+                msg.payloads.retain(|payload| {
+                    !(payload.is("stanza-id", stanza_id::NS) && payload.attr("by") == Some(&*by))
+                });
+                let id = StanzaId::new(correlation::unique_id(), self.by.clone());
+                msg.payloads.push(Element::from(id));
+            }
+            stanza
+        }
+    }
+
+    /// Stamp message replies without one with an `<origin-id/>` (XEP-0359).
+    pub fn origin_id() -> WithOriginId {
+        WithOriginId { _p: () }
+    }
+
+    /// Stamps message replies with an `<origin-id/>`.
+    #[derive(Clone, Debug)]
+    pub struct WithOriginId {
+        _p: (),
+    }
+
+    impl<F> WrapSealed<F> for WithOriginId
+    where
+        F: Filter + Clone + Send,
+        F::Extract: Reply,
+        F::Error: IsReject,
+    {
+        type Wrapped = WithTransform<WithOriginId, F>;
+
+        fn wrap(&self, filter: F) -> Self::Wrapped {
+            WithTransform::new(self.clone(), filter)
+        }
+    }
+
+    impl Transform for WithOriginId {
+        fn apply(&self, mut stanza: Stanza) -> Stanza {
+            if let Stanza::Message(ref mut msg) = stanza {
+                if stanza_id::origin_id(msg).is_none() {
+                    msg.payloads.push(
+                        Element::builder("origin-id", stanza_id::NS)
+                            .attr("id", correlation::unique_id())
+                            .build(),
+                    );
+                }
+            }
+            stanza
+        }
+    }
+
+    /// Stamp message replies with `hint` (XEP-0334), unless they already
+    /// carry it.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use wax::hints::Hint;
+    /// use wax::Filter;
+    ///
+    /// let route = typing_notifications.with(wax::reply::with::hint(Hint::NoStore));
+    /// ```
+    pub fn hint(hint: Hint) -> WithHint {
+        WithHint { hint }
+    }
+
+    /// Stamps message replies with a processing hint.
+    #[derive(Clone, Copy, Debug)]
+    pub struct WithHint {
+        hint: Hint,
+    }
+
+    impl<F> WrapSealed<F> for WithHint
+    where
+        F: Filter + Clone + Send,
+        F::Extract: Reply,
+        F::Error: IsReject,
+    {
+        type Wrapped = WithTransform<WithHint, F>;
+
+        fn wrap(&self, filter: F) -> Self::Wrapped {
+            WithTransform::new(*self, filter)
+        }
+    }
+
+    impl Transform for WithHint {
+        fn apply(&self, mut stanza: Stanza) -> Stanza {
+            if let Stanza::Message(ref mut msg) = stanza {
+                let hint = Element::from(self.hint);
+                if !msg
+                    .payloads
+                    .iter()
+                    .any(|payload| payload.is(hint.name(), hints::NS))
+                {
+                    msg.payloads.push(hint);
+                }
+            }
+            stanza
+        }
+    }
+
+    /// Attach `nick` to message and presence replies (XEP-0172), replacing
+    /// any nickname they already carry.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use wax::Filter;
+    ///
+    /// let route = subscribe_contact.with(wax::reply::with::nick("Tybalt"));
+    /// ```
+    pub fn nick(nick: impl Into<String>) -> WithNick {
+        WithNick {
+            nick: nick::element(nick),
+        }
+    }
+
+    /// Attaches a nickname to replies.
+    #[derive(Clone, Debug)]
+    pub struct WithNick {
+        nick: Element,
+    }
+
+    impl<F> WrapSealed<F> for WithNick
+    where
+        F: Filter + Clone + Send,
+        F::Extract: Reply,
+        F::Error: IsReject,
+    {
+        type Wrapped = WithTransform<WithNick, F>;
+
+        fn wrap(&self, filter: F) -> Self::Wrapped {
+            WithTransform::new(self.clone(), filter)
+        }
+    }
+
+    impl Transform for WithNick {
+        fn apply(&self, mut stanza: Stanza) -> Stanza {
+            let payloads = match stanza {
+                Stanza::Message(ref mut msg) => &mut msg.payloads,
+                Stanza::Presence(ref mut pres) => &mut pres.payloads,
+                Stanza::Iq(_) => return stanza,
+            };
+            payloads.retain(|payload| !payload.is("nick", nick::NS));
+            payloads.push(self.nick.clone());
+            stanza
+        }
+    }
+
+    /// Attach `headers` to message and presence replies (XEP-0131), after
+    /// any they already carry.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use wax::Filter;
+    ///
+    /// let route = triage.with(wax::reply::with::headers([("Workflow", "triage")]));
+    /// ```
+    pub fn headers<N, V>(headers: impl IntoIterator<Item = (N, V)>) -> WithHeaders
+    where
+        N: Into<String>,
+        V: Into<String>,
+    {
+        WithHeaders {
+            headers: shim::element(headers),
+        }
+    }
+
+    /// Attaches SHIM headers to replies.
+    #[derive(Clone, Debug)]
+    pub struct WithHeaders {
+        headers: Element,
+    }
+
+    impl<F> WrapSealed<F> for WithHeaders
+    where
+        F: Filter + Clone + Send,
+        F::Extract: Reply,
+        F::Error: IsReject,
+    {
+        type Wrapped = WithTransform<WithHeaders, F>;
+
+        fn wrap(&self, filter: F) -> Self::Wrapped {
+            WithTransform::new(self.clone(), filter)
+        }
+    }
+
+    impl Transform for WithHeaders {
+        fn apply(&self, mut stanza: Stanza) -> Stanza {
+            let payloads = match stanza {
+                Stanza::Message(ref mut msg) => &mut msg.payloads,
+                Stanza::Presence(ref mut pres) => &mut pres.payloads,
+                Stanza::Iq(_) => return stanza,
+            };
+            match payloads
+                .iter_mut()
+                .find(|payload| payload.is("headers", shim::NS))
+            {
+                Some(existing) => {
+                    for header in self.headers.children() {
+                        existing.append_child(header.clone());
+                    }
+                }
+                None => payloads.push(self.headers.clone()),
+            }
+            stanza
+        }
+    }
+
+    /// Attach `payload` to message and presence replies, after the payloads
+    /// they already carry.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use wax::Filter;
+    ///
+    /// let route = bot.with(wax::reply::with::payload(branding()));
+    /// ```
+    pub fn payload(payload: impl Into<Element>) -> WithPayload {
+        WithPayload {
+            payload: payload.into(),
+        }
+    }
+
+    /// Attaches a payload to replies.
+    #[derive(Clone, Debug)]
+    pub struct WithPayload {
+        payload: Element,
+    }
+
+    impl<F> WrapSealed<F> for WithPayload
+    where
+        F: Filter + Clone + Send,
+        F::Extract: Reply,
+        F::Error: IsReject,
+    {
+        type Wrapped = WithTransform<WithPayload, F>;
+
+        fn wrap(&self, filter: F) -> Self::Wrapped {
+            WithTransform::new(self.clone(), filter)
+        }
+    }
+
+    impl Transform for WithPayload {
+        fn apply(&self, mut stanza: Stanza) -> Stanza {
+            match stanza {
+                Stanza::Message(ref mut msg) => msg.payloads.push(self.payload.clone()),
+                Stanza::Presence(ref mut pres) => pres.payloads.push(self.payload.clone()),
+                Stanza::Iq(_) => {}
+            }
+            stanza
+        }
+    }
+
+    /// Change every reply with `func`.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use wax::{Filter, Stanza};
+    ///
+    /// let route = bot.with(wax::reply::with::map(|mut stanza: Stanza| {
+    ///     if let Stanza::Message(ref mut msg) = stanza {
+    ///         msg.type_ = MessageType::Chat;
+    ///     }
+    ///     stanza
+    /// }));
+    /// ```
+    pub fn map<FN>(func: FN) -> WithMap<FN>
+    where
+        FN: Fn(Stanza) -> Stanza + Clone + Send + 'static,
+    {
+        WithMap { func }
+    }
+
+    /// Changes replies with a function.
+    #[derive(Clone, Copy)]
+    pub struct WithMap<FN> {
+        func: FN,
+    }
+
+    impl<FN> fmt::Debug for WithMap<FN> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("WithMap").finish_non_exhaustive()
+        }
+    }
+
+    impl<FN, F> WrapSealed<F> for WithMap<FN>
+    where
+        FN: Fn(Stanza) -> Stanza + Clone + Send + 'static,
+        F: Filter + Clone + Send,
+        F::Extract: Reply,
+        F::Error: IsReject,
+    {
+        type Wrapped = WithTransform<WithMap<FN>, F>;
+
+        fn wrap(&self, filter: F) -> Self::Wrapped {
+            WithTransform::new(self.clone(), filter)
+        }
+    }
+
+    impl<FN> Transform for WithMap<FN>
+    where
+        FN: Fn(Stanza) -> Stanza + Clone + Send + 'static,
+    {
+        fn apply(&self, stanza: Stanza) -> Stanza {
+            (self.func)(stanza)
+        }
+    }
+}
+
+pub(crate) mod internal {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use futures_util::{ready, TryFuture};
+    use pin_project::pin_project;
+    use tokio_xmpp::Stanza;
+
+    use super::{Reply, Response};
+    use crate::disco::Features;
+    use crate::filter::{Filter, FilterBase, Internal};
+    use crate::reject::IsReject;
+
+    /// A change applied to every reply of a filter.
+    pub trait Transform: Clone + Send + 'static {
+        fn apply(&self, stanza: Stanza) -> Stanza;
+    }
+
+    #[allow(missing_debug_implementations)]
+    pub struct Transformed(Response);
+
+    impl Reply for Transformed {
+        #[inline]
+        fn into_response(self) -> Response {
+            self.0
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    #[derive(Clone, Copy)]
+    pub struct WithTransform<T, F> {
+        transform: T,
+        filter: F,
+    }
+
+    impl<T, F> WithTransform<T, F> {
+        pub(super) fn new(transform: T, filter: F) -> Self {
+            WithTransform { transform, filter }
+        }
+    }
+
+    impl<T, F> FilterBase for WithTransform<T, F>
+    where
+        T: Transform,
+        F: Filter + Clone + Send,
+        F::Extract: Reply,
+        F::Error: IsReject,
+    {
+        type Extract = (Transformed,);
+        type Error = F::Error;
+        type Future = WithTransformFuture<T, F::Future>;
+
+        fn filter(&self, _: Internal) -> Self::Future {
+            WithTransformFuture {
+                transform: self.transform.clone(),
+                future: self.filter.filter(Internal),
+            }
+        }
+
+        fn advertise(&self, features: &mut Features) {
+            self.filter.advertise(features);
+        }
+    }
+
+    #[allow(missing_debug_implementations)]
+    #[pin_project]
+    pub struct WithTransformFuture<T, F> {
+        transform: T,
+        #[pin]
+        future: F,
+    }
+
+    impl<T, F> Future for WithTransformFuture<T, F>
+    where
+        T: Transform,
+        F: TryFuture,
+        F::Ok: Reply,
+        F::Error: IsReject,
+    {
+        type Output = Result<(Transformed,), F::Error>;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            let pin = self.project();
+            let reply = ready!(pin.future.try_poll(cx))?.into_response();
+            let transform = pin.transform.clone();
+            Poll::Ready(Ok((Transformed(
+                reply.map(move |stanza| transform.apply(stanza)),
+            ),)))
+        }
+    }
+}
+
 mod sealed {
     pub trait ReplySealed {}
 
     impl<T: ReplySealed + Send> ReplySealed for Option<T> {}
+    impl<T: ReplySealed + Send> ReplySealed for Vec<T> {}
+    impl<T: ReplySealed + Send, E: ReplySealed + Send> ReplySealed for Result<T, E> {}
+    impl<T: ReplySealed + Send> ReplySealed for Result<T, super::StanzaError> {}
+    impl<T: ReplySealed + Send, const N: usize> ReplySealed for [T; N] {}
     impl ReplySealed for crate::filters::log::internal::Logged {}
+    impl ReplySealed for crate::filters::receipts::internal::Acked {}
+    impl ReplySealed for super::internal::Transformed {}
 }
 
 pub(crate) use self::sealed::ReplySealed;
+
+#[cfg(test)]
+mod tests {
+    use futures_util::{stream, TryFuture};
+    use xmpp_parsers::jid::Jid;
+
+    use super::*;
+    use crate::reject::IsReject;
+    use crate::relay::Relay;
+    use crate::Filter;
+
+    const JULIET: &str = "juliet@capulet.lit/balcony";
+    const SMS: &str = "sms.example.org";
+
+    fn jid(jid: &str) -> Jid {
+        Jid::new(jid).unwrap()
+    }
+
+    fn ping() -> Stanza {
+        Stanza::Iq(Iq::Get {
+            from: Some(jid(JULIET)),
+            to: Some(jid(SMS)),
+            id: "ping".to_owned(),
+            payload: Element::builder("ping", "urn:xmpp:ping").build(),
+        })
+    }
+
+    fn chat() -> Stanza {
+        let mut msg = Message::new(Some(jid(SMS)));
+        msg.from = Some(jid(JULIET));
+        msg.id = Some(Id("chat".to_owned()));
+        Stanza::Message(msg)
+    }
+
+    async fn respond<F>(filter: F, stanza: Stanza) -> Vec<Stanza>
+    where
+        F: Filter,
+        <F::Future as TryFuture>::Ok: Reply,
+        <F::Future as TryFuture>::Error: IsReject,
+    {
+        let response = crate::service(filter).call_stanza(stanza).await.unwrap();
+        response.into_stream().collect().await
+    }
+
+    fn kind(stanza: &Stanza) -> &'static str {
+        match stanza {
+            Stanza::Iq(_) => "iq",
+            Stanza::Message(_) => "message",
+            Stanza::Presence(_) => "presence",
+        }
+    }
+
+    fn body(stanza: &Stanza) -> Option<&str> {
+        match stanza {
+            Stanza::Message(msg) => msg.bodies.values().next().map(|body| body.0.as_str()),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn addresses_iq_results_and_errors() {
+        let replies = respond(crate::any().map(iq_empty_result), ping()).await;
+        let [Stanza::Iq(Iq::Result {
+            from,
+            to,
+            id,
+            payload: None,
+        })] = &replies[..]
+        else {
+            panic!("expected an empty result, got {:?}", replies);
+        };
+        assert_eq!((from, to), (&Some(jid(SMS)), &Some(jid(JULIET))));
+        assert_eq!(id, "ping");
+
+        let error = || iq_error(DefinedCondition::NotAllowed, "Pings are closed");
+        let replies = respond(crate::any().map(error), ping()).await;
+        let [Stanza::Iq(Iq::Error {
+            to, error, payload, ..
+        })] = &replies[..]
+        else {
+            panic!("expected an error, got {:?}", replies);
+        };
+        assert_eq!(to, &Some(jid(JULIET)));
+        assert_eq!(error.type_, ErrorType::Cancel);
+        assert_eq!(error.defined_condition, DefinedCondition::NotAllowed);
+        assert!(payload.as_ref().unwrap().is("ping", "urn:xmpp:ping"));
+
+        let replies = respond(crate::any().map(iq_empty_result), chat()).await;
+        assert!(replies.is_empty(), "answered a message: {:?}", replies);
+    }
+
+    #[tokio::test]
+    async fn sends_every_reply_in_order() {
+        let replies = || [message().body("one"), message().body("two")];
+        let sent = respond(crate::any().map(replies), chat()).await;
+        assert_eq!(
+            sent.iter().map(body).collect::<Vec<_>>(),
+            [Some("one"), Some("two")]
+        );
+        let Stanza::Message(first) = &sent[0] else {
+            panic!("expected a message");
+        };
+        assert_eq!(first.to, Some(jid(JULIET)));
+        assert_eq!(first.from, Some(jid(SMS)));
+
+        let nothing = respond(crate::any().map(|| vec![(), ()]), chat()).await;
+        assert!(nothing.is_empty());
+    }
+
+    #[tokio::test]
+    async fn streams_after_buffered_stanzas() {
+        let streamed = stream::iter([chat(), ping()]);
+        let mut response = Response::from(ping());
+        response.append(super::stream(streamed));
+        response.push(chat());
+        assert_eq!(
+            response.stanzas().iter().map(kind).collect::<Vec<_>>(),
+            ["iq"]
+        );
+        assert!(response.is_streamed());
+        assert!(!response.is_empty());
+
+        let sent: Vec<_> = response.into_stream().collect().await;
+        assert_eq!(
+            sent.iter().map(kind).collect::<Vec<_>>(),
+            ["iq", "message", "iq", "message"]
+        );
+    }
+
+    #[tokio::test]
+    async fn addresses_stanza_errors() {
+        let refuse = || {
+            Err::<Message, _>(StanzaError::new(
+                ErrorType::Auth,
+                DefinedCondition::Forbidden,
+                "en",
+                "Not you",
+            ))
+        };
+        let replies = respond(crate::any().map(refuse), chat()).await;
+        let [Stanza::Message(error)] = &replies[..] else {
+            panic!("expected an error, got {:?}", replies);
+        };
+        assert_eq!(error.type_, MessageType::Error);
+        assert_eq!(error.to, Some(jid(JULIET)));
+        assert_eq!(error.id, Some(Id("chat".to_owned())));
+    }
+
+    #[tokio::test]
+    async fn addresses_stanza_errors_while_relaying() {
+        let romeo = jid("romeo@montague.lit/garden");
+        let route = crate::relay::param().map(move |relay: Relay| {
+            vec![
+                Ok(relay.to(romeo.clone())),
+                Err(StanzaError::new(
+                    ErrorType::Wait,
+                    DefinedCondition::RecipientUnavailable,
+                    "en",
+                    "Only relayed once",
+                )),
+            ]
+        });
+        let replies = respond(route, chat()).await;
+        let [Stanza::Message(relayed), Stanza::Message(error)] = &replies[..] else {
+            panic!(
+                "expected the relayed message and an error, got {:?}",
+                replies
+            );
+        };
+        assert_eq!(relayed.to, Some(jid("romeo@montague.lit/garden")));
+        assert_eq!(relayed.from, Some(jid(JULIET)));
+        assert_eq!(error.type_, MessageType::Error);
+        assert_eq!(error.to, Some(jid(JULIET)));
+        assert_eq!(error.from, Some(jid(SMS)));
+    }
+
+    #[tokio::test]
+    async fn boxes_replies_of_any_type() {
+        let route = crate::id::param().map(|id: String| -> BoxedReply {
+            match id.as_str() {
+                "ping" => boxed(iq_empty_result()),
+                _ => boxed(message().body("hi")),
+            }
+        });
+        let replies = respond(route.clone(), ping()).await;
+        assert!(matches!(replies[..], [Stanza::Iq(Iq::Result { .. })]));
+        let replies = respond(route, chat()).await;
+        assert_eq!(replies.iter().map(body).collect::<Vec<_>>(), [Some("hi")]);
+    }
+
+    #[tokio::test]
+    async fn forwards_from_the_recipient() {
+        let sent: DateTime = "2002-09-10T23:08:25Z".parse().unwrap();
+        let moderator = jid("nurse@capulet.lit");
+        let forward = move || forwarded(chat(), moderator.clone(), sent.clone());
+        let replies = respond(crate::any().map(forward), ping()).await;
+        let [Stanza::Message(msg)] = &replies[..] else {
+            panic!("expected a message, got {:?}", replies);
+        };
+        assert_eq!(msg.to, Some(jid("nurse@capulet.lit")));
+        assert_eq!(msg.from, Some(jid(SMS)));
+        assert!(msg.payloads[0].is("forwarded", "urn:xmpp:forward:0"));
+    }
+}
@@ -0,0 +1,158 @@
+//! Serde support for stanzas.
+//!
+//! Available with the `wax-serde` feature.
+//!
+//! Stanzas are serialized as their XML text, which keeps spools and test
+//! fixtures readable and round-trips every payload, known or not. Use
+//! [`XmlStanza`] as a standalone value, or the [`stanza`] module with
+//! `#[serde(with = "...")]` on a `Stanza` field:
+//!
+//! ```ignore
+//! use serde::{Deserialize, Serialize};
+//! use wax::Stanza;
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Spooled {
+//!     attempts: u32,
+//!     #[serde(with = "wax::serialize::stanza")]
+//!     stanza: Stanza,
+//! }
+//! ```
+//!
+//! Rejections serialize through [`Rejection::summary`](crate::Rejection::summary).
+
+use std::ops::{Deref, DerefMut};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tokio_xmpp::Stanza;
+use xmpp_parsers::iq::Iq;
+use xmpp_parsers::message::Message;
+use xmpp_parsers::minidom::Element;
+use xmpp_parsers::presence::Presence;
+
+/// A [`Stanza`] that serializes as its XML text.
+#[derive(Clone, Debug)]
+pub struct XmlStanza(pub Stanza);
+
+impl XmlStanza {
+    /// Unwrap the stanza.
+    pub fn into_inner(self) -> Stanza {
+        self.0
+    }
+}
+
+impl From<Stanza> for XmlStanza {
+    fn from(stanza: Stanza) -> Self {
+        XmlStanza(stanza)
+    }
+}
+
+impl From<XmlStanza> for Stanza {
+    fn from(stanza: XmlStanza) -> Self {
+        stanza.0
+    }
+}
+
+impl Deref for XmlStanza {
+    type Target = Stanza;
+
+    fn deref(&self) -> &Stanza {
+        &self.0
+    }
+}
+
+impl DerefMut for XmlStanza {
+    fn deref_mut(&mut self) -> &mut Stanza {
+        &mut self.0
+    }
+}
+
+impl Serialize for XmlStanza {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        stanza::serialize(&self.0, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for XmlStanza {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        stanza::deserialize(deserializer).map(XmlStanza)
+    }
+}
+
+/// Render a stanza as XML text.
+pub fn to_xml(stanza: &Stanza) -> Result<String, crate::Error> {
+    let elem = match stanza.clone() {
+        Stanza::Iq(iq) => Element::from(iq),
+        Stanza::Message(msg) => Element::from(msg),
+        Stanza::Presence(presence) => Element::from(presence),
+    };
+    let mut xml = Vec::new();
+    elem.write_to(&mut xml).map_err(crate::Error::new)?;
+    String::from_utf8(xml).map_err(crate::Error::new)
+}
+
+/// Parse a stanza from XML text.
+pub fn from_xml(xml: &str) -> Result<Stanza, crate::Error> {
+    let elem: Element = xml.parse().map_err(crate::Error::new)?;
+    match elem.name() {
+        "iq" => Iq::try_from(elem)
+            .map(Stanza::Iq)
+            .map_err(crate::Error::new),
+        "message" => Message::try_from(elem)
+            .map(Stanza::Message)
+            .map_err(crate::Error::new),
+        "presence" => Presence::try_from(elem)
+            .map(Stanza::Presence)
+            .map_err(crate::Error::new),
+        other => Err(crate::Error::new(format!("not a stanza: <{}/>", other))),
+    }
+}
+
+/// Serialize and deserialize a [`Stanza`] field as XML text.
+///
+/// For use with `#[serde(with = "wax::serialize::stanza")]`.
+pub mod stanza {
+    use serde::de::Error as _;
+    use serde::ser::Error as _;
+    use serde::{Deserialize, Deserializer, Serializer};
+    use tokio_xmpp::Stanza;
+
+    /// Serialize a stanza as XML text.
+    pub fn serialize<S: Serializer>(stanza: &Stanza, serializer: S) -> Result<S::Ok, S::Error> {
+        let xml = super::to_xml(stanza).map_err(S::Error::custom)?;
+        serializer.serialize_str(&xml)
+    }
+
+    /// Deserialize a stanza from XML text.
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Stanza, D::Error> {
+        let xml = String::deserialize(deserializer)?;
+        super::from_xml(&xml).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xmpp_parsers::jid::Jid;
+    use xmpp_parsers::message::Lang;
+
+    #[test]
+    fn round_trip() {
+        let msg = Message::new(Some(Jid::new("juliet@capulet.lit").unwrap()))
+            .with_body(Lang::default(), "wherefore".to_owned());
+
+        let json = serde_json::to_string(&XmlStanza(Stanza::Message(msg.clone()))).unwrap();
+        let back: XmlStanza = serde_json::from_str(&json).unwrap();
+
+        match back.into_inner() {
+            Stanza::Message(back) => {
+                assert_eq!(back.to, msg.to);
+                assert_eq!(
+                    back.get_best_body_cloned(vec![]),
+                    msg.get_best_body_cloned(vec![])
+                );
+            }
+            other => panic!("expected a message, got {:?}", other),
+        }
+    }
+}
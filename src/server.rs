@@ -1,27 +1,40 @@
-#[cfg(feature = "tls")]
-use std::path::Path;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
 
+use std::task::{Context, Poll};
+
+use futures_util::future::{self, BoxFuture, FutureExt, LocalBoxFuture};
 use futures_util::TryFuture;
-use tokio_xmpp::connect::TcpServerConnector;
-use tokio_xmpp::{self, Component};
+use tokio_xmpp::connect::{ServerConnector, TcpServerConnector};
+use tokio_xmpp::{self, Component, Stanza};
+use tower_layer::Layer;
+use tower_service::Service;
+use xmpp_parsers::jid::Jid;
 
 use crate::correlation;
 use crate::filter::Filter;
 use crate::reject::IsReject;
-use crate::reply::Reply;
+use crate::reply::{Reply, Response};
 
 /// A trait for types that can serve XMPP stanzas using a filter chain.
 pub trait ServeComponent: Sized {
+    /// The connection of this component.
+    type Connector: ServerConnector;
+
     /// Start serving stanzas using the provided filter.
-    fn serve<F>(self, filter: F) -> Server<F, run::Standard>
+    fn serve<F>(self, filter: F) -> Server<F, run::Standard, Self::Connector>
     where
         F: Filter + Clone + Send + Sync + 'static,
         F::Extract: Reply,
         F::Error: IsReject;
 }
 
-impl ServeComponent for Component<TcpServerConnector> {
-    fn serve<F>(self, filter: F) -> Server<F, run::Standard>
+impl<C: ServerConnector> ServeComponent for Component<C> {
+    type Connector = C;
+
+    fn serve<F>(self, filter: F) -> Server<F, run::Standard, C>
     where
         F: Filter + Clone + Send + Sync + 'static,
         F::Extract: Reply,
@@ -31,11 +44,23 @@ impl ServeComponent for Component<TcpServerConnector> {
             filter,
             component: self,
             runner: run::Standard,
+            reconnect: None,
+            outbound_capacity: None,
+            outbound_batch: DEFAULT_OUTBOUND_BATCH,
+            layered: None,
+            disco: None,
+            last_activity: None,
+            fill_from: false,
+            concurrency: None,
+            overflow: Overflow::default(),
+            shard_by_sender: false,
+            #[cfg(feature = "http-ingress")]
+            ingress: None,
         }
     }
 }
 
-impl<F, R> std::fmt::Debug for Server<F, R> {
+impl<F, R, C: ServerConnector> std::fmt::Debug for Server<F, R, C> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_fmt(format_args!("Jid: {}", self.component.jid))
     }
@@ -51,68 +76,571 @@ impl<F, R> std::fmt::Debug for Server<F, R> {
 ///
 /// It is not otherwise nameable, since it is a builder type using typestate
 /// to allow for ergonomic configuration.
-pub struct Server<F, R> {
-    component: Component<TcpServerConnector>,
+pub struct Server<F, R, C: ServerConnector = TcpServerConnector> {
+    component: Component<C>,
     filter: F,
     runner: R,
+    reconnect: Option<Reconnect<C>>,
+    outbound_capacity: Option<usize>,
+    outbound_batch: usize,
+    layered: Option<StanzaService>,
+    disco: Option<crate::disco::Identity>,
+    last_activity: Option<crate::last::LastActivity>,
+    fill_from: bool,
+    concurrency: Option<usize>,
+    overflow: Overflow,
+    shard_by_sender: bool,
+    #[cfg(feature = "http-ingress")]
+    ingress: Option<crate::ingress::Ingress>,
+}
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+const DEFAULT_OUTBOUND_BATCH: usize = 64;
+
+const DEFAULT_CONCURRENCY: usize = 1024;
+
+/// The type-erased stanza service that layers are applied to.
+///
+/// Its error type is boxed, so middleware errors of any type (timeouts,
+/// overload) can be surfaced by the run loop.
+pub type StanzaService = Box<
+    dyn Service<
+            Stanza,
+            Response = Response,
+            Error = BoxError,
+            Future = BoxFuture<'static, Result<Response, BoxError>>,
+        > + Send,
+>;
+
+struct Boxed<S>(S);
+
+impl<S> Service<Stanza> for Boxed<S>
+where
+    S: Service<Stanza, Response = Response>,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Response, BoxError>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, stanza: Stanza) -> Self::Future {
+        let future = self.0.call(stanza);
+        Box::pin(async move { future.await.map_err(Into::into) })
+    }
 }
 
-impl<F, R> Server<F, R>
+fn boxed<S>(service: S) -> StanzaService
+where
+    S: Service<Stanza, Response = Response> + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+{
+    Box::new(Boxed(service))
+}
+
+/// What a concurrent [`Server`] does with stanzas arriving while it handles
+/// as many as it may at once.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Overflow {
+    /// Stop reading stanzas until one finishes, leaving the others waiting
+    /// in the stream.
+    #[default]
+    Queue,
+    /// Answer them with a `wait`-type `resource-constraint` error. IQ
+    /// responses, errors, and messages or presences without an id are
+    /// dropped instead, as they must not be answered.
+    Reject,
+}
+
+/// How a [`Server`] retries when the connection to the XMPP server is lost.
+///
+/// Delays grow exponentially from `initial_delay`, capped at `max_delay`.
+#[derive(Clone, Debug)]
+pub struct ReconnectPolicy {
+    /// Give up after this many consecutive failed attempts; `None` retries forever.
+    pub max_attempts: Option<u32>,
+    /// The delay before the first attempt.
+    pub initial_delay: Duration,
+    /// The upper bound on the delay between attempts.
+    pub max_delay: Duration,
+}
+
+impl ReconnectPolicy {
+    /// The delay before the given (zero-based) attempt.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.initial_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay)
+    }
+
+    /// Whether another attempt is allowed after `attempts` failures.
+    pub fn allows(&self, attempts: u32) -> bool {
+        self.max_attempts.map_or(true, |max| attempts < max)
+    }
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            max_attempts: None,
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+type Connect<C> =
+    Arc<dyn Fn() -> BoxFuture<'static, Result<Component<C>, tokio_xmpp::Error>> + Send + Sync>;
+
+#[derive(Clone)]
+pub(crate) struct Reconnect<C: ServerConnector = TcpServerConnector> {
+    policy: ReconnectPolicy,
+    connect: Connect<C>,
+}
+
+impl<C: ServerConnector> Reconnect<C> {
+    /// Connect, retrying according to the policy.
+    pub(crate) async fn connect(&self) -> Result<Component<C>, tokio_xmpp::Error> {
+        let mut attempts = 0;
+        loop {
+            match (self.connect)().await {
+                Ok(component) => return Ok(component),
+                Err(err) => {
+                    attempts += 1;
+                    if !self.policy.allows(attempts) {
+                        return Err(err);
+                    }
+                    let delay = self.policy.delay(attempts - 1);
+                    tracing::warn!(
+                        "connection attempt {} failed: {}; retrying in {:?}",
+                        attempts,
+                        err,
+                        delay
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn reconnect<C, M, Fut>(policy: ReconnectPolicy, connect: M) -> Reconnect<C>
+where
+    C: ServerConnector,
+    M: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<Component<C>, tokio_xmpp::Error>> + Send + 'static,
+{
+    Reconnect {
+        policy,
+        connect: Arc::new(move || Box::pin(connect())),
+    }
+}
+
+impl<F, R, C: ServerConnector> Server<F, R, C> {
+    /// Swap the runner, keeping the rest of the configuration.
+    fn with_runner<R2>(self, runner: R2) -> (Server<F, R2, C>, R) {
+        let server = Server {
+            component: self.component,
+            filter: self.filter,
+            runner,
+            reconnect: self.reconnect,
+            outbound_capacity: self.outbound_capacity,
+            outbound_batch: self.outbound_batch,
+            layered: self.layered,
+            disco: self.disco,
+            last_activity: self.last_activity,
+            fill_from: self.fill_from,
+            concurrency: self.concurrency,
+            overflow: self.overflow,
+            shard_by_sender: self.shard_by_sender,
+            #[cfg(feature = "http-ingress")]
+            ingress: self.ingress,
+        };
+        (server, self.runner)
+    }
+}
+
+impl<F, R, C> Server<F, R, C>
 where
     F: Filter + Clone + Send + Sync + 'static,
     <F::Future as TryFuture>::Ok: Reply,
     <F::Future as TryFuture>::Error: IsReject,
     R: run::Run,
+    C: ServerConnector,
 {
     /// Add graceful shutdown support to this server.
     ///
+    /// Once `shutdown_signal` resolves, the server stops reading stanzas,
+    /// sends the stanzas handlers already queued, closes the stream, and
+    /// `run()` returns.
+    ///
     /// # Example
     ///
-    /// ```
-    /// # async fn ex(addr: std::net::SocketAddr) {
-    /// # use wax::Filter;
-    /// # let filter = wax::any().map(|| "ok");
-    /// wax::serve(filter)
-    ///     .bind(addr).await
+    /// ```ignore
+    /// component
+    ///     .serve(routes)
     ///     .graceful(async {
-    ///         // some signal in here, such as ctrl_c
+    ///         tokio::signal::ctrl_c().await.ok();
     ///     })
-    ///     .run().await;
-    /// # }
+    ///     .run()
+    ///     .await?;
     /// ```
-    // pub fn graceful<Fut>(self, shutdown_signal: Fut) -> Server<F, run::Graceful<Fut>>
-    // where
-    //     Fut: Future<Output = ()> + Send + 'static,
-    // {
-    //     Server {
-    //         component: self.component,
-    //         filter: self.filter,
-    //         runner: run::Graceful(shutdown_signal),
-    //     }
-    // }
+    pub fn graceful<Fut>(self, shutdown_signal: Fut) -> Server<F, run::Graceful<Fut>, C>
+    where
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.with_runner(run::Graceful(shutdown_signal)).0
+    }
+
+    /// Reconnect when the connection to the XMPP server is lost.
+    ///
+    /// `connect` is called to establish each new connection, with delays
+    /// between failed attempts governed by `policy`. Without this, the
+    /// server stops with [`Kind::StreamClosed`] when the stream closes.
+    ///
+    /// [`Kind::StreamClosed`]: crate::error::Kind::StreamClosed
+    pub fn reconnect<M, Fut>(self, policy: ReconnectPolicy, connect: M) -> Self
+    where
+        M: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Component<C>, tokio_xmpp::Error>> + Send + 'static,
+    {
+        self.with_reconnect(reconnect(policy, connect))
+    }
+
+    pub(crate) fn with_reconnect(mut self, reconnect: Reconnect<C>) -> Self {
+        self.reconnect = Some(reconnect);
+        self
+    }
+
+    /// Bound the queue of stanzas sent outside of replies.
+    ///
+    /// By default the queue is unbounded. Once a bounded queue is full,
+    /// further stanzas are dropped with a warning.
+    pub fn outbound_capacity(mut self, capacity: usize) -> Self {
+        self.outbound_capacity = Some(capacity);
+        self
+    }
+
+    /// Write up to `max` queued outbound stanzas before flushing the stream.
+    ///
+    /// Stanzas already waiting in the outbound queue are serialized into the
+    /// stream's write buffer back to back and flushed together, rather than
+    /// flushing after each one. Defaults to 64; `1` flushes every stanza.
+    ///
+    /// The write buffer belongs to the tokio-xmpp stream, which reuses it
+    /// across sends; the server keeps no buffer pool of its own.
+    pub fn outbound_batch(mut self, max: usize) -> Self {
+        self.outbound_batch = max.max(1);
+        self
+    }
+
+    /// Stamp replies without a `from` as coming from the JID the stanza
+    /// they answer was addressed to, or from the component's JID.
+    ///
+    /// XMPP servers bounce stanzas from components that have no `from`, or
+    /// one outside of the component's domain, with an `invalid-from` error.
+    /// Replies that set their own `from` are left alone.
+    pub fn fill_from(mut self) -> Self {
+        self.fill_from = true;
+        self
+    }
+
+    /// Handle stanzas concurrently, each on its own task.
+    ///
+    /// By default stanzas are handled one at a time, so one slow handler,
+    /// e.g. waiting on a database, holds up every other sender. Concurrent
+    /// handlers reply as they finish, so replies may be sent in another
+    /// order than the stanzas they answer arrived in, unless sharded with
+    /// [`Server::shard_by_sender`]. Up to 1024 stanzas are handled at once,
+    /// see [`Server::max_concurrency`].
+    ///
+    /// Filters are still built on the run loop, so they can reach the
+    /// outbound queue as usual.
+    pub fn concurrent(mut self) -> Self {
+        self.concurrency = Some(DEFAULT_CONCURRENCY);
+        self
+    }
+
+    /// Handle stanzas concurrently, up to `limit` at once.
+    ///
+    /// Bounding the stanzas in flight bounds the memory they hold and the
+    /// load they put on backends. What happens to stanzas arriving past the
+    /// limit is set with [`Server::overflow`].
+    pub fn max_concurrency(mut self, limit: usize) -> Self {
+        self.concurrency = Some(limit.max(1));
+        self
+    }
+
+    /// Set what happens to stanzas arriving while `max_concurrency` stanzas
+    /// are in flight. Defaults to [`Overflow::Queue`].
+    pub fn overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Keep the stanzas of each sender in order when handling concurrently.
+    ///
+    /// Stanzas are sharded by the bare JID of their `from`: a sender's
+    /// stanza is handled only once the reply to its previous one is sent,
+    /// while stanzas of different senders are still handled in parallel.
+    /// Gateways relaying chats to another network usually want this, so
+    /// messages are not reordered. Stanzas without a `from` are not
+    /// sharded.
+    ///
+    /// Does nothing unless stanzas are handled concurrently, see
+    /// [`Server::concurrent`].
+    pub fn shard_by_sender(mut self) -> Self {
+        self.shard_by_sender = true;
+        self
+    }
+
+    /// Wrap the stanza service in a tower [`Layer`].
+    ///
+    /// Layers see the service built from the filter with
+    /// [`service()`](crate::service()), boxed as a [`StanzaService`]. Calling
+    /// `layer` again wraps the result, so the last layer added is outermost.
+    /// A `tower::ServiceBuilder` is itself a layer, so standard middleware
+    /// (timeouts, rate limits, concurrency limits) can be stacked with it.
+    ///
+    /// If a layer fails a stanza, the sender gets a `wait`-type
+    /// `resource-constraint` error.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use std::time::Duration;
+    /// use tower::ServiceBuilder;
+    ///
+    /// component
+    ///     .serve(routes)
+    ///     .layer(ServiceBuilder::new().timeout(Duration::from_secs(5)))
+    ///     .run()
+    ///     .await?;
+    /// ```
+    pub fn layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<StanzaService>,
+        L::Service: Service<Stanza, Response = Response> + Send + 'static,
+        <L::Service as Service<Stanza>>::Error: Into<BoxError>,
+        <L::Service as Service<Stanza>>::Future: Send + 'static,
+        F::Future: 'static,
+    {
+        let inner = match self.layered.take() {
+            Some(inner) => inner,
+            None => boxed(crate::service(self.filter.clone())),
+        };
+        self.layered = Some(boxed(layer.layer(inner)));
+        self
+    }
+
+    /// Answer `disco#info` queries about the component itself.
+    ///
+    /// The result lists `identity` and every feature advertised by the
+    /// served filter (see [`disco::features`](crate::disco::features)),
+    /// followed by the forms of `identity`.
+    /// Queries for a node, or addressed to other JIDs of the component,
+    /// still go through the filter.
+    pub fn disco(mut self, identity: crate::disco::Identity) -> Self {
+        self.disco = Some(identity);
+        self
+    }
+
+    /// Record when each JID was last seen, to answer `jabber:iq:last`
+    /// queries.
+    ///
+    /// Every incoming stanza updates the record of its sender in memory;
+    /// the records are written to the store in the background at most once
+    /// per [flush interval](crate::last::LastActivity::flush_interval), and
+    /// when the server shuts down. Serve
+    /// [`LastActivity::filter`](crate::last::LastActivity::filter) to answer
+    /// the queries.
+    pub fn last_activity(mut self, last_activity: crate::last::LastActivity) -> Self {
+        self.last_activity = Some(last_activity);
+        self
+    }
+
+    /// Accept stanzas pushed over HTTP while this server runs.
+    ///
+    /// Available with the `http-ingress` feature.
+    #[cfg(feature = "http-ingress")]
+    pub fn ingress(mut self, ingress: crate::ingress::Ingress) -> Self {
+        self.ingress = Some(ingress);
+        self
+    }
 
     /// Run this server.
-    pub async fn run(self) {
-        R::run(self).await;
+    ///
+    /// Only returns `Ok` once a graceful shutdown completes. Otherwise the
+    /// server runs until the connection to the XMPP server fails for good,
+    /// see [`Kind`] for how.
+    ///
+    /// [`Kind`]: crate::error::Kind
+    pub async fn run(self) -> Result<(), crate::Error> {
+        R::run(self).await
+    }
+}
+
+/// Several servers run together, e.g. one per component of a gateway.
+///
+/// The servers share a shutdown signal, and their outbound queues: a stanza
+/// queued by any of their handlers is sent by the server whose JID has the
+/// domain of its `from`, else by the server that queued it. `run()` returns
+/// once every server stopped, or with the first error.
+///
+/// # Example
+///
+/// ```ignore
+/// wax::Servers::new()
+///     .add(sms.serve(sms_routes))
+///     .add(mms.serve(mms_routes))
+///     .graceful(async {
+///         tokio::signal::ctrl_c().await.ok();
+///     })
+///     .run()
+///     .await?;
+/// ```
+#[derive(Default)]
+pub struct Servers {
+    members: Vec<Member>,
+    shutdown_signal: Option<BoxFuture<'static, ()>>,
+}
+
+struct Member {
+    jid: Jid,
+    outbound_capacity: Option<usize>,
+    serve: Box<
+        dyn FnOnce(
+            run::Shutdown,
+            correlation::Outbound,
+            correlation::OutboundReceiver,
+        ) -> LocalBoxFuture<'static, Result<(), crate::Error>>,
+    >,
+}
+
+impl Servers {
+    /// Create an empty set of servers.
+    pub fn new() -> Self {
+        Servers::default()
+    }
+
+    /// Add a server, configured as when run on its own.
+    pub fn add<F, C>(mut self, server: Server<F, run::Standard, C>) -> Self
+    where
+        F: Filter + Clone + Send + Sync + 'static,
+        <F::Future as TryFuture>::Ok: Reply,
+        <F::Future as TryFuture>::Error: IsReject,
+        C: ServerConnector,
+    {
+        self.members.push(Member {
+            jid: server.component.jid.clone(),
+            outbound_capacity: server.outbound_capacity,
+            serve: Box::new(move |shutdown_signal, outbound_tx, outbound_rx| {
+                Box::pin(run::serve(
+                    server,
+                    shutdown_signal,
+                    outbound_tx,
+                    outbound_rx,
+                ))
+            }),
+        });
+        self
+    }
+
+    /// Shut every server down gracefully once `shutdown_signal` resolves.
+    ///
+    /// See [`Server::graceful`].
+    pub fn graceful<Fut>(mut self, shutdown_signal: Fut) -> Self
+    where
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.shutdown_signal = Some(Box::pin(shutdown_signal));
+        self
+    }
+
+    /// Run these servers.
+    pub async fn run(self) -> Result<(), crate::Error> {
+        let shutdown_signal = self
+            .shutdown_signal
+            .unwrap_or_else(|| Box::pin(future::pending()))
+            .shared();
+        let channels: Vec<_> = self
+            .members
+            .iter()
+            .map(|member| correlation::outbound_channel(member.outbound_capacity))
+            .collect();
+        let routes: Arc<HashMap<_, _>> = Arc::new(
+            self.members
+                .iter()
+                .zip(&channels)
+                .map(|(member, (outbound_tx, _))| {
+                    (member.jid.domain().as_str().to_owned(), outbound_tx.clone())
+                })
+                .collect(),
+        );
+        let serving =
+            self.members
+                .into_iter()
+                .zip(channels)
+                .map(|(member, (outbound_tx, outbound_rx))| {
+                    let outbound_tx = correlation::Outbound::Routed {
+                        own: Box::new(outbound_tx),
+                        routes: routes.clone(),
+                    };
+                    (member.serve)(shutdown_signal.clone(), outbound_tx, outbound_rx)
+                });
+        future::try_join_all(serving).await?;
+        Ok(())
     }
 }
 
-mod run {
+impl std::fmt::Debug for Servers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.members.iter().map(|member| &member.jid))
+            .finish()
+    }
+}
+
+pub(crate) mod run {
     use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use futures::{FutureExt, Sink, SinkExt, StreamExt};
+    use futures_util::future::{self, BoxFuture, Either, LocalBoxFuture};
+    use tokio::sync::oneshot::{self, error::TryRecvError};
+    use tokio::sync::Semaphore;
+    use tokio::task::{JoinError, JoinSet};
+    use tokio_xmpp::connect::ServerConnector;
+    use tokio_xmpp::{Component, Stanza};
+    use xmpp_parsers::iq::Iq;
+    use xmpp_parsers::jid::{BareJid, Jid};
+    use xmpp_parsers::stanza_error::{DefinedCondition, ErrorType, StanzaError};
 
-    use futures::{SinkExt, StreamExt};
-    use tokio::sync::mpsc;
-    use tokio_xmpp::Stanza;
+    use super::{Overflow, StanzaService};
+    use crate::correlation::{self, CorrelationContext, Outbound, OutboundReceiver};
+    use crate::disco;
+    use crate::filter::service::make_error_stanza;
+    use crate::filters::stanza::{from_of, to_of};
+    use crate::reply::Response;
 
-    use crate::correlation::{self, CorrelationContext};
+    /// The shutdown signal of servers run together.
+    pub(crate) type Shutdown = future::Shared<BoxFuture<'static, ()>>;
 
     pub trait Run {
         #[allow(async_fn_in_trait)]
-        async fn run<F>(server: super::Server<F, Self>)
+        async fn run<F, C>(server: super::Server<F, Self, C>) -> Result<(), crate::Error>
         where
             F: super::Filter + Clone + Send + Sync + 'static,
             <F::Future as super::TryFuture>::Ok: super::Reply,
             <F::Future as super::TryFuture>::Error: super::IsReject,
+            C: ServerConnector,
             Self: Sized;
     }
 
@@ -120,110 +648,454 @@ mod run {
     pub struct Standard;
 
     impl Run for Standard {
-        async fn run<F>(mut server: super::Server<F, Self>)
+        async fn run<F, C>(server: super::Server<F, Self, C>) -> Result<(), crate::Error>
         where
             F: super::Filter + Clone + Send + Sync + 'static,
             <F::Future as super::TryFuture>::Ok: super::Reply,
             <F::Future as super::TryFuture>::Error: super::IsReject,
+            C: ServerConnector,
             Self: Sized,
         {
-            let (outbound_tx, mut outbound_rx) = mpsc::unbounded_channel::<Stanza>();
-            let ctx = RefCell::new(CorrelationContext::new(outbound_tx));
-            let svc = crate::service(server.filter.clone());
-
-            loop {
-                tokio::select! {
-                    stanza = server.component.next() => {
-                        let stanza = stanza.expect("XMPP stream closed unexpectedly");
-
-                        // Check if this stanza's ID is pending
-                        // if let Some(tx) = correlation::try_take_pending(&stanza) {
-                        //     tx.send(stanza).expect("failed to route response to pending request");
-                        //     continue;
-                        // }
-
-                        // Not pending - run through filters with ctx set
-
-                        let response = correlation::set(&ctx, || svc.call_stanza(stanza)).await;
-                        if let Ok(Some(reply)) = response {
-                            if let Err(err) = server.component.send(reply).await {
-                                tracing::error!("failed to send reply: {:?}", err);
-                            }
+            let (outbound_tx, outbound_rx) =
+                correlation::outbound_channel(server.outbound_capacity);
+            serve(server, future::pending(), outbound_tx, outbound_rx).await
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct Graceful<Fut>(pub(super) Fut);
+
+    impl<Fut> Run for Graceful<Fut>
+    where
+        Fut: super::Future<Output = ()> + Send + 'static,
+    {
+        async fn run<F, C>(server: super::Server<F, Self, C>) -> Result<(), crate::Error>
+        where
+            F: super::Filter + Clone + Send + Sync + 'static,
+            <F::Future as super::TryFuture>::Ok: super::Reply,
+            <F::Future as super::TryFuture>::Error: super::IsReject,
+            C: ServerConnector,
+            Self: Sized,
+        {
+            let (server, Graceful(shutdown_signal)) = server.with_runner(Standard);
+            let (outbound_tx, outbound_rx) =
+                correlation::outbound_channel(server.outbound_capacity);
+            serve(server, shutdown_signal, outbound_tx, outbound_rx).await
+        }
+    }
+
+    /// Handle stanzas until `shutdown_signal` resolves, then finish the
+    /// stanzas in flight, send what is left in the outbound queue and close
+    /// the stream.
+    pub(super) async fn serve<F, C>(
+        mut server: super::Server<F, Standard, C>,
+        shutdown_signal: impl super::Future<Output = ()>,
+        outbound_tx: Outbound,
+        mut outbound_rx: OutboundReceiver,
+    ) -> Result<(), crate::Error>
+    where
+        F: super::Filter + Clone + Send + Sync + 'static,
+        <F::Future as super::TryFuture>::Ok: super::Reply,
+        <F::Future as super::TryFuture>::Error: super::IsReject,
+        C: ServerConnector,
+    {
+        #[cfg(feature = "http-ingress")]
+        let mut stop_ingress = None;
+        #[cfg(feature = "http-ingress")]
+        if let Some(ingress) = server.ingress.take() {
+            let jid = server.component.jid.clone();
+            let (stop, stopped) = oneshot::channel::<()>();
+            stop_ingress = Some(stop);
+            tokio::spawn(ingress.serve(jid, outbound_tx.clone(), async move {
+                let _ = stopped.await;
+            }));
+        }
+        let ctx = RefCell::new(CorrelationContext::new(outbound_tx));
+        let svc = crate::service(server.filter.clone());
+        let disco = server
+            .disco
+            .take()
+            .map(|identity| disco::Info::new(identity, disco::features(&server.filter)));
+        let mut shutdown_signal = std::pin::pin!(shutdown_signal);
+        let mut in_flight = JoinSet::new();
+        let mut shards = Shards::default();
+        let permits = server
+            .concurrency
+            .map(|limit| Arc::new(Semaphore::new(limit)));
+        // While the stream is being replaced, replies and outbound stanzas
+        // wait for the new one, but shutdown is still honored.
+        let mut reconnecting: Option<
+            LocalBoxFuture<'static, Result<Component<C>, tokio_xmpp::Error>>,
+        > = None;
+
+        loop {
+            let connected = reconnecting.is_none();
+            // With `Overflow::Queue`, stop reading once every permit is taken.
+            let reading = connected
+                && (server.overflow == Overflow::Reject
+                    || permits
+                        .as_ref()
+                        .map_or(true, |permits| permits.available_permits() > 0));
+            tokio::select! {
+                stanza = server.component.next(), if reading => {
+                    let stanza = match (stanza, &server.reconnect) {
+                        (Some(stanza), _) => stanza,
+                        (None, Some(reconnect)) => {
+                            tracing::warn!("XMPP stream closed, reconnecting");
+                            let reconnect = reconnect.clone();
+                            reconnecting = Some(Box::pin(async move { reconnect.connect().await }));
+                            continue;
                         }
+                        (None, None) => return Err(crate::Error::stream_closed()),
+                    };
+
+                    // Check if this stanza's ID is pending
+                    // if let Some(tx) = correlation::try_take_pending(&stanza) {
+                    //     tx.send(stanza).expect("failed to route response to pending request");
+                    //     continue;
+                    // }
+
+                    // Not pending - run through filters with ctx set
+
+                    if let Some(flush) = server
+                        .last_activity
+                        .as_ref()
+                        .and_then(|last| last.observe(&stanza))
+                    {
+                        tokio::spawn(flush);
                     }
 
-                    Some(outbound) = outbound_rx.recv() => {
-                        if let Err(err) = server.component.send(outbound).await {
-                            tracing::error!("failed to send outbound stanza: {:?}", err);
+                    let permit = match permits.clone().map(Semaphore::try_acquire_owned) {
+                        Some(Ok(permit)) => Some(permit),
+                        Some(Err(_)) => {
+                            tracing::debug!("too many stanzas in flight, rejecting");
+                            let response = overflow_error(&stanza).into();
+                            check_sent(
+                                send_response(&mut server.component, response).await,
+                                server.reconnect.is_some(),
+                            )?;
+                            continue;
+                        }
+                        None => None,
+                    };
+
+                    let recipient = server.fill_from.then(|| {
+                        to_of(&stanza)
+                            .unwrap_or(&server.component.jid)
+                            .clone()
+                    });
+
+                    let (finished, previous) = match from_of(&stanza) {
+                        Some(from) if server.shard_by_sender && permits.is_some() => {
+                            let (finished, previous) = shards.enter(from.to_bare());
+                            (Some(finished), previous)
+                        }
+                        _ => (None, None),
+                    };
+
+                    let disco_reply = disco
+                        .as_ref()
+                        .and_then(|info| info.answer(&stanza, &server.component.jid));
+                    let handling = match (disco_reply, &mut server.layered) {
+                        (Some(reply), _) => Either::Left(future::ready(Response::from(reply))),
+                        (None, Some(layered)) => {
+                            Either::Right(Either::Left(call_layered(&ctx, layered, stanza).await))
                         }
+                        (None, None) => Either::Right(Either::Right(
+                            correlation::set(&ctx, || svc.call_stanza(stanza)).map(|response| {
+                                response.unwrap_or_else(|infallible| match infallible {})
+                            }),
+                        )),
+                    };
+                    let handling = async move {
+                        if let Some(previous) = previous {
+                            // Resolves once the reply to the sender's previous
+                            // stanza is sent, or its handler failed.
+                            let _ = previous.await;
+                        }
+                        let response = handling.await;
+                        drop(permit);
+                        let response = match recipient {
+                            Some(recipient) => {
+                                response.map(move |reply| fill_from(reply, &recipient))
+                            }
+                            None => response,
+                        };
+                        (response, finished)
+                    };
+
+                    if permits.is_some() {
+                        in_flight.spawn(handling);
+                    } else {
+                        check_sent(
+                            send_response(&mut server.component, handling.await.0).await,
+                            server.reconnect.is_some(),
+                        )?;
                     }
                 }
+
+                component = async {
+                    reconnecting.as_mut().expect("only polled while reconnecting").await
+                }, if !connected => {
+                    reconnecting = None;
+                    server.component = component.map_err(crate::Error::handshake)?;
+                    tracing::info!("reconnected");
+                }
+
+                Some(handled) = in_flight.join_next(), if connected => {
+                    check_sent(
+                        send_handled(&mut server.component, handled).await,
+                        server.reconnect.is_some(),
+                    )?;
+                    shards.prune(in_flight.len());
+                }
+
+                Some(outbound) = outbound_rx.recv(), if connected => {
+                    check_sent(
+                        send_batch(
+                            &mut server.component,
+                            &mut outbound_rx,
+                            outbound,
+                            server.outbound_batch,
+                        )
+                        .await,
+                        server.reconnect.is_some(),
+                    )?;
+                }
+
+                () = &mut shutdown_signal => {
+                    tracing::debug!("shutdown signal received, starting graceful shutdown");
+                    break;
+                }
+            }
+        }
+
+        // Stop taking HTTP requests, so what they queued is drained below.
+        #[cfg(feature = "http-ingress")]
+        drop(stop_ingress);
+
+        if let Some(last) = &server.last_activity {
+            last.flush().await;
+        }
+
+        if reconnecting.is_some() {
+            tracing::warn!(
+                "shut down while reconnecting, dropping {} stanzas in flight and the outbound queue",
+                in_flight.len()
+            );
+            return Ok(());
+        }
+
+        while let Some(handled) = in_flight.join_next().await {
+            send_handled(&mut server.component, handled)
+                .await
+                .map_err(crate::Error::send)?;
+        }
+
+        while let Some(outbound) = outbound_rx.try_recv() {
+            send_batch(
+                &mut server.component,
+                &mut outbound_rx,
+                outbound,
+                server.outbound_batch,
+            )
+            .await
+            .map_err(crate::Error::send)?;
+        }
+        if let Err(err) = server.component.close().await {
+            tracing::error!("failed to close the XMPP stream: {:?}", err);
+        }
+        Ok(())
+    }
+
+    /// Fail with a stanza that could not be sent, unless reconnecting
+    /// will replace the broken stream.
+    fn check_sent(
+        result: Result<(), tokio_xmpp::Error>,
+        reconnecting: bool,
+    ) -> Result<(), crate::Error> {
+        match result {
+            Err(err) if reconnecting => {
+                tracing::warn!("failed to send stanza: {:?}", err);
+                Ok(())
+            }
+            result => result.map_err(crate::Error::send),
+        }
+    }
+
+    /// The `resource-constraint` error rejecting `stanza` for lack of
+    /// capacity, unless it must not be answered: IQ results and errors,
+    /// error messages and presences, and stanzas without an id are dropped.
+    fn overflow_error(stanza: &Stanza) -> Option<Stanza> {
+        if matches!(stanza, Stanza::Iq(Iq::Result { .. } | Iq::Error { .. })) {
+            return None;
+        }
+        let error = StanzaError::new(
+            ErrorType::Wait,
+            DefinedCondition::ResourceConstraint,
+            "en",
+            "Too many stanzas in flight",
+        );
+        make_error_stanza(stanza, error)
+    }
+
+    /// The last stanza in flight of each sender, when sharding by sender.
+    #[derive(Default)]
+    pub(super) struct Shards(HashMap<BareJid, oneshot::Receiver<()>>);
+
+    impl Shards {
+        /// Track a stanza from `sender`. Returns the signal to drop once
+        /// its reply is sent, and the signal of the previous stanza of
+        /// `sender` if it may still be in flight.
+        pub(super) fn enter(
+            &mut self,
+            sender: BareJid,
+        ) -> (oneshot::Sender<()>, Option<oneshot::Receiver<()>>) {
+            let (finished, finished_rx) = oneshot::channel();
+            (finished, self.0.insert(sender, finished_rx))
+        }
+
+        /// Forget senders with nothing left in flight.
+        ///
+        /// Each sender tracked has a stanza in flight unless there are more
+        /// senders than `in_flight` stanzas, so the map is only scanned then.
+        pub(super) fn prune(&mut self, in_flight: usize) {
+            if self.0.len() > in_flight {
+                self.0
+                    .retain(|_, finished| matches!(finished.try_recv(), Err(TryRecvError::Empty)));
             }
         }
+
+        pub(super) fn len(&self) -> usize {
+            self.0.len()
+        }
+    }
+
+    /// The reply to a concurrently handled stanza, with the signal letting
+    /// the next stanza of its sender be handled when sharding.
+    type Handled = (Response, Option<oneshot::Sender<()>>);
+
+    /// Set the `from` of `stanza` to `recipient` if it has none.
+    fn fill_from(mut stanza: Stanza, recipient: &Jid) -> Stanza {
+        let from = match stanza {
+            Stanza::Message(ref mut msg) => &mut msg.from,
+            Stanza::Presence(ref mut pres) => &mut pres.from,
+            Stanza::Iq(
+                Iq::Get { ref mut from, .. }
+                | Iq::Set { ref mut from, .. }
+                | Iq::Result { ref mut from, .. }
+                | Iq::Error { ref mut from, .. },
+            ) => from,
+        };
+        if from.is_none() {
+            *from = Some(recipient.clone());
+        }
+        stanza
     }
 
-    // #[derive(Debug)]
-    // pub struct Graceful<Fut>(pub(super) Fut);
-
-    // impl<Fut> Run for Graceful<Fut>
-    // where
-    //     Fut: super::Future<Output = ()> + Send + 'static,
-    // {
-    //     async fn run<F>(mut server: super::Server<F, Self, Component<TcpServerConnector>>)
-    //     where
-    //         F: super::Filter + Clone + Send + Sync + 'static,
-    //         <F::Future as super::TryFuture>::Ok: super::Reply,
-    //         <F::Future as super::TryFuture>::Error: super::IsReject,
-    //         Self: Sized,
-    //     {
-    //         use futures_util::future;
-
-    //         let pipeline = server.pipeline;
-    //         let graceful_util = hyper_util::server::graceful::GracefulShutdown::new();
-    //         let mut shutdown_signal = std::pin::pin!(server.runner.0);
-    //         loop {
-    //             let accept = std::pin::pin!(server.acceptor.accept());
-    //             let accepting = match future::select(accept, &mut shutdown_signal).await {
-    //                 future::Either::Left((Ok(fut), _)) => fut,
-    //                 future::Either::Left((Err(err), _)) => {
-    //                     handle_accept_error(err).await;
-    //                     continue;
-    //                 }
-    //                 future::Either::Right(((), _)) => {
-    //                     tracing::debug!("shutdown signal received, starting graceful shutdown");
-    //                     break;
-    //                 }
-    //             };
-    //             let svc = crate::service(server.filter.clone());
-    //             let svc = hyper_util::service::TowerToHyperService::new(svc);
-    //             let watcher = graceful_util.watcher();
-    //             tokio::spawn(async move {
-    //                 let io = match accepting.await {
-    //                     Ok(io) => io,
-    //                     Err(err) => {
-    //                         tracing::debug!("server accepting error: {:?}", err);
-    //                         return;
-    //                     }
-    //                 };
-    //                 let mut hyper = hyper_util::server::conn::auto::Builder::new(
-    //                     hyper_util::rt::TokioExecutor::new(),
-    //                 );
-    //                 hyper.http1().pipeline_flush(pipeline);
-    //                 let conn = hyper.serve_connection_with_upgrades(io, svc);
-    //                 let conn = watcher.watch(conn);
-    //                 if let Err(err) = conn.await {
-    //                     tracing::error!("server connection error: {:?}", err)
-    //                 }
-    //             });
-    //         }
-
-    //         drop(server.acceptor); // close listener
-    //         graceful_util.shutdown().await;
-    //     }
-    // }
+    /// Send the stanzas of `response` in order, flushing whenever a
+    /// streamed response has nothing ready.
+    async fn send_response<C: ServerConnector>(
+        component: &mut Component<C>,
+        response: Response,
+    ) -> Result<(), tokio_xmpp::Error> {
+        if response.is_empty() {
+            return Ok(());
+        }
+        component
+            .send_all(&mut response.into_stream().map(Ok))
+            .await
+    }
 
+    /// Feed `first` and whatever else is already queued, up to `max`
+    /// stanzas, then flush once.
+    ///
+    /// If a write fails partway, the rest of the batch is left in the queue
+    /// and the stanzas written so far are reported as possibly lost: they
+    /// may still sit unflushed in the broken stream's buffer.
+    pub(super) async fn send_batch<S>(
+        sink: &mut S,
+        outbound_rx: &mut OutboundReceiver,
+        first: Stanza,
+        max: usize,
+    ) -> Result<(), tokio_xmpp::Error>
+    where
+        S: Sink<Stanza, Error = tokio_xmpp::Error> + Unpin,
+    {
+        let mut written = 0;
+        let mut next = Some(first);
+        let result = loop {
+            let Some(stanza) = next.take() else {
+                break sink.flush().await;
+            };
+            if let Err(err) = sink.feed(stanza).await {
+                break Err(err);
+            }
+            written += 1;
+            if written < max {
+                next = outbound_rx.try_recv();
+            }
+        };
+        if result.is_err() && written > 0 {
+            tracing::warn!(
+                "outbound batch failed after writing {} stanzas, which may not have been delivered",
+                written
+            );
+        }
+        result
+    }
+
+    /// Send the reply of a concurrently handled stanza, then let the next
+    /// stanza of its sender be handled.
+    async fn send_handled<C: ServerConnector>(
+        component: &mut Component<C>,
+        handled: Result<Handled, JoinError>,
+    ) -> Result<(), tokio_xmpp::Error> {
+        match handled {
+            Ok((response, finished)) => {
+                let result = send_response(component, response).await;
+                drop(finished);
+                result
+            }
+            Err(err) => {
+                tracing::error!("stanza handler failed: {}", err);
+                Ok(())
+            }
+        }
+    }
+
+    /// Wait for the layered service to be ready, then call it.
+    async fn call_layered(
+        ctx: &RefCell<CorrelationContext>,
+        service: &mut StanzaService,
+        stanza: Stanza,
+    ) -> BoxFuture<'static, Response> {
+        if let Err(err) = future::poll_fn(|cx| service.poll_ready(cx)).await {
+            return Box::pin(future::ready(layer_failed(&stanza, err)));
+        }
+        let original = stanza.clone();
+        let call = correlation::set(ctx, || service.call(stanza));
+        Box::pin(async move {
+            call.await
+                .unwrap_or_else(|err| layer_failed(&original, err))
+        })
+    }
+
+    fn layer_failed(original: &Stanza, err: super::BoxError) -> Response {
+        tracing::warn!("stanza service failed: {}", err);
+        let error = StanzaError::new(
+            ErrorType::Wait,
+            DefinedCondition::ResourceConstraint,
+            "en",
+            err.to_string(),
+        );
+        make_error_stanza(original, error).into()
+    }
+
+    /// Log a failed `accept`, backing off for a second unless the error
+    /// only concerns the connection being accepted.
     // TODO: allow providing your own handler
-    async fn handle_accept_error(e: std::io::Error) {
+    pub(crate) async fn handle_accept_error(e: std::io::Error) {
         if is_connection_error(&e) {
             return;
         }
@@ -251,3 +1123,423 @@ mod run {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::{mpsc, oneshot, Semaphore};
+    use xmpp_parsers::iq::Iq;
+    use xmpp_parsers::jid::BareJid;
+
+    use super::*;
+    use crate::connect::Connector;
+    use crate::fake_server::{attr, FakeServer, Peer};
+    use crate::reject::Rejection;
+
+    const JID: &str = "sms.example.org";
+
+    // Acknowledges IQ requests, holding those whose id starts with `slow`
+    // until `gate` lets them through. The id of every stanza is sent on
+    // `started` as its handling starts.
+    fn gated(
+        gate: Arc<Semaphore>,
+        started: mpsc::UnboundedSender<String>,
+    ) -> impl Filter<Extract = (Option<Iq>,), Error = Rejection> + Clone + Send + Sync + 'static
+    {
+        crate::id::param().and_then(move |id: String| {
+            let gate = gate.clone();
+            let reply = crate::reply::iq_empty_result();
+            let _ = started.send(id.clone());
+            async move {
+                if id.starts_with("slow") {
+                    gate.acquire().await.unwrap().forget();
+                }
+                Ok::<_, Rejection>(reply)
+            }
+        })
+    }
+
+    // A gated route, its gate, and the ids of the stanzas it starts
+    // handling.
+    fn routes() -> (
+        impl Filter<Extract = (Option<Iq>,), Error = Rejection> + Clone + Send + Sync + 'static,
+        Arc<Semaphore>,
+        mpsc::UnboundedReceiver<String>,
+    ) {
+        let gate = Arc::new(Semaphore::new(0));
+        let (started_tx, started_rx) = mpsc::unbounded_channel();
+        (gated(gate.clone(), started_tx), gate, started_rx)
+    }
+
+    async fn connect(fake: &FakeServer) -> (Component<TcpServerConnector>, Peer) {
+        connect_as(fake, JID).await
+    }
+
+    async fn connect_as(fake: &FakeServer, jid: &str) -> (Component<TcpServerConnector>, Peer) {
+        let connector = Connector::new(jid, "secret").host("127.0.0.1", fake.port());
+        let (component, peer) = tokio::join!(connector.connect(), fake.accept_component());
+        (component.unwrap(), peer)
+    }
+
+    // Run `test` while `server` runs, which must not stop first.
+    async fn alongside<T>(
+        server: impl Future<Output = Result<(), crate::Error>>,
+        test: impl Future<Output = T>,
+    ) -> T {
+        tokio::select! {
+            result = server => panic!("server stopped: {:?}", result),
+            output = test => output,
+        }
+    }
+
+    fn get(id: &str, from: &str) -> String {
+        format!(
+            "<iq type='get' id='{}' from='{}' to='{}'><ping xmlns='urn:xmpp:ping'/></iq>",
+            id, from, JID
+        )
+    }
+
+    async fn next_started(started: &mut mpsc::UnboundedReceiver<String>) -> String {
+        tokio::time::timeout(Duration::from_secs(5), started.recv())
+            .await
+            .expect("timed out waiting for a handler")
+            .unwrap()
+    }
+
+    async fn expect_result(peer: &mut Peer, id: &str) {
+        let result = peer.stanza().await;
+        assert_eq!(attr(&result, "id"), Some(id), "in {}", result);
+        assert_eq!(attr(&result, "type"), Some("result"), "in {}", result);
+    }
+
+    #[tokio::test]
+    async fn queues_overflowing_stanzas() {
+        let fake = FakeServer::bind().await;
+        let (component, mut peer) = connect(&fake).await;
+        let (routes, gate, mut started) = routes();
+        let server = component.serve(routes).max_concurrency(1);
+
+        alongside(server.run(), async {
+            peer.send(&get("slow", "juliet@capulet.lit/balcony")).await;
+            assert_eq!(next_started(&mut started).await, "slow");
+
+            peer.send(&get("next", "romeo@montague.lit/garden")).await;
+            let waiting = tokio::time::timeout(Duration::from_millis(100), started.recv());
+            assert!(waiting.await.is_err(), "read past the limit");
+
+            gate.add_permits(1);
+            expect_result(&mut peer, "slow").await;
+            assert_eq!(next_started(&mut started).await, "next");
+            expect_result(&mut peer, "next").await;
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn rejects_overflowing_requests() {
+        let fake = FakeServer::bind().await;
+        let (component, mut peer) = connect(&fake).await;
+        let (routes, gate, mut started) = routes();
+        let server = component
+            .serve(routes)
+            .max_concurrency(1)
+            .overflow(Overflow::Reject);
+
+        alongside(server.run(), async {
+            peer.send(&get("slow", "juliet@capulet.lit/balcony")).await;
+            assert_eq!(next_started(&mut started).await, "slow");
+
+            peer.send(&get("extra", "romeo@montague.lit/garden")).await;
+            let error = peer.stanza().await;
+            assert_eq!(attr(&error, "id"), Some("extra"));
+            assert_eq!(attr(&error, "type"), Some("error"));
+            assert!(error.contains("resource-constraint"), "in {}", error);
+
+            // Neither answered: only the message after them is.
+            peer.send(
+                "<iq type='result' id='stray' from='romeo@montague.lit/garden' \
+                 to='sms.example.org'/>",
+            )
+            .await;
+            peer.send(
+                "<message type='error' id='bounce' from='romeo@montague.lit/garden' \
+                 to='sms.example.org'><error type='cancel'><item-not-found \
+                 xmlns='urn:ietf:params:xml:ns:xmpp-stanzas'/></error></message>",
+            )
+            .await;
+            peer.send(
+                "<message id='chat' from='romeo@montague.lit/garden' \
+                 to='sms.example.org'><body>Hi</body></message>",
+            )
+            .await;
+            let error = peer.stanza().await;
+            assert_eq!(attr(&error, "id"), Some("chat"), "in {}", error);
+            assert_eq!(attr(&error, "type"), Some("error"), "in {}", error);
+
+            gate.add_permits(1);
+            expect_result(&mut peer, "slow").await;
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn finishes_stanzas_in_flight_on_shutdown() {
+        let fake = FakeServer::bind().await;
+        let (component, mut peer) = connect(&fake).await;
+        let (routes, gate, mut started) = routes();
+        let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+        let server = component.serve(routes).concurrent().graceful(async {
+            let _ = shutdown_rx.await;
+        });
+
+        let test = async {
+            peer.send(&get("slow", "juliet@capulet.lit/balcony")).await;
+            assert_eq!(next_started(&mut started).await, "slow");
+
+            shutdown.send(()).unwrap();
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            gate.add_permits(1);
+            expect_result(&mut peer, "slow").await;
+            peer.expect_end().await;
+        };
+        let (result, ()) = tokio::join!(server.run(), test);
+        result.unwrap();
+    }
+
+    #[tokio::test]
+    async fn slow_stanzas_do_not_hold_up_others() {
+        let fake = FakeServer::bind().await;
+        let (component, mut peer) = connect(&fake).await;
+        let (routes, gate, mut started) = routes();
+        let server = component.serve(routes).concurrent();
+
+        alongside(server.run(), async {
+            peer.send(&get("slow", "juliet@capulet.lit/balcony")).await;
+            assert_eq!(next_started(&mut started).await, "slow");
+            peer.send(&get("fast", "romeo@montague.lit/garden")).await;
+            expect_result(&mut peer, "fast").await;
+
+            gate.add_permits(1);
+            expect_result(&mut peer, "slow").await;
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn reconnects_when_the_stream_closes() {
+        let fake = FakeServer::bind().await;
+        let (component, peer) = connect(&fake).await;
+        let (routes, _gate, _started) = routes();
+        let connector = Connector::new(JID, "secret").host("127.0.0.1", fake.port());
+        let policy = ReconnectPolicy {
+            initial_delay: Duration::from_millis(10),
+            ..ReconnectPolicy::default()
+        };
+        let server = component.serve(routes).reconnect(policy, move || {
+            let connector = connector.clone();
+            async move { connector.connect().await }
+        });
+
+        alongside(server.run(), async {
+            drop(peer);
+            let mut peer = fake.accept_component().await;
+            peer.send(&get("again", "juliet@capulet.lit/balcony")).await;
+            expect_result(&mut peer, "again").await;
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn shuts_down_while_reconnecting() {
+        let fake = FakeServer::bind().await;
+        let (component, peer) = connect(&fake).await;
+        let (routes, _gate, _started) = routes();
+        let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+        let server = component
+            .serve(routes)
+            .reconnect(ReconnectPolicy::default(), || {
+                future::pending::<Result<Component<TcpServerConnector>, tokio_xmpp::Error>>()
+            })
+            .graceful(async {
+                let _ = shutdown_rx.await;
+            });
+
+        let test = async move {
+            drop(peer);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            shutdown.send(()).unwrap();
+        };
+        let run = tokio::time::timeout(Duration::from_secs(5), server.run());
+        let (result, ()) = tokio::join!(run, test);
+        result
+            .expect("shutdown waited for the reconnection")
+            .unwrap();
+    }
+
+    #[test]
+    fn shards_forget_senders_with_nothing_in_flight() {
+        let mut shards = run::Shards::default();
+        let juliet = BareJid::new("juliet@capulet.lit").unwrap();
+        let romeo = BareJid::new("romeo@montague.lit").unwrap();
+
+        let (first, previous) = shards.enter(juliet.clone());
+        assert!(previous.is_none());
+        let (second, previous) = shards.enter(juliet.clone());
+        assert!(previous.is_some());
+        let (third, _) = shards.enter(romeo);
+        assert_eq!(shards.len(), 2);
+
+        drop((first, previous, second));
+        shards.prune(2);
+        assert_eq!(shards.len(), 2, "pruned with as many stanzas in flight");
+        shards.prune(1);
+        assert_eq!(shards.len(), 1);
+        drop(third);
+        shards.prune(0);
+        assert_eq!(shards.len(), 0);
+
+        for n in 0..100 {
+            let sender = BareJid::new(&format!("user{}@capulet.lit", n)).unwrap();
+            drop(shards.enter(sender));
+            shards.prune(0);
+        }
+        assert_eq!(shards.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn keeps_the_order_of_each_sender() {
+        let fake = FakeServer::bind().await;
+        let (component, mut peer) = connect(&fake).await;
+        let (routes, gate, mut started) = routes();
+        let server = component.serve(routes).concurrent().shard_by_sender();
+
+        alongside(server.run(), async {
+            peer.send(&get("slow", "juliet@capulet.lit/balcony")).await;
+            assert_eq!(next_started(&mut started).await, "slow");
+            peer.send(&get("after", "juliet@capulet.lit/chamber")).await;
+            peer.send(&get("other", "romeo@montague.lit/garden")).await;
+            assert_eq!(next_started(&mut started).await, "other");
+            expect_result(&mut peer, "other").await;
+
+            gate.add_permits(1);
+            expect_result(&mut peer, "slow").await;
+            assert_eq!(next_started(&mut started).await, "after");
+            expect_result(&mut peer, "after").await;
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn servers_stop_with_the_first_error() {
+        let (sms_server, mms_server) = (FakeServer::bind().await, FakeServer::bind().await);
+        let (sms, sms_peer) = connect_as(&sms_server, "sms.example.org").await;
+        let (mms, mut mms_peer) = connect_as(&mms_server, "mms.example.org").await;
+        let (routes, _gate, _started) = routes();
+        let servers = Servers::new()
+            .add(sms.serve(routes.clone()))
+            .add(mms.serve(routes));
+
+        let test = async move {
+            drop(sms_peer);
+            mms_peer.expect_closed().await;
+        };
+        let (result, ()) = tokio::join!(servers.run(), test);
+        let err = result.expect_err("the sms stream closed");
+        assert_eq!(err.kind(), crate::error::Kind::StreamClosed);
+    }
+
+    // Takes `capacity` stanzas, then fails to write.
+    struct Broken {
+        written: Vec<Stanza>,
+        flushed: usize,
+        capacity: usize,
+    }
+
+    impl futures::Sink<Stanza> for Broken {
+        type Error = tokio_xmpp::Error;
+
+        fn poll_ready(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Result<(), Self::Error>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn start_send(
+            mut self: std::pin::Pin<&mut Self>,
+            stanza: Stanza,
+        ) -> Result<(), Self::Error> {
+            if self.written.len() == self.capacity {
+                return Err(tokio_xmpp::Error::Io(std::io::ErrorKind::BrokenPipe.into()));
+            }
+            self.written.push(stanza);
+            Ok(())
+        }
+
+        fn poll_flush(
+            mut self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Result<(), Self::Error>> {
+            self.flushed = self.written.len();
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Result<(), Self::Error>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    // An outbound queue holding `count` messages.
+    fn queued(count: usize) -> crate::correlation::OutboundReceiver {
+        let (outbound, outbound_rx) = crate::correlation::outbound_channel(None);
+        for _ in 0..count {
+            outbound
+                .send(Stanza::Message(xmpp_parsers::message::Message::new(None)))
+                .unwrap();
+        }
+        outbound_rx
+    }
+
+    #[tokio::test]
+    async fn batches_queued_stanzas() {
+        let mut sink = Broken {
+            written: Vec::new(),
+            flushed: 0,
+            capacity: usize::MAX,
+        };
+        let mut outbound_rx = queued(4);
+        let first = outbound_rx.try_recv().unwrap();
+
+        run::send_batch(&mut sink, &mut outbound_rx, first, 3)
+            .await
+            .unwrap();
+        assert_eq!(sink.written.len(), 3);
+        assert_eq!(sink.flushed, 3);
+        assert!(outbound_rx.try_recv().is_some());
+        assert!(outbound_rx.try_recv().is_none());
+    }
+
+    #[tokio::test]
+    async fn abandons_a_batch_that_fails_partway() {
+        let mut sink = Broken {
+            written: Vec::new(),
+            flushed: 0,
+            capacity: 2,
+        };
+        let mut outbound_rx = queued(5);
+        let first = outbound_rx.try_recv().unwrap();
+
+        assert!(run::send_batch(&mut sink, &mut outbound_rx, first, 64)
+            .await
+            .is_err());
+        // Two were written but never flushed, the third failed, and the
+        // rest are still queued.
+        assert_eq!(sink.written.len(), 2);
+        assert_eq!(sink.flushed, 0);
+        assert!(outbound_rx.try_recv().is_some());
+        assert!(outbound_rx.try_recv().is_some());
+        assert!(outbound_rx.try_recv().is_none());
+    }
+}
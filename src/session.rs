@@ -0,0 +1,321 @@
+//! Per-JID sessions.
+//!
+//! Multi-step conversations (registration wizards, bot dialogs) need to
+//! remember where each user is between stanzas. The [`session`] filter loads
+//! a typed session object keyed by the sender's bare JID, creating a default
+//! one if none exists yet. The handler mutates it, and the changes are
+//! persisted back into a [`Namespace`] of the shared
+//! [`KvStore`](crate::store::KvStore) once the handler is done with it.
+//!
+//! Loading a session waits until the previous session of the same user is
+//! saved, so each stanza sees the changes of the one before it.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use serde::{Deserialize, Serialize};
+//! use wax::session::Session;
+//! use wax::store::{KvStore, MemoryStore};
+//! use wax::Filter;
+//!
+//! #[derive(Default, Deserialize, Serialize)]
+//! struct Wizard {
+//!     step: u32,
+//! }
+//!
+//! let store = MemoryStore::new();
+//!
+//! let route = wax::message::body::param()
+//!     .and(wax::session(store.namespace("wizard")))
+//!     .and_then(|body: String, mut session: Session<Wizard>| async move {
+//!         session.step += 1;
+//!         Ok::<_, wax::Rejection>(wax::sink())
+//!     });
+//! ```
+
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use xmpp_parsers::jid::{BareJid, Jid};
+
+use crate::filter::Filter;
+use crate::generic::One;
+use crate::reject::Rejection;
+use crate::store::Namespace;
+
+/// A session loaded for the sender of the current stanza.
+///
+/// Dereferences to the session object. Once changed, the session is
+/// persisted when dropped, i.e. when the handler holding it completes. The
+/// write then happens in the background, so handlers that must know it
+/// succeeded call [`save`](Session::save) instead.
+///
+/// The next stanza of the same user only loads its session once this one
+/// is saved or discarded, so a handler must not load the session of a user
+/// it already holds.
+pub struct Session<S> {
+    jid: BareJid,
+    // `None` once consumed, so dropping does nothing.
+    value: Option<S>,
+    is_new: bool,
+    changed: bool,
+    store: Namespace,
+    encode: fn(&S) -> serde_json::Result<Vec<u8>>,
+    // Held until the session is saved or discarded.
+    lock: Option<KeyLock>,
+}
+
+impl<S> Session<S> {
+    /// The bare JID this session belongs to.
+    pub fn jid(&self) -> &BareJid {
+        &self.jid
+    }
+
+    /// Whether this session was created for this stanza, rather than loaded.
+    pub fn is_new(&self) -> bool {
+        self.is_new
+    }
+
+    /// Consume the session, returning the session object without saving it.
+    pub fn into_inner(mut self) -> S {
+        self.value.take().expect("session consumed only once")
+    }
+
+    /// Remove the session from the store, discarding any changes.
+    pub async fn destroy(mut self) -> Result<(), Rejection> {
+        self.value = None;
+        self.store.delete(self.jid.as_str()).await
+    }
+
+    /// Persist the session now, changed or not.
+    pub async fn save(mut self) -> Result<(), Rejection> {
+        let value = self.value.take().expect("session consumed only once");
+        let raw = (self.encode)(&value).map_err(|err| {
+            tracing::error!("failed to encode session of {}: {}", self.jid, err);
+            crate::reject::internal_server_error()
+        })?;
+        self.store.put_raw(self.jid.as_str(), raw).await
+    }
+}
+
+impl<S> Deref for Session<S> {
+    type Target = S;
+
+    fn deref(&self) -> &S {
+        self.value.as_ref().expect("session consumed only once")
+    }
+}
+
+impl<S> DerefMut for Session<S> {
+    fn deref_mut(&mut self) -> &mut S {
+        self.changed = true;
+        self.value.as_mut().expect("session consumed only once")
+    }
+}
+
+impl<S> Drop for Session<S> {
+    fn drop(&mut self) {
+        let Some(value) = self.value.take().filter(|_| self.changed) else {
+            return;
+        };
+        let raw = match (self.encode)(&value) {
+            Ok(raw) => raw,
+            Err(err) => {
+                tracing::error!("failed to encode session of {}: {}", self.jid, err);
+                return;
+            }
+        };
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            tracing::warn!(
+                "session of {} dropped outside of a runtime, not saved",
+                self.jid
+            );
+            return;
+        };
+        let store = self.store.clone();
+        let jid = self.jid.clone();
+        let lock = self.lock.take();
+        runtime.spawn(async move {
+            if let Err(rejection) = store.put_raw(jid.as_str(), raw).await {
+                tracing::error!("failed to save session of {}: {:?}", jid, rejection);
+            }
+            drop(lock);
+        });
+    }
+}
+
+/// A lock per user, shared by the clones of a [`session`] filter.
+#[derive(Clone, Default)]
+struct Locks(Arc<DashMap<BareJid, Arc<Mutex<()>>>>);
+
+impl Locks {
+    async fn lock(&self, jid: &BareJid) -> KeyLock {
+        let lock = self.0.entry(jid.clone()).or_default().clone();
+        KeyLock {
+            guard: Some(lock.lock_owned().await),
+            locks: self.clone(),
+            jid: jid.clone(),
+        }
+    }
+}
+
+/// The lock of one user, forgotten once nobody holds or waits for it.
+struct KeyLock {
+    guard: Option<OwnedMutexGuard<()>>,
+    locks: Locks,
+    jid: BareJid,
+}
+
+impl Drop for KeyLock {
+    fn drop(&mut self) {
+        drop(self.guard.take());
+        self.locks
+            .0
+            .remove_if(&self.jid, |_, lock| Arc::strong_count(lock) == 1);
+    }
+}
+
+impl<S: fmt::Debug> fmt::Debug for Session<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Session")
+            .field("jid", &self.jid)
+            .field("value", &self.value)
+            .field("is_new", &self.is_new)
+            .field("changed", &self.changed)
+            .finish()
+    }
+}
+
+/// Load the session of the stanza's sender from `store`.
+///
+/// Sessions are keyed by the sender's bare JID within the given namespace.
+/// If no session has been saved for the sender yet, a `S::default()` is
+/// created. Rejects with `item-not-found` if the stanza has no `from`, and
+/// with whatever the store rejects with if loading fails.
+///
+/// A new session is only stored once changed.
+pub fn session<S>(
+    store: Namespace,
+) -> impl Filter<Extract = One<Session<S>>, Error = Rejection> + Clone
+where
+    S: Default + DeserializeOwned + Serialize + Send + 'static,
+{
+    let locks = Locks::default();
+    crate::require_from().and_then(move |from: Jid| {
+        let store = store.clone();
+        let locks = locks.clone();
+        async move {
+            let jid = from.to_bare();
+            let lock = locks.lock(&jid).await;
+            let loaded = store.get::<S>(jid.as_str()).await?;
+            let is_new = loaded.is_none();
+            Ok::<_, Rejection>(Session {
+                jid,
+                value: Some(loaded.unwrap_or_default()),
+                is_new,
+                changed: false,
+                store,
+                encode: |value: &S| serde_json::to_vec(value),
+                lock: Some(lock),
+            })
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use tokio_xmpp::Stanza;
+    use xmpp_parsers::message::Message;
+
+    use super::*;
+    use crate::store::{KvStore, MemoryStore};
+
+    #[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
+    struct Wizard {
+        step: u32,
+    }
+
+    fn from_juliet() -> Stanza {
+        let mut msg = Message::new(Some("wizard.capulet.lit".parse().unwrap()));
+        msg.from = Some("juliet@capulet.lit/balcony".parse().unwrap());
+        Stanza::Message(msg)
+    }
+
+    // Run `route` once, then let the session saved on drop reach the store.
+    async fn handle<F>(route: F)
+    where
+        F: Filter + Clone + Send + Sync + 'static,
+        <F::Future as futures_util::TryFuture>::Ok: crate::Reply,
+        <F::Future as futures_util::TryFuture>::Error: crate::reject::IsReject,
+    {
+        let _ = crate::service(route).call_stanza(from_juliet()).await;
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn persists_changes_on_drop() {
+        let store = MemoryStore::new().namespace("wizard");
+
+        let step = session::<Wizard>(store.clone()).map(|mut session: Session<Wizard>| {
+            session.step += 1;
+            crate::sink()
+        });
+        handle(step.clone()).await;
+        handle(step).await;
+
+        assert_eq!(
+            store.get::<Wizard>("juliet@capulet.lit").await.unwrap(),
+            Some(Wizard { step: 2 })
+        );
+    }
+
+    #[tokio::test]
+    async fn loads_wait_for_the_previous_save() {
+        let store = MemoryStore::new().namespace("wizard");
+
+        let step = session::<Wizard>(store.clone()).map(|mut session: Session<Wizard>| {
+            session.step += 1;
+            crate::sink()
+        });
+        // Without yielding in between, so the first save may still be
+        // pending when the second stanza loads.
+        for _ in 0..3 {
+            let _ = crate::service(step.clone())
+                .call_stanza(from_juliet())
+                .await;
+        }
+        handle(step).await;
+
+        assert_eq!(
+            store.get::<Wizard>("juliet@capulet.lit").await.unwrap(),
+            Some(Wizard { step: 4 })
+        );
+    }
+
+    #[tokio::test]
+    async fn skips_unchanged_and_discarded_sessions() {
+        let store = MemoryStore::new().namespace("wizard");
+
+        let peek = session::<Wizard>(store.clone()).map(|session: Session<Wizard>| {
+            assert!(session.is_new());
+            crate::sink()
+        });
+        handle(peek).await;
+        let discard = session::<Wizard>(store.clone()).map(|mut session: Session<Wizard>| {
+            session.step = 5;
+            let _ = session.into_inner();
+            crate::sink()
+        });
+        handle(discard).await;
+
+        assert_eq!(store.list().await.unwrap(), Vec::<String>::new());
+    }
+}
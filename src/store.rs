@@ -0,0 +1,333 @@
+//! Key-value storage shared by the built-in subsystems.
+//!
+//! Every built-in subsystem that persists something ([sessions](crate::session)
+//! and the XEP handlers added on top of it) is generic over a single
+//! [`KvStore`], so one storage backend configuration covers all of them.
+//! Each subsystem works inside its own [`Namespace`], so they can share a
+//! store without stepping on each other's keys.
+//!
+//! Two backends are provided: [`MemoryStore`], and `RedisStore` with the
+//! `wax-redis` feature.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use wax::store::{KvStore, MemoryStore};
+//!
+//! let store = MemoryStore::new();
+//! let nicks = store.namespace("nicks");
+//!
+//! nicks.put("juliet@capulet.lit", &"Jules").await?;
+//! let nick: Option<String> = nicks.get("juliet@capulet.lit").await?;
+//! ```
+
+use std::fmt;
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use futures_util::future::{self, BoxFuture};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::reject::{self, Rejection};
+
+/// A namespaced key-value store.
+///
+/// Values are opaque bytes; [`Namespace`] layers JSON encoding on top.
+pub trait KvStore: Send + Sync + 'static {
+    /// Load the value stored under `key` in namespace `ns`.
+    fn get<'a>(
+        &'a self,
+        ns: &'a str,
+        key: &'a str,
+    ) -> BoxFuture<'a, Result<Option<Vec<u8>>, Rejection>>;
+
+    /// Store `value` under `key` in namespace `ns`, replacing any previous value.
+    fn put<'a>(
+        &'a self,
+        ns: &'a str,
+        key: &'a str,
+        value: Vec<u8>,
+    ) -> BoxFuture<'a, Result<(), Rejection>>;
+
+    /// Remove `key` from namespace `ns`.
+    fn delete<'a>(&'a self, ns: &'a str, key: &'a str) -> BoxFuture<'a, Result<(), Rejection>>;
+
+    /// List every key in namespace `ns`, in no particular order.
+    fn list<'a>(&'a self, ns: &'a str) -> BoxFuture<'a, Result<Vec<String>, Rejection>>;
+
+    /// A typed handle onto namespace `ns` of this store.
+    fn namespace(self, ns: impl Into<String>) -> Namespace
+    where
+        Self: Sized,
+    {
+        Namespace {
+            store: Arc::new(self),
+            ns: ns.into().into(),
+        }
+    }
+}
+
+impl<T: KvStore + ?Sized> KvStore for Arc<T> {
+    fn get<'a>(
+        &'a self,
+        ns: &'a str,
+        key: &'a str,
+    ) -> BoxFuture<'a, Result<Option<Vec<u8>>, Rejection>> {
+        (**self).get(ns, key)
+    }
+
+    fn put<'a>(
+        &'a self,
+        ns: &'a str,
+        key: &'a str,
+        value: Vec<u8>,
+    ) -> BoxFuture<'a, Result<(), Rejection>> {
+        (**self).put(ns, key, value)
+    }
+
+    fn delete<'a>(&'a self, ns: &'a str, key: &'a str) -> BoxFuture<'a, Result<(), Rejection>> {
+        (**self).delete(ns, key)
+    }
+
+    fn list<'a>(&'a self, ns: &'a str) -> BoxFuture<'a, Result<Vec<String>, Rejection>> {
+        (**self).list(ns)
+    }
+}
+
+/// A typed view of one namespace of a [`KvStore`].
+///
+/// Values are encoded as JSON. Clones share the same store.
+#[derive(Clone)]
+pub struct Namespace {
+    store: Arc<dyn KvStore>,
+    ns: Arc<str>,
+}
+
+impl Namespace {
+    /// The name of this namespace.
+    pub fn name(&self) -> &str {
+        &self.ns
+    }
+
+    /// Load and decode the value stored under `key`.
+    pub async fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, Rejection> {
+        match self.store.get(&self.ns, key).await? {
+            Some(raw) => serde_json::from_slice(&raw).map(Some).map_err(|err| {
+                tracing::error!("invalid value for {}/{}: {}", self.ns, key, err);
+                reject::internal_server_error()
+            }),
+            None => Ok(None),
+        }
+    }
+
+    /// Encode and store `value` under `key`.
+    pub async fn put<T: Serialize + ?Sized>(&self, key: &str, value: &T) -> Result<(), Rejection> {
+        let raw = serde_json::to_vec(value).map_err(|err| {
+            tracing::error!("failed to encode value for {}/{}: {}", self.ns, key, err);
+            reject::internal_server_error()
+        })?;
+        self.store.put(&self.ns, key, raw).await
+    }
+
+    /// Store `raw`, already encoded, under `key`.
+    pub(crate) async fn put_raw(&self, key: &str, raw: Vec<u8>) -> Result<(), Rejection> {
+        self.store.put(&self.ns, key, raw).await
+    }
+
+    /// Remove `key`.
+    pub async fn delete(&self, key: &str) -> Result<(), Rejection> {
+        self.store.delete(&self.ns, key).await
+    }
+
+    /// List every key in this namespace.
+    pub async fn list(&self) -> Result<Vec<String>, Rejection> {
+        self.store.list(&self.ns).await
+    }
+}
+
+impl fmt::Debug for Namespace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Namespace").field(&self.ns).finish()
+    }
+}
+
+/// An in-memory [`KvStore`].
+///
+/// Everything is lost when the process exits. Clones share the same storage.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStore {
+    data: Arc<DashMap<(String, String), Vec<u8>>>,
+}
+
+impl MemoryStore {
+    /// Create an empty store.
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+}
+
+impl KvStore for MemoryStore {
+    fn get<'a>(
+        &'a self,
+        ns: &'a str,
+        key: &'a str,
+    ) -> BoxFuture<'a, Result<Option<Vec<u8>>, Rejection>> {
+        let value = self
+            .data
+            .get(&(ns.to_owned(), key.to_owned()))
+            .map(|v| v.clone());
+        Box::pin(future::ok(value))
+    }
+
+    fn put<'a>(
+        &'a self,
+        ns: &'a str,
+        key: &'a str,
+        value: Vec<u8>,
+    ) -> BoxFuture<'a, Result<(), Rejection>> {
+        self.data.insert((ns.to_owned(), key.to_owned()), value);
+        Box::pin(future::ok(()))
+    }
+
+    fn delete<'a>(&'a self, ns: &'a str, key: &'a str) -> BoxFuture<'a, Result<(), Rejection>> {
+        self.data.remove(&(ns.to_owned(), key.to_owned()));
+        Box::pin(future::ok(()))
+    }
+
+    fn list<'a>(&'a self, ns: &'a str) -> BoxFuture<'a, Result<Vec<String>, Rejection>> {
+        let keys = self
+            .data
+            .iter()
+            .filter(|entry| entry.key().0 == ns)
+            .map(|entry| entry.key().1.clone())
+            .collect();
+        Box::pin(future::ok(keys))
+    }
+}
+
+#[cfg(feature = "wax-redis")]
+pub use self::redis_store::RedisStore;
+
+#[cfg(feature = "wax-redis")]
+mod redis_store {
+    use ::redis::AsyncCommands;
+    use futures_util::future::BoxFuture;
+
+    use super::KvStore;
+    use crate::ext::redis::{self as ext_redis, RedisPool};
+    use crate::reject::Rejection;
+
+    /// A [`KvStore`] backed by Redis.
+    ///
+    /// Available with the `wax-redis` feature. Values live under
+    /// `{prefix}data:{ns}:{key}`, and the keys of each namespace are indexed
+    /// in a set under `{prefix}keys:{ns}` so they can be listed without a
+    /// `SCAN`.
+    #[derive(Clone, Debug)]
+    pub struct RedisStore {
+        pool: RedisPool,
+        prefix: String,
+    }
+
+    impl RedisStore {
+        /// Create a store whose keys all start with `prefix`.
+        pub fn new(pool: RedisPool, prefix: impl Into<String>) -> Self {
+            RedisStore {
+                pool,
+                prefix: prefix.into(),
+            }
+        }
+
+        fn data_key(&self, ns: &str, key: &str) -> String {
+            format!("{}data:{}:{}", self.prefix, ns, key)
+        }
+
+        fn index_key(&self, ns: &str) -> String {
+            format!("{}keys:{}", self.prefix, ns)
+        }
+    }
+
+    impl KvStore for RedisStore {
+        fn get<'a>(
+            &'a self,
+            ns: &'a str,
+            key: &'a str,
+        ) -> BoxFuture<'a, Result<Option<Vec<u8>>, Rejection>> {
+            Box::pin(async move {
+                let mut con = ext_redis::connection(&self.pool).await?;
+                con.get(self.data_key(ns, key))
+                    .await
+                    .map_err(ext_redis::rejection)
+            })
+        }
+
+        fn put<'a>(
+            &'a self,
+            ns: &'a str,
+            key: &'a str,
+            value: Vec<u8>,
+        ) -> BoxFuture<'a, Result<(), Rejection>> {
+            Box::pin(async move {
+                let mut con = ext_redis::connection(&self.pool).await?;
+                ::redis::pipe()
+                    .atomic()
+                    .set(self.data_key(ns, key), value)
+                    .ignore()
+                    .sadd(self.index_key(ns), key)
+                    .ignore()
+                    .query_async::<()>(&mut *con)
+                    .await
+                    .map_err(ext_redis::rejection)
+            })
+        }
+
+        fn delete<'a>(&'a self, ns: &'a str, key: &'a str) -> BoxFuture<'a, Result<(), Rejection>> {
+            Box::pin(async move {
+                let mut con = ext_redis::connection(&self.pool).await?;
+                ::redis::pipe()
+                    .atomic()
+                    .del(self.data_key(ns, key))
+                    .ignore()
+                    .srem(self.index_key(ns), key)
+                    .ignore()
+                    .query_async::<()>(&mut *con)
+                    .await
+                    .map_err(ext_redis::rejection)
+            })
+        }
+
+        fn list<'a>(&'a self, ns: &'a str) -> BoxFuture<'a, Result<Vec<String>, Rejection>> {
+            Box::pin(async move {
+                let mut con = ext_redis::connection(&self.pool).await?;
+                con.smembers(self.index_key(ns))
+                    .await
+                    .map_err(ext_redis::rejection)
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn namespaces_are_isolated() {
+        let store = MemoryStore::new();
+        let a = store.clone().namespace("a");
+        let b = store.namespace("b");
+
+        a.put("key", &1u32).await.unwrap();
+        b.put("key", &2u32).await.unwrap();
+        b.put("other", &3u32).await.unwrap();
+
+        assert_eq!(a.get::<u32>("key").await.unwrap(), Some(1));
+        assert_eq!(b.get::<u32>("key").await.unwrap(), Some(2));
+        assert_eq!(a.list().await.unwrap(), vec!["key".to_owned()]);
+
+        b.delete("key").await.unwrap();
+        assert_eq!(b.get::<u32>("key").await.unwrap(), None);
+        assert_eq!(a.get::<u32>("key").await.unwrap(), Some(1));
+    }
+}
@@ -0,0 +1,264 @@
+//! Message Styling (XEP-0393).
+//!
+//! [`parse`] turns the body of a message into a tree of [`Block`]s and
+//! [`Span`]s, which a bridge can convert into the rich text of another
+//! network; [`render`] turns such a tree back into styled plain text.
+//!
+//! # Example
+//!
+//! ```
+//! use wax::styling::{self, Block, Span};
+//!
+//! let blocks = styling::parse("> *hello* world");
+//! assert_eq!(
+//!     blocks,
+//!     vec![Block::Quote(vec![Block::Line(vec![
+//!         Span::Strong(vec![Span::Plain("hello".to_owned())]),
+//!         Span::Plain(" world".to_owned()),
+//!     ])])]
+//! );
+//! assert_eq!(styling::render(&blocks), "> *hello* world");
+//! ```
+
+/// A block of styled text.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Block {
+    /// A line of text.
+    Line(Vec<Span>),
+    /// Preformatted lines, between fences.
+    Pre {
+        /// The text after the opening fence, e.g. a language, if any.
+        info: Option<String>,
+        /// The lines, unstyled.
+        text: String,
+    },
+    /// Quoted blocks.
+    Quote(Vec<Block>),
+}
+
+/// A span of styled text within a line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Span {
+    /// Unstyled text.
+    Plain(String),
+    /// `*strong*` text.
+    Strong(Vec<Span>),
+    /// `_emphasized_` text.
+    Emphasis(Vec<Span>),
+    /// `~struck through~` text.
+    Strike(Vec<Span>),
+    /// `` `preformatted` `` text, unstyled.
+    Pre(String),
+}
+
+/// Parse styled text.
+pub fn parse(text: &str) -> Vec<Block> {
+    let lines: Vec<&str> = text.split('\n').collect();
+    blocks(&lines)
+}
+
+/// Render blocks as styled text.
+pub fn render(blocks: &[Block]) -> String {
+    let mut lines = Vec::new();
+    render_blocks(blocks, &mut lines);
+    lines.join("\n")
+}
+
+/// The text of blocks, without styling directives or quote markers.
+pub fn plain(blocks: &[Block]) -> String {
+    let mut lines = Vec::new();
+    plain_blocks(blocks, &mut lines);
+    lines.join("\n")
+}
+
+fn blocks(lines: &[&str]) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        if let Some(info) = line.strip_prefix("```") {
+            // A block runs to its closing fence, or to the end of its parent.
+            let end = lines[i + 1..]
+                .iter()
+                .position(|line| *line == "```")
+                .map_or(lines.len(), |end| i + 1 + end);
+            let info = info.trim();
+            blocks.push(Block::Pre {
+                info: (!info.is_empty()).then(|| info.to_owned()),
+                text: lines[i + 1..end].join("\n"),
+            });
+            i = end + 1;
+        } else if line.starts_with('>') {
+            let mut quoted = Vec::new();
+            while let Some(line) = lines.get(i).and_then(|line| line.strip_prefix('>')) {
+                quoted.push(line.strip_prefix(' ').unwrap_or(line));
+                i += 1;
+            }
+            blocks.push(Block::Quote(self::blocks(&quoted)));
+        } else {
+            let chars: Vec<char> = line.chars().collect();
+            blocks.push(Block::Line(spans(&chars)));
+            i += 1;
+        }
+    }
+    blocks
+}
+
+fn spans(line: &[char]) -> Vec<Span> {
+    let mut spans = Vec::new();
+    let mut text = String::new();
+    let mut i = 0;
+    while i < line.len() {
+        let c = line[i];
+        if let Some(end) = closing(line, i) {
+            if !text.is_empty() {
+                spans.push(Span::Plain(std::mem::take(&mut text)));
+            }
+            let inner = &line[i + 1..end];
+            spans.push(match c {
+                '*' => Span::Strong(self::spans(inner)),
+                '_' => Span::Emphasis(self::spans(inner)),
+                '~' => Span::Strike(self::spans(inner)),
+                _ => Span::Pre(inner.iter().collect()),
+            });
+            i = end + 1;
+        } else {
+            text.push(c);
+            i += 1;
+        }
+    }
+    if !text.is_empty() {
+        spans.push(Span::Plain(text));
+    }
+    spans
+}
+
+// The position of the directive closing a span opened at `start`, if the
+// character there opens one.
+fn closing(line: &[char], start: usize) -> Option<usize> {
+    let c = line[start];
+    if !is_directive(c) {
+        return None;
+    }
+    let opens = line
+        .get(start + 1)
+        .map_or(false, |next| !next.is_whitespace())
+        && (start == 0 || line[start - 1].is_whitespace() || is_directive(line[start - 1]));
+    if !opens {
+        return None;
+    }
+    (start + 2..line.len()).find(|&end| line[end] == c && !line[end - 1].is_whitespace())
+}
+
+fn is_directive(c: char) -> bool {
+    matches!(c, '*' | '_' | '~' | '`')
+}
+
+fn render_blocks(blocks: &[Block], lines: &mut Vec<String>) {
+    for block in blocks {
+        match block {
+            Block::Line(spans) => {
+                let mut line = String::new();
+                render_spans(spans, &mut line);
+                lines.push(line);
+            }
+            Block::Pre { info, text } => {
+                lines.push(format!("```{}", info.as_deref().unwrap_or_default()));
+                lines.extend(text.split('\n').map(str::to_owned));
+                lines.push("```".to_owned());
+            }
+            Block::Quote(blocks) => {
+                let mut quoted = Vec::new();
+                render_blocks(blocks, &mut quoted);
+                lines.extend(quoted.into_iter().map(|line| {
+                    if line.starts_with('>') || line.is_empty() {
+                        format!(">{}", line)
+                    } else {
+                        format!("> {}", line)
+                    }
+                }));
+            }
+        }
+    }
+}
+
+fn render_spans(spans: &[Span], out: &mut String) {
+    for span in spans {
+        let (directive, inner) = match span {
+            Span::Plain(text) => {
+                out.push_str(text);
+                continue;
+            }
+            Span::Pre(text) => {
+                out.push('`');
+                out.push_str(text);
+                out.push('`');
+                continue;
+            }
+            Span::Strong(inner) => ('*', inner),
+            Span::Emphasis(inner) => ('_', inner),
+            Span::Strike(inner) => ('~', inner),
+        };
+        out.push(directive);
+        render_spans(inner, out);
+        out.push(directive);
+    }
+}
+
+fn plain_blocks(blocks: &[Block], lines: &mut Vec<String>) {
+    for block in blocks {
+        match block {
+            Block::Line(spans) => {
+                let mut line = String::new();
+                plain_spans(spans, &mut line);
+                lines.push(line);
+            }
+            Block::Pre { text, .. } => lines.extend(text.split('\n').map(str::to_owned)),
+            Block::Quote(blocks) => plain_blocks(blocks, lines),
+        }
+    }
+}
+
+fn plain_spans(spans: &[Span], out: &mut String) {
+    for span in spans {
+        match span {
+            Span::Plain(text) | Span::Pre(text) => out.push_str(text),
+            Span::Strong(inner) | Span::Emphasis(inner) | Span::Strike(inner) => {
+                plain_spans(inner, out)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spans_follow_the_directive_rules() {
+        assert_eq!(
+            parse("not *strong * nor* *this*"),
+            vec![Block::Line(vec![
+                Span::Plain("not ".to_owned()),
+                Span::Strong(vec![Span::Plain("strong * nor".to_owned())]),
+                Span::Plain(" ".to_owned()),
+                Span::Strong(vec![Span::Plain("this".to_owned())]),
+            ])]
+        );
+        assert_eq!(
+            parse("`*pre*` and **"),
+            vec![Block::Line(vec![
+                Span::Pre("*pre*".to_owned()),
+                Span::Plain(" and **".to_owned()),
+            ])]
+        );
+    }
+
+    #[test]
+    fn blocks_round_trip() {
+        let text = "> quoted\n>> nested\n```rust\nlet _x = *y*;\n```\n_done_";
+        let blocks = parse(text);
+        assert_eq!(render(&blocks), text);
+        assert_eq!(plain(&blocks), "quoted\nnested\nlet _x = *y*;\ndone");
+    }
+}
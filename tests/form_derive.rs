@@ -0,0 +1,64 @@
+#![deny(warnings)]
+use wax::form::{DataForm, Field, FormType, FromDataForm};
+
+#[derive(Debug, PartialEq, FromDataForm)]
+#[form(form_type = "urn:example:signup")]
+struct Signup {
+    #[form(var = "phone-number")]
+    phone: String,
+    nick: Option<String>,
+    #[form(var = "accept-terms")]
+    accepted: bool,
+    interests: Vec<String>,
+}
+
+fn signup() -> DataForm {
+    DataForm::new(FormType::Submit)
+        .form_type("urn:example:signup")
+        .field(Field::new("phone-number").value("+15555550100"))
+        .field(Field::new("accept-terms").value("true"))
+}
+
+#[test]
+fn reads_required_optional_and_multi_valued_fields() {
+    assert_eq!(Signup::form_type(), Some("urn:example:signup"));
+
+    let form = signup()
+        .field(Field::new("nick").value("juliet"))
+        .field(Field::new("interests").value("balconies").value("poison"));
+    assert_eq!(
+        Signup::from_data_form(&form).unwrap(),
+        Signup {
+            phone: "+15555550100".to_owned(),
+            nick: Some("juliet".to_owned()),
+            accepted: true,
+            interests: vec!["balconies".to_owned(), "poison".to_owned()],
+        }
+    );
+
+    // Optional and multi-valued fields may be left out.
+    let signup = Signup::from_data_form(&signup()).unwrap();
+    assert_eq!(signup.nick, None);
+    assert!(signup.interests.is_empty());
+}
+
+#[test]
+fn rejects_missing_and_malformed_fields() {
+    let missing = DataForm::new(FormType::Submit)
+        .form_type("urn:example:signup")
+        .field(Field::new("accept-terms").value("true"));
+    let err = Signup::from_data_form(&missing).unwrap_err();
+    assert_eq!(err.to_string(), "missing required field `phone-number`");
+
+    let malformed = DataForm::new(FormType::Submit)
+        .field(Field::new("phone-number").value("+15555550100"))
+        .field(Field::new("accept-terms").value("maybe"));
+    let err = Signup::from_data_form(&malformed).unwrap_err();
+    assert_eq!(err.to_string(), "field `accept-terms`: not a boolean");
+}
+
+#[test]
+fn rejects_bad_input() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/form_derive/*.rs");
+}
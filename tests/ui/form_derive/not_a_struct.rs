@@ -0,0 +1,9 @@
+use wax::form::FromDataForm;
+
+#[derive(FromDataForm)]
+enum Answer {
+    Yes,
+    No,
+}
+
+fn main() {}
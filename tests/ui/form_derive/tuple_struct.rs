@@ -0,0 +1,6 @@
+use wax::form::FromDataForm;
+
+#[derive(FromDataForm)]
+struct Phone(String);
+
+fn main() {}
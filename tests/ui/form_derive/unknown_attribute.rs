@@ -0,0 +1,9 @@
+use wax::form::FromDataForm;
+
+#[derive(FromDataForm)]
+struct Signup {
+    #[form(name = "phone-number")]
+    phone: String,
+}
+
+fn main() {}
@@ -0,0 +1,104 @@
+//! Derive macros for wax.
+//!
+//! Use them through wax with its `derive` feature, e.g.
+//! `wax::form::FromDataForm`.
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, LitStr};
+
+/// Derive `wax::form::FromDataForm` for a struct with named fields.
+///
+/// Each struct field is read from the form field of the same name, or the
+/// one named with `#[form(var = "...")]`. `#[form(form_type = "...")]` on the
+/// struct sets the expected `FORM_TYPE`.
+#[proc_macro_derive(FromDataForm, attributes(form))]
+pub fn derive_from_data_form(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let mut form_type = None;
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("form"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("form_type") {
+                form_type = Some(meta.value()?.parse::<LitStr>()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `form_type`"))
+            }
+        })?;
+    }
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    name,
+                    "FromDataForm needs a struct with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                name,
+                "FromDataForm can only be derived for structs",
+            ))
+        }
+    };
+
+    let mut reads = Vec::new();
+    for field in fields {
+        let ident = field.ident.as_ref().expect("named field");
+        let mut var = LitStr::new(&ident.to_string(), ident.span());
+        for attr in field
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("form"))
+        {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("var") {
+                    var = meta.value()?.parse::<LitStr>()?;
+                    Ok(())
+                } else {
+                    Err(meta.error("expected `var`"))
+                }
+            })?;
+        }
+        reads.push(quote! {
+            #ident: ::wax::form::FromField::from_field(#var, form.values(#var))?
+        });
+    }
+
+    let form_type = match form_type {
+        Some(form_type) => quote! { ::std::option::Option::Some(#form_type) },
+        None => quote! { ::std::option::Option::None },
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::wax::form::FromDataForm for #name #ty_generics #where_clause {
+            fn form_type() -> ::std::option::Option<&'static str> {
+                #form_type
+            }
+
+            fn from_data_form(
+                form: &::wax::form::DataForm,
+            ) -> ::std::result::Result<Self, ::wax::form::FormError> {
+                ::std::result::Result::Ok(#name {
+                    #(#reads),*
+                })
+            }
+        }
+    })
+}